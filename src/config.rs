@@ -1,7 +1,13 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 pub const DEFAULT_IMAGE: &str = "alpine:latest";
 
+/// Per-repo configuration file, looked up at the git root.
+pub const REPO_CONFIG_FILE: &str = ".box.toml";
+
 /// Return the user's home directory from the HOME environment variable.
 /// Returns an error if HOME is not set or is empty.
 pub fn home_dir() -> Result<String> {
@@ -20,6 +26,8 @@ pub struct BoxConfig {
     pub command: Vec<String>,
     pub env: Vec<String>,
     pub ssh: bool,
+    pub docker_args: String,
+    pub worktree: bool,
 }
 
 pub struct BoxConfigInput {
@@ -29,24 +37,669 @@ pub struct BoxConfigInput {
     pub project_dir: String,
     pub command: Option<Vec<String>>,
     pub env: Vec<String>,
-    pub ssh: bool,
+    pub ssh: Option<bool>,
+    pub docker_args: Option<String>,
+    pub template: Option<Template>,
+    pub worktree: Option<bool>,
+}
+
+/// User-wide defaults read from `$XDG_CONFIG_HOME/box/config.toml`
+/// (`~/.config/box/config.toml` when `XDG_CONFIG_HOME` is unset).
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GlobalConfig {
+    pub image: Option<String>,
+    pub command: Option<String>,
+    pub env: Vec<String>,
+    pub docker_args: Option<String>,
+    pub ssh: Option<bool>,
+    pub workspace_dir: Option<String>,
+    /// Create workspaces with `git worktree add` instead of `git clone --local`.
+    pub worktree: Option<bool>,
+    /// `git clone --depth` for new workspaces.
+    pub clone_depth: Option<u32>,
+    /// `git clone --filter` for new workspaces, e.g. `blob:none`.
+    pub clone_filter: Option<String>,
+    /// Fetch Git LFS files into new workspaces that use them (default: true).
+    pub lfs: Option<bool>,
+    /// Check out submodules in new workspaces that have them (default: true).
+    pub submodules: Option<bool>,
+    /// Untracked files to copy from the project into new workspaces, as globs.
+    pub copy_untracked: Vec<String>,
+    /// Keep new workspaces in a Docker volume instead of a bind-mounted directory.
+    pub volume: Option<bool>,
+    /// Run containers as the host user's uid:gid.
+    pub user_map: Option<bool>,
+    /// Default `docker run --network` mode for new sessions.
+    pub network: Option<String>,
+    /// Minutes a session may sit idle before `box autostop` stops it.
+    pub idle_timeout: Option<u64>,
+    /// Age in days after which `box gc` removes workspaces of removed sessions.
+    pub gc_older_than: Option<u64>,
+    /// Key sequence for detaching from a session, in docker's `--detach-keys` format.
+    pub detach_keys: Option<String>,
+    /// Dotfiles git URL or local directory, installed into every new session.
+    pub dotfiles: Option<String>,
+    /// Install script inside the dotfiles, relative to their root.
+    pub dotfiles_install: Option<String>,
+    /// Shared cache volumes mounted into every new session (see `resolve_cache`).
+    pub caches: Vec<String>,
+    pub hooks: Hooks,
+}
+
+impl GlobalConfig {
+    pub fn path() -> Result<PathBuf> {
+        let base = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(home_dir()?).join(".config"),
+        };
+        Ok(base.join("box").join("config.toml"))
+    }
+
+    /// Load the global config. A missing file (or no resolvable location) yields the empty config.
+    pub fn load() -> Result<GlobalConfig> {
+        match Self::path() {
+            Ok(path) => load_toml(&path),
+            Err(_) => Ok(GlobalConfig::default()),
+        }
+    }
+}
+
+/// Settings read from `.box.toml` at the root of a repository.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    pub image: Option<String>,
+    pub mount_path: Option<String>,
+    pub env: Vec<String>,
+    pub docker_args: Option<String>,
+    pub command: Option<String>,
+    pub worktree: Option<bool>,
+    pub clone_depth: Option<u32>,
+    pub clone_filter: Option<String>,
+    pub lfs: Option<bool>,
+    pub submodules: Option<bool>,
+    pub copy_untracked: Vec<String>,
+    pub network: Option<String>,
+    pub caches: Vec<String>,
+    pub hooks: Hooks,
+}
+
+impl RepoConfig {
+    /// Load `.box.toml` from `project_dir`. A missing file yields the empty config.
+    pub fn load(project_dir: &str) -> Result<RepoConfig> {
+        load_toml(&Path::new(project_dir).join(REPO_CONFIG_FILE))
+    }
+}
+
+/// Lifecycle hooks from the `[hooks]` table of `.box.toml` or the global config.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Runs once after `box create` starts the new container (default: in the container).
+    pub post_create: Option<Hook>,
+    /// Runs before `box resume` starts a stopped session (host only).
+    pub pre_resume: Option<Hook>,
+    /// Runs after `box stop` stops a session (host only).
+    pub post_stop: Option<Hook>,
+    /// Host commands told about every lifecycle event (see `events::emit`).
+    /// Only read from the global config.
+    pub events: Vec<String>,
+}
+
+/// A hook command: a plain string, or `{ run = "...", on = "host" | "container" }`.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Hook {
+    Command(String),
+    Spec { run: String, on: Option<HookTarget> },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookTarget {
+    Host,
+    Container,
+}
+
+impl Hook {
+    pub fn command(&self) -> &str {
+        match self {
+            Hook::Command(run) | Hook::Spec { run, .. } => run,
+        }
+    }
+
+    pub fn target(&self, default: HookTarget) -> HookTarget {
+        match self {
+            Hook::Spec { on: Some(on), .. } => *on,
+            _ => default,
+        }
+    }
+}
+
+/// Hooks for a project: each hook in `.box.toml` replaces the global one.
+/// Event hooks are the user's own and come from the global config only, so a
+/// cloned repository can neither switch them off nor add host commands that
+/// run on every event; `events` in `.box.toml` is ignored.
+pub fn hooks(project_dir: &str) -> Result<Hooks> {
+    let repo = RepoConfig::load(project_dir)?.hooks;
+    let global = GlobalConfig::load()?.hooks;
+    Ok(Hooks {
+        post_create: repo.post_create.or(global.post_create),
+        pre_resume: repo.pre_resume.or(global.pre_resume),
+        post_stop: repo.post_stop.or(global.post_stop),
+        events: global.events,
+    })
+}
+
+/// Reusable session settings stored as `~/.box/templates/<name>.toml`.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Template {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount_path: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docker_args: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub caches: Vec<String>,
+}
+
+impl Template {
+    pub fn dir() -> Result<PathBuf> {
+        crate::storage::templates_dir()
+    }
+
+    pub fn path(name: &str) -> Result<PathBuf> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!(
+                "Invalid template name '{}'. Use only letters, digits, hyphens, and underscores.",
+                name
+            );
+        }
+        Ok(Self::dir()?.join(format!("{}.toml", name)))
+    }
+
+    /// Load a saved template, or `registry/name` from a shared registry.
+    pub fn load(name: &str) -> Result<Template> {
+        if let Some((registry, template)) = name.split_once('/') {
+            return crate::registry::load(registry, template);
+        }
+        let path = Self::path(name)?;
+        if !path.is_file() {
+            bail!("Template '{}' not found.", name);
+        }
+        load_toml(&path)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let path = Self::path(name)?;
+        std::fs::create_dir_all(Self::dir()?).context("Failed to create templates directory")?;
+        std::fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn remove(name: &str) -> Result<()> {
+        if let Some((registry, _)) = name.split_once('/') {
+            bail!(
+                "Template '{}' comes from registry '{}'. Remove the registry with `box template remove --registry {}`.",
+                name,
+                registry,
+                registry
+            );
+        }
+        let path = Self::path(name)?;
+        if !path.is_file() {
+            bail!("Template '{}' not found.", name);
+        }
+        std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))
+    }
+
+    /// Names of all saved templates, sorted, followed by the cached
+    /// templates of shared registries as `registry/name`.
+    pub fn list() -> Result<Vec<String>> {
+        let dir = Self::dir()?;
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut names: Vec<String> = std::fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.strip_suffix(".toml").map(|n| n.to_string())
+            })
+            .collect();
+        names.sort();
+        names.extend(crate::registry::templates()?);
+        Ok(names)
+    }
+}
+
+/// Parse a TOML config file, treating a missing file as the default value.
+pub fn load_toml<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Default image for a new session in `project_dir`, with the same
+/// precedence as `resolve`: `.box.toml`, `$BOX_DEFAULT_IMAGE`, the global
+/// config, then the image detected from the project type.
+pub fn default_image(project_dir: Option<&str>) -> String {
+    project_dir
+        .and_then(|dir| RepoConfig::load(dir).ok()?.image)
+        .or_else(|| std::env::var("BOX_DEFAULT_IMAGE").ok())
+        .or_else(|| GlobalConfig::load().ok().and_then(|g| g.image))
+        .or_else(|| project_dir.and_then(detect_image).map(String::from))
+        .unwrap_or_else(|| DEFAULT_IMAGE.to_string())
+}
+
+/// Toolchain images for project types, by the file that marks them. The
+/// first match wins.
+const PROJECT_IMAGES: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust:latest"),
+    ("go.mod", "golang:latest"),
+    ("package.json", "node:lts"),
+    ("pyproject.toml", "python:3"),
+    ("requirements.txt", "python:3"),
+];
+
+/// Image with the toolchain for the project in `project_dir`, if its type
+/// is recognized.
+pub fn detect_image(project_dir: &str) -> Option<&'static str> {
+    let dir = Path::new(project_dir);
+    PROJECT_IMAGES
+        .iter()
+        .find(|(marker, _)| dir.join(marker).is_file())
+        .map(|(_, image)| *image)
+}
+
+/// Cache volumes for the toolchains in `project_dir`, by the files that mark
+/// them. Rust and Go point at the cache directories of the official images
+/// picked by `detect_image`.
+const PROJECT_CACHES: &[(&str, &str)] = &[
+    ("Cargo.toml", "cargo:/usr/local/cargo/registry"),
+    ("go.mod", "go:/go/pkg/mod"),
+    ("package.json", "npm"),
+    ("pyproject.toml", "pip"),
+    ("requirements.txt", "pip"),
+    ("pom.xml", "maven"),
+    ("build.gradle", "gradle"),
+    ("build.gradle.kts", "gradle"),
+];
+
+/// Cache volumes worth mounting for the project in `project_dir`.
+pub fn detect_caches(project_dir: &str) -> Vec<&'static str> {
+    let dir = Path::new(project_dir);
+    let mut caches: Vec<&'static str> = Vec::new();
+    for (marker, cache) in PROJECT_CACHES {
+        if dir.join(marker).is_file() && !caches.contains(cache) {
+            caches.push(cache);
+        }
+    }
+    caches
+}
+
+/// A commented `.box.toml` for `project_dir`, filled in with the detected
+/// image, the default mount path and the caches for its toolchains. The
+/// remaining keys are left commented out as examples.
+pub fn init_config(project_dir: &str) -> String {
+    let image = detect_image(project_dir).unwrap_or(DEFAULT_IMAGE);
+    let caches = detect_caches(project_dir);
+    let caches_line = if caches.is_empty() {
+        "# caches = [\"npm\"]".to_string()
+    } else {
+        let quoted: Vec<String> = caches.iter().map(|c| format!("{:?}", c)).collect();
+        format!("caches = [{}]", quoted.join(", "))
+    };
+    format!(
+        r#"# Session defaults for box in this repository. All keys are optional, and
+# command-line flags and templates override them.
+
+# Image for new sessions
+image = "{image}"
+
+# Where the workspace is mounted inside the container
+mount_path = "{mount_path}"
+
+# Shared cache volumes: a preset ({presets}) or NAME:/container/path
+{caches_line}
+
+# Environment variables for every session
+# env = ["KEY=value"]
+
+# Command to run instead of the image's default
+# command = "bash"
+
+# Extra `docker run` flags
+# docker_args = "--network host"
+
+# Network mode: none, bridge, host, or a docker network name
+# network = "bridge"
+
+# Create workspaces with `git worktree add` instead of a clone
+# worktree = true
+
+# Untracked files to copy into new workspaces, as globs
+# copy_untracked = [".env"]
+
+# Commands run at points in a session's life
+# [hooks]
+# post_create = "make setup"
+"#,
+        image = image,
+        mount_path = derive_mount_path(project_dir),
+        presets = CACHE_PRESETS
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", "),
+        caches_line = caches_line,
+    )
+}
+
+/// Default command text for new sessions when no repo config applies.
+pub fn default_command() -> String {
+    std::env::var("BOX_DEFAULT_CMD")
+        .ok()
+        .or_else(|| GlobalConfig::load().ok().and_then(|g| g.command))
+        .unwrap_or_default()
+}
+
+/// Default extra docker flags: `$BOX_DOCKER_ARGS`, then the global config.
+pub fn default_docker_args() -> String {
+    std::env::var("BOX_DOCKER_ARGS")
+        .ok()
+        .or_else(|| GlobalConfig::load().ok().and_then(|g| g.docker_args))
+        .unwrap_or_default()
+}
+
+/// Detach key sequence for attached sessions: `$BOX_DETACH_KEYS`, then the
+/// global config. `None` keeps docker's default (ctrl-p,ctrl-q).
+pub fn detach_keys() -> Option<String> {
+    std::env::var("BOX_DETACH_KEYS")
+        .ok()
+        .or_else(|| GlobalConfig::load().ok().and_then(|g| g.detach_keys))
+        .filter(|keys| !keys.is_empty())
+}
+
+/// Shell for `box shell`: `$BOX_SHELL`. `None` picks bash when the image has
+/// it, else sh.
+pub fn shell() -> Option<String> {
+    std::env::var("BOX_SHELL")
+        .ok()
+        .filter(|shell| !shell.trim().is_empty())
+}
+
+/// Whether new sessions keep their workspace in a Docker volume by default.
+pub fn volume_workspace() -> bool {
+    GlobalConfig::load()
+        .ok()
+        .and_then(|g| g.volume)
+        .unwrap_or(false)
 }
 
+/// Network mode for a new session: the command line, then `.box.toml`, then
+/// the global config. `None` leaves docker's default bridge network.
+pub fn network(project_dir: &str, cli: Option<String>) -> Result<Option<String>> {
+    if cli.is_some() {
+        return Ok(cli);
+    }
+    Ok(RepoConfig::load(project_dir)?
+        .network
+        .or(GlobalConfig::load()?.network)
+        .filter(|n| !n.is_empty()))
+}
+
+/// Shallow/partial clone settings for a new workspace: the command line,
+/// then `.box.toml`, then the global config. A depth of 0 or an empty filter
+/// turns the setting off.
+pub fn clone_options(
+    project_dir: &str,
+    depth: Option<u32>,
+    filter: Option<String>,
+) -> Result<(Option<u32>, Option<String>)> {
+    let repo = RepoConfig::load(project_dir)?;
+    let global = GlobalConfig::load()?;
+    let depth = depth
+        .or(repo.clone_depth)
+        .or(global.clone_depth)
+        .filter(|&d| d > 0);
+    let filter = filter
+        .or(repo.clone_filter)
+        .or(global.clone_filter)
+        .filter(|f| !f.is_empty());
+    Ok((depth, filter))
+}
+
+/// Whether to fetch Git LFS files and check out submodules in a new
+/// workspace: `.box.toml`, then the global config, on by default.
+pub fn lfs_and_submodules(project_dir: &str) -> (bool, bool) {
+    let repo = RepoConfig::load(project_dir).unwrap_or_default();
+    let global = GlobalConfig::load().unwrap_or_default();
+    (
+        repo.lfs.or(global.lfs).unwrap_or(true),
+        repo.submodules.or(global.submodules).unwrap_or(true),
+    )
+}
+
+/// Globs of untracked files (e.g. `.env`, `local-settings/**`) to copy from
+/// the project into a new workspace, from the global config and `.box.toml`.
+pub fn copy_untracked(project_dir: &str) -> Result<Vec<String>> {
+    let mut globs = GlobalConfig::load()?.copy_untracked;
+    for glob in RepoConfig::load(project_dir)?.copy_untracked {
+        if !globs.contains(&glob) {
+            globs.push(glob);
+        }
+    }
+    Ok(globs)
+}
+
+/// Idle timeout in minutes for sessions that don't set their own.
+/// `None` (or 0) leaves them running.
+pub fn idle_timeout() -> Option<u64> {
+    GlobalConfig::load()
+        .ok()
+        .and_then(|g| g.idle_timeout)
+        .filter(|&minutes| minutes > 0)
+}
+
+/// Days an orphaned workspace is kept before `box gc` removes it (default 7).
+pub fn gc_older_than() -> u64 {
+    GlobalConfig::load()
+        .ok()
+        .and_then(|g| g.gc_older_than)
+        .unwrap_or(7)
+}
+
+/// Whether new sessions run as the host user by default.
+pub fn user_map() -> bool {
+    GlobalConfig::load()
+        .ok()
+        .and_then(|g| g.user_map)
+        .unwrap_or(false)
+}
+
+/// Dotfiles to install into new sessions.
+#[derive(Debug, PartialEq)]
+pub struct Dotfiles {
+    /// Git URL or local directory.
+    pub source: String,
+    /// Install script relative to the dotfiles root; `None` looks for the usual names.
+    pub install: Option<String>,
+}
+
+impl Dotfiles {
+    /// The local directory, or `None` if `source` is a git URL to clone.
+    pub fn local_path(&self) -> Result<Option<PathBuf>> {
+        if self.source.starts_with(['/', '~', '.']) {
+            Ok(Some(PathBuf::from(expand_tilde(&self.source)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Dotfiles for new sessions: `$BOX_DOTFILES`, then `dotfiles` in the global config.
+pub fn dotfiles() -> Option<Dotfiles> {
+    let global = GlobalConfig::load().unwrap_or_default();
+    std::env::var("BOX_DOTFILES")
+        .ok()
+        .or(global.dotfiles)
+        .filter(|s| !s.is_empty())
+        .map(|source| Dotfiles {
+            source,
+            install: global.dotfiles_install.filter(|s| !s.is_empty()),
+        })
+}
+
+/// Built-in cache volumes: name and the directory they are mounted at.
+pub const CACHE_PRESETS: &[(&str, &str)] = &[
+    ("cargo", "/root/.cargo/registry"),
+    ("npm", "/root/.npm"),
+    ("pip", "/root/.cache/pip"),
+    ("go", "/root/go/pkg/mod"),
+    ("maven", "/root/.m2/repository"),
+    ("gradle", "/root/.gradle/caches"),
+];
+
+/// Resolve a cache entry, either a preset name or `NAME:/container/path`,
+/// to `NAME:/container/path`. The volume is named `box-cache-NAME`.
+pub fn resolve_cache(spec: &str) -> Result<String> {
+    let (name, path) = match spec.split_once(':') {
+        Some((name, path)) => (name, path.to_string()),
+        None => match CACHE_PRESETS.iter().find(|(preset, _)| *preset == spec) {
+            Some((_, path)) => (spec, path.to_string()),
+            None => {
+                let presets: Vec<&str> = CACHE_PRESETS.iter().map(|(n, _)| *n).collect();
+                bail!(
+                    "Unknown cache '{}'. Use one of {} or NAME:/container/path.",
+                    spec,
+                    presets.join(", ")
+                );
+            }
+        },
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid cache name '{}'. Use letters, digits, hyphens, or underscores.",
+            name
+        );
+    }
+    if !path.starts_with('/') {
+        bail!("Cache path '{}' must be an absolute container path.", path);
+    }
+    Ok(format!("{}:{}", name, path))
+}
+
+/// Cache volumes for a new session from the global config, `.box.toml`, the
+/// template and the command line. A later entry replaces an earlier one with
+/// the same name.
+pub fn caches(project_dir: &str, template: &[String], cli: &[String]) -> Result<Vec<String>> {
+    let global = GlobalConfig::load()?.caches;
+    let repo = RepoConfig::load(project_dir)?.caches;
+    let mut caches: Vec<String> = Vec::new();
+    for spec in global.iter().chain(&repo).chain(template).chain(cli) {
+        let cache = resolve_cache(spec)?;
+        let name = cache.split(':').next().unwrap_or_default();
+        caches.retain(|c| c.split(':').next() != Some(name));
+        caches.push(cache);
+    }
+    Ok(caches)
+}
+
+pub fn expand_tilde(path: &str) -> Result<String> {
+    if path == "~" {
+        return home_dir();
+    }
+    match path.strip_prefix("~/") {
+        Some(rest) => Ok(format!("{}/{}", home_dir()?, rest)),
+        None => Ok(path.to_string()),
+    }
+}
+
+/// Resolve session settings. Precedence: CLI flags > template > `.box.toml` >
+/// environment variables > global config > built-in defaults. The default
+/// image follows the project type (see `detect_image`).
 pub fn resolve(input: BoxConfigInput) -> Result<BoxConfig> {
+    resolve_with(input, GlobalConfig::load()?)
+}
+
+fn resolve_with(input: BoxConfigInput, global: GlobalConfig) -> Result<BoxConfig> {
+    let repo = RepoConfig::load(&input.project_dir)?;
+    let template = input.template.unwrap_or_default();
+
     let mount_path = input
         .mount_path
+        .or(template.mount_path)
+        .or(repo.mount_path)
         .unwrap_or_else(|| derive_mount_path(&input.project_dir));
-    let image = input.image.unwrap_or_else(|| {
-        std::env::var("BOX_DEFAULT_IMAGE").unwrap_or_else(|_| DEFAULT_IMAGE.to_string())
-    });
+    let image = input
+        .image
+        .or(template.image)
+        .or(repo.image)
+        .or_else(|| std::env::var("BOX_DEFAULT_IMAGE").ok())
+        .or(global.image)
+        .or_else(|| detect_image(&input.project_dir).map(String::from))
+        .unwrap_or_else(|| DEFAULT_IMAGE.to_string());
     let command = match input.command {
-        None => match std::env::var("BOX_DEFAULT_CMD") {
-            Ok(val) if !val.is_empty() => shell_words::split(&val)
-                .map_err(|e| anyhow::anyhow!("Failed to parse BOX_DEFAULT_CMD: {}", e))?,
-            _ => vec![],
-        },
         Some(cmd) => cmd,
+        None => match template.command.or(repo.command) {
+            Some(val) => shell_words::split(&val).map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to parse command in {} or template: {}",
+                    REPO_CONFIG_FILE,
+                    e
+                )
+            })?,
+            None => match std::env::var("BOX_DEFAULT_CMD") {
+                Ok(val) if !val.is_empty() => shell_words::split(&val)
+                    .map_err(|e| anyhow::anyhow!("Failed to parse BOX_DEFAULT_CMD: {}", e))?,
+                Ok(_) => vec![],
+                Err(_) => match global.command {
+                    Some(val) => shell_words::split(&val).map_err(|e| {
+                        anyhow::anyhow!("Failed to parse command in global config: {}", e)
+                    })?,
+                    None => vec![],
+                },
+            },
+        },
     };
+    let docker_args = input
+        .docker_args
+        .or(template.docker_args)
+        .or(repo.docker_args)
+        .or_else(|| std::env::var("BOX_DOCKER_ARGS").ok())
+        .or(global.docker_args)
+        .unwrap_or_default();
+    let ssh = input.ssh.or(global.ssh).unwrap_or(true);
+    let worktree = input
+        .worktree
+        .or(repo.worktree)
+        .or(global.worktree)
+        .unwrap_or(false);
+
+    // Least specific first so that entries given on the command line win.
+    let mut env = global.env;
+    env.extend(repo.env);
+    env.extend(template.env);
+    env.extend(input.env);
 
     Ok(BoxConfig {
         name: input.name,
@@ -54,8 +707,10 @@ pub fn resolve(input: BoxConfigInput) -> Result<BoxConfig> {
         image,
         mount_path,
         command,
-        env: input.env,
-        ssh: input.ssh,
+        env,
+        ssh,
+        docker_args,
+        worktree,
     })
 }
 
@@ -113,6 +768,7 @@ mod tests {
         let saved_cmd = std::env::var("BOX_DEFAULT_CMD").ok();
         std::env::remove_var("BOX_DEFAULT_IMAGE");
         std::env::remove_var("BOX_DEFAULT_CMD");
+        std::env::remove_var("BOX_DOCKER_ARGS");
 
         let config = resolve(BoxConfigInput {
             name: "test".to_string(),
@@ -121,7 +777,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
 
@@ -135,6 +794,8 @@ mod tests {
                 command: vec![],
                 env: vec![],
                 ssh: false,
+                docker_args: String::new(),
+                worktree: false,
             }
         );
 
@@ -157,7 +818,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
 
@@ -175,7 +839,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
 
@@ -195,7 +862,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.image, "ubuntu:latest");
@@ -218,7 +888,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.image, "python:3.11");
@@ -271,6 +944,7 @@ mod tests {
     #[test]
     fn test_resolve_full() {
         let _lock = ENV_LOCK.lock().unwrap();
+        std::env::remove_var("BOX_DOCKER_ARGS");
         let config = resolve(BoxConfigInput {
             name: "full".to_string(),
             image: Some("python:3.11".to_string()),
@@ -278,7 +952,10 @@ mod tests {
             project_dir: "/home/user/project".to_string(),
             command: Some(vec!["python".to_string(), "main.py".to_string()]),
             env: vec!["FOO=bar".to_string()],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
 
@@ -292,6 +969,8 @@ mod tests {
                 command: vec!["python".to_string(), "main.py".to_string()],
                 env: vec!["FOO=bar".to_string()],
                 ssh: false,
+                docker_args: String::new(),
+                worktree: false,
             }
         );
     }
@@ -308,7 +987,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.command, vec!["bash".to_string()]);
@@ -330,7 +1012,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: Some(vec!["sh".to_string()]),
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.command, vec!["sh".to_string()]);
@@ -352,7 +1037,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(
@@ -381,7 +1069,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.command, Vec::<String>::new());
@@ -403,7 +1094,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         });
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("BOX_DEFAULT_CMD"));
@@ -425,7 +1119,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: None,
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.command, Vec::<String>::new());
@@ -446,7 +1143,10 @@ mod tests {
             project_dir: "/home/user/myproject".to_string(),
             command: Some(vec![]),
             env: vec![],
-            ssh: false,
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
         })
         .unwrap();
         assert_eq!(config.command, Vec::<String>::new());
@@ -455,4 +1155,685 @@ mod tests {
             None => std::env::remove_var("BOX_DEFAULT_CMD"),
         }
     }
+
+    fn repo_with_config(content: &str) -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join(REPO_CONFIG_FILE), content).unwrap();
+        tmp
+    }
+
+    fn input_for(project_dir: &str) -> BoxConfigInput {
+        BoxConfigInput {
+            name: "test".to_string(),
+            image: None,
+            mount_path: None,
+            project_dir: project_dir.to_string(),
+            command: None,
+            env: vec![],
+            ssh: Some(false),
+            docker_args: None,
+            template: None,
+            worktree: None,
+        }
+    }
+
+    #[test]
+    fn test_detect_image() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_string_lossy().to_string();
+        assert_eq!(detect_image(&dir), None);
+
+        std::fs::write(tmp.path().join("package.json"), "{}").unwrap();
+        assert_eq!(detect_image(&dir), Some("node:lts"));
+        // A Rust crate with a JS frontend is still a Rust project
+        std::fs::write(tmp.path().join("Cargo.toml"), "").unwrap();
+        assert_eq!(detect_image(&dir), Some("rust:latest"));
+    }
+
+    #[test]
+    fn test_resolve_detected_image() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        std::env::remove_var("BOX_DEFAULT_IMAGE");
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_string_lossy().to_string();
+        std::fs::write(tmp.path().join("go.mod"), "module example.com/app\n").unwrap();
+
+        let config = resolve_with(input_for(&dir), GlobalConfig::default()).unwrap();
+        assert_eq!(config.image, "golang:latest");
+
+        // Any configured image wins over detection
+        let global = GlobalConfig {
+            image: Some("mydev".to_string()),
+            ..GlobalConfig::default()
+        };
+        let config = resolve_with(input_for(&dir), global).unwrap();
+        assert_eq!(config.image, "mydev");
+
+        std::fs::write(tmp.path().join(REPO_CONFIG_FILE), "image = \"ubuntu\"\n").unwrap();
+        let config = resolve_with(input_for(&dir), GlobalConfig::default()).unwrap();
+        assert_eq!(config.image, "ubuntu");
+    }
+
+    #[test]
+    fn test_repo_config_missing_is_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = RepoConfig::load(&tmp.path().to_string_lossy()).unwrap();
+        assert_eq!(repo, RepoConfig::default());
+    }
+
+    #[test]
+    fn test_repo_config_parses_all_fields() {
+        let tmp = repo_with_config(
+            r#"
+image = "rust:latest"
+mount_path = "/src"
+env = ["FOO=bar"]
+docker_args = "--network host"
+command = "cargo test"
+"#,
+        );
+        let repo = RepoConfig::load(&tmp.path().to_string_lossy()).unwrap();
+        assert_eq!(
+            repo,
+            RepoConfig {
+                image: Some("rust:latest".to_string()),
+                mount_path: Some("/src".to_string()),
+                env: vec!["FOO=bar".to_string()],
+                docker_args: Some("--network host".to_string()),
+                command: Some("cargo test".to_string()),
+                worktree: None,
+                clone_depth: None,
+                clone_filter: None,
+                lfs: None,
+                submodules: None,
+                copy_untracked: vec![],
+                network: None,
+                caches: vec![],
+                hooks: Hooks::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_repo_config_hooks() {
+        let tmp = repo_with_config(
+            r#"
+[hooks]
+post_create = "npm ci"
+pre_resume = { run = "make deps", on = "host" }
+events = ["~/bin/box-notify"]
+"#,
+        );
+        let hooks = RepoConfig::load(&tmp.path().to_string_lossy())
+            .unwrap()
+            .hooks;
+        let post_create = hooks.post_create.unwrap();
+        assert_eq!(post_create.command(), "npm ci");
+        assert_eq!(
+            post_create.target(HookTarget::Container),
+            HookTarget::Container
+        );
+        let pre_resume = hooks.pre_resume.unwrap();
+        assert_eq!(pre_resume.command(), "make deps");
+        assert_eq!(pre_resume.target(HookTarget::Container), HookTarget::Host);
+        assert_eq!(hooks.post_stop, None);
+        assert_eq!(hooks.events, ["~/bin/box-notify"]);
+
+        let tmp = repo_with_config("[hooks]\npost_create = { run = \"x\", on = \"moon\" }\n");
+        assert!(RepoConfig::load(&tmp.path().to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn test_repo_config_rejects_unknown_keys() {
+        let tmp = repo_with_config("imgae = \"typo\"\n");
+        let err = RepoConfig::load(&tmp.path().to_string_lossy()).unwrap_err();
+        assert!(err.to_string().contains(REPO_CONFIG_FILE));
+    }
+
+    #[test]
+    fn test_resolve_repo_config_overrides_env() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        std::env::set_var("BOX_DEFAULT_IMAGE", "ubuntu:latest");
+        std::env::set_var("BOX_DEFAULT_CMD", "bash");
+        std::env::set_var("BOX_DOCKER_ARGS", "--network none");
+        let tmp = repo_with_config(
+            r#"
+image = "rust:latest"
+mount_path = "/src"
+docker_args = "--network host"
+command = "cargo test"
+"#,
+        );
+        let config = resolve(input_for(&tmp.path().to_string_lossy())).unwrap();
+        assert_eq!(config.image, "rust:latest");
+        assert_eq!(config.mount_path, "/src");
+        assert_eq!(config.command, vec!["cargo", "test"]);
+        assert_eq!(config.docker_args, "--network host");
+        std::env::remove_var("BOX_DEFAULT_IMAGE");
+        std::env::remove_var("BOX_DEFAULT_CMD");
+        std::env::remove_var("BOX_DOCKER_ARGS");
+    }
+
+    #[test]
+    fn test_resolve_cli_overrides_repo_config() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let tmp = repo_with_config(
+            r#"
+image = "rust:latest"
+docker_args = "--network host"
+command = "cargo test"
+env = ["FOO=repo", "BAR=1"]
+"#,
+        );
+        let config = resolve(BoxConfigInput {
+            image: Some("python:3.11".to_string()),
+            command: Some(vec!["sh".to_string()]),
+            docker_args: Some("-e X=1".to_string()),
+            env: vec!["FOO=cli".to_string()],
+            ..input_for(&tmp.path().to_string_lossy())
+        })
+        .unwrap();
+        assert_eq!(config.image, "python:3.11");
+        assert_eq!(config.command, vec!["sh"]);
+        assert_eq!(config.docker_args, "-e X=1");
+        // CLI env comes last so it takes precedence in `docker run`
+        assert_eq!(config.env, vec!["FOO=repo", "BAR=1", "FOO=cli"]);
+    }
+
+    #[test]
+    fn test_resolve_env_docker_args_fallback() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        std::env::set_var("BOX_DOCKER_ARGS", "--network none");
+        let config = resolve(input_for("/home/user/myproject")).unwrap();
+        assert_eq!(config.docker_args, "--network none");
+        std::env::remove_var("BOX_DOCKER_ARGS");
+    }
+
+    #[test]
+    fn test_global_config_path_uses_xdg_config_home() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        std::env::set_var("XDG_CONFIG_HOME", "/tmp/xdg-config");
+        assert_eq!(
+            GlobalConfig::path().unwrap(),
+            PathBuf::from("/tmp/xdg-config/box/config.toml")
+        );
+        std::env::remove_var("XDG_CONFIG_HOME");
+        let home = home_dir().unwrap();
+        assert_eq!(
+            GlobalConfig::path().unwrap(),
+            PathBuf::from(home).join(".config/box/config.toml")
+        );
+        if let Some(v) = saved {
+            std::env::set_var("XDG_CONFIG_HOME", v);
+        }
+    }
+
+    #[test]
+    fn test_global_config_load_and_workspaces_dir() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let saved_home = std::env::var("HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", tmp.path());
+        std::env::set_var("HOME", tmp.path().join("home"));
+        std::env::remove_var("XDG_DATA_HOME");
+        std::env::remove_var("BOX_WORKSPACE_DIR");
+
+        assert_eq!(GlobalConfig::load().unwrap(), GlobalConfig::default());
+        let home = home_dir().unwrap();
+        assert_eq!(
+            crate::storage::workspaces_dir().unwrap(),
+            PathBuf::from(&home).join(".local/share/box/workspaces")
+        );
+
+        std::fs::create_dir_all(tmp.path().join("box")).unwrap();
+        std::fs::write(
+            tmp.path().join("box/config.toml"),
+            "image = \"mydev\"\nssh = false\nworkspace_dir = \"~/scratch/box\"\n",
+        )
+        .unwrap();
+        let global = GlobalConfig::load().unwrap();
+        assert_eq!(global.image.as_deref(), Some("mydev"));
+        assert_eq!(global.ssh, Some(false));
+        assert_eq!(
+            crate::storage::workspace_dir("sess").unwrap(),
+            PathBuf::from(&home).join("scratch/box/sess")
+        );
+
+        std::env::set_var("BOX_WORKSPACE_DIR", "/mnt/scratch/box");
+        assert_eq!(
+            crate::storage::workspace_dir("sess").unwrap(),
+            PathBuf::from("/mnt/scratch/box/sess")
+        );
+        std::env::remove_var("BOX_WORKSPACE_DIR");
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+        match saved_home {
+            Some(h) => std::env::set_var("HOME", h),
+            None => std::env::remove_var("HOME"),
+        }
+    }
+
+    #[test]
+    fn test_detach_keys_env_over_global() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", tmp.path());
+        std::env::remove_var("BOX_DETACH_KEYS");
+
+        assert_eq!(detach_keys(), None);
+
+        std::fs::create_dir_all(tmp.path().join("box")).unwrap();
+        std::fs::write(
+            tmp.path().join("box/config.toml"),
+            "detach_keys = \"ctrl-x,x\"\n",
+        )
+        .unwrap();
+        assert_eq!(detach_keys().as_deref(), Some("ctrl-x,x"));
+
+        std::env::set_var("BOX_DETACH_KEYS", "ctrl-a,d");
+        assert_eq!(detach_keys().as_deref(), Some("ctrl-a,d"));
+        std::env::set_var("BOX_DETACH_KEYS", "");
+        assert_eq!(detach_keys(), None);
+        std::env::remove_var("BOX_DETACH_KEYS");
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_clone_options_precedence() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        let project_dir = project.to_str().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", tmp.path());
+
+        assert_eq!(
+            clone_options(project_dir, None, None).unwrap(),
+            (None, None)
+        );
+
+        std::fs::create_dir_all(tmp.path().join("box")).unwrap();
+        std::fs::write(
+            tmp.path().join("box/config.toml"),
+            "clone_depth = 50\nclone_filter = \"blob:none\"\n",
+        )
+        .unwrap();
+        std::fs::write(project.join(REPO_CONFIG_FILE), "clone_depth = 1\n").unwrap();
+        assert_eq!(
+            clone_options(project_dir, None, None).unwrap(),
+            (Some(1), Some("blob:none".to_string()))
+        );
+        // 0 and "" on the command line turn the defaults off
+        assert_eq!(
+            clone_options(project_dir, Some(0), Some(String::new())).unwrap(),
+            (None, None)
+        );
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_lfs_and_submodules_precedence() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        let project_dir = project.to_str().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", tmp.path());
+
+        assert_eq!(lfs_and_submodules(project_dir), (true, true));
+
+        std::fs::create_dir_all(tmp.path().join("box")).unwrap();
+        std::fs::write(
+            tmp.path().join("box/config.toml"),
+            "lfs = false\nsubmodules = false\n",
+        )
+        .unwrap();
+        assert_eq!(lfs_and_submodules(project_dir), (false, false));
+        std::fs::write(project.join(REPO_CONFIG_FILE), "submodules = true\n").unwrap();
+        assert_eq!(lfs_and_submodules(project_dir), (false, true));
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_dotfiles_env_over_global() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", tmp.path());
+        std::env::remove_var("BOX_DOTFILES");
+
+        assert_eq!(dotfiles(), None);
+
+        std::fs::create_dir_all(tmp.path().join("box")).unwrap();
+        std::fs::write(
+            tmp.path().join("box/config.toml"),
+            "dotfiles = \"https://example.com/dotfiles.git\"\ndotfiles_install = \"setup\"\n",
+        )
+        .unwrap();
+        let df = dotfiles().unwrap();
+        assert_eq!(df.source, "https://example.com/dotfiles.git");
+        assert_eq!(df.install.as_deref(), Some("setup"));
+        assert_eq!(df.local_path().unwrap(), None);
+
+        std::env::set_var("BOX_DOTFILES", "/home/me/dotfiles");
+        let df = dotfiles().unwrap();
+        assert_eq!(
+            df.local_path().unwrap(),
+            Some(PathBuf::from("/home/me/dotfiles"))
+        );
+        std::env::remove_var("BOX_DOTFILES");
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_global_config_below_env() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        std::env::remove_var("BOX_DEFAULT_CMD");
+        std::env::remove_var("BOX_DOCKER_ARGS");
+        std::env::set_var("BOX_DEFAULT_IMAGE", "ubuntu:latest");
+        let global = GlobalConfig {
+            image: Some("mydev".to_string()),
+            command: Some("bash -l".to_string()),
+            env: vec!["FOO=global".to_string()],
+            docker_args: Some("--network host".to_string()),
+            ssh: Some(false),
+            workspace_dir: None,
+            worktree: None,
+            clone_depth: None,
+            clone_filter: None,
+            lfs: None,
+            submodules: None,
+            copy_untracked: vec![],
+            volume: None,
+            user_map: None,
+            network: None,
+            idle_timeout: None,
+            gc_older_than: None,
+            detach_keys: None,
+            dotfiles: None,
+            dotfiles_install: None,
+            caches: vec![],
+            hooks: Hooks::default(),
+        };
+        let config = resolve_with(
+            BoxConfigInput {
+                ssh: None,
+                env: vec!["FOO=cli".to_string()],
+                ..input_for("/home/user/myproject")
+            },
+            global,
+        )
+        .unwrap();
+        assert_eq!(config.image, "ubuntu:latest");
+        assert_eq!(config.command, vec!["bash", "-l"]);
+        assert_eq!(config.docker_args, "--network host");
+        assert_eq!(config.env, vec!["FOO=global", "FOO=cli"]);
+        assert!(!config.ssh);
+        std::env::remove_var("BOX_DEFAULT_IMAGE");
+    }
+
+    #[test]
+    fn test_resolve_ssh_defaults_to_enabled() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let config = resolve_with(
+            BoxConfigInput {
+                ssh: None,
+                ..input_for("/home/user/myproject")
+            },
+            GlobalConfig::default(),
+        )
+        .unwrap();
+        assert!(config.ssh);
+    }
+
+    #[test]
+    fn test_resolve_template_between_cli_and_repo() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        std::env::remove_var("BOX_DOCKER_ARGS");
+        let tmp = repo_with_config(
+            r#"
+image = "rust:latest"
+command = "cargo test"
+env = ["FOO=repo"]
+"#,
+        );
+        let template = Template {
+            image: Some("node:20".to_string()),
+            env: vec!["FOO=template".to_string()],
+            docker_args: Some("--network host".to_string()),
+            ..Template::default()
+        };
+        let config = resolve_with(
+            BoxConfigInput {
+                command: Some(vec!["sh".to_string()]),
+                env: vec!["FOO=cli".to_string()],
+                template: Some(template),
+                ..input_for(&tmp.path().to_string_lossy())
+            },
+            GlobalConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(config.image, "node:20");
+        assert_eq!(config.command, vec!["sh"]);
+        assert_eq!(config.docker_args, "--network host");
+        assert_eq!(config.env, vec!["FOO=repo", "FOO=template", "FOO=cli"]);
+    }
+
+    #[test]
+    fn test_resolve_cache() {
+        assert_eq!(
+            resolve_cache("cargo").unwrap(),
+            "cargo:/root/.cargo/registry"
+        );
+        assert_eq!(
+            resolve_cache("ccache:/root/.ccache").unwrap(),
+            "ccache:/root/.ccache"
+        );
+        assert!(resolve_cache("unknown").is_err());
+        assert!(resolve_cache("bad name:/x").is_err());
+        assert!(resolve_cache("npm:relative").is_err());
+    }
+
+    #[test]
+    fn test_init_config_detects_project() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(tmp.path().join("package.json"), "{}").unwrap();
+        let dir = tmp.path().to_str().unwrap();
+
+        let config: RepoConfig = toml::from_str(&init_config(dir)).unwrap();
+        assert_eq!(config.image.as_deref(), Some("rust:latest"));
+        assert_eq!(config.mount_path, Some(derive_mount_path(dir)));
+        assert_eq!(config.caches, ["cargo:/usr/local/cargo/registry", "npm"]);
+        for cache in &config.caches {
+            resolve_cache(cache).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_init_config_unknown_project() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config: RepoConfig =
+            toml::from_str(&init_config(tmp.path().to_str().unwrap())).unwrap();
+        assert_eq!(config.image.as_deref(), Some(DEFAULT_IMAGE));
+        assert!(config.caches.is_empty());
+        assert_eq!(config.hooks, Hooks::default());
+    }
+
+    #[test]
+    fn test_hooks_events_only_from_global_config() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let config_home = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", config_home.path());
+        std::fs::create_dir_all(config_home.path().join("box")).unwrap();
+        std::fs::write(
+            config_home.path().join("box/config.toml"),
+            "[hooks]\nevents = [\"~/bin/box-notify\"]\npost_stop = \"echo global\"\n",
+        )
+        .unwrap();
+        let tmp = repo_with_config(
+            "[hooks]\nevents = [\"curl evil.example\"]\npost_stop = \"echo repo\"\n",
+        );
+
+        let hooks = hooks(&tmp.path().to_string_lossy()).unwrap();
+        assert_eq!(hooks.events, ["~/bin/box-notify"]);
+        assert_eq!(hooks.post_stop.unwrap().command(), "echo repo");
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_caches_merge_sources() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let config_home = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", config_home.path());
+        std::fs::create_dir_all(config_home.path().join("box")).unwrap();
+        std::fs::write(
+            config_home.path().join("box/config.toml"),
+            "caches = [\"cargo\", \"pip\"]\n",
+        )
+        .unwrap();
+        let tmp = repo_with_config("caches = [\"npm\"]\n");
+
+        let caches = caches(
+            &tmp.path().to_string_lossy(),
+            &["go".to_string()],
+            &["cargo:/usr/local/cargo/registry".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            caches,
+            vec![
+                "pip:/root/.cache/pip",
+                "npm:/root/.npm",
+                "go:/root/go/pkg/mod",
+                "cargo:/usr/local/cargo/registry",
+            ]
+        );
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_copy_untracked_merge_sources() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved = std::env::var("XDG_CONFIG_HOME").ok();
+        let config_home = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", config_home.path());
+        std::fs::create_dir_all(config_home.path().join("box")).unwrap();
+        std::fs::write(
+            config_home.path().join("box/config.toml"),
+            "copy_untracked = [\".envrc\"]\n",
+        )
+        .unwrap();
+        let tmp = repo_with_config("copy_untracked = [\".env\", \".envrc\", \"local/**\"]\n");
+
+        assert_eq!(
+            copy_untracked(&tmp.path().to_string_lossy()).unwrap(),
+            vec![".envrc", ".env", "local/**"]
+        );
+
+        match saved {
+            Some(v) => std::env::set_var("XDG_CONFIG_HOME", v),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_template_save_load_list_remove() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let saved_home = std::env::var("HOME").ok();
+        let tmp = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", tmp.path());
+        std::env::remove_var("XDG_DATA_HOME");
+
+        assert!(Template::list().unwrap().is_empty());
+        let template = Template {
+            image: Some("rust:latest".to_string()),
+            command: Some("cargo watch".to_string()),
+            env: vec!["RUST_LOG=debug".to_string()],
+            ..Template::default()
+        };
+        template.save("rust").unwrap();
+        assert_eq!(Template::list().unwrap(), vec!["rust"]);
+        assert_eq!(Template::load("rust").unwrap(), template);
+
+        Template::remove("rust").unwrap();
+        assert!(Template::load("rust")
+            .unwrap_err()
+            .to_string()
+            .contains("not found"));
+        assert!(Template::path("../evil").is_err());
+
+        match saved_home {
+            Some(h) => std::env::set_var("HOME", h),
+            None => std::env::remove_var("HOME"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_worktree_layering() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let global = || GlobalConfig {
+            worktree: Some(true),
+            ..GlobalConfig::default()
+        };
+        let config = resolve_with(input_for("/home/user/myproject"), global()).unwrap();
+        assert!(config.worktree);
+
+        let tmp = repo_with_config("worktree = false\n");
+        let config = resolve_with(input_for(&tmp.path().to_string_lossy()), global()).unwrap();
+        assert!(!config.worktree);
+
+        let config = resolve_with(
+            BoxConfigInput {
+                worktree: Some(true),
+                ..input_for(&tmp.path().to_string_lossy())
+            },
+            global(),
+        )
+        .unwrap();
+        assert!(config.worktree);
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let home = home_dir().unwrap();
+        assert_eq!(expand_tilde("~").unwrap(), home);
+        assert_eq!(expand_tilde("~/x").unwrap(), format!("{}/x", home));
+        assert_eq!(expand_tilde("/abs").unwrap(), "/abs");
+    }
 }
@@ -1,259 +1,670 @@
 use anyhow::{bail, Result};
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
 use std::process::Command;
 
 use crate::config;
+use crate::engine;
+use crate::session::SessionSummary;
+use crate::storage;
+
+/// Shallow/partial clone settings for new workspaces.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CloneOptions<'a> {
+    /// Fetch only this many commits of history.
+    pub depth: Option<u32>,
+    /// Partial clone filter, e.g. `blob:none`.
+    pub filter: Option<&'a str>,
+}
 
-/// Create a workspace directory on the host for the session.
-/// On first run, clones the project repo via `git clone --local`.
-/// Returns the host path. The directory is writable by the owner and group so container users with the appropriate group can write.
-pub fn ensure_workspace(home: &str, name: &str, project_dir: &str) -> Result<String> {
-    let dir_path = Path::new(home).join(".box").join("workspaces").join(name);
-    let dir = dir_path.to_string_lossy().to_string();
-    let git_dir = dir_path.join(".git");
+impl CloneOptions<'_> {
+    fn is_set(&self) -> bool {
+        self.depth.is_some() || self.filter.is_some()
+    }
 
-    if !Path::new(&git_dir).exists() {
-        eprintln!("\x1b[2mrunning clone command:\x1b[0m");
-        eprintln!("git clone --local {} {}", project_dir, dir);
+    /// Extra `git clone` arguments.
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(depth) = self.depth {
+            // Keep every branch's tip so `--branch` can still check one out
+            args.extend([
+                "--depth".to_string(),
+                depth.to_string(),
+                "--no-single-branch".to_string(),
+            ]);
+        }
+        if let Some(filter) = self.filter {
+            args.push(format!("--filter={}", filter));
+        }
+        args
+    }
+}
+
+/// Create the workspace directory `dir` on the host for the session.
+/// On first run, clones the project repo via `git clone --local`, or with
+/// `worktree` adds a detached `git worktree` of the project instead.
+/// Unless `owned` (the container writes as the host user), the directory is
+/// made world-writable so non-root container users can write.
+/// A new workspace checks out `branch` (a branch, tag or commit) instead of
+/// the project's `HEAD`, and is cloned from `url` instead of the project when
+/// given. Git LFS files and submodules are then fetched into it unless
+/// turned off in the config, and untracked files matching `copy_untracked`
+/// are copied over from the project.
+pub fn ensure_workspace(
+    dir: &str,
+    project_dir: &str,
+    worktree: bool,
+    owned: bool,
+    branch: Option<&str>,
+    url: Option<&str>,
+    clone: CloneOptions,
+) -> Result<()> {
+    let git_dir = Path::new(dir).join(".git");
+    let fresh = !git_dir.exists();
+
+    if worktree && fresh {
+        if let Some(parent) = Path::new(dir).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // A local branch is checked out as itself; anything else detached
+        let mut args = vec!["-C", project_dir, "worktree", "add"];
+        match branch {
+            Some(b) if crate::git::is_branch(Path::new(project_dir), b) => args.extend([dir, b]),
+            Some(b) => args.extend(["--detach", dir, b]),
+            None => args.extend(["--detach", dir]),
+        }
+        eprintln!("\x1b[2mrunning worktree command:\x1b[0m");
+        eprintln!("git {}", args.join(" "));
         let status = Command::new("git")
-            .args(["clone", "--local", project_dir, &dir])
+            .args(&args)
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
             .status()?;
         if !status.success() {
-            bail!("git clone --local failed");
+            bail!("git worktree add failed");
+        }
+    } else if fresh {
+        if let Some(url) = url {
+            let args = clone.args();
+            eprintln!("\x1b[2mrunning clone command:\x1b[0m");
+            eprintln!("git clone {} {} {}", args.join(" "), url, dir);
+            let status = Command::new("git")
+                .arg("clone")
+                .args(&args)
+                .args([url, dir])
+                .env("GIT_LFS_SKIP_SMUDGE", "1")
+                .status()?;
+            if !status.success() {
+                bail!("git clone {} failed", url);
+            }
+        } else {
+            clone_local(dir, project_dir, clone)?;
         }
 
-        // git clone --local sets origin to the host path, which won't exist
-        // inside the container. Re-point origin to the real remote URL.
-        if let Ok(output) = Command::new("git")
-            .args(["-C", project_dir, "remote", "get-url", "origin"])
-            .output()
-        {
-            if output.status.success() {
-                let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !url.is_empty() {
-                    eprintln!("\x1b[2mrunning remote update:\x1b[0m");
-                    eprintln!("git remote set-url origin {}", url);
-                    let _ = Command::new("git")
-                        .args(["-C", &dir, "remote", "set-url", "origin", &url])
-                        .status();
-                }
+        if let Some(branch) = branch {
+            eprintln!("\x1b[2mrunning checkout command:\x1b[0m");
+            eprintln!("git checkout {}", branch);
+            let status = Command::new("git")
+                .args(["-C", dir, "checkout", "--quiet", branch])
+                .env("GIT_LFS_SKIP_SMUDGE", "1")
+                .status()?;
+            if !status.success() {
+                bail!("git checkout {} failed", branch);
             }
         }
     }
 
+    if fresh {
+        let (lfs, submodules) = config::lfs_and_submodules(project_dir);
+        fetch_lfs_and_submodules(dir, lfs, submodules);
+        if url.is_none() {
+            copy_untracked(dir, project_dir)?;
+        }
+    }
+
+    // Without a mapped user the container usually runs as root or some other
+    // uid, so open the workspace up instead.
     #[cfg(unix)]
-    {
+    if !owned {
         use std::os::unix::fs::PermissionsExt;
-        let mut perms = std::fs::metadata(&dir)?.permissions();
+        let mut perms = std::fs::metadata(dir)?.permissions();
         perms.set_mode(0o777);
-        std::fs::set_permissions(&dir, perms)?;
+        std::fs::set_permissions(dir, perms)?;
     }
 
-    Ok(dir)
+    Ok(())
 }
 
-/// Remove the workspace directory for a session.
-pub fn remove_workspace(name: &str) {
-    if let Ok(home) = config::home_dir() {
-        let dir = Path::new(&home).join(".box").join("workspaces").join(name);
-        let _ = std::fs::remove_dir_all(&dir);
+/// Clone `project_dir` into `dir` with `git clone --local`, or over `file://`
+/// for a shallow/partial clone, which local clones ignore.
+fn clone_local(dir: &str, project_dir: &str, clone: CloneOptions) -> Result<()> {
+    if clone.is_set() {
+        let args = clone.args();
+        let src = format!("file://{}", project_dir);
+        eprintln!("\x1b[2mrunning clone command:\x1b[0m");
+        eprintln!("git clone {} {} {}", args.join(" "), src, dir);
+        // The project's upload-pack refuses filters unless allowed
+        let status = Command::new("git")
+            .args(["-c", "uploadpack.allowFilter=true", "clone"])
+            .args(&args)
+            .args([&src, dir])
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .status()?;
+        if !status.success() {
+            bail!("git clone failed");
+        }
+    } else {
+        eprintln!("\x1b[2mrunning clone command:\x1b[0m");
+        eprintln!("git clone --local {} {}", project_dir, dir);
+        let status = Command::new("git")
+            .args(["clone", "--local", project_dir, dir])
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .status()?;
+        if !status.success() {
+            bail!("git clone --local failed");
+        }
+    }
+
+    // git clone --local sets origin to the host path, which won't exist
+    // inside the container. Re-point origin to the real remote URL.
+    if let Ok(output) = Command::new("git")
+        .args(["-C", project_dir, "remote", "get-url", "origin"])
+        .output()
+    {
+        if output.status.success() {
+            let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !url.is_empty() {
+                eprintln!("\x1b[2mrunning remote update:\x1b[0m");
+                eprintln!("git remote set-url origin {}", url);
+                let _ = Command::new("git")
+                    .args(["-C", dir, "remote", "set-url", "origin", &url])
+                    .status();
+            }
+        }
     }
+    Ok(())
 }
 
-pub fn check() -> Result<()> {
-    let docker_exists = Command::new("docker")
-        .arg("version")
+/// Check out Git LFS files and submodules in the new workspace `dir`.
+/// Clones and worktrees are made with LFS smudging off so a missing or
+/// unreachable LFS server can't fail them; failures here only warn, leaving
+/// pointer files or empty submodule directories behind.
+fn fetch_lfs_and_submodules(dir: &str, lfs: bool, submodules: bool) {
+    let path = Path::new(dir);
+    if lfs && crate::git::uses_lfs(path) {
+        if !crate::git::lfs_installed() {
+            eprintln!(
+                "\x1b[2mwarning:\x1b[0m the repository uses Git LFS but git-lfs is not installed; LFS files are left as pointers"
+            );
+        } else {
+            for args in [["lfs", "fetch"], ["lfs", "checkout"]] {
+                eprintln!("\x1b[2mrunning lfs command:\x1b[0m");
+                eprintln!("git {}", args.join(" "));
+                let ok = Command::new("git")
+                    .args(["-C", dir])
+                    .args(args)
+                    .status()
+                    .is_ok_and(|s| s.success());
+                if !ok {
+                    eprintln!("\x1b[2mwarning:\x1b[0m git {} failed", args.join(" "));
+                    break;
+                }
+            }
+        }
+    }
+    if submodules && crate::git::has_submodules(path) {
+        eprintln!("\x1b[2mrunning submodule command:\x1b[0m");
+        eprintln!("git submodule update --init --recursive");
+        let ok = Command::new("git")
+            .args(["-C", dir, "submodule", "update", "--init", "--recursive"])
+            .status()
+            .is_ok_and(|s| s.success());
+        if !ok {
+            eprintln!("\x1b[2mwarning:\x1b[0m git submodule update failed");
+        }
+    }
+}
+
+/// Copy the project's untracked files matching the `copy_untracked` globs
+/// (local config such as `.env` that a clone leaves out) into `dir`.
+fn copy_untracked(dir: &str, project_dir: &str) -> Result<()> {
+    let globs = config::copy_untracked(project_dir)?;
+    let files = crate::git::untracked_matching(Path::new(project_dir), &globs)?;
+    if files.is_empty() {
+        return Ok(());
+    }
+    eprintln!("\x1b[2mcopying untracked files:\x1b[0m");
+    for file in &files {
+        eprintln!("{}", file);
+        let (src, dst) = (Path::new(project_dir).join(file), Path::new(dir).join(file));
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let meta = std::fs::symlink_metadata(&src)?;
+        #[cfg(unix)]
+        if meta.file_type().is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&src)?, &dst)?;
+            continue;
+        }
+        std::fs::copy(&src, &dst)?;
+    }
+    Ok(())
+}
+
+/// Image used to clone into, copy between and export volume workspaces.
+const VOLUME_HELPER_IMAGE: &str = "alpine/git";
+
+/// Name of the Docker volume holding the workspace of a `--volume` session.
+pub fn workspace_volume(name: &str) -> String {
+    format!("box-ws-{}", name)
+}
+
+fn volume_exists(volume: &str) -> bool {
+    Command::new("docker")
+        .args(["volume", "inspect", volume])
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
         .status()
         .map(|s| s.success())
-        .unwrap_or(false);
+        .unwrap_or(false)
+}
 
-    if !docker_exists {
-        bail!("docker is not installed. See https://docs.docker.com/get-docker/");
+/// Run `script` with `sh` in a throwaway helper container.
+fn run_volume_helper(mounts: &[String], script: &str, script_args: &[&str]) -> Result<bool> {
+    let mut cmd = Command::new("docker");
+    cmd.args(["run", "--rm"]);
+    // Relabeling would touch the whole project or export dir on the host;
+    // the short-lived helper runs unconfined instead.
+    if host_security().selinux {
+        cmd.args(["--security-opt", "label=disable"]);
+    }
+    for mount in mounts {
+        cmd.args(["-v", mount]);
     }
+    cmd.args([
+        "--entrypoint",
+        "sh",
+        VOLUME_HELPER_IMAGE,
+        "-c",
+        script,
+        "sh",
+    ])
+    .args(script_args);
+    Ok(cmd.status()?.success())
+}
 
-    let info = Command::new("docker")
-        .arg("info")
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .status()?;
+/// Create the Docker volume for a session's workspace and clone the project
+/// into it from a helper container, so builds never touch a bind mount.
+/// A `remote` daemon can't see the project directory, so the repository is
+/// streamed to it as a git bundle instead.
+pub fn ensure_workspace_volume(
+    name: &str,
+    project_dir: &str,
+    remote: bool,
+    branch: Option<&str>,
+    clone: CloneOptions,
+) -> Result<()> {
+    let volume = workspace_volume(name);
+    if volume_exists(&volume) {
+        return Ok(());
+    }
 
-    if !info.success() {
-        bail!("Docker daemon is not running. Please start Docker.");
+    // The clone starts with origin at /src; point it at the real remote like
+    // ensure_workspace does.
+    let origin = crate::git::remote_url(Path::new(project_dir)).unwrap_or_default();
+    eprintln!("\x1b[2mcloning into volume:\x1b[0m {}", volume);
+    let branch = branch.unwrap_or_default();
+    if remote {
+        return clone_bundle_into_volume(name, project_dir, &origin, branch);
+    }
+    let ok = run_volume_helper(
+        &[
+            format!("{}:/workspace", volume),
+            format!("{}:/src:ro", project_dir),
+        ],
+        // $3 holds shallow/partial clone flags, which need a file:// source
+        "src=/src; [ -z \"$3\" ] || src=file:///src; \
+         git -c safe.directory='*' -c uploadpack.allowFilter=true clone --quiet $3 \"$src\" /workspace \
+         && { [ -z \"$2\" ] || git -C /workspace checkout --quiet \"$2\"; } \
+         && { [ -z \"$1\" ] || git -C /workspace remote set-url origin \"$1\"; } \
+         && chmod 777 /workspace",
+        &[&origin, branch, &clone.args().join(" ")],
+    )?;
+    if !ok {
+        remove_workspace_volume(name);
+        bail!("Failed to clone {} into volume {}", project_dir, volume);
     }
+    Ok(())
+}
 
+fn clone_bundle_into_volume(
+    name: &str,
+    project_dir: &str,
+    origin: &str,
+    branch: &str,
+) -> Result<()> {
+    let volume = workspace_volume(name);
+    let mut bundle = Command::new("git")
+        .args([
+            "-C",
+            project_dir,
+            "bundle",
+            "create",
+            "--quiet",
+            "-",
+            "HEAD",
+            "--all",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    let status = Command::new("docker")
+        .args(["run", "--rm", "-i", "-v", &format!("{}:/workspace", volume)])
+        .args(["--entrypoint", "sh", VOLUME_HELPER_IMAGE, "-c"])
+        .arg(
+            "cat > /tmp/repo.bundle \
+             && git clone --quiet /tmp/repo.bundle /workspace \
+             && { [ -z \"$2\" ] || git -C /workspace checkout --quiet \"$2\"; } \
+             && { [ -z \"$1\" ] || git -C /workspace remote set-url origin \"$1\"; } \
+             && chmod 777 /workspace",
+        )
+        .args(["sh", origin, branch])
+        .stdin(bundle.stdout.take().expect("piped stdout"))
+        .status()?;
+    let bundled = bundle.wait()?.success();
+    if !bundled || !status.success() {
+        remove_workspace_volume(name);
+        bail!("Failed to copy {} into volume {}", project_dir, volume);
+    }
     Ok(())
 }
 
-const SSH_CONTAINER_PATH: &str = "/run/host-services/ssh-auth.sock";
+/// Copy one session's workspace volume into a new one for another session.
+pub fn copy_workspace_volume(src: &str, dst: &str) -> Result<()> {
+    let (src, dst) = (workspace_volume(src), workspace_volume(dst));
+    if !volume_exists(&src) {
+        return Ok(());
+    }
+    if volume_exists(&dst) {
+        bail!("Volume '{}' already exists.", dst);
+    }
+    let ok = run_volume_helper(
+        &[format!("{}:/src:ro", src), format!("{}:/dst", dst)],
+        "cp -a /src/. /dst/",
+        &[],
+    )?;
+    if !ok {
+        bail!("Failed to copy volume {} to {}", src, dst);
+    }
+    Ok(())
+}
 
-/// Return (host_path, container_path) for SSH agent forwarding.
-///
-/// On macOS, both Docker Desktop and OrbStack expose a magic VM-internal socket
-/// at `/run/host-services/ssh-auth.sock` that forwards to the host SSH agent.
-/// Mounting the raw host socket (e.g. 1Password's) does NOT work because Unix
-/// sockets cannot cross the VM boundary.
-///
-/// On Linux, the host socket from `SSH_AUTH_SOCK` is used directly.
-fn ssh_agent_paths() -> Result<(String, String)> {
-    if cfg!(target_os = "macos") {
-        Ok((
-            "/run/host-services/ssh-auth.sock".to_string(),
-            SSH_CONTAINER_PATH.to_string(),
-        ))
-    } else {
-        let host = std::env::var("SSH_AUTH_SOCK").map_err(|_| {
-            anyhow::anyhow!("SSH_AUTH_SOCK is not set. Cannot forward SSH agent on Linux.")
-        })?;
-        Ok((host, SSH_CONTAINER_PATH.to_string()))
+/// Copy a session's workspace volume out to `dest` on the host, owned by the
+/// current user.
+pub fn export_workspace_volume(name: &str, dest: &Path) -> Result<()> {
+    let volume = workspace_volume(name);
+    if !volume_exists(&volume) {
+        bail!(
+            "Session '{}' has no workspace volume yet. Resume it once first.",
+            name
+        );
+    }
+    std::fs::create_dir_all(dest)?;
+    let dest = std::fs::canonicalize(dest)?;
+    let owner = host_user();
+    let ok = run_volume_helper(
+        &[
+            format!("{}:/workspace:ro", volume),
+            format!("{}:/export", dest.display()),
+        ],
+        "cp -a /workspace/. /export/ && chown -R \"$1\" /export",
+        &[&owner],
+    )?;
+    if !ok {
+        bail!("Failed to export volume {} to {}", volume, dest.display());
     }
+    Ok(())
 }
 
-/// Fix SSH agent socket permissions for non-root container users.
-///
-/// OrbStack sets restrictive permissions (0660) on the forwarded SSH agent socket,
-/// which prevents non-root container users from accessing it. This runs a one-shot
-/// container as root to make the socket world-accessible. Silently ignored if it fails.
-fn fix_ssh_socket_permissions(image: &str) {
-    let mount = format!("{p}:{p}", p = SSH_CONTAINER_PATH);
+/// Volume holding `/var/lib/docker` of the nested daemon in a `--dind` session.
+pub fn dind_volume(name: &str) -> String {
+    format!("box-dind-{}", name)
+}
+
+/// Where the Docker socket is, both on the daemon's host and in the container.
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+pub fn remove_workspace_volume(name: &str) {
     let _ = Command::new("docker")
-        .args([
-            "run",
-            "--rm",
-            "--user",
-            "root",
-            "--entrypoint",
-            "chmod",
-            "-v",
-            &mount,
-            image,
-            "666",
-            SSH_CONTAINER_PATH,
-        ])
+        .args(["volume", "rm", "--force", &workspace_volume(name)])
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
         .status();
 }
 
-/// Restore terminal state after an interactive Docker session.
-/// Writes show-cursor and attribute-reset escape sequences. Best-effort; errors ignored.
-fn restore_terminal() {
-    let _ = std::io::stdout().write_all(b"\x1b[?25h\x1b[0m");
-    let _ = std::io::stdout().flush();
+/// The host user as `uid:gid`, for `docker run --user`.
+pub fn host_user() -> String {
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    format!("{}:{}", uid, gid)
 }
 
-pub struct DockerRunConfig<'a> {
-    pub name: &'a str,
-    pub project_dir: &'a str,
-    pub image: &'a str,
-    pub mount_path: &'a str,
-    pub cmd: &'a [String],
-    pub env: &'a [String],
-    pub home: &'a str,
-    pub docker_args: Option<&'a str>,
-    pub ssh: bool,
-    pub detach: bool,
+/// Copy an existing workspace (including uncommitted changes) to a new location.
+pub fn copy_workspace(src: &Path, dst: &Path) -> Result<()> {
+    if dst.exists() {
+        bail!("Workspace '{}' already exists.", dst.display());
+    }
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let status = Command::new("cp").arg("-a").arg(src).arg(dst).status()?;
+    if !status.success() {
+        bail!("Failed to copy workspace to '{}'", dst.display());
+    }
+    Ok(())
 }
 
-/// Build the docker run argument list without executing. Used by run_container and tests.
-pub fn build_run_args(cfg: &DockerRunConfig) -> Result<Vec<String>> {
-    let workspace_dir = Path::new(cfg.home)
-        .join(".box")
-        .join("workspaces")
-        .join(cfg.name);
-    let workspace_dir = workspace_dir.to_string_lossy();
-    let interactive_flag = if cfg.detach { "-d" } else { "-it" };
-    let mut args: Vec<String> = vec![
-        "run".into(),
-        interactive_flag.into(),
-        "--name".into(),
-        format!("box-{}", cfg.name),
-        "--hostname".into(),
-        format!("box-{}", cfg.name),
-        "-v".into(),
-        format!("{}:{}", workspace_dir, cfg.mount_path),
-        "-w".into(),
-        cfg.mount_path.into(),
-    ];
+/// Copy a file or directory tree on the host, like `cp -R`.
+pub fn copy_path(src: &Path, dst: &Path) -> Result<()> {
+    if !src.exists() {
+        bail!("'{}' does not exist.", src.display());
+    }
+    let status = Command::new("cp").arg("-R").arg(src).arg(dst).status()?;
+    if !status.success() {
+        bail!("Failed to copy '{}' to '{}'", src.display(), dst.display());
+    }
+    Ok(())
+}
 
-    // Mount host ~/.gitconfig so git user.name/user.email etc. are available
-    let gitconfig = Path::new(cfg.home).join(".gitconfig");
-    if gitconfig.exists() {
-        args.push("-v".into());
-        args.push(format!("{}:/etc/gitconfig:ro", gitconfig.display()));
+/// Copy between a session's container and the host with `docker cp`. The
+/// session side is given as `(name, path)`; works on stopped containers too.
+pub fn copy_container_path(
+    name: &str,
+    container_path: &str,
+    host_path: &Path,
+    to_host: bool,
+) -> Result<()> {
+    let container = format!("box-{}:{}", name, container_path);
+    let host = host_path.display().to_string();
+    let (src, dst) = if to_host {
+        (container.as_str(), host.as_str())
+    } else {
+        (host.as_str(), container.as_str())
+    };
+    let status = Command::new("docker").args(["cp", src, dst]).status()?;
+    if !status.success() {
+        bail!("docker cp {} {} failed", src, dst);
     }
+    Ok(())
+}
 
-    if cfg.ssh {
-        let (host_path, container_path) = ssh_agent_paths()?;
-        args.push("-v".into());
-        args.push(format!("{}:{}", host_path, container_path));
-        args.push("-e".into());
-        args.push(format!("SSH_AUTH_SOCK={}", container_path));
+/// Total size in bytes of the files under `dir` (symlinks are not followed).
+pub fn workspace_size(dir: &Path) -> u64 {
+    let mut total = 0;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(meta) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            total += workspace_size(&entry.path());
+        } else {
+            total += meta.len();
+        }
     }
+    total
+}
 
-    if let Some(extra) = cfg.docker_args {
-        if !extra.is_empty() {
-            match shell_words::split(extra) {
-                Ok(extra_args) => args.extend(extra_args),
-                Err(e) => {
-                    bail!("Failed to parse docker args: {}", e);
-                }
-            }
+/// Remove the workspace directory (or volume) for a session, and the storage
+/// of its nested Docker daemon. Worktree workspaces are also unregistered
+/// from the project repository.
+pub fn remove_workspace(name: &str) {
+    remove_workspace_volume(name);
+    let _ = Command::new("docker")
+        .args(["volume", "rm", "--force", &dind_volume(name)])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    if let Ok(dir) = storage::workspace_dir(name) {
+        let common_dir = worktree_common_dir(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        if let Some(common_dir) = common_dir {
+            let _ = Command::new("git")
+                .arg("--git-dir")
+                .arg(&common_dir)
+                .args(["worktree", "prune"])
+                .status();
         }
     }
+}
 
-    for entry in cfg.env {
-        args.push("-e".into());
-        args.push(entry.clone());
+/// For a `git worktree` checkout, return the main repository's `.git` directory.
+/// The checkout's `.git` is a file containing `gitdir: <repo>/.git/worktrees/<id>`.
+fn worktree_common_dir(dir: &Path) -> Option<std::path::PathBuf> {
+    let content = std::fs::read_to_string(dir.join(".git")).ok()?;
+    let gitdir = Path::new(content.trim().strip_prefix("gitdir:")?.trim());
+    let worktrees = gitdir.parent()?;
+    if worktrees.file_name()? != "worktrees" {
+        return None;
     }
+    Some(worktrees.parent()?.to_path_buf())
+}
 
-    args.push(cfg.image.into());
+/// Availability of the docker CLI and daemon.
+pub enum DockerStatus {
+    /// The `docker` CLI isn't installed.
+    Missing,
+    /// The CLI works but the daemon can't be reached.
+    DaemonDown {
+        client: String,
+    },
+    Ready {
+        client: String,
+        server: String,
+    },
+}
 
-    if !cfg.cmd.is_empty() {
-        args.extend(cfg.cmd.iter().cloned());
+pub fn docker_status() -> DockerStatus {
+    let Ok(output) = Command::new("docker")
+        .args([
+            "version",
+            "--format",
+            "{{.Client.Version}}\t{{.Server.Version}}",
+        ])
+        .stderr(std::process::Stdio::null())
+        .output()
+    else {
+        return DockerStatus::Missing;
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (client, server) = stdout
+        .trim()
+        .split_once('\t')
+        .unwrap_or((stdout.trim(), ""));
+    if output.status.success() && !server.is_empty() {
+        DockerStatus::Ready {
+            client: client.to_string(),
+            server: server.to_string(),
+        }
+    } else {
+        DockerStatus::DaemonDown {
+            client: client.to_string(),
+        }
     }
+}
 
-    Ok(args)
+pub fn check() -> Result<()> {
+    match docker_status() {
+        DockerStatus::Missing => {
+            bail!("docker is not installed. See https://docs.docker.com/get-docker/")
+        }
+        DockerStatus::DaemonDown { .. } => {
+            bail!("Docker daemon is not running. Please start Docker.")
+        }
+        DockerStatus::Ready { .. } => Ok(()),
+    }
 }
 
-pub fn run_container(cfg: &DockerRunConfig) -> Result<i32> {
-    ensure_workspace(cfg.home, cfg.name, cfg.project_dir)?;
+const SSH_CONTAINER_PATH: &str = "/run/host-services/ssh-auth.sock";
+
+/// The VM (if any) the docker daemon runs in, which decides how the host's
+/// SSH agent can reach containers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Runtime {
+    /// Docker Desktop or OrbStack, which expose the host agent at a fixed
+    /// path inside their VM.
+    Desktop,
+    /// A Colima profile.
+    Colima(String),
+    /// A Lima instance running dockerd.
+    Lima(String),
+    /// A daemon on the host itself, which can mount the host socket directly.
+    Native,
+}
 
-    if cfg.ssh && std::cfg!(target_os = "macos") {
-        fix_ssh_socket_permissions(cfg.image);
+impl Runtime {
+    pub fn is_vm(&self) -> bool {
+        *self != Runtime::Native
     }
 
-    let args = build_run_args(cfg)?;
-    eprintln!("\x1b[2mrunning container:\x1b[0m");
-    eprintln!("docker {}\n", shell_words::join(&args));
+    /// How to make the host's SSH agent available in this runtime.
+    pub fn ssh_forward_hint(&self) -> &'static str {
+        match self {
+            Runtime::Desktop => "update Docker Desktop/OrbStack and restart it",
+            Runtime::Colima(_) => "restart Colima with `colima start --ssh-agent`",
+            Runtime::Lima(_) => {
+                "set `ssh.forwardAgent: true` in the Lima instance config and restart it"
+            }
+            Runtime::Native => "start an agent (`eval \"$(ssh-agent)\" && ssh-add`)",
+        }
+    }
+}
 
-    if cfg.detach {
-        let output = Command::new("docker").args(&args).output()?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!("docker run failed: {}", stderr.trim());
+impl std::fmt::Display for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Runtime::Desktop => write!(f, "Docker Desktop/OrbStack"),
+            Runtime::Colima(profile) => write!(f, "Colima (profile {})", profile),
+            Runtime::Lima(instance) => write!(f, "Lima (instance {})", instance),
+            Runtime::Native => write!(f, "native"),
         }
-        let container_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        println!("{}", container_id);
-        println!("Run `box {}` to attach.", cfg.name);
-        Ok(0)
-    } else {
-        let status = Command::new("docker")
-            .args(&args)
-            .stdin(std::process::Stdio::inherit())
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .status()?;
-        restore_terminal();
-        Ok(status.code().unwrap_or(1))
     }
 }
 
-pub fn container_exists(name: &str) -> bool {
+/// Send docker commands for the rest of the process to `context`, the way
+/// `docker --context` would. `None` keeps the current context.
+pub fn use_context(context: Option<&str>) {
+    if let Some(context) = context {
+        // DOCKER_HOST would take precedence over the context
+        std::env::remove_var("DOCKER_HOST");
+        std::env::set_var("DOCKER_CONTEXT", context);
+    }
+}
+
+/// Whether `context` is a docker context known to the CLI.
+pub fn context_exists(context: &str) -> bool {
     Command::new("docker")
-        .args(["container", "inspect", &format!("box-{}", name)])
+        .args(["context", "inspect", context])
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
         .status()
@@ -261,167 +672,2849 @@ pub fn container_exists(name: &str) -> bool {
         .unwrap_or(false)
 }
 
-pub fn container_is_running(name: &str) -> bool {
-    let output = Command::new("docker")
-        .args([
-            "container",
-            "inspect",
-            "-f",
-            "{{.State.Running}}",
-            &format!("box-{}", name),
-        ])
-        .stderr(std::process::Stdio::null())
-        .output();
-    match output {
-        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).trim() == "true",
-        _ => false,
+/// The daemon endpoint from `DOCKER_HOST` or the current docker context.
+/// Cached for the life of the process, so call `use_context` first.
+fn docker_endpoint() -> &'static str {
+    static ENDPOINT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    ENDPOINT.get_or_init(|| match std::env::var("DOCKER_HOST") {
+        Ok(host) if !host.is_empty() => host,
+        _ => Command::new("docker")
+            .args([
+                "context",
+                "inspect",
+                "--format",
+                "{{.Endpoints.docker.Host}}",
+            ])
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default(),
+    })
+}
+
+/// Detect the runtime from the daemon endpoint.
+pub fn runtime() -> Runtime {
+    runtime_from_endpoint(docker_endpoint(), cfg!(target_os = "macos"))
+}
+
+/// Whether the daemon runs on another machine (a `tcp://` or `ssh://`
+/// endpoint), where host paths can't be bind-mounted.
+pub fn is_remote() -> bool {
+    is_remote_endpoint(docker_endpoint())
+}
+
+fn is_remote_endpoint(endpoint: &str) -> bool {
+    !endpoint.is_empty() && !endpoint.starts_with("unix://") && !endpoint.starts_with("npipe://")
+}
+
+fn runtime_from_endpoint(endpoint: &str, macos: bool) -> Runtime {
+    let parts: Vec<&str> = endpoint.trim_start_matches("unix://").split('/').collect();
+    // .../.colima/<profile>/docker.sock, or ~/.config/colima/<profile>/docker.sock
+    if let Some(i) = parts.iter().position(|p| *p == ".colima" || *p == "colima") {
+        if let [profile, "docker.sock"] = parts[i + 1..] {
+            return Runtime::Colima(profile.to_string());
+        }
+    }
+    // ~/.lima/<instance>/sock/docker.sock
+    if let Some(i) = parts.iter().position(|p| *p == ".lima") {
+        if let [instance, "sock", "docker.sock"] = parts[i + 1..] {
+            return Runtime::Lima(instance.to_string());
+        }
+    }
+    if macos || endpoint.contains("/.docker/desktop/") {
+        Runtime::Desktop
+    } else {
+        Runtime::Native
+    }
+}
+
+/// Host features that change how bind mounts and users have to be set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HostSecurity {
+    /// SELinux is enforcing, so bind mounts need a `:z`/`:Z` relabel.
+    pub selinux: bool,
+    /// The daemon runs rootless, so container root is the host user.
+    pub rootless: bool,
+    /// The daemon is Podman, which can keep the host uid with `--userns=keep-id`.
+    pub podman: bool,
+}
+
+/// Detect SELinux and rootless Docker/Podman. Only a daemon on this machine
+/// shares its policy and user namespace with the host, so VMs and remote
+/// daemons get the defaults. Cached for the life of the process.
+pub fn host_security() -> HostSecurity {
+    static SECURITY: std::sync::OnceLock<HostSecurity> = std::sync::OnceLock::new();
+    *SECURITY.get_or_init(|| {
+        if runtime().is_vm() || is_remote() {
+            return HostSecurity::default();
+        }
+        let podman = is_podman();
+        let options = Command::new("docker")
+            .args(["info", "--format", "{{json .SecurityOptions}}"])
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).into_owned());
+        let rootless = match options {
+            Some(options) => security_options_rootless(&options),
+            // podman-docker's `docker info` has no SecurityOptions; Podman is
+            // rootless whenever it isn't run as root.
+            None => podman && unsafe { libc::geteuid() } != 0,
+        };
+        HostSecurity {
+            selinux: selinux_enforcing(),
+            rootless,
+            podman,
+        }
+    })
+}
+
+fn selinux_enforcing() -> bool {
+    std::fs::read_to_string("/sys/fs/selinux/enforce")
+        .map(|s| s.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Podman, either as the `docker` command (podman-docker) or behind a
+/// docker-compatible socket.
+fn is_podman() -> bool {
+    let output = |args: &[&str]| {
+        Command::new("docker")
+            .args(args)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).to_lowercase())
+            .unwrap_or_default()
+    };
+    output(&["--version"]).contains("podman")
+        || output(&[
+            "version",
+            "--format",
+            "{{range .Server.Components}}{{.Name}} {{end}}",
+        ])
+        .contains("podman")
+}
+
+/// Whether `docker info`'s SecurityOptions (a JSON list like
+/// `["name=seccomp,profile=builtin","name=rootless"]`) mark a rootless daemon.
+fn security_options_rootless(json: &str) -> bool {
+    serde_json::from_str::<Vec<String>>(json.trim())
+        .unwrap_or_default()
+        .iter()
+        .any(|opt| opt.split(',').any(|kv| kv == "name=rootless"))
+}
+
+/// Return (host_path, container_path) for SSH agent forwarding.
+///
+/// Docker Desktop and OrbStack expose a magic VM-internal socket at
+/// `/run/host-services/ssh-auth.sock` that forwards to the host SSH agent.
+/// Mounting the raw host socket (e.g. 1Password's) does NOT work because Unix
+/// sockets cannot cross the VM boundary.
+///
+/// Colima and Lima forward the agent over their SSH connection to the VM, at
+/// a path only known inside the VM, so it is asked for.
+///
+/// A native daemon uses the host socket from `SSH_AUTH_SOCK` directly.
+pub fn ssh_agent_paths() -> Result<(String, String)> {
+    let host = match runtime() {
+        Runtime::Desktop => SSH_CONTAINER_PATH.to_string(),
+        Runtime::Colima(profile) => {
+            vm_ssh_auth_sock(Command::new("colima").args(["ssh", "--profile", &profile, "--"]))?
+        }
+        Runtime::Lima(instance) => {
+            vm_ssh_auth_sock(Command::new("limactl").args(["shell", &instance]))?
+        }
+        Runtime::Native => std::env::var("SSH_AUTH_SOCK")
+            .map_err(|_| anyhow::anyhow!("SSH_AUTH_SOCK is not set. Cannot forward SSH agent."))?,
+    };
+    Ok((host, SSH_CONTAINER_PATH.to_string()))
+}
+
+/// `$SSH_AUTH_SOCK` inside a VM, read through `shell` (a command that runs
+/// its arguments in the VM).
+fn vm_ssh_auth_sock(shell: &mut Command) -> Result<String> {
+    let output = shell
+        .args(["sh", "-c", "echo $SSH_AUTH_SOCK"])
+        .stderr(std::process::Stdio::null())
+        .output()?;
+    let sock = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || sock.is_empty() {
+        let runtime = runtime();
+        bail!(
+            "SSH agent forwarding is not enabled in the {} VM: {}",
+            runtime,
+            runtime.ssh_forward_hint()
+        );
+    }
+    Ok(sock)
+}
+
+/// Whether `path` is a socket as seen from inside a container, i.e. inside
+/// Docker's VM on macOS.
+pub fn socket_visible_to_containers(path: &str) -> bool {
+    Command::new("docker")
+        .args([
+            "run",
+            "--rm",
+            "-v",
+            &format!("{}:/run/probe.sock", path),
+            "alpine:latest",
+            "test",
+            "-S",
+            "/run/probe.sock",
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Fix SSH agent socket permissions for non-root container users.
+///
+/// OrbStack (0660) and Lima/Colima (0600) set restrictive permissions on the
+/// forwarded SSH agent socket, which prevents non-root container users from accessing it. This runs a one-shot
+/// container as root to make the socket world-accessible. Silently ignored if it fails.
+fn fix_ssh_socket_permissions(image: &str, host_path: &str) {
+    let mount = format!("{}:{}", host_path, SSH_CONTAINER_PATH);
+    let _ = Command::new("docker")
+        .args([
+            "run",
+            "--rm",
+            "--user",
+            "root",
+            "--entrypoint",
+            "chmod",
+            "-v",
+            &mount,
+            image,
+            "666",
+            SSH_CONTAINER_PATH,
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+}
+
+/// Make the forwarded SSH agent socket usable again before a session starts.
+/// Only VM-based runtimes need it; does nothing when there is no agent.
+pub fn repair_ssh_socket(image: &str) {
+    if !runtime().is_vm() {
+        return;
+    }
+    if let Ok((host_path, _)) = ssh_agent_paths() {
+        fix_ssh_socket_permissions(image, &host_path);
+    }
+}
+
+/// Where the forwarded gpg-agent socket and the host's public keyring are
+/// mounted. `GNUPGHOME` points here.
+pub const GPG_CONTAINER_HOME: &str = "/run/gnupg";
+
+/// Host side of gpg-agent forwarding: the agent's extra socket, which only
+/// allows signing and decrypting with keys the agent already has, and the
+/// GnuPG home holding the public keyring.
+pub struct GpgAgent {
+    pub socket: String,
+    pub home: String,
+}
+
+/// Locate the host's gpg-agent with `gpgconf`, starting the agent if needed.
+pub fn gpg_agent() -> Result<GpgAgent> {
+    if let Runtime::Colima(_) | Runtime::Lima(_) = runtime() {
+        bail!("GPG agent forwarding isn't supported with Colima or Lima; the host socket isn't visible in their VM.");
+    }
+    let gpgconf = |args: &[&str]| -> Result<String> {
+        let output = Command::new("gpgconf")
+            .args(args)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .map_err(|_| {
+                anyhow::anyhow!("gpgconf not found. Install GnuPG to forward the gpg agent.")
+            })?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let _ = gpgconf(&["--launch", "gpg-agent"])?;
+    let socket = gpgconf(&["--list-dirs", "agent-extra-socket"])?;
+    let home = gpgconf(&["--list-dirs", "homedir"])?;
+    if socket.is_empty() || !Path::new(&socket).exists() {
+        bail!("gpg-agent has no extra socket. Check that `extra-socket` isn't disabled in gpg-agent.conf.");
+    }
+    Ok(GpgAgent { socket, home })
+}
+
+/// `docker run` flags forwarding `agent` into the container: the extra socket
+/// as the container's agent socket, the public keyring and trust database
+/// read-only, and `GNUPGHOME`. Pinentry prompts appear on the host.
+fn gpg_mounts(cfg: &DockerRunConfig, agent: &GpgAgent) -> Vec<String> {
+    let mut args = vec![
+        "-v".to_string(),
+        bind_mount(
+            cfg,
+            &agent.socket,
+            &format!("{}/S.gpg-agent", GPG_CONTAINER_HOME),
+            &["z"],
+        ),
+    ];
+    for file in ["pubring.kbx", "pubring.gpg", "trustdb.gpg"] {
+        let path = Path::new(&agent.home).join(file);
+        if path.is_file() {
+            args.push("-v".into());
+            args.push(bind_mount(
+                cfg,
+                &path.display().to_string(),
+                &format!("{}/{}", GPG_CONTAINER_HOME, file),
+                &["ro", "z"],
+            ));
+        }
+    }
+    args.push("-e".into());
+    args.push(format!("GNUPGHOME={}", GPG_CONTAINER_HOME));
+    // The terminal docker allocates for the session's main process
+    args.push("-e".into());
+    args.push("GPG_TTY=/dev/console".into());
+    args
+}
+
+/// Where the git credential shim of a session is mounted.
+pub const GIT_AUTH_DIR: &str = "/run/box-git";
+
+/// Prompt answerer for `GIT_ASKPASS`: the first line of the credential file
+/// is the username, the second the password.
+const GIT_ASKPASS_SCRIPT: &str = "#!/bin/sh
+case \"$1\" in
+Username*) sed -n 1p /run/box-git/credential ;;
+*) sed -n 2p /run/box-git/credential ;;
+esac
+";
+
+/// Host directory with the git credential shim of session `name`.
+fn git_auth_dir(name: &str) -> Result<std::path::PathBuf> {
+    Ok(storage::sessions_dir()?.join(name).join("git-auth"))
+}
+
+/// Write the askpass shim of session `name` and fetch fresh credentials for
+/// its origin (`url`, else the project's `origin`) from the host's credential
+/// helpers or `gh`. Failing to get credentials only warns, so the session
+/// still starts; git inside then fails to authenticate as it would without.
+pub fn refresh_git_auth(name: &str, project_dir: &str, url: Option<&str>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = git_auth_dir(name)?;
+    std::fs::create_dir_all(&dir)?;
+    let askpass = dir.join("askpass");
+    std::fs::write(&askpass, GIT_ASKPASS_SCRIPT)?;
+    std::fs::set_permissions(&askpass, std::fs::Permissions::from_mode(0o755))?;
+
+    let path = dir.join("credential");
+    let origin = url
+        .map(String::from)
+        .or_else(|| crate::git::remote_url(Path::new(project_dir)));
+    let content = match origin.as_deref().map(crate::git::credential) {
+        Some(Ok(credential)) => format!("{}\n{}\n", credential.username, credential.password),
+        Some(Err(e)) => {
+            eprintln!(
+                "\x1b[2mwarning:\x1b[0m git credentials not forwarded: {}",
+                e
+            );
+            String::new()
+        }
+        None => {
+            eprintln!("\x1b[2mwarning:\x1b[0m git credentials not forwarded: the project has no origin remote");
+            String::new()
+        }
+    };
+    // Only the owner may read the token; written in place so a running
+    // container sees the new contents.
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    std::io::Write::write_all(&mut &file, content.as_bytes())?;
+    Ok(())
+}
+
+/// Empty the forwarded git credential of session `name`, so the token
+/// doesn't sit on disk while the session is stopped. `refresh_git_auth`
+/// fetches a new one on the next start.
+fn clear_git_auth(name: &str) {
+    if let Ok(path) = git_auth_dir(name).map(|dir| dir.join("credential")) {
+        if path.exists() {
+            let _ = std::fs::write(path, "");
+        }
+    }
+}
+
+/// `clear_git_auth` once a foreground session returns, unless the user only
+/// detached and the container is still running.
+fn clear_git_auth_if_exited(name: &str) {
+    let has_credential = git_auth_dir(name)
+        .and_then(|dir| Ok(std::fs::metadata(dir.join("credential"))?.len() > 0))
+        .unwrap_or(false);
+    if has_credential && !container_is_running(name) {
+        clear_git_auth(name);
+    }
+}
+
+/// `docker run` flags for the git credential shim of session `name`: the shim
+/// directory read-only, `GIT_ASKPASS`, and an empty `credential.helper` so
+/// host helpers named in the mounted `~/.gitconfig` aren't tried.
+fn git_auth_mounts(cfg: &DockerRunConfig) -> Result<Vec<String>> {
+    let dir = git_auth_dir(cfg.name)?;
+    Ok(vec![
+        "-v".to_string(),
+        bind_mount(cfg, &dir.display().to_string(), GIT_AUTH_DIR, &["ro", "z"]),
+        "-e".to_string(),
+        format!("GIT_ASKPASS={}/askpass", GIT_AUTH_DIR),
+        "-e".to_string(),
+        "GIT_CONFIG_COUNT=1".to_string(),
+        "-e".to_string(),
+        "GIT_CONFIG_KEY_0=credential.helper".to_string(),
+        "-e".to_string(),
+        "GIT_CONFIG_VALUE_0=".to_string(),
+    ])
+}
+
+/// Restore terminal state after an interactive Docker session.
+/// Writes show-cursor and attribute-reset escape sequences. Best-effort; errors ignored.
+fn restore_terminal() {
+    let _ = std::io::stdout().write_all(b"\x1b[?25h\x1b[0m");
+    let _ = std::io::stdout().flush();
+}
+
+/// Where session secrets are mounted inside the container.
+pub const SECRETS_DIR: &str = "/run/secrets";
+
+pub struct DockerRunConfig<'a> {
+    pub name: &'a str,
+    pub project_dir: &'a str,
+    pub image: &'a str,
+    pub mount_path: &'a str,
+    pub cmd: &'a [String],
+    pub env: &'a [String],
+    pub home: &'a str,
+    pub workspace: &'a str,
+    pub docker_args: Option<&'a str>,
+    pub ssh: bool,
+    pub worktree: bool,
+    /// Branch or commit a newly created workspace checks out.
+    pub branch: Option<&'a str>,
+    /// Remote a newly created workspace is cloned from instead of the project.
+    pub url: Option<&'a str>,
+    /// Shallow/partial clone settings for a newly created workspace.
+    pub clone: CloneOptions<'a>,
+    pub cpus: Option<&'a str>,
+    pub memory: Option<&'a str>,
+    pub ports: &'a [String],
+    pub secrets: &'a [String],
+    /// Shared cache volumes as `NAME:/container/path`.
+    pub caches: &'a [String],
+    /// Keep the workspace in the `box-ws-<name>` volume instead of bind-mounting it.
+    pub volume: bool,
+    /// Run as this `uid:gid` instead of the image's user.
+    pub user: Option<&'a str>,
+    /// `--network` mode, e.g. `none` for a fully isolated sandbox.
+    pub network: Option<&'a str>,
+    /// Drop all capabilities and privilege escalation, and make the root
+    /// filesystem read-only with a tmpfs `/tmp`.
+    pub hardened: bool,
+    /// Forward the host's gpg-agent (see `gpg_mounts`).
+    pub gpg: bool,
+    /// Answer git's HTTPS credential prompts with the host's credentials
+    /// (see `refresh_git_auth`).
+    pub git_auth: bool,
+    /// Mount the daemon's socket at `/var/run/docker.sock`.
+    pub docker_socket: bool,
+    /// Run privileged with the nested daemon's storage in `box-dind-<name>`.
+    pub dind: bool,
+    /// The daemon is on another machine, so nothing from the host is mounted.
+    pub remote: bool,
+    /// SELinux labels and user namespaces to account for in mounts.
+    pub security: HostSecurity,
+    pub detach: bool,
+    /// With `detach`, still allocate a terminal so the caller can attach
+    /// right after setting the container up.
+    pub tty: bool,
+    pub detach_keys: Option<&'a str>,
+    /// Remove the container when it exits. A one-shot run only gets a
+    /// terminal with `tty`, so it also works piped or in CI.
+    pub rm: bool,
+}
+
+/// `-v` value for bind-mounting host path `src` at `dst`. `options` may end
+/// with an SELinux relabel (`z` shared, `Z` private), dropped unless SELinux
+/// is enforcing.
+fn bind_mount(cfg: &DockerRunConfig, src: &str, dst: &str, options: &[&str]) -> String {
+    let options: Vec<&str> = options
+        .iter()
+        .copied()
+        .filter(|o| cfg.security.selinux || !o.eq_ignore_ascii_case("z"))
+        .collect();
+    if options.is_empty() {
+        format!("{}:{}", src, dst)
+    } else {
+        format!("{}:{}:{}", src, dst, options.join(","))
+    }
+}
+
+/// Build the docker run argument list without executing. Used by run_container and tests.
+pub fn build_run_args(cfg: &DockerRunConfig) -> Result<Vec<String>> {
+    let mut args: Vec<String> = vec!["run".into()];
+    if cfg.detach {
+        args.push("-d".into());
+    }
+    if cfg.rm {
+        args.push("--rm".into());
+    }
+    if cfg.rm && !cfg.tty {
+        args.push("-i".into());
+    } else if !cfg.detach || cfg.tty {
+        args.push("-it".into());
+    }
+    if let (false, Some(keys)) = (cfg.detach, cfg.detach_keys) {
+        args.push("--detach-keys".into());
+        args.push(keys.into());
+    }
+    args.extend([
+        "--name".into(),
+        format!("box-{}", cfg.name),
+        "--hostname".into(),
+        format!("box-{}", cfg.name),
+        "-v".into(),
+        if cfg.volume {
+            format!("{}:{}", workspace_volume(cfg.name), cfg.mount_path)
+        } else {
+            // Only this container uses the workspace, so it gets a private label
+            bind_mount(cfg, cfg.workspace, cfg.mount_path, &["Z"])
+        },
+        "-w".into(),
+        cfg.mount_path.into(),
+    ]);
+
+    // A worktree's `.git` file points into the project's `.git` by absolute
+    // host path, so mount it at the same path for git to work in the container.
+    if cfg.worktree {
+        let project_git = format!("{}/.git", cfg.project_dir.trim_end_matches('/'));
+        args.push("-v".into());
+        args.push(bind_mount(cfg, &project_git, &project_git, &["z"]));
+    }
+
+    // A mapped uid usually has no passwd entry, so docker would leave HOME at
+    // `/`. Session env entries come later and can override it.
+    // Rootless daemons shift uids: container root already is the host user,
+    // and Podman can map the host uid to itself instead.
+    match cfg.user {
+        Some(_) if cfg.security.rootless && cfg.security.podman => {
+            args.push("--userns=keep-id".into());
+        }
+        Some(_) if cfg.security.rootless => {}
+        Some(user) => {
+            args.push("--user".into());
+            args.push(user.into());
+            args.push("-e".into());
+            args.push("HOME=/tmp".into());
+        }
+        None => {}
+    }
+
+    // Only the workspace, caches and /tmp stay writable, so HOME moves to
+    // /tmp for tools that write dotfiles.
+    if cfg.hardened {
+        args.extend(
+            [
+                "--cap-drop",
+                "ALL",
+                "--security-opt",
+                "no-new-privileges",
+                "--read-only",
+                "--tmpfs",
+                "/tmp:exec",
+            ]
+            .map(String::from),
+        );
+        if !args.iter().any(|a| a == "HOME=/tmp") {
+            args.push("-e".into());
+            args.push("HOME=/tmp".into());
+        }
+    }
+
+    // Mount host ~/.gitconfig so git user.name/user.email etc. are available
+    let gitconfig = Path::new(cfg.home).join(".gitconfig");
+    if gitconfig.exists() && !cfg.remote {
+        args.push("-v".into());
+        args.push(bind_mount(
+            cfg,
+            &gitconfig.display().to_string(),
+            "/etc/gitconfig",
+            &["ro", "z"],
+        ));
+    }
+
+    // The host's agent socket doesn't exist on a remote daemon
+    if cfg.ssh && !cfg.remote {
+        let (host_path, container_path) = ssh_agent_paths()?;
+        args.push("-v".into());
+        args.push(bind_mount(cfg, &host_path, &container_path, &["z"]));
+        args.push("-e".into());
+        args.push(format!("SSH_AUTH_SOCK={}", container_path));
+    }
+
+    if cfg.gpg && !cfg.remote {
+        args.extend(gpg_mounts(cfg, &gpg_agent()?));
+    }
+
+    if cfg.git_auth && !cfg.remote {
+        args.extend(git_auth_mounts(cfg)?);
+    }
+
+    // The socket is the daemon's own, so this works with a remote daemon too.
+    // It must keep its label under SELinux, so labeling is turned off instead.
+    if cfg.docker_socket {
+        args.push("-v".into());
+        args.push(format!("{}:{}", DOCKER_SOCKET, DOCKER_SOCKET));
+        if cfg.security.selinux {
+            args.push("--security-opt".into());
+            args.push("label=disable".into());
+        }
+    }
+    if cfg.dind {
+        args.push("--privileged".into());
+        args.push("-v".into());
+        args.push(format!("{}:/var/lib/docker", dind_volume(cfg.name)));
+    }
+
+    if let Some(network) = cfg.network {
+        args.push("--network".into());
+        args.push(network.into());
+    }
+
+    for port in cfg.ports {
+        args.push("-p".into());
+        args.push(port.clone());
+    }
+
+    // Secrets live on a tmpfs so nothing of them lands in the container's
+    // writable layer (or in `docker commit` snapshots).
+    if !cfg.secrets.is_empty() {
+        args.push("--tmpfs".into());
+        args.push(SECRETS_DIR.into());
+        for secret in cfg.secrets {
+            let (name, path) = secret.split_once('=').unwrap_or((secret, ""));
+            args.push("-v".into());
+            let target = format!("{}/{}", SECRETS_DIR, name);
+            args.push(bind_mount(cfg, path, &target, &["ro", "z"]));
+        }
+    }
+
+    for cache in cfg.caches {
+        let (name, path) = cache.split_once(':').unwrap_or((cache, ""));
+        args.push("-v".into());
+        args.push(format!("box-cache-{}:{}", name, path));
+    }
+
+    if let Some(cpus) = cfg.cpus {
+        args.push("--cpus".into());
+        args.push(cpus.into());
+    }
+    if let Some(memory) = cfg.memory {
+        args.push("--memory".into());
+        args.push(memory.into());
+    }
+
+    if let Some(extra) = cfg.docker_args {
+        if !extra.is_empty() {
+            match shell_words::split(extra) {
+                Ok(extra_args) => args.extend(extra_args),
+                Err(e) => {
+                    bail!("Failed to parse docker args: {}", e);
+                }
+            }
+        }
+    }
+
+    for entry in cfg.env {
+        args.push("-e".into());
+        args.push(entry.clone());
+    }
+
+    args.push(cfg.image.into());
+
+    if !cfg.cmd.is_empty() {
+        args.extend(cfg.cmd.iter().cloned());
+    }
+
+    Ok(args)
+}
+
+/// Whether `image` is available locally.
+pub fn image_exists(image: &str) -> bool {
+    image_id(image).is_some()
+}
+
+/// The local image ID `image` currently refers to.
+pub fn image_id(image: &str) -> Option<String> {
+    let output = Command::new("docker")
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The user `image` runs as (its `USER`), empty when it runs as root.
+fn image_user(image: &str) -> Option<String> {
+    let output = Command::new("docker")
+        .args(["image", "inspect", "--format", "{{.Config.User}}", image])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether a `USER` value (`name`, `uid` or either with `:group`) is root.
+fn is_root_user(user: &str) -> bool {
+    matches!(user.split(':').next(), Some("" | "root" | "0"))
+}
+
+/// Fail with a suggestion unless `image` is available locally or its
+/// registry knows it, so a mistyped reference is caught before the session
+/// is saved.
+pub fn check_image(image: &str) -> Result<()> {
+    if image_exists(image) {
+        return Ok(());
+    }
+    let found = Command::new("docker")
+        .args(["manifest", "inspect", image])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if found {
+        return Ok(());
+    }
+    let local = local_images();
+    let similar = same_repository(image, &local);
+    if similar.is_empty() {
+        bail!(
+            "Image '{}' was not found locally or in its registry. Check the name and tag, or run `docker login` if the registry is private.",
+            image
+        );
+    }
+    bail!(
+        "Image '{}' was not found locally or in its registry. Did you mean {}?",
+        image,
+        similar.join(", ")
+    )
+}
+
+/// Resolve `image` to `repository@sha256:...`, pulling it first if needed,
+/// so the session keeps running exactly this image.
+pub fn pin_digest(image: &str) -> Result<String> {
+    if image.contains('@') {
+        return Ok(image.to_string());
+    }
+    ensure_image(image)?;
+    let output = Command::new("docker")
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{range .RepoDigests}}{{println .}}{{end}}",
+            image,
+        ])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to inspect image '{}': {}",
+            image,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let digests: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    pinned_reference(image, &digests).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Image '{}' has no registry digest (was it built or retagged locally?), so it can't be pinned.",
+            image
+        )
+    })
+}
+
+/// `image`'s repository at the digest its `RepoDigests` records for that
+/// repository. Digests of other repositories (e.g. the source of a local
+/// retag) aren't used, as the result couldn't be pulled.
+fn pinned_reference(image: &str, repo_digests: &[String]) -> Option<String> {
+    let repo = image_repository(image);
+    let entry = repo_digests.iter().find(|d| image_repository(d) == repo)?;
+    let (_, digest) = entry.split_once('@')?;
+    Some(format!("{}@{}", repo, digest))
+}
+
+/// Repository part of an image reference, without tag or digest.
+fn image_repository(image: &str) -> &str {
+    let image = image.split('@').next().unwrap_or(image);
+    match image.rfind(':') {
+        Some(i) if !image[i..].contains('/') => &image[..i],
+        _ => image,
+    }
+}
+
+/// Local images from the same repository as `image`, as suggestions for a
+/// mistyped tag.
+fn same_repository<'a>(image: &str, local: &'a [String]) -> Vec<&'a str> {
+    let repo = image_repository(image);
+    local
+        .iter()
+        .filter(|l| image_repository(l) == repo)
+        .map(String::as_str)
+        .collect()
+}
+
+/// Pull `image` unless it's already available locally.
+pub fn ensure_image(image: &str) -> Result<()> {
+    if image_exists(image) {
+        return Ok(());
+    }
+    pull_image(image)
+}
+
+/// Pull `image`, showing layer and byte progress on one line. Falls back to
+/// `docker pull` without a local socket, or when the daemon refuses the pull
+/// (e.g. for registries that need the CLI's stored credentials).
+pub fn pull_image(image: &str) -> Result<()> {
+    eprintln!("\x1b[2mpulling image:\x1b[0m {}", image);
+    if let Some(client) = engine::Client::detect() {
+        let tty = std::io::stderr().is_terminal();
+        let mut progress = PullProgress::default();
+        let result = client.pull_image(image, &mut |event| {
+            progress.update(event);
+            if tty {
+                eprint!("\r\x1b[2K{}", progress.line());
+            }
+        });
+        if tty {
+            eprint!("\r\x1b[2K");
+        }
+        match result {
+            Ok(()) => {
+                eprintln!("{}", progress.line());
+                return Ok(());
+            }
+            Err(e) => eprintln!(
+                "\x1b[2mengine pull failed:\x1b[0m {}; retrying with docker pull",
+                e
+            ),
+        }
+    }
+    let status = Command::new("docker").args(["pull", image]).status()?;
+    if !status.success() {
+        bail!("Failed to pull image '{}'", image);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct LayerProgress {
+    current: u64,
+    total: u64,
+    done: bool,
+}
+
+/// Per-layer state built from the daemon's pull progress messages.
+#[derive(Debug, Default)]
+struct PullProgress {
+    layers: BTreeMap<String, LayerProgress>,
+}
+
+impl PullProgress {
+    fn update(&mut self, event: &serde_json::Value) {
+        let (Some(id), Some(status)) = (event["id"].as_str(), event["status"].as_str()) else {
+            return;
+        };
+        // "Pulling from library/alpine" carries the tag as its id
+        if status.starts_with("Pulling from") {
+            return;
+        }
+        let layer = self.layers.entry(id.to_string()).or_default();
+        match status {
+            "Downloading" => {
+                let detail = &event["progressDetail"];
+                layer.current = detail["current"].as_u64().unwrap_or(layer.current);
+                layer.total = detail["total"].as_u64().unwrap_or(layer.total);
+            }
+            "Download complete" | "Pull complete" | "Already exists" => {
+                layer.current = layer.total;
+                layer.done = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn line(&self) -> String {
+        let done = self.layers.values().filter(|l| l.done).count();
+        let current: u64 = self.layers.values().map(|l| l.current).sum();
+        let total: u64 = self.layers.values().map(|l| l.total).sum();
+        let mut line = format!("{}/{} layers", done, self.layers.len());
+        if total > 0 {
+            line.push_str(&format!(
+                "  {:.1}/{:.1} MB ({}%)",
+                current as f64 / 1e6,
+                total as f64 / 1e6,
+                current * 100 / total
+            ));
+        }
+        line
+    }
+}
+
+/// Create and start the container for `cfg`. `on_started` is called once the
+/// container is up, and not at all if it fails to start.
+pub fn run_container(cfg: &DockerRunConfig, on_started: impl FnOnce()) -> Result<i32> {
+    if cfg.remote && !cfg.secrets.is_empty() {
+        bail!("Secrets are mounted from host files and can't be used with a remote Docker host.");
+    }
+    if cfg.remote && cfg.gpg {
+        bail!("GPG agent forwarding mounts a host socket and can't be used with a remote Docker host.");
+    }
+    if cfg.remote && cfg.git_auth {
+        bail!("Git credential forwarding mounts host files and can't be used with a remote Docker host.");
+    }
+    for secret in cfg.secrets {
+        let (name, path) = secret.split_once('=').unwrap_or((secret, ""));
+        if !Path::new(path).exists() {
+            bail!("Secret '{}' source '{}' does not exist.", name, path);
+        }
+    }
+    if cfg.volume {
+        ensure_workspace_volume(cfg.name, cfg.project_dir, cfg.remote, cfg.branch, cfg.clone)?;
+    } else {
+        // Under a rootless daemon container root owns files as the host user
+        ensure_workspace(
+            cfg.workspace,
+            cfg.project_dir,
+            cfg.worktree,
+            cfg.user.is_some() || cfg.security.rootless,
+            cfg.branch,
+            cfg.url,
+            cfg.clone,
+        )?;
+    }
+
+    if cfg.ssh && !cfg.remote {
+        repair_ssh_socket(cfg.image);
+    }
+
+    if cfg.git_auth {
+        refresh_git_auth(cfg.name, cfg.project_dir, cfg.url)?;
+    }
+
+    ensure_image(cfg.image)?;
+
+    // The credential file is 0600 and owned by the host user, so only
+    // container root (or the mapped host user) can read it.
+    if cfg.git_auth && cfg.user.is_none() && !cfg.security.rootless {
+        if let Some(user) = image_user(cfg.image).filter(|u| !is_root_user(u)) {
+            eprintln!(
+                "\x1b[2mwarning:\x1b[0m git credentials can't be read by the image's user '{}'; use --user-map or run as root",
+                user
+            );
+        }
+    }
+
+    let args = build_run_args(cfg)?;
+    eprintln!("\x1b[2mrunning container:\x1b[0m");
+    eprintln!("docker {}\n", shell_words::join(&args));
+
+    if cfg.detach {
+        let output = Command::new("docker").args(&args).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("docker run failed: {}", stderr.trim());
+        }
+        on_started();
+        if !cfg.tty {
+            let container_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            println!("{}", container_id);
+            println!("Run `box {}` to attach.", cfg.name);
+        }
+        Ok(0)
+    } else {
+        let mut child = Command::new("docker")
+            .args(&args)
+            .stdin(std::process::Stdio::inherit())
+            .stdout(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .spawn()?;
+        // `docker run` stays in the foreground for the whole session, so
+        // watch for the container to come up while it runs.
+        let mut on_started = Some(on_started);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if on_started.is_some() && container_is_running(cfg.name) {
+                if let Some(f) = on_started.take() {
+                    f();
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        };
+        restore_terminal();
+        clear_git_auth_if_exited(cfg.name);
+        // A quick command can finish before it was seen running; docker's own
+        // failures (125-127) mean it never started.
+        if let Some(f) = on_started {
+            if !matches!(status.code(), Some(125..=127) | None) {
+                f();
+            }
+        }
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+pub fn container_exists(name: &str) -> bool {
+    if let Some(client) = engine::Client::detect() {
+        return matches!(
+            client.inspect_container(&format!("box-{}", name)),
+            Ok(Some(_))
+        );
+    }
+    Command::new("docker")
+        .args(["container", "inspect", &format!("box-{}", name)])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// The `State` object from `docker inspect`, or `None` if the container doesn't exist.
+pub fn container_state(name: &str) -> Option<serde_json::Value> {
+    if let Some(client) = engine::Client::detect() {
+        return match client.inspect_container(&format!("box-{}", name)) {
+            Ok(Some(info)) => Some(info["State"].clone()),
+            _ => None,
+        };
+    }
+    let output = Command::new("docker")
+        .args([
+            "container",
+            "inspect",
+            "--format",
+            "{{json .State}}",
+            &format!("box-{}", name),
+        ])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+pub fn container_is_paused(name: &str) -> bool {
+    container_state(name)
+        .and_then(|state| state["Paused"].as_bool())
+        .unwrap_or(false)
+}
+
+pub fn container_is_running(name: &str) -> bool {
+    if let Some(client) = engine::Client::detect() {
+        return match client.inspect_container(&format!("box-{}", name)) {
+            Ok(Some(info)) => info["State"]["Running"].as_bool().unwrap_or(false),
+            _ => false,
+        };
+    }
+    let output = Command::new("docker")
+        .args([
+            "container",
+            "inspect",
+            "-f",
+            "{{.State.Running}}",
+            &format!("box-{}", name),
+        ])
+        .stderr(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).trim() == "true",
+        _ => false,
+    }
+}
+
+/// Return the set of session names whose containers are currently running.
+pub fn running_sessions() -> std::collections::HashSet<String> {
+    session_containers(false, None)
+}
+
+/// Return the set of session names whose containers are paused.
+pub fn paused_sessions() -> std::collections::HashSet<String> {
+    session_containers(false, Some("paused"))
+}
+
+/// Return the set of session names that have a `box-*` container, running or not.
+pub fn all_session_containers() -> std::collections::HashSet<String> {
+    session_containers(true, None)
+}
+
+/// Return the set of session names whose containers have exited.
+pub fn exited_sessions() -> std::collections::HashSet<String> {
+    session_containers(true, Some("exited"))
+}
+
+/// Size in bytes of a session container's writable layer.
+pub fn container_size(name: &str) -> u64 {
+    Command::new("docker")
+        .args([
+            "container",
+            "inspect",
+            "--size",
+            "--format",
+            "{{.SizeRw}}",
+            &format!("box-{}", name),
+        ])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Untagged images left behind by `box commit` (labelled `box.session`), as
+/// (id, size in bytes).
+pub fn dangling_session_images() -> Vec<(String, u64)> {
+    let Ok(output) = Command::new("docker")
+        .args([
+            "images",
+            "--quiet",
+            "--no-trunc",
+            "--filter",
+            "dangling=true",
+            "--filter",
+            "label=box.session",
+        ])
+        .stderr(std::process::Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut ids: Vec<&str> = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    if !output.status.success() || ids.is_empty() {
+        return Vec::new();
+    }
+    let Ok(output) = Command::new("docker")
+        .args(["image", "inspect", "--format", "{{.Id}}\t{{.Size}}"])
+        .args(&ids)
+        .stderr(std::process::Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (id, size) = line.split_once('\t')?;
+            Some((id.to_string(), size.trim().parse().unwrap_or(0)))
+        })
+        .collect()
+}
+
+pub fn remove_image(id: &str) -> Result<()> {
+    let output = Command::new("docker").args(["rmi", id]).output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to remove image {}: {}",
+            id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// List local images as `repository:tag`, skipping dangling ones. Empty when
+/// docker is unavailable.
+pub fn local_images() -> Vec<String> {
+    let Ok(output) = Command::new("docker")
+        .args(["images", "--format", "{{.Repository}}:{{.Tag}}"])
+        .output()
+    else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    parse_image_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_image_list(output: &str) -> Vec<String> {
+    let mut images: Vec<String> = output
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.contains("<none>"))
+        .map(String::from)
+        .collect();
+    images.sort();
+    images.dedup();
+    images
+}
+
+/// Fill in the container status of session summaries from docker: the
+/// `running`/`paused` flags, plus uptime or last exit for the listings.
+pub fn refresh_status(sessions: &mut [SessionSummary]) {
+    let names: Vec<String> = sessions.iter().map(|s| s.name.clone()).collect();
+    let states = container_states(&names);
+    for s in sessions {
+        let state = states.get(&s.name);
+        apply_state(s, state);
+    }
+}
+
+/// Copy the fields of a container's `State` that listings show into `s`.
+fn apply_state(s: &mut SessionSummary, state: Option<&serde_json::Value>) {
+    let state = state.unwrap_or(&serde_json::Value::Null);
+    let time = |key: &str| {
+        state[key]
+            .as_str()
+            // Docker reports unset times as the zero time
+            .filter(|t| !t.starts_with("0001-"))
+            .unwrap_or_default()
+            .to_string()
+    };
+    s.running = state["Running"].as_bool().unwrap_or(false);
+    s.paused = state["Paused"].as_bool().unwrap_or(false);
+    if s.running {
+        s.started_at = time("StartedAt");
+        s.finished_at = String::new();
+        s.exit_code = None;
+    } else {
+        s.started_at = String::new();
+        s.finished_at = time("FinishedAt");
+        s.exit_code = if s.finished_at.is_empty() {
+            None
+        } else {
+            state["ExitCode"].as_i64()
+        };
+    }
+}
+
+/// `State` of each named session's container, keyed by session name.
+/// Sessions without a container are left out.
+fn container_states(names: &[String]) -> std::collections::HashMap<String, serde_json::Value> {
+    if names.is_empty() {
+        return Default::default();
+    }
+    if let Some(client) = engine::Client::detect() {
+        // One request per container, so spread them over a few threads
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let client = &client;
+        return std::thread::scope(|scope| {
+            let handles: Vec<_> = names
+                .chunks(names.len().div_ceil(threads))
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|name| {
+                                let info =
+                                    client.inspect_container(&format!("box-{}", name)).ok()??;
+                                Some((name.clone(), info["State"].clone()))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap_or_default())
+                .collect()
+        });
+    }
+    let mut cmd = Command::new("docker");
+    cmd.args([
+        "container",
+        "inspect",
+        "--format",
+        "{{.Name}}\t{{json .State}}",
+    ]);
+    cmd.args(names.iter().map(|n| format!("box-{}", n)));
+    // Missing containers make inspect fail, but the others are still printed
+    match cmd.stderr(std::process::Stdio::null()).output() {
+        Ok(o) => parse_container_states(&String::from_utf8_lossy(&o.stdout)),
+        Err(_) => Default::default(),
+    }
+}
+
+fn parse_container_states(output: &str) -> std::collections::HashMap<String, serde_json::Value> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, state) = line.split_once('\t')?;
+            let name = name.trim_start_matches('/').strip_prefix("box-")?;
+            Some((name.to_string(), serde_json::from_str(state).ok()?))
+        })
+        .collect()
+}
+
+fn session_containers(all: bool, status: Option<&str>) -> std::collections::HashSet<String> {
+    if let Some(client) = engine::Client::detect() {
+        return match client.list_containers(all, "box-", status) {
+            Ok(containers) => containers
+                .iter()
+                .flat_map(engine::container_names)
+                .filter_map(|n| n.strip_prefix("box-").map(|s| s.to_string()))
+                .collect(),
+            Err(_) => std::collections::HashSet::new(),
+        };
+    }
+    let status_filter = status.map(|s| format!("status={}", s));
+    let mut args = vec!["ps"];
+    if all {
+        args.push("-a");
+    }
+    if let Some(filter) = &status_filter {
+        args.extend(["--filter", filter.as_str()]);
+    }
+    args.extend(["--filter", "name=box-", "--format", "{{.Names}}"]);
+    let output = Command::new("docker")
+        .args(&args)
+        .stderr(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .filter_map(|line| line.strip_prefix("box-"))
+            .map(|s| s.to_string())
+            .collect(),
+        _ => std::collections::HashSet::new(),
+    }
+}
+
+/// CPU usage (percent) of each running session, from a single
+/// `docker stats` sample. Empty when docker is unavailable.
+pub fn session_cpu_usage() -> std::collections::HashMap<String, f64> {
+    let output = Command::new("docker")
+        .args([
+            "stats",
+            "--no-stream",
+            "--format",
+            "{{.Name}}\t{{.CPUPerc}}",
+        ])
+        .stderr(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => parse_cpu_stats(&String::from_utf8_lossy(&o.stdout)),
+        _ => std::collections::HashMap::new(),
+    }
+}
+
+fn parse_cpu_stats(output: &str) -> std::collections::HashMap<String, f64> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (container, cpu) = line.split_once('\t')?;
+            let name = container.trim().strip_prefix("box-")?;
+            let cpu = cpu.trim().trim_end_matches('%').parse().ok()?;
+            Some((name.to_string(), cpu))
+        })
+        .collect()
+}
+
+/// Sessions with a terminal attached from this host: a running
+/// `docker attach`, `docker exec -it`, `docker start -a` or `docker run -it`
+/// client for the session's container.
+pub fn attached_sessions() -> std::collections::HashSet<String> {
+    let output = Command::new("ps")
+        .args(["-A", "-o", "args="])
+        .stderr(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .filter_map(attached_session)
+            .collect(),
+        _ => std::collections::HashSet::new(),
+    }
+}
+
+/// The session a `docker` client command line keeps a terminal attached to.
+fn attached_session(cmdline: &str) -> Option<String> {
+    let args: Vec<&str> = cmdline.split_whitespace().collect();
+    let program = args.first()?;
+    if Path::new(program).file_name()? != "docker" {
+        return None;
+    }
+    let subcommand = *args.get(1)?;
+    let interactive = |short: char, long: &str| {
+        args.iter().any(|a| {
+            *a == long || (a.starts_with('-') && !a.starts_with("--") && a.contains(short))
+        })
+    };
+    let attached = match subcommand {
+        "attach" => true,
+        "start" => interactive('a', "--attach"),
+        "exec" | "run" => interactive('t', "--tty") && !interactive('d', "--detach"),
+        _ => false,
+    };
+    if !attached {
+        return None;
+    }
+    let container = if subcommand == "run" {
+        args.iter()
+            .position(|a| *a == "--name")
+            .and_then(|i| args.get(i + 1).copied())
+            .or_else(|| args.iter().find_map(|a| a.strip_prefix("--name=")))?
+    } else {
+        args[2..]
+            .iter()
+            .copied()
+            .find(|a| a.starts_with("box-") && !a.contains([':', '/', '=']))?
+    };
+    container.strip_prefix("box-").map(|name| name.to_string())
+}
+
+/// Start an existing container and attach to it. `on_started` is called
+/// once the container is up, before attaching.
+pub fn start_container(name: &str, on_started: impl FnOnce()) -> Result<i32> {
+    // Start container in background first, then attach separately.
+    // This avoids the PTY size race condition that `docker start -ai` has,
+    // where the terminal inside may not receive the correct dimensions.
+    let status = Command::new("docker")
+        .args(["start", &format!("box-{}", name)])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+
+    if !status.success() {
+        return Ok(status.code().unwrap_or(1));
+    }
+    on_started();
+
+    attach_container(name)
+}
+
+pub fn attach_container(name: &str) -> Result<i32> {
+    let mut args = vec!["attach".to_string()];
+    if let Some(keys) = config::detach_keys() {
+        args.push("--detach-keys".into());
+        args.push(keys);
+    }
+    args.push(format!("box-{}", name));
+    let mut child = Command::new("docker")
+        .args(&args)
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .spawn()?;
+
+    // After attaching, the container's PTY may retain stale dimensions from a
+    // previous session. Send SIGWINCH to the docker-attach process after a
+    // short delay so Docker re-reads the current terminal size and pushes a
+    // resize event to the container. This eliminates the need for a manual
+    // pane resize to recover rendering.
+    #[cfg(unix)]
+    {
+        let pid = child.id();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGWINCH);
+            }
+        });
+    }
+
+    let status = child.wait()?;
+    restore_terminal();
+    clear_git_auth_if_exited(name);
+
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn exec_container(name: &str, cmd: &[String]) -> Result<i32> {
+    let mut args = vec!["exec".to_string(), "-it".to_string()];
+    if let Some(keys) = config::detach_keys() {
+        args.push("--detach-keys".into());
+        args.push(keys);
+    }
+    args.push(format!("box-{}", name));
+    args.extend(cmd.iter().cloned());
+
+    let status = Command::new("docker")
+        .args(&args)
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    restore_terminal();
+
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Command that starts an interactive shell: `shell` split into words, or
+/// bash when it is installed and sh otherwise.
+pub fn shell_command(shell: Option<&str>) -> Vec<String> {
+    match shell {
+        Some(shell) => shell_words::split(shell).unwrap_or_else(|_| vec![shell.to_string()]),
+        None => vec![
+            "sh".into(),
+            "-c".into(),
+            "command -v bash >/dev/null 2>&1 && exec bash || exec sh".into(),
+        ],
+    }
+}
+
+/// Build the docker exec argument list for `box shell` without executing.
+pub fn build_shell_args(name: &str, workdir: &str, shell: Option<&str>) -> Vec<String> {
+    let mut args = vec!["exec".to_string(), "-it".to_string()];
+    if let Some(keys) = config::detach_keys() {
+        args.push("--detach-keys".into());
+        args.push(keys);
+    }
+    args.push("-w".into());
+    args.push(workdir.into());
+    args.push("-e".into());
+    args.push(format!("BOX_SESSION={}", name));
+    args.push(format!("box-{}", name));
+    args.extend(shell_command(shell));
+    args
+}
+
+/// Open an interactive shell in the session's running container.
+pub fn shell_container(name: &str, workdir: &str, shell: Option<&str>) -> Result<i32> {
+    let status = Command::new("docker")
+        .args(build_shell_args(name, workdir, shell))
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    restore_terminal();
+
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Options for `docker logs`.
+#[derive(Debug, Default)]
+pub struct LogsOptions<'a> {
+    pub follow: bool,
+    pub tail: Option<&'a str>,
+    pub since: Option<&'a str>,
+}
+
+/// Build the docker logs argument list without executing. Used by logs and tests.
+pub fn build_logs_args(name: &str, opts: &LogsOptions) -> Vec<String> {
+    let mut args = vec!["logs".to_string()];
+    if opts.follow {
+        args.push("--follow".into());
+    }
+    if let Some(tail) = opts.tail {
+        args.push("--tail".into());
+        args.push(tail.into());
+    }
+    if let Some(since) = opts.since {
+        args.push("--since".into());
+        args.push(since.into());
+    }
+    args.push(format!("box-{}", name));
+    args
+}
+
+pub fn logs(name: &str, opts: &LogsOptions) -> Result<i32> {
+    let status = Command::new("docker")
+        .args(build_logs_args(name, opts))
+        .status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Capture the last `tail` lines of a container's output, stdout and stderr combined.
+pub fn recent_logs(name: &str, tail: &str) -> Result<String> {
+    let opts = LogsOptions {
+        follow: false,
+        tail: Some(tail),
+        since: None,
+    };
+    let output = Command::new("docker")
+        .args(build_logs_args(name, &opts))
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{}", stderr.trim());
+    }
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(text)
+}
+
+pub fn start_container_detached(name: &str) -> Result<i32> {
+    start_session(name)?;
+    println!("Container box-{} started in background.", name);
+    println!("Run `box {}` to attach.", name);
+    Ok(0)
+}
+
+/// Start an existing container in the background without printing anything.
+pub fn start_session(name: &str) -> Result<()> {
+    let output = Command::new("docker")
+        .args(["start", &format!("box-{}", name)])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to start session '{}': {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub fn stop_container(name: &str, timeout: Option<u32>) -> Result<i32> {
+    stop_session(name, timeout)?;
+    println!("Session '{}' stopped.", name);
+    Ok(0)
+}
+
+/// SIGKILL the session's container right away, without a grace period.
+pub fn kill_container(name: &str) -> Result<i32> {
+    if let Some(client) = engine::Client::detect() {
+        client
+            .kill_container(&format!("box-{}", name))
+            .map_err(|e| anyhow::anyhow!("Failed to kill session '{}': {}", name, e))?;
+    } else {
+        let output = Command::new("docker")
+            .args(["kill", &format!("box-{}", name)])
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Failed to kill session '{}': {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    clear_git_auth(name);
+    println!("Session '{}' killed.", name);
+    Ok(0)
+}
+
+/// Build the docker stop argument list without executing. Used by stop and tests.
+pub fn build_stop_args(name: &str, timeout: Option<u32>) -> Vec<String> {
+    let mut args = vec!["stop".to_string()];
+    if let Some(t) = timeout {
+        args.push("-t".into());
+        args.push(t.to_string());
+    }
+    args.push(format!("box-{}", name));
+    args
+}
+
+/// Wait for the session's container to exit and return its exit code.
+pub fn wait_container(name: &str) -> Result<i32> {
+    let output = Command::new("docker")
+        .args(["wait", &format!("box-{}", name)])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to wait for session '{}': {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let code = String::from_utf8_lossy(&output.stdout);
+    code.trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Unexpected output from docker wait: {}", code.trim()))
+}
+
+/// Stop a running container without printing anything. `timeout` is the
+/// number of seconds to wait before killing it (docker's default when `None`).
+pub fn stop_session(name: &str, timeout: Option<u32>) -> Result<()> {
+    if let Some(client) = engine::Client::detect() {
+        client
+            .stop_container(&format!("box-{}", name), timeout)
+            .map_err(|e| anyhow::anyhow!("Failed to stop session '{}': {}", name, e))?;
+    } else {
+        let output = Command::new("docker")
+            .args(build_stop_args(name, timeout))
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Failed to stop session '{}': {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    clear_git_auth(name);
+    Ok(())
+}
+
+pub fn pause_container(name: &str) -> Result<()> {
+    set_paused(name, true)
+}
+
+pub fn unpause_container(name: &str) -> Result<()> {
+    set_paused(name, false)
+}
+
+fn set_paused(name: &str, paused: bool) -> Result<()> {
+    let verb = if paused { "pause" } else { "unpause" };
+    if let Some(client) = engine::Client::detect() {
+        let id = format!("box-{}", name);
+        let result = if paused {
+            client.pause_container(&id)
+        } else {
+            client.unpause_container(&id)
+        };
+        return result.map_err(|e| anyhow::anyhow!("Failed to {} session '{}': {}", verb, name, e));
+    }
+    let status = Command::new("docker")
+        .args([verb, &format!("box-{}", name)])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        bail!("Failed to {} session '{}'", verb, name);
+    }
+    Ok(())
+}
+
+/// Snapshot a session's container filesystem as an image labelled with the session name.
+pub fn commit_container(name: &str, tag: &str, session_label: &str) -> Result<()> {
+    let status = Command::new("docker")
+        .args([
+            "commit",
+            "--change",
+            &format!("LABEL box.session={}", session_label),
+            &format!("box-{}", name),
+            tag,
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        bail!("docker commit failed for session '{}'", name);
+    }
+    Ok(())
+}
+
+/// Install (if needed) and start sshd in the session's container, authorizing
+/// `public_key` for the container's default user. Returns that user's name.
+pub fn ensure_sshd(name: &str, public_key: &str) -> Result<String> {
+    let container = format!("box-{}", name);
+    let output = Command::new("docker")
+        .args(["exec", &container, "id", "-un"])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "Failed to determine the container user for session '{}'. With --user-map the uid needs an entry in the image's /etc/passwd.",
+            name
+        );
+    }
+    let user = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let status = Command::new("docker")
+        .args([
+            "exec",
+            "-u",
+            "root",
+            "-e",
+            &format!("BOX_SSH_KEY={}", public_key.trim()),
+            &container,
+            "sh",
+            "-c",
+            SSHD_SETUP_SCRIPT,
+            "sh",
+            &user,
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        bail!("Failed to start sshd in session '{}'", name);
+    }
+    Ok(user)
+}
+
+const SSHD_SETUP_SCRIPT: &str = r#"set -e
+user="$1"
+if [ ! -x /usr/sbin/sshd ]; then
+  if command -v apk >/dev/null 2>&1; then
+    apk add --no-cache -q openssh-server
+  elif command -v apt-get >/dev/null 2>&1; then
+    apt-get update -qq && DEBIAN_FRONTEND=noninteractive apt-get install -y -qq openssh-server
+  elif command -v dnf >/dev/null 2>&1; then
+    dnf install -y -q openssh-server
+  else
+    echo "sshd is not installed and no supported package manager was found" >&2
+    exit 1
+  fi
+fi
+ssh-keygen -A
+mkdir -p /run/sshd
+# sshd refuses locked accounts even for key auth
+sed -i "s/^$user:![^:]*:/$user:*:/" /etc/shadow 2>/dev/null || true
+home=$(awk -F: -v u="$user" '$1 == u { print $6 }' /etc/passwd)
+home="${home:-/root}"
+mkdir -p "$home/.ssh"
+touch "$home/.ssh/authorized_keys"
+grep -qxF "$BOX_SSH_KEY" "$home/.ssh/authorized_keys" || printf '%s
+' "$BOX_SSH_KEY" >> "$home/.ssh/authorized_keys"
+chmod 700 "$home/.ssh"
+chmod 600 "$home/.ssh/authorized_keys"
+chown -R "$user" "$home/.ssh"
+if [ -f /run/box-sshd.pid ] && kill -0 "$(cat /run/box-sshd.pid)" 2>/dev/null; then
+  exit 0
+fi
+/usr/sbin/sshd -o PidFile=/run/box-sshd.pid -o PermitRootLogin=prohibit-password
+"#;
+
+/// Run a shell command in the session's container, non-interactively, with
+/// output going to the terminal. Returns whether it succeeded.
+pub fn exec_script(name: &str, script: &str) -> Result<bool> {
+    let status = Command::new("docker")
+        .args(["exec", "-e", &format!("BOX_SESSION={}", name)])
+        .arg(format!("box-{}", name))
+        .args(["sh", "-c", script])
+        .status()?;
+    Ok(status.success())
+}
+
+/// Copy `dir` to `~/dotfiles` in the session's container and run its install
+/// script as the container user. Without an install script, top-level
+/// dotfiles are symlinked into the home directory.
+pub fn install_dotfiles(name: &str, dir: &Path, install: Option<&str>) -> Result<()> {
+    let container = format!("box-{}", name);
+    let output = Command::new("docker")
+        .args(["exec", &container, "sh", "-c", "id -u; printf %s \"$HOME\""])
+        .output()?;
+    if !output.status.success() {
+        bail!("Failed to inspect the container user of session '{}'", name);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (user, home) = stdout.split_once('\n').unwrap_or(("root", "/root"));
+
+    let dest = format!("{}:{}/dotfiles", container, home);
+    let status = Command::new("docker")
+        .args(["cp", "-q", &format!("{}/.", dir.display()), &dest])
+        .status()?;
+    if !status.success() {
+        bail!("Failed to copy dotfiles into session '{}'", name);
+    }
+    // `docker cp` leaves the files owned by root
+    let _ = Command::new("docker")
+        .args(["exec", "-u", "root", &container, "chown", "-R", user])
+        .arg(format!("{}/dotfiles", home))
+        .status();
+
+    let status = Command::new("docker")
+        .args([
+            "exec",
+            &container,
+            "sh",
+            "-c",
+            DOTFILES_INSTALL_SCRIPT,
+            "sh",
+        ])
+        .arg(install.unwrap_or(""))
+        .status()?;
+    if !status.success() {
+        bail!("Dotfiles install script failed in session '{}'", name);
+    }
+    Ok(())
+}
+
+const DOTFILES_INSTALL_SCRIPT: &str = r#"cd "$HOME/dotfiles" || exit 1
+if [ -n "$1" ] && [ ! -f "$1" ]; then
+  echo "dotfiles install script '$1' not found" >&2
+  exit 1
+fi
+for f in "$1" install.sh install bootstrap.sh bootstrap script/bootstrap setup.sh setup script/setup; do
+  [ -n "$f" ] && [ -f "$f" ] || continue
+  if [ -x "$f" ]; then exec "./$f"; else exec sh "./$f"; fi
+done
+for f in .[!.]*; do
+  [ -e "$f" ] && [ "$f" != .git ] || continue
+  ln -sfn "$HOME/dotfiles/$f" "$HOME/$f"
+done
+"#;
+
+/// Ports currently published by the session's container, as `80/tcp -> 0.0.0.0:8080`.
+pub fn published_ports(name: &str) -> Vec<String> {
+    if let Some(client) = engine::Client::detect() {
+        return match client.inspect_container(&format!("box-{}", name)) {
+            Ok(Some(info)) => format_port_bindings(&info["NetworkSettings"]["Ports"]),
+            _ => Vec::new(),
+        };
+    }
+    let output = Command::new("docker")
+        .args(["port", &format!("box-{}", name)])
+        .stderr(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .map(|l| l.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Render `NetworkSettings.Ports` from `docker inspect` the way `docker port` does.
+fn format_port_bindings(ports: &serde_json::Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(map) = ports.as_object() {
+        for (container_port, bindings) in map {
+            for binding in bindings.as_array().into_iter().flatten() {
+                let ip = binding["HostIp"].as_str().unwrap_or("");
+                let port = binding["HostPort"].as_str().unwrap_or("");
+                let host = if ip.contains(':') {
+                    format!("[{}]:{}", ip, port)
+                } else {
+                    format!("{}:{}", ip, port)
+                };
+                lines.push(format!("{} -> {}", container_port, host));
+            }
+        }
+    }
+    lines.sort();
+    lines
+}
+
+/// Change the resource limits of an existing container in place.
+pub fn update_limits(name: &str, cpus: Option<&str>, memory: Option<&str>) -> Result<()> {
+    let mut args = vec!["update".to_string()];
+    if let Some(cpus) = cpus {
+        args.extend(["--cpus".to_string(), cpus.to_string()]);
+    }
+    if let Some(memory) = memory {
+        // Lift the swap cap along with memory so raising the limit isn't rejected
+        args.extend([
+            "--memory".to_string(),
+            memory.to_string(),
+            "--memory-swap".to_string(),
+            "-1".to_string(),
+        ]);
+    }
+    args.push(format!("box-{}", name));
+    let status = Command::new("docker")
+        .args(&args)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!("docker update failed for session '{}'", name);
+    }
+    Ok(())
+}
+
+pub fn remove_container(name: &str) {
+    clear_git_auth(name);
+    if let Some(client) = engine::Client::detect() {
+        let _ = client.remove_container(&format!("box-{}", name), true);
+        return;
+    }
+    let _ = Command::new("docker")
+        .args(["rm", "-f", &format!("box-{}", name)])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_repository() {
+        assert_eq!(image_repository("alpine"), "alpine");
+        assert_eq!(image_repository("rust:1.80"), "rust");
+        assert_eq!(image_repository("localhost:5000/app"), "localhost:5000/app");
+        assert_eq!(
+            image_repository("localhost:5000/app:v2"),
+            "localhost:5000/app"
+        );
+        assert_eq!(image_repository("alpine@sha256:abc"), "alpine");
+    }
+
+    #[test]
+    fn test_pinned_reference() {
+        let digests = [
+            "docker.io/library/python@sha256:111".to_string(),
+            "python@sha256:222".to_string(),
+        ];
+        assert_eq!(
+            pinned_reference("python:3.12", &digests).as_deref(),
+            Some("python@sha256:222")
+        );
+        assert_eq!(
+            pinned_reference("docker.io/library/python:3.12", &digests).as_deref(),
+            Some("docker.io/library/python@sha256:111")
+        );
+        // A local retag only carries digests of the repository it came from
+        assert_eq!(pinned_reference("myalias:latest", &digests), None);
+        assert_eq!(pinned_reference("mydev:latest", &[]), None);
+    }
+
+    #[test]
+    fn test_same_repository() {
+        let local = [
+            "python:3.11",
+            "python:3.12-slim",
+            "pythonista:1",
+            "rust:1.80",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            same_repository("python:3.1", &local),
+            ["python:3.11", "python:3.12-slim"]
+        );
+        assert!(same_repository("node:20", &local).is_empty());
+    }
+
+    #[test]
+    fn test_parse_image_list() {
+        let output = "ubuntu:24.04\n<none>:<none>\nalpine:latest\nubuntu:24.04\n\n";
+        assert_eq!(parse_image_list(output), ["alpine:latest", "ubuntu:24.04"]);
+    }
+
+    #[test]
+    fn test_runtime_from_endpoint() {
+        let detect = |endpoint: &str, macos: bool| runtime_from_endpoint(endpoint, macos);
+        assert_eq!(
+            detect("unix:///Users/me/.colima/default/docker.sock", true),
+            Runtime::Colima("default".into())
+        );
+        assert_eq!(
+            detect("unix:///Users/me/.config/colima/work/docker.sock", true),
+            Runtime::Colima("work".into())
+        );
+        assert_eq!(
+            detect("unix:///Users/me/.lima/docker/sock/docker.sock", true),
+            Runtime::Lima("docker".into())
+        );
+        assert_eq!(
+            detect("unix:///Users/me/.orbstack/run/docker.sock", true),
+            Runtime::Desktop
+        );
+        assert_eq!(
+            detect("unix:///home/me/.docker/desktop/docker.sock", false),
+            Runtime::Desktop
+        );
+        assert_eq!(
+            detect("unix:///var/run/docker.sock", false),
+            Runtime::Native
+        );
+        assert_eq!(detect("", false), Runtime::Native);
+        assert!(!Runtime::Native.is_vm());
+    }
+
+    #[test]
+    fn test_is_remote_endpoint() {
+        assert!(is_remote_endpoint("tcp://10.0.0.5:2376"));
+        assert!(is_remote_endpoint("ssh://me@build-box"));
+        assert!(!is_remote_endpoint("unix:///var/run/docker.sock"));
+        assert!(!is_remote_endpoint("npipe:////./pipe/docker_engine"));
+        assert!(!is_remote_endpoint(""));
+    }
+
+    #[test]
+    fn test_parse_cpu_stats() {
+        let output = "box-dev\t12.50%\nother\t3.00%\nbox-idle\t0.00%\nbox-bad\t--\n";
+        let usage = parse_cpu_stats(output);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["dev"], 12.5);
+        assert_eq!(usage["idle"], 0.0);
+    }
+
+    #[test]
+    fn test_parse_container_states() {
+        let output = concat!(
+            "/box-dev\t{\"Running\":true,\"StartedAt\":\"2025-01-01T00:00:00Z\"}\n",
+            "/other\t{\"Running\":true}\n",
+            "/box-bad\tnot json\n",
+        );
+        let states = parse_container_states(output);
+        assert_eq!(states.len(), 1);
+        assert_eq!(states["dev"]["Running"], true);
+    }
+
+    #[test]
+    fn test_apply_state() {
+        let mut s = SessionSummary {
+            name: "dev".to_string(),
+            project_dir: String::new(),
+            image: String::new(),
+            command: String::new(),
+            created_at: String::new(),
+            resumed_at: String::new(),
+            running: false,
+            paused: false,
+            started_at: String::new(),
+            finished_at: String::new(),
+            exit_code: None,
+        };
+        let running = serde_json::json!({
+            "Running": true,
+            "Paused": false,
+            "StartedAt": "2025-01-02T00:00:00Z",
+            "FinishedAt": "2025-01-01T00:00:00Z",
+            "ExitCode": 0,
+        });
+        apply_state(&mut s, Some(&running));
+        assert!(s.running);
+        assert_eq!(s.started_at, "2025-01-02T00:00:00Z");
+        assert_eq!(s.exit_code, None);
+
+        let crashed = serde_json::json!({
+            "Running": false,
+            "StartedAt": "2025-01-02T00:00:00Z",
+            "FinishedAt": "2025-01-03T00:00:00Z",
+            "ExitCode": 137,
+        });
+        apply_state(&mut s, Some(&crashed));
+        assert!(!s.running);
+        assert!(s.started_at.is_empty());
+        assert_eq!(s.finished_at, "2025-01-03T00:00:00Z");
+        assert_eq!(s.exit_code, Some(137));
+
+        // Created but never started
+        let created = serde_json::json!({
+            "Running": false,
+            "FinishedAt": "0001-01-01T00:00:00Z",
+            "ExitCode": 0,
+        });
+        apply_state(&mut s, Some(&created));
+        assert_eq!(s.exit_code, None);
+
+        apply_state(&mut s, None);
+        assert!(!s.running);
+        assert_eq!(s.exit_code, None);
+    }
+
+    #[test]
+    fn test_attached_session() {
+        let attached = |cmd: &str| attached_session(cmd);
+        assert_eq!(attached("docker attach box-dev").as_deref(), Some("dev"));
+        assert_eq!(
+            attached("/usr/local/bin/docker exec -it -w /work box-dev bash").as_deref(),
+            Some("dev")
+        );
+        assert_eq!(attached("docker start -ai box-dev").as_deref(), Some("dev"));
+        assert_eq!(
+            attached(
+                "docker run -it -v box-cache-cargo:/root/.cargo/registry --name box-dev ubuntu"
+            )
+            .as_deref(),
+            Some("dev")
+        );
+        assert_eq!(attached("docker start box-dev"), None);
+        assert_eq!(attached("docker exec box-dev ls"), None);
+        assert_eq!(attached("docker run -dit --name box-dev ubuntu"), None);
+        assert_eq!(attached("docker logs -f box-dev"), None);
+        assert_eq!(attached("vim box-dev"), None);
+    }
+
+    fn default_config<'a>() -> DockerRunConfig<'a> {
+        DockerRunConfig {
+            name: "sess",
+            project_dir: "/tmp/project",
+            image: "alpine:latest",
+            mount_path: "/workspace",
+            cmd: &[],
+            env: &[],
+            home: "/home/user",
+            workspace: "/home/user/.box/workspaces/sess",
+            docker_args: None,
+            ssh: false,
+            worktree: false,
+            branch: None,
+            url: None,
+            clone: CloneOptions::default(),
+            cpus: None,
+            memory: None,
+            ports: &[],
+            secrets: &[],
+            caches: &[],
+            volume: false,
+            user: None,
+            network: None,
+            hardened: false,
+            gpg: false,
+            git_auth: false,
+            docker_socket: false,
+            dind: false,
+            remote: false,
+            security: HostSecurity::default(),
+            detach: false,
+            tty: false,
+            detach_keys: None,
+            rm: false,
+        }
+    }
+
+    #[test]
+    fn test_build_shell_args() {
+        let args = build_shell_args("sess", "/workspace/app", Some("zsh -l"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-w" && w[1] == "/workspace/app"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-e" && w[1] == "BOX_SESSION=sess"));
+        let container = args.iter().position(|a| a == "box-sess").unwrap();
+        assert_eq!(&args[container + 1..], ["zsh", "-l"]);
+
+        let args = build_shell_args("sess", "/workspace/app", None);
+        let container = args.iter().position(|a| a == "box-sess").unwrap();
+        assert_eq!(&args[container + 1..], shell_command(None));
+        assert_eq!(&args[container + 1..container + 3], ["sh", "-c"]);
+    }
+
+    #[test]
+    fn test_build_stop_args() {
+        assert_eq!(build_stop_args("sess", None), vec!["stop", "box-sess"]);
+        assert_eq!(
+            build_stop_args("sess", Some(60)),
+            vec!["stop", "-t", "60", "box-sess"]
+        );
+    }
+
+    #[test]
+    fn test_build_logs_args() {
+        assert_eq!(
+            build_logs_args("sess", &LogsOptions::default()),
+            vec!["logs", "box-sess"]
+        );
+        assert_eq!(
+            build_logs_args(
+                "sess",
+                &LogsOptions {
+                    follow: true,
+                    tail: Some("100"),
+                    since: Some("10m"),
+                }
+            ),
+            vec!["logs", "--follow", "--tail", "100", "--since", "10m", "box-sess"]
+        );
+    }
+
+    #[test]
+    fn test_build_run_args_ports() {
+        let ports = vec!["8080:80".to_string(), "127.0.0.1:5432:5432".to_string()];
+        let args = build_run_args(&DockerRunConfig {
+            ports: &ports,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args.windows(2).any(|w| w[0] == "-p" && w[1] == "8080:80"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-p" && w[1] == "127.0.0.1:5432:5432"));
+    }
+
+    #[test]
+    fn test_build_run_args_secrets() {
+        let args = build_run_args(&default_config()).unwrap();
+        assert!(!args.contains(&"--tmpfs".to_string()));
+
+        let secrets = vec!["npm=/home/user/.npmrc".to_string()];
+        let args = build_run_args(&DockerRunConfig {
+            secrets: &secrets,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--tmpfs" && w[1] == "/run/secrets"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "/home/user/.npmrc:/run/secrets/npm:ro"));
+    }
+
+    #[test]
+    fn test_build_run_args_volume_workspace() {
+        let args = build_run_args(&DockerRunConfig {
+            volume: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "box-ws-sess:/workspace"));
+        assert!(!args.iter().any(|a| a.contains(".box/workspaces")));
+    }
+
+    #[test]
+    fn test_build_run_args_user_map() {
+        let args = build_run_args(&default_config()).unwrap();
+        assert!(!args.contains(&"--user".to_string()));
+
+        let args = build_run_args(&DockerRunConfig {
+            user: Some("1000:1000"),
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--user" && w[1] == "1000:1000"));
+        assert!(args.windows(2).any(|w| w[0] == "-e" && w[1] == "HOME=/tmp"));
+    }
+
+    #[test]
+    fn test_build_run_args_selinux() {
+        let secrets = ["npm=/home/user/.npmrc".to_string()];
+        let cfg = DockerRunConfig {
+            worktree: true,
+            secrets: &secrets,
+            security: HostSecurity {
+                selinux: true,
+                ..HostSecurity::default()
+            },
+            ..default_config()
+        };
+        let args = build_run_args(&cfg).unwrap();
+        let mounts: Vec<&str> = args
+            .windows(2)
+            .filter(|w| w[0] == "-v")
+            .map(|w| w[1].as_str())
+            .collect();
+        assert!(mounts.contains(&"/home/user/.box/workspaces/sess:/workspace:Z"));
+        assert!(mounts.contains(&"/tmp/project/.git:/tmp/project/.git:z"));
+        assert!(mounts.contains(&"/home/user/.npmrc:/run/secrets/npm:ro,z"));
+    }
+
+    #[test]
+    fn test_build_run_args_rootless_user_map() {
+        let rootless = HostSecurity {
+            rootless: true,
+            ..HostSecurity::default()
+        };
+        let args = build_run_args(&DockerRunConfig {
+            user: Some("1000:1000"),
+            security: rootless,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(!args.contains(&"--user".to_string()));
+        assert!(!args.contains(&"--userns=keep-id".to_string()));
+
+        let args = build_run_args(&DockerRunConfig {
+            user: Some("1000:1000"),
+            security: HostSecurity {
+                podman: true,
+                ..rootless
+            },
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args.contains(&"--userns=keep-id".to_string()));
+        assert!(!args.contains(&"--user".to_string()));
+    }
+
+    #[test]
+    fn test_build_run_args_hardened() {
+        let args = build_run_args(&default_config()).unwrap();
+        assert!(!args.contains(&"--read-only".to_string()));
+
+        let args = build_run_args(&DockerRunConfig {
+            hardened: true,
+            gpg: false,
+            git_auth: false,
+            docker_socket: false,
+            dind: false,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--cap-drop" && w[1] == "ALL"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--security-opt" && w[1] == "no-new-privileges"));
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--tmpfs" && w[1] == "/tmp:exec"));
+        assert!(args.windows(2).any(|w| w[0] == "-e" && w[1] == "HOME=/tmp"));
+
+        let args = build_run_args(&DockerRunConfig {
+            hardened: true,
+            gpg: false,
+            git_auth: false,
+            docker_socket: false,
+            dind: false,
+            user: Some("1000:1000"),
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(args.iter().filter(|a| *a == "HOME=/tmp").count(), 1);
     }
-}
 
-/// Return the set of session names whose containers are currently running.
-pub fn running_sessions() -> std::collections::HashSet<String> {
-    let output = Command::new("docker")
-        .args(["ps", "--filter", "name=box-", "--format", "{{.Names}}"])
-        .stderr(std::process::Stdio::null())
-        .output();
-    match output {
-        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
-            .lines()
-            .filter_map(|line| line.strip_prefix("box-"))
-            .map(|s| s.to_string())
-            .collect(),
-        _ => std::collections::HashSet::new(),
+    #[test]
+    fn test_security_options_rootless() {
+        assert!(security_options_rootless(
+            r#"["name=seccomp,profile=builtin","name=rootless","name=cgroupns"]"#
+        ));
+        assert!(!security_options_rootless(
+            r#"["name=seccomp,profile=builtin","name=selinux"]"#
+        ));
+        assert!(!security_options_rootless("null"));
     }
-}
 
-pub fn start_container(name: &str) -> Result<i32> {
-    // Start container in background first, then attach separately.
-    // This avoids the PTY size race condition that `docker start -ai` has,
-    // where the terminal inside may not receive the correct dimensions.
-    let status = Command::new("docker")
-        .args(["start", &format!("box-{}", name)])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
+    #[test]
+    fn test_pull_progress() {
+        let mut progress = PullProgress::default();
+        for event in [
+            serde_json::json!({"status": "Pulling from library/rust", "id": "1.80"}),
+            serde_json::json!({"status": "Pulling fs layer", "id": "a"}),
+            serde_json::json!({"status": "Already exists", "id": "b"}),
+            serde_json::json!({
+                "status": "Downloading",
+                "id": "a",
+                "progressDetail": {"current": 1_000_000, "total": 4_000_000}
+            }),
+        ] {
+            progress.update(&event);
+        }
+        assert_eq!(progress.line(), "1/2 layers  1.0/4.0 MB (25%)");
 
-    if !status.success() {
-        return Ok(status.code().unwrap_or(1));
+        progress.update(&serde_json::json!({"status": "Pull complete", "id": "a"}));
+        assert_eq!(progress.line(), "2/2 layers  4.0/4.0 MB (100%)");
     }
 
-    attach_container(name)
-}
+    #[test]
+    fn test_build_run_args_network() {
+        let args = build_run_args(&default_config()).unwrap();
+        assert!(!args.contains(&"--network".to_string()));
 
-pub fn attach_container(name: &str) -> Result<i32> {
-    let mut child = Command::new("docker")
-        .args(["attach", &format!("box-{}", name)])
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .spawn()?;
+        let args = build_run_args(&DockerRunConfig {
+            network: Some("none"),
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--network" && w[1] == "none"));
+    }
 
-    // After attaching, the container's PTY may retain stale dimensions from a
-    // previous session. Send SIGWINCH to the docker-attach process after a
-    // short delay so Docker re-reads the current terminal size and pushes a
-    // resize event to the container. This eliminates the need for a manual
-    // pane resize to recover rendering.
-    #[cfg(unix)]
-    {
-        let pid = child.id();
-        std::thread::spawn(move || {
-            std::thread::sleep(std::time::Duration::from_millis(200));
-            unsafe {
-                libc::kill(pid as libc::pid_t, libc::SIGWINCH);
-            }
+    #[test]
+    fn test_build_run_args_caches() {
+        let caches = vec!["cargo:/root/.cargo/registry".to_string()];
+        let args = build_run_args(&DockerRunConfig {
+            caches: &caches,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "box-cache-cargo:/root/.cargo/registry"));
+    }
+
+    #[test]
+    fn test_format_port_bindings() {
+        let ports = serde_json::json!({
+            "80/tcp": [
+                { "HostIp": "0.0.0.0", "HostPort": "8080" },
+                { "HostIp": "::", "HostPort": "8080" }
+            ],
+            "9000/tcp": null
         });
+        assert_eq!(
+            format_port_bindings(&ports),
+            vec!["80/tcp -> 0.0.0.0:8080", "80/tcp -> [::]:8080"]
+        );
     }
 
-    let status = child.wait()?;
-    restore_terminal();
+    #[test]
+    fn test_build_run_args_limits_before_docker_args() {
+        let args = build_run_args(&DockerRunConfig {
+            cpus: Some("2"),
+            memory: Some("4g"),
+            docker_args: Some("--memory 8g"),
+            ..default_config()
+        })
+        .unwrap();
+        let cpus = args.iter().position(|a| a == "--cpus").unwrap();
+        assert_eq!(args[cpus + 1], "2");
+        let memory = args.iter().position(|a| a == "--memory").unwrap();
+        assert_eq!(args[memory + 1], "4g");
+        // Explicit docker args come later so they win
+        let extra = args.iter().rposition(|a| a == "--memory").unwrap();
+        assert!(extra > memory);
+    }
 
-    Ok(status.code().unwrap_or(1))
-}
+    #[test]
+    fn test_build_run_args_worktree_mounts_project_git() {
+        let args = build_run_args(&DockerRunConfig {
+            worktree: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "/tmp/project/.git:/tmp/project/.git"));
 
-pub fn exec_container(name: &str, cmd: &[String]) -> Result<i32> {
-    let mut args = vec![
-        "exec".to_string(),
-        "-it".to_string(),
-        format!("box-{}", name),
-    ];
-    args.extend(cmd.iter().cloned());
+        let args = build_run_args(&default_config()).unwrap();
+        assert!(!args.iter().any(|a| a.contains("/tmp/project/.git")));
+    }
 
-    let status = Command::new("docker")
-        .args(&args)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
-    restore_terminal();
+    #[test]
+    fn test_ensure_workspace_worktree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        std::fs::create_dir_all(&project).unwrap();
+        git(&project, &["init", "-q"]);
+        git(
+            &project,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        );
 
-    Ok(status.code().unwrap_or(1))
-}
+        let ws = tmp.path().join("workspaces/sess");
+        ensure_workspace(
+            ws.to_str().unwrap(),
+            project.to_str().unwrap(),
+            true,
+            false,
+            None,
+            None,
+            CloneOptions::default(),
+        )
+        .unwrap();
+        assert!(ws.join(".git").is_file());
+        assert_eq!(
+            worktree_common_dir(&ws).unwrap().canonicalize().unwrap(),
+            project.join(".git").canonicalize().unwrap()
+        );
+    }
 
-pub fn start_container_detached(name: &str) -> Result<i32> {
-    let status = Command::new("docker")
-        .args(["start", &format!("box-{}", name)])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
+    #[test]
+    fn test_ensure_workspace_branch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        let git = |dir: &Path, args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stderr(std::process::Stdio::null())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        std::fs::create_dir_all(&project).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        git(&project, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        let first = git(&project, &["rev-parse", "HEAD"]);
+        git(&project, &["checkout", "-q", "-b", "feature"]);
+        git(
+            &project,
+            &["commit", "-q", "--allow-empty", "-m", "feature"],
+        );
+        let feature = git(&project, &["rev-parse", "HEAD"]);
+        git(&project, &["checkout", "-q", "main"]);
+
+        let ensure = |name: &str, worktree: bool, branch: &str| {
+            let ws = tmp.path().join("workspaces").join(name);
+            ensure_workspace(
+                ws.to_str().unwrap(),
+                project.to_str().unwrap(),
+                worktree,
+                false,
+                Some(branch),
+                None,
+                CloneOptions::default(),
+            )
+            .unwrap();
+            ws
+        };
+
+        let ws = ensure("clone", false, "feature");
+        assert_eq!(git(&ws, &["rev-parse", "HEAD"]), feature);
+        assert_eq!(git(&ws, &["branch", "--show-current"]), "feature");
+
+        let ws = ensure("commit", false, &first);
+        assert_eq!(git(&ws, &["rev-parse", "HEAD"]), first);
+
+        let ws = ensure("worktree", true, "feature");
+        assert_eq!(git(&ws, &["branch", "--show-current"]), "feature");
+
+        let ws = ensure("detached", true, &first);
+        assert_eq!(git(&ws, &["rev-parse", "HEAD"]), first);
+        assert_eq!(git(&ws, &["branch", "--show-current"]), "");
+    }
 
-    if status.success() {
-        println!("Container box-{} started in background.", name);
-        println!("Run `box {}` to attach.", name);
-        Ok(0)
-    } else {
-        Ok(status.code().unwrap_or(1))
+    #[test]
+    fn test_ensure_workspace_url() {
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = tmp.path().join("remote");
+        let git = |dir: &Path, args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stderr(std::process::Stdio::null())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        std::fs::create_dir_all(&remote).unwrap();
+        git(&remote, &["init", "-q", "-b", "main"]);
+        git(&remote, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&remote, &["branch", "feature"]);
+        let url = format!("file://{}", remote.display());
+
+        // The project dir isn't a repository; everything comes from the URL
+        let ws = tmp.path().join("workspaces/sess");
+        ensure_workspace(
+            ws.to_str().unwrap(),
+            tmp.path().to_str().unwrap(),
+            false,
+            false,
+            Some("feature"),
+            Some(&url),
+            CloneOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(git(&ws, &["remote", "get-url", "origin"]), url);
+        assert_eq!(git(&ws, &["branch", "--show-current"]), "feature");
     }
-}
 
-pub fn stop_container(name: &str) -> Result<i32> {
-    let status = Command::new("docker")
-        .args(["stop", &format!("box-{}", name)])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::inherit())
-        .status()?;
+    #[test]
+    fn test_ensure_workspace_shallow_partial() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        let git = |dir: &Path, args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stderr(std::process::Stdio::null())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        std::fs::create_dir_all(&project).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        for msg in ["one", "two", "three"] {
+            git(&project, &["commit", "-q", "--allow-empty", "-m", msg]);
+        }
+        git(&project, &["branch", "feature", "HEAD~1"]);
+
+        let ws = tmp.path().join("workspaces/sess");
+        ensure_workspace(
+            ws.to_str().unwrap(),
+            project.to_str().unwrap(),
+            false,
+            false,
+            Some("feature"),
+            None,
+            CloneOptions {
+                depth: Some(1),
+                filter: Some("blob:none"),
+            },
+        )
+        .unwrap();
+        assert_eq!(git(&ws, &["rev-parse", "--is-shallow-repository"]), "true");
+        assert_eq!(git(&ws, &["rev-list", "--count", "HEAD"]), "1");
+        assert_eq!(git(&ws, &["branch", "--show-current"]), "feature");
+        assert_eq!(
+            git(&ws, &["config", "remote.origin.partialclonefilter"]),
+            "blob:none"
+        );
+    }
 
-    if status.success() {
-        println!("Session '{}' stopped.", name);
-        Ok(0)
-    } else {
-        Ok(status.code().unwrap_or(1))
+    #[test]
+    fn test_fetch_lfs_and_submodules() {
+        let tmp = tempfile::tempdir().unwrap();
+        let git = |dir: &Path, args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(["-c", "protocol.file.allow=always"])
+                .args(args)
+                .stderr(std::process::Stdio::null())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        let lib = tmp.path().join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        git(&lib, &["init", "-q", "-b", "main"]);
+        std::fs::write(lib.join("lib.txt"), "lib").unwrap();
+        git(&lib, &["add", "lib.txt"]);
+        git(&lib, &["commit", "-q", "-m", "lib"]);
+        let project = tmp.path().join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        git(
+            &project,
+            &["submodule", "add", "-q", lib.to_str().unwrap(), "lib"],
+        );
+        git(&project, &["commit", "-q", "-m", "add lib"]);
+
+        // git refuses local-path submodules unless file:// is allowed
+        std::env::set_var("GIT_CONFIG_COUNT", "1");
+        std::env::set_var("GIT_CONFIG_KEY_0", "protocol.file.allow");
+        std::env::set_var("GIT_CONFIG_VALUE_0", "always");
+        let ensure = |name: &str, submodules: bool| {
+            let ws = tmp.path().join("workspaces").join(name);
+            git(
+                tmp.path(),
+                &[
+                    "clone",
+                    "-q",
+                    project.to_str().unwrap(),
+                    ws.to_str().unwrap(),
+                ],
+            );
+            fetch_lfs_and_submodules(ws.to_str().unwrap(), true, submodules);
+            ws
+        };
+        let with = ensure("with", true);
+        let without = ensure("without", false);
+        std::env::remove_var("GIT_CONFIG_COUNT");
+
+        assert!(with.join("lib/lib.txt").is_file());
+        assert!(!without.join("lib/lib.txt").exists());
     }
-}
 
-pub fn remove_container(name: &str) {
-    let _ = Command::new("docker")
-        .args(["rm", "-f", &format!("box-{}", name)])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .status();
-}
+    #[test]
+    fn test_ensure_workspace_copy_untracked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path().join("project");
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(dir)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        std::fs::create_dir_all(project.join("local-settings/dev")).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        std::fs::write(project.join(".gitignore"), ".env\nlocal-settings/\n").unwrap();
+        git(&project, &["add", ".gitignore"]);
+        git(&project, &["commit", "-q", "-m", "init"]);
+        std::fs::write(
+            project.join(config::REPO_CONFIG_FILE),
+            "copy_untracked = [\".env\", \"local-settings/**\"]\n",
+        )
+        .unwrap();
+        std::fs::write(project.join(".env"), "TOKEN=x").unwrap();
+        std::fs::write(project.join("local-settings/dev/app.json"), "{}").unwrap();
+        std::fs::write(project.join("scratch.txt"), "").unwrap();
+
+        for (name, worktree) in [("clone", false), ("worktree", true)] {
+            let ws = tmp.path().join("workspaces").join(name);
+            ensure_workspace(
+                ws.to_str().unwrap(),
+                project.to_str().unwrap(),
+                worktree,
+                false,
+                None,
+                None,
+                CloneOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(std::fs::read_to_string(ws.join(".env")).unwrap(), "TOKEN=x");
+            assert!(ws.join("local-settings/dev/app.json").is_file());
+            assert!(!ws.join("scratch.txt").exists());
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_worktree_common_dir_for_clone_is_none() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join(".git")).unwrap();
+        assert!(worktree_common_dir(tmp.path()).is_none());
+    }
 
-    fn default_config<'a>() -> DockerRunConfig<'a> {
-        DockerRunConfig {
-            name: "sess",
-            project_dir: "/tmp/project",
-            image: "alpine:latest",
-            mount_path: "/workspace",
-            cmd: &[],
-            env: &[],
-            home: "/home/user",
-            docker_args: None,
-            ssh: false,
-            detach: false,
-        }
+    #[test]
+    fn test_workspace_size() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+        std::fs::write(tmp.path().join("top.txt"), "12345").unwrap();
+        std::fs::write(tmp.path().join("a/b/deep.txt"), "123").unwrap();
+        assert_eq!(workspace_size(tmp.path()), 8);
+        assert_eq!(workspace_size(&tmp.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_copy_workspace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("sub/file.txt"), "dirty").unwrap();
+
+        let dst = tmp.path().join("nested/dst");
+        copy_workspace(&src, &dst).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dst.join("sub/file.txt")).unwrap(),
+            "dirty"
+        );
+
+        let err = copy_workspace(&src, &dst).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
     }
 
     #[test]
     fn test_build_run_args_basic() {
         let args = build_run_args(&DockerRunConfig {
             name: "test-session",
+            workspace: "/home/user/.box/workspaces/test-session",
             ..default_config()
         })
         .unwrap();
@@ -614,6 +3707,54 @@ mod tests {
         assert!(!args.contains(&"-it".to_string()));
     }
 
+    #[test]
+    fn test_build_run_args_detach_keys() {
+        let args = build_run_args(&DockerRunConfig {
+            detach_keys: Some("ctrl-x,x"),
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(&args[1..4], ["-it", "--detach-keys", "ctrl-x,x"]);
+
+        // Meaningless without an attached terminal
+        let args = build_run_args(&DockerRunConfig {
+            detach: true,
+            detach_keys: Some("ctrl-x,x"),
+            ..default_config()
+        })
+        .unwrap();
+        assert!(!args.contains(&"--detach-keys".to_string()));
+    }
+
+    #[test]
+    fn test_build_run_args_detached_tty() {
+        let args = build_run_args(&DockerRunConfig {
+            detach: true,
+            tty: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(&args[1..3], ["-d", "-it"]);
+    }
+
+    #[test]
+    fn test_build_run_args_rm() {
+        let args = build_run_args(&DockerRunConfig {
+            rm: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(&args[1..3], ["--rm", "-i"]);
+
+        let args = build_run_args(&DockerRunConfig {
+            rm: true,
+            tty: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(&args[1..3], ["--rm", "-it"]);
+    }
+
     #[test]
     fn test_build_run_args_detached_with_command() {
         let cmd = vec!["sleep".to_string(), "60".to_string()];
@@ -660,4 +3801,74 @@ mod tests {
             format!("SSH_AUTH_SOCK={}", SSH_CONTAINER_PATH)
         );
     }
+
+    #[test]
+    fn test_gpg_mounts() {
+        let home = tempfile::tempdir().unwrap();
+        std::fs::write(home.path().join("pubring.kbx"), "").unwrap();
+        let agent = GpgAgent {
+            socket: "/run/user/1000/gnupg/S.gpg-agent.extra".to_string(),
+            home: home.path().display().to_string(),
+        };
+        let args = gpg_mounts(&default_config(), &agent);
+        assert_eq!(
+            args,
+            [
+                "-v".to_string(),
+                "/run/user/1000/gnupg/S.gpg-agent.extra:/run/gnupg/S.gpg-agent".to_string(),
+                "-v".to_string(),
+                format!("{}/pubring.kbx:/run/gnupg/pubring.kbx:ro", agent.home),
+                "-e".to_string(),
+                "GNUPGHOME=/run/gnupg".to_string(),
+                "-e".to_string(),
+                "GPG_TTY=/dev/console".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_git_auth_mounts() {
+        let args = git_auth_mounts(&DockerRunConfig {
+            git_auth: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert_eq!(args[0], "-v");
+        assert!(args[1].ends_with("/git-auth:/run/box-git:ro"));
+        assert!(args.contains(&"GIT_ASKPASS=/run/box-git/askpass".to_string()));
+        assert!(args.contains(&"GIT_CONFIG_VALUE_0=".to_string()));
+    }
+
+    #[test]
+    fn test_is_root_user() {
+        for user in ["", "root", "0", "0:0", "root:wheel"] {
+            assert!(is_root_user(user), "{}", user);
+        }
+        for user in ["node", "1000", "1000:1000", "rooty"] {
+            assert!(!is_root_user(user), "{}", user);
+        }
+    }
+
+    #[test]
+    fn test_build_run_args_docker_access() {
+        let args = build_run_args(&DockerRunConfig {
+            docker_socket: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "/var/run/docker.sock:/var/run/docker.sock"));
+        assert!(!args.contains(&"--privileged".to_string()));
+
+        let args = build_run_args(&DockerRunConfig {
+            dind: true,
+            ..default_config()
+        })
+        .unwrap();
+        assert!(args.contains(&"--privileged".to_string()));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "box-dind-sess:/var/lib/docker"));
+    }
 }
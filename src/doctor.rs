@@ -0,0 +1,252 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::docker::{self, DockerStatus};
+use crate::storage;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    Warn,
+    Error,
+}
+
+/// Result of one `box doctor` check.
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub level: Level,
+    pub check: &'static str,
+    pub detail: String,
+    /// What to do about a warning or error.
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    pub fn ok(check: &'static str, detail: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Ok,
+            check,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    pub fn warn(check: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Warn,
+            check,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    pub fn error(check: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Error,
+            check,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    /// Render as `ok    check        detail`, with the fix on the next line.
+    pub fn render(&self) -> String {
+        let label = match self.level {
+            Level::Ok => "\x1b[32mok\x1b[0m   ",
+            Level::Warn => "\x1b[33mwarn\x1b[0m ",
+            Level::Error => "\x1b[31merror\x1b[0m",
+        };
+        let mut out = format!("{} {:<12} {}", label, self.check, self.detail);
+        if let Some(fix) = &self.fix {
+            out.push_str(&format!("\n{:<19}\x1b[2mfix:\x1b[0m {}", "", fix));
+        }
+        out
+    }
+}
+
+/// Docker CLI and daemon availability, with their versions.
+pub fn check_docker(status: &DockerStatus) -> Finding {
+    match status {
+        DockerStatus::Missing => Finding::error(
+            "docker",
+            "docker CLI not found",
+            "install Docker Desktop, OrbStack or Docker Engine: https://docs.docker.com/get-docker/",
+        ),
+        DockerStatus::DaemonDown { client } => Finding::error(
+            "docker",
+            format!("client {}, but the daemon is not reachable", client),
+            "start Docker (open Docker Desktop/OrbStack, or `sudo systemctl start docker`)",
+        ),
+        DockerStatus::Ready { client, server } => Finding::ok(
+            "docker",
+            format!("client {}, server {}", client, server),
+        ),
+    }
+}
+
+/// SSH agent forwarding: the host agent from `SSH_AUTH_SOCK`, and when docker
+/// runs in a VM, the socket the VM exposes to containers.
+pub fn check_ssh_agent(docker_ready: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    match std::env::var("SSH_AUTH_SOCK") {
+        Ok(sock) if !sock.is_empty() => {
+            let finding = if !is_socket(Path::new(&sock)) {
+                Finding::warn(
+                    "ssh agent",
+                    format!("SSH_AUTH_SOCK={} is not a socket", sock),
+                    "restart your SSH agent, or disable forwarding with `ssh = false`",
+                )
+            } else {
+                ssh_add_finding(&sock)
+            };
+            findings.push(finding);
+        }
+        _ => findings.push(Finding::warn(
+            "ssh agent",
+            "SSH_AUTH_SOCK is not set",
+            "start an agent (`eval \"$(ssh-agent)\" && ssh-add`), or disable forwarding with `ssh = false`",
+        )),
+    }
+
+    let runtime = docker::runtime();
+    if runtime.is_vm() && docker_ready {
+        findings.push(match docker::ssh_agent_paths() {
+            Ok((host, _)) if docker::socket_visible_to_containers(&host) => {
+                Finding::ok("ssh forward", format!("{} via {}", host, runtime))
+            }
+            Ok((host, _)) => Finding::warn(
+                "ssh forward",
+                format!("{} is not available to containers in {}", host, runtime),
+                runtime.ssh_forward_hint(),
+            ),
+            Err(e) => Finding::warn("ssh forward", e.to_string(), runtime.ssh_forward_hint()),
+        });
+    }
+    findings
+}
+
+fn ssh_add_finding(sock: &str) -> Finding {
+    let code = Command::new("ssh-add")
+        .arg("-l")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .ok()
+        .and_then(|s| s.code());
+    match code {
+        Some(0) => Finding::ok("ssh agent", format!("{} has keys loaded", sock)),
+        Some(1) => Finding::warn(
+            "ssh agent",
+            format!("{} has no keys loaded", sock),
+            "add a key with `ssh-add`",
+        ),
+        Some(_) => Finding::error(
+            "ssh agent",
+            format!("cannot connect to the agent at {}", sock),
+            "restart your SSH agent and open a new shell",
+        ),
+        None => Finding::ok("ssh agent", sock),
+    }
+}
+
+/// The data directories exist (or can be created) and are writable.
+pub fn check_storage() -> Vec<Finding> {
+    let dirs = [
+        ("data dir", storage::data_dir()),
+        ("workspaces", storage::workspaces_dir()),
+    ];
+    dirs.into_iter()
+        .map(|(check, dir)| match dir {
+            Ok(dir) => check_writable(check, &dir),
+            Err(e) => Finding::error(check, e.to_string(), "set HOME or XDG_DATA_HOME"),
+        })
+        .collect()
+}
+
+fn check_writable(check: &'static str, dir: &Path) -> Finding {
+    if dir.is_dir() {
+        if writable(dir) {
+            Finding::ok(check, dir.display().to_string())
+        } else {
+            Finding::error(
+                check,
+                format!("{} is not writable", dir.display()),
+                format!("sudo chown -R \"$(id -u):$(id -g)\" {}", dir.display()),
+            )
+        }
+    } else if dir.exists() {
+        Finding::error(
+            check,
+            format!("{} is not a directory", dir.display()),
+            format!("move {} out of the way", dir.display()),
+        )
+    } else {
+        // Created on first use; the nearest existing ancestor must be writable.
+        match dir.ancestors().find(|p| p.exists()) {
+            Some(parent) if !writable(parent) => Finding::error(
+                check,
+                format!("{} can't be created", dir.display()),
+                format!("sudo chown \"$(id -u):$(id -g)\" {}", parent.display()),
+            ),
+            _ => Finding::ok(check, format!("{} (not created yet)", dir.display())),
+        }
+    }
+}
+
+fn writable(dir: &Path) -> bool {
+    let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+}
+
+fn is_socket(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path)
+        .map(|m| m.file_type().is_socket())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_docker() {
+        assert_eq!(check_docker(&DockerStatus::Missing).level, Level::Error);
+        let down = check_docker(&DockerStatus::DaemonDown {
+            client: "27.1.1".to_string(),
+        });
+        assert_eq!(down.level, Level::Error);
+        assert!(down.detail.contains("27.1.1"));
+        let ready = check_docker(&DockerStatus::Ready {
+            client: "27.1.1".to_string(),
+            server: "26.0.0".to_string(),
+        });
+        assert_eq!(ready, Finding::ok("docker", "client 27.1.1, server 26.0.0"));
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(check_writable("dir", tmp.path()).level, Level::Ok);
+        let missing = tmp.path().join("a/b");
+        let finding = check_writable("dir", &missing);
+        assert_eq!(finding.level, Level::Ok);
+        assert!(finding.detail.contains("not created yet"));
+        let file = tmp.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(check_writable("dir", &file).level, Level::Error);
+    }
+
+    #[test]
+    fn test_render() {
+        let finding = Finding::warn("ssh agent", "no keys", "ssh-add");
+        let out = finding.render();
+        assert!(out.contains("ssh agent    no keys"));
+        assert!(out.lines().nth(1).unwrap().ends_with("fix:\x1b[0m ssh-add"));
+        assert!(!Finding::ok("docker", "27").render().contains('\n'));
+    }
+}
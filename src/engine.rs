@@ -0,0 +1,527 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config;
+
+/// Minimal Docker Engine API client speaking HTTP/1.1 over the daemon's Unix socket.
+///
+/// Only used for cheap status queries, lifecycle calls (inspect, ps, stop, rm)
+/// and image pulls, whose progress stream the CLI doesn't expose.
+/// Interactive paths (run, attach, exec) keep using the `docker` CLI.
+pub struct Client {
+    socket: PathBuf,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json(&self) -> Result<Value> {
+        serde_json::from_slice(&self.body).context("Invalid JSON from Docker daemon")
+    }
+
+    /// Turn a non-2xx response into an error carrying the daemon's message.
+    fn error(&self) -> anyhow::Error {
+        let message = serde_json::from_slice::<Value>(&self.body)
+            .ok()
+            .and_then(|v| v["message"].as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| String::from_utf8_lossy(&self.body).trim().to_string());
+        anyhow::anyhow!("Docker API error ({}): {}", self.status, message)
+    }
+}
+
+impl Client {
+    /// Locate a local daemon socket. Returns `None` when the CLI should be used
+    /// instead, e.g. for TCP/SSH `DOCKER_HOST`s or non-default docker contexts.
+    pub fn detect() -> Option<Client> {
+        let socket = socket_path()?;
+        if UnixStream::connect(&socket).is_err() {
+            return None;
+        }
+        Some(Client { socket })
+    }
+
+    pub fn request(&self, method: &str, path: &str) -> Result<Response> {
+        self.request_within(method, path, Duration::from_secs(60))
+    }
+
+    /// Like `request`, for calls the daemon may take longer than a minute to answer.
+    pub fn request_within(&self, method: &str, path: &str, timeout: Duration) -> Result<Response> {
+        let mut stream = UnixStream::connect(&self.socket)
+            .with_context(|| format!("Failed to connect to {}", self.socket.display()))?;
+        stream.set_read_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: docker\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            method, path
+        )?;
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw)?;
+        parse_response(&raw)
+    }
+
+    /// Inspect a container. Returns `Ok(None)` if it does not exist.
+    pub fn inspect_container(&self, id: &str) -> Result<Option<Value>> {
+        let resp = self.request("GET", &format!("/containers/{}/json", id))?;
+        match resp.status {
+            200 => Ok(Some(resp.json()?)),
+            404 => Ok(None),
+            _ => Err(resp.error()),
+        }
+    }
+
+    /// List containers whose name contains `name_filter`, optionally only those in `status`.
+    pub fn list_containers(
+        &self,
+        all: bool,
+        name_filter: &str,
+        status: Option<&str>,
+    ) -> Result<Vec<Value>> {
+        let mut filters = serde_json::json!({ "name": [name_filter] });
+        if let Some(status) = status {
+            filters["status"] = serde_json::json!([status]);
+        }
+        let filters = filters.to_string();
+        let path = format!(
+            "/containers/json?all={}&filters={}",
+            if all { 1 } else { 0 },
+            percent_encode(&filters)
+        );
+        let resp = self.request("GET", &path)?;
+        if resp.status != 200 {
+            return Err(resp.error());
+        }
+        match resp.json()? {
+            Value::Array(items) => Ok(items),
+            _ => bail!("Unexpected response from Docker daemon"),
+        }
+    }
+
+    /// Stop a container, killing it after `timeout` seconds (the container's
+    /// own stop timeout when `None`).
+    pub fn stop_container(&self, id: &str, timeout: Option<u32>) -> Result<()> {
+        let resp = match timeout {
+            Some(t) => self.request_within(
+                "POST",
+                &format!("/containers/{}/stop?t={}", id, t),
+                Duration::from_secs(60 + u64::from(t)),
+            )?,
+            None => self.request("POST", &format!("/containers/{}/stop", id))?,
+        };
+        match resp.status {
+            // 304: already stopped
+            204 | 304 => Ok(()),
+            _ => Err(resp.error()),
+        }
+    }
+
+    pub fn kill_container(&self, id: &str) -> Result<()> {
+        let resp = self.request("POST", &format!("/containers/{}/kill", id))?;
+        match resp.status {
+            204 => Ok(()),
+            _ => Err(resp.error()),
+        }
+    }
+
+    pub fn pause_container(&self, id: &str) -> Result<()> {
+        let resp = self.request("POST", &format!("/containers/{}/pause", id))?;
+        match resp.status {
+            204 => Ok(()),
+            _ => Err(resp.error()),
+        }
+    }
+
+    pub fn unpause_container(&self, id: &str) -> Result<()> {
+        let resp = self.request("POST", &format!("/containers/{}/unpause", id))?;
+        match resp.status {
+            204 => Ok(()),
+            _ => Err(resp.error()),
+        }
+    }
+
+    /// Pull `image`, calling `on_event` with each progress message as it arrives.
+    pub fn pull_image(&self, image: &str, on_event: &mut dyn FnMut(&Value)) -> Result<()> {
+        let (name, tag) = split_image_ref(image);
+        let mut stream = UnixStream::connect(&self.socket)
+            .with_context(|| format!("Failed to connect to {}", self.socket.display()))?;
+        stream.set_read_timeout(Some(Duration::from_secs(300)))?;
+        write!(
+            stream,
+            "POST /images/create?fromImage={}&tag={} HTTP/1.1\r\nHost: docker\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            percent_encode(name),
+            percent_encode(tag)
+        )?;
+
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                bail!("Malformed HTTP response from Docker daemon");
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| anyhow::anyhow!("Malformed HTTP status line from Docker daemon"))?;
+        let chunked = head.lines().any(|l| {
+            let l = l.to_ascii_lowercase();
+            l.starts_with("transfer-encoding:") && l.contains("chunked")
+        });
+        if status != 200 {
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest)?;
+            let body = if chunked {
+                decode_chunked(&rest)?
+            } else {
+                rest
+            };
+            return Err(Response { status, body }.error());
+        }
+
+        // The body is a stream of newline-separated JSON messages, possibly
+        // split across chunks.
+        let mut pending = Vec::new();
+        loop {
+            if chunked {
+                let mut size_line = String::new();
+                if reader.read_line(&mut size_line)? == 0 {
+                    break;
+                }
+                let size_str = size_line.split(';').next().unwrap_or("").trim();
+                let size = usize::from_str_radix(size_str, 16)
+                    .map_err(|_| anyhow::anyhow!("Invalid chunk size '{}'", size_str))?;
+                if size == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk)?;
+                pending.extend_from_slice(&chunk[..size]);
+            } else {
+                let mut buf = [0; 8192];
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                pending.extend_from_slice(&buf[..n]);
+            }
+            while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=pos).collect();
+                handle_pull_message(&line, on_event)?;
+            }
+        }
+        handle_pull_message(&pending, on_event)
+    }
+
+    pub fn remove_container(&self, id: &str, force: bool) -> Result<()> {
+        let path = format!(
+            "/containers/{}?force={}",
+            id,
+            if force { "true" } else { "false" }
+        );
+        let resp = self.request("DELETE", &path)?;
+        match resp.status {
+            204 => Ok(()),
+            _ => Err(resp.error()),
+        }
+    }
+}
+
+fn handle_pull_message(line: &[u8], on_event: &mut dyn FnMut(&Value)) -> Result<()> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let message: Value = serde_json::from_slice(line).context("Invalid JSON from Docker daemon")?;
+    if let Some(error) = message["error"].as_str() {
+        bail!("{}", error);
+    }
+    on_event(&message);
+    Ok(())
+}
+
+/// Split an image reference into the `fromImage` and `tag` pull parameters.
+/// A digest goes in `tag`; a missing tag means `latest`.
+fn split_image_ref(image: &str) -> (&str, &str) {
+    if let Some((name, digest)) = image.split_once('@') {
+        return (name, digest);
+    }
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image, "latest"),
+    }
+}
+
+/// Strip the leading `/` Docker puts in front of container names.
+pub fn container_names(container: &Value) -> Vec<String> {
+    container["Names"]
+        .as_array()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|n| n.as_str())
+                .map(|n| n.trim_start_matches('/').to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn socket_path() -> Option<PathBuf> {
+    match std::env::var("DOCKER_HOST") {
+        Ok(host) if !host.is_empty() => return host.strip_prefix("unix://").map(PathBuf::from),
+        _ => {}
+    }
+    match std::env::var("DOCKER_CONTEXT") {
+        Ok(ctx) if !ctx.is_empty() && ctx != "default" => return None,
+        _ => {}
+    }
+    let home = config::home_dir().ok()?;
+    if current_context(Path::new(&home)).is_some() {
+        return None;
+    }
+    [
+        PathBuf::from("/var/run/docker.sock"),
+        Path::new(&home).join(".docker/run/docker.sock"),
+        Path::new(&home).join(".orbstack/run/docker.sock"),
+    ]
+    .into_iter()
+    .find(|p| p.exists())
+}
+
+/// Return the non-default docker context selected in `~/.docker/config.json`, if any.
+fn current_context(home: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(home.join(".docker/config.json")).ok()?;
+    let value: Value = serde_json::from_str(&content).ok()?;
+    match value["currentContext"].as_str() {
+        Some(ctx) if !ctx.is_empty() && ctx != "default" => Some(ctx.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response> {
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Malformed HTTP response from Docker daemon"))?;
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Malformed HTTP status line from Docker daemon"))?;
+    let chunked = lines.any(|l| {
+        let l = l.to_ascii_lowercase();
+        l.starts_with("transfer-encoding:") && l.contains("chunked")
+    });
+    let body = &raw[header_end + 4..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow::anyhow!("Malformed chunked response"))?;
+        let size_str = String::from_utf8_lossy(&data[..line_end]);
+        let size_str = size_str.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .map_err(|_| anyhow::anyhow!("Invalid chunk size '{}'", size_str))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if data.len() < size {
+            bail!("Truncated chunked response");
+        }
+        out.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or(&[]);
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_parse_response_content_length() {
+        let raw =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n[]";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"[]");
+    }
+
+    #[test]
+    fn test_parse_response_chunked() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\n\r\n";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.body, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn test_parse_response_malformed() {
+        assert!(parse_response(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_error_uses_daemon_message() {
+        let resp = Response {
+            status: 404,
+            body: br#"{"message":"No such container: box-x"}"#.to_vec(),
+        };
+        assert_eq!(
+            resp.error().to_string(),
+            "Docker API error (404): No such container: box-x"
+        );
+    }
+
+    #[test]
+    fn test_split_image_ref() {
+        assert_eq!(split_image_ref("alpine"), ("alpine", "latest"));
+        assert_eq!(split_image_ref("rust:1.80"), ("rust", "1.80"));
+        assert_eq!(
+            split_image_ref("localhost:5000/team/app"),
+            ("localhost:5000/team/app", "latest")
+        );
+        assert_eq!(
+            split_image_ref("localhost:5000/team/app:v2"),
+            ("localhost:5000/team/app", "v2")
+        );
+        assert_eq!(
+            split_image_ref("alpine@sha256:abc"),
+            ("alpine", "sha256:abc")
+        );
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(
+            percent_encode(r#"{"name":["box-"]}"#),
+            "%7B%22name%22%3A%5B%22box-%22%5D%7D"
+        );
+    }
+
+    #[test]
+    fn test_container_names_strips_slash() {
+        let value = serde_json::json!({ "Names": ["/box-a", "/other"] });
+        assert_eq!(container_names(&value), vec!["box-a", "other"]);
+    }
+
+    #[test]
+    fn test_current_context() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(current_context(tmp.path()), None);
+
+        std::fs::create_dir_all(tmp.path().join(".docker")).unwrap();
+        std::fs::write(
+            tmp.path().join(".docker/config.json"),
+            r#"{"currentContext":"orbstack"}"#,
+        )
+        .unwrap();
+        assert_eq!(current_context(tmp.path()), Some("orbstack".to_string()));
+
+        std::fs::write(
+            tmp.path().join(".docker/config.json"),
+            r#"{"currentContext":"default"}"#,
+        )
+        .unwrap();
+        assert_eq!(current_context(tmp.path()), None);
+    }
+
+    #[test]
+    fn test_request_over_unix_socket() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("docker.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let n = conn.read(&mut buf).unwrap();
+            let req = String::from_utf8_lossy(&buf[..n]).to_string();
+            conn.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 38\r\n\r\n{\"message\":\"No such container: box-x\"}",
+            )
+            .unwrap();
+            req
+        });
+
+        let client = Client { socket };
+        let result = client.inspect_container("box-x").unwrap();
+        assert!(result.is_none());
+
+        let req = server.join().unwrap();
+        assert!(req.starts_with("GET /containers/box-x/json HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_pull_image_streams_messages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("docker.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let n = conn.read(&mut buf).unwrap();
+            let req = String::from_utf8_lossy(&buf[..n]).to_string();
+            // The second message is split across chunks
+            conn.write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  19\r\n{\"status\":\"Pulling\"}\n{\"id\r\n\
+                  1a\r\n\":\"a\",\"status\":\"Waiting\"}\n\r\n\
+                  0\r\n\r\n",
+            )
+            .unwrap();
+            req
+        });
+
+        let client = Client { socket };
+        let mut statuses = Vec::new();
+        client
+            .pull_image("rust:1.80", &mut |event| {
+                statuses.push(event["status"].as_str().unwrap_or("").to_string())
+            })
+            .unwrap();
+        assert_eq!(statuses, vec!["Pulling", "Waiting"]);
+
+        let req = server.join().unwrap();
+        assert!(req.starts_with("POST /images/create?fromImage=rust&tag=1.80 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_pull_message_error() {
+        let err = handle_pull_message(br#"{"error":"manifest unknown"}"#, &mut |_| {}).unwrap_err();
+        assert_eq!(err.to_string(), "manifest unknown");
+        assert!(handle_pull_message(b"  \n", &mut |_| panic!()).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config;
+use crate::session::Session;
+use crate::storage;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long `emit` waits for each hook before leaving it to finish on its
+/// own, so a slow hook can't hold up the command or the session manager.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A point in a session's life reported to the `events` hooks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Created,
+    Started,
+    Stopped,
+    Removed,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Created => "created",
+            Event::Started => "started",
+            Event::Stopped => "stopped",
+            Event::Removed => "removed",
+        }
+    }
+}
+
+/// Environment describing `event` on `sess`, passed to every event hook.
+fn env(event: Event, sess: &Session) -> Vec<(&'static str, String)> {
+    let workspace = storage::workspace_dir(&sess.name)
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default();
+    vec![
+        ("BOX_EVENT", event.as_str().to_string()),
+        ("BOX_SESSION", sess.name.clone()),
+        ("BOX_PROJECT_DIR", sess.project_dir.clone()),
+        ("BOX_IMAGE", sess.image.clone()),
+        ("BOX_WORKSPACE", workspace),
+        ("BOX_BRANCH", sess.branch.clone().unwrap_or_default()),
+        ("BOX_CONTAINER", format!("box-{}", sess.name)),
+        ("BOX_TIME", chrono::Utc::now().to_rfc3339()),
+    ]
+}
+
+/// Run `hook` with `sh -c` and `env`, waiting at most `timeout` for it.
+/// `Ok(None)` means it is still running; it is left to finish by itself.
+fn run_hook(
+    hook: &str,
+    env: &[(&'static str, String)],
+    timeout: Duration,
+) -> std::io::Result<Option<ExitStatus>> {
+    let mut child = Command::new("sh")
+        .args(["-c", hook])
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Run each configured event hook with `sh -c` on the host, in order. Their
+/// output is discarded so they can't disturb the session manager, and a
+/// failing hook is only reported: events never fail the command behind them.
+/// A hook still running after `HOOK_TIMEOUT` is left running in the
+/// background and the next one starts.
+pub fn emit(event: Event, sess: &Session) {
+    let hooks = match config::hooks(&sess.project_dir) {
+        Ok(hooks) => hooks.events,
+        Err(e) => {
+            eprintln!("\x1b[2mwarning:\x1b[0m {}", e);
+            return;
+        }
+    };
+    let env = env(event, sess);
+    for hook in hooks {
+        match run_hook(&hook, &env, HOOK_TIMEOUT) {
+            Ok(Some(s)) if s.success() => {}
+            Ok(None) => eprintln!(
+                "\x1b[2mwarning:\x1b[0m {} event hook still running after {}s, continuing: {}",
+                event.as_str(),
+                HOOK_TIMEOUT.as_secs(),
+                hook
+            ),
+            Ok(Some(s)) => eprintln!(
+                "\x1b[2mwarning:\x1b[0m {} event hook failed ({}): {}",
+                event.as_str(),
+                s,
+                hook
+            ),
+            Err(e) => eprintln!(
+                "\x1b[2mwarning:\x1b[0m {} event hook failed: {}: {}",
+                event.as_str(),
+                hook,
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env() {
+        let mut sess = Session::from(crate::config::BoxConfig {
+            name: "dev".to_string(),
+            project_dir: "/tmp/project".to_string(),
+            image: "alpine:latest".to_string(),
+            mount_path: "/workspace".to_string(),
+            command: vec![],
+            env: vec![],
+            ssh: false,
+            docker_args: String::new(),
+            worktree: false,
+        });
+        sess.branch = Some("feature".to_string());
+        let env = env(Event::Stopped, &sess);
+        let get = |key: &str| env.iter().find(|(k, _)| *k == key).unwrap().1.as_str();
+        assert_eq!(get("BOX_EVENT"), "stopped");
+        assert_eq!(get("BOX_SESSION"), "dev");
+        assert_eq!(get("BOX_PROJECT_DIR"), "/tmp/project");
+        assert_eq!(get("BOX_BRANCH"), "feature");
+        assert_eq!(get("BOX_CONTAINER"), "box-dev");
+        assert!(chrono::DateTime::parse_from_rfc3339(get("BOX_TIME")).is_ok());
+    }
+
+    #[test]
+    fn test_run_hook_timeout() {
+        let env = [("BOX_EVENT", "started".to_string())];
+        let status = run_hook("test \"$BOX_EVENT\" = started", &env, HOOK_TIMEOUT).unwrap();
+        assert!(status.unwrap().success());
+
+        let start = Instant::now();
+        let status = run_hook("sleep 5", &env, Duration::from_millis(100)).unwrap();
+        assert!(status.is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// Which side wins when a file changed on both sides since the last pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conflict {
+    /// The side whose copy was modified last.
+    Newer,
+    Host,
+    Workspace,
+}
+
+impl Conflict {
+    pub fn parse(s: &str) -> Option<Conflict> {
+        match s {
+            "newer" => Some(Conflict::Newer),
+            "host" => Some(Conflict::Host),
+            "workspace" => Some(Conflict::Workspace),
+            _ => None,
+        }
+    }
+}
+
+/// Size and modification time of a file, or `None` when it's absent.
+type Stamp = Option<(u64, SystemTime)>;
+
+/// Tracked and untracked, non-ignored files of the checkout at `dir`, so
+/// build output and dependencies stay on their own side.
+fn scan(dir: &Path) -> Result<BTreeMap<String, (u64, SystemTime)>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args([
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ])
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "git ls-files failed in {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let mut files = BTreeMap::new();
+    for path in String::from_utf8_lossy(&output.stdout).split('\0') {
+        if path.is_empty() || files.contains_key(path) {
+            continue;
+        }
+        // Deleted tracked files are still listed; submodules show up as directories
+        if let Ok(meta) = std::fs::symlink_metadata(dir.join(path)) {
+            if !meta.is_dir() {
+                let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.insert(path.to_string(), (meta.len(), mtime));
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn stamp(dir: &Path, path: &str) -> Stamp {
+    let meta = std::fs::symlink_metadata(dir.join(path)).ok()?;
+    Some((
+        meta.len(),
+        meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+    ))
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    match (std::fs::read(a), std::fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Make `to/path` match `from/path`: copy it over, or delete it when it's
+/// gone from `from`.
+fn apply(from: &Path, to: &Path, path: &str) -> Result<()> {
+    let (src, dst) = (from.join(path), to.join(path));
+    let meta = match std::fs::symlink_metadata(&src) {
+        Ok(meta) => meta,
+        Err(_) => {
+            if std::fs::symlink_metadata(&dst).is_ok() {
+                std::fs::remove_file(&dst)?;
+            }
+            return Ok(());
+        }
+    };
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if meta.file_type().is_symlink() {
+        let _ = std::fs::remove_file(&dst);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(std::fs::read_link(&src)?, &dst)?;
+        return Ok(());
+    }
+    std::fs::copy(&src, &dst)?;
+    Ok(())
+}
+
+/// Which way a file was copied (or deleted) by a pass.
+#[derive(Debug, PartialEq)]
+pub enum Direction {
+    ToWorkspace,
+    ToHost,
+}
+
+/// Two-way file sync between a host checkout and a session's workspace.
+/// Each pass compares both sides against what they looked like after the
+/// previous one and carries every change across. The first pass has nothing
+/// to compare against, so files differing on both sides go through
+/// `conflict`.
+pub struct Watcher {
+    host: PathBuf,
+    workspace: PathBuf,
+    conflict: Conflict,
+    last_host: BTreeMap<String, (u64, SystemTime)>,
+    last_workspace: BTreeMap<String, (u64, SystemTime)>,
+}
+
+impl Watcher {
+    pub fn new(host: &Path, workspace: &Path, conflict: Conflict) -> Watcher {
+        Watcher {
+            host: host.to_path_buf(),
+            workspace: workspace.to_path_buf(),
+            conflict,
+            last_host: BTreeMap::new(),
+            last_workspace: BTreeMap::new(),
+        }
+    }
+
+    /// Carry changes made since the last pass across, returning the paths
+    /// that were copied or deleted and in which direction.
+    pub fn pass(&mut self) -> Result<Vec<(Direction, String)>> {
+        let host = scan(&self.host)?;
+        let workspace = scan(&self.workspace)?;
+        let paths: BTreeSet<&String> = host
+            .keys()
+            .chain(workspace.keys())
+            .chain(self.last_host.keys())
+            .chain(self.last_workspace.keys())
+            .collect();
+
+        let mut synced = Vec::new();
+        for path in paths {
+            let (h, w) = (host.get(path).copied(), workspace.get(path).copied());
+            let host_changed = h != self.last_host.get(path).copied();
+            let workspace_changed = w != self.last_workspace.get(path).copied();
+            let direction = match (host_changed, workspace_changed) {
+                (false, false) => continue,
+                (true, false) => Direction::ToWorkspace,
+                (false, true) => Direction::ToHost,
+                (true, true) => {
+                    if h.is_none() && w.is_none()
+                        || same_contents(&self.host.join(path), &self.workspace.join(path))
+                    {
+                        continue;
+                    }
+                    self.resolve(h, w)
+                }
+            };
+            let (from, to) = match direction {
+                Direction::ToWorkspace => (&self.host, &self.workspace),
+                Direction::ToHost => (&self.workspace, &self.host),
+            };
+            apply(from, to, path)?;
+            synced.push((direction, path.clone()));
+        }
+
+        // Record what both sides look like now, including the copies just
+        // made, so they don't count as changes next time
+        self.last_host = host;
+        self.last_workspace = workspace;
+        for (_, path) in &synced {
+            for (dir, last) in [
+                (&self.host, &mut self.last_host),
+                (&self.workspace, &mut self.last_workspace),
+            ] {
+                match stamp(dir, path) {
+                    Some(s) => last.insert(path.clone(), s),
+                    None => last.remove(path),
+                };
+            }
+        }
+        Ok(synced)
+    }
+
+    fn resolve(&self, host: Stamp, workspace: Stamp) -> Direction {
+        match self.conflict {
+            Conflict::Host => Direction::ToWorkspace,
+            Conflict::Workspace => Direction::ToHost,
+            // A deletion loses to an edit on the other side
+            Conflict::Newer => match (host, workspace) {
+                (Some((_, h)), Some((_, w))) if w > h => Direction::ToHost,
+                (None, Some(_)) => Direction::ToHost,
+                _ => Direction::ToWorkspace,
+            },
+        }
+    }
+
+    /// Run passes every `interval` until interrupted, reporting each change.
+    pub fn run(&mut self, interval: Duration) -> Result<()> {
+        loop {
+            for (direction, path) in self.pass()? {
+                let arrow = match direction {
+                    Direction::ToWorkspace => "host → workspace",
+                    Direction::ToHost => "workspace → host",
+                };
+                eprintln!("\x1b[2m{}:\x1b[0m {}", arrow, path);
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["init", "-q"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+    }
+
+    /// Set `path`'s modification time `secs` seconds into the future, so
+    /// edits within the same clock tick still register.
+    fn touch(path: &Path, secs: u64) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_conflict() {
+        assert_eq!(Conflict::parse("newer"), Some(Conflict::Newer));
+        assert_eq!(Conflict::parse("host"), Some(Conflict::Host));
+        assert_eq!(Conflict::parse("workspace"), Some(Conflict::Workspace));
+        assert_eq!(Conflict::parse("ours"), None);
+    }
+
+    #[test]
+    fn test_pass_carries_changes_both_ways() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (host, ws) = (tmp.path().join("host"), tmp.path().join("ws"));
+        repo(&host);
+        repo(&ws);
+        std::fs::write(host.join("a.txt"), "a").unwrap();
+        std::fs::create_dir_all(host.join("target")).unwrap();
+        std::fs::write(host.join("target/out"), "build").unwrap();
+
+        let mut watcher = Watcher::new(&host, &ws, Conflict::Newer);
+        let synced = watcher.pass().unwrap();
+        assert_eq!(synced, vec![(Direction::ToWorkspace, "a.txt".to_string())]);
+        assert_eq!(std::fs::read_to_string(ws.join("a.txt")).unwrap(), "a");
+        assert!(!ws.join("target/out").exists());
+        assert!(watcher.pass().unwrap().is_empty());
+
+        std::fs::create_dir_all(ws.join("src")).unwrap();
+        std::fs::write(ws.join("src/b.txt"), "b").unwrap();
+        std::fs::remove_file(ws.join("a.txt")).unwrap();
+        let synced = watcher.pass().unwrap();
+        assert_eq!(
+            synced,
+            vec![
+                (Direction::ToHost, "a.txt".to_string()),
+                (Direction::ToHost, "src/b.txt".to_string()),
+            ]
+        );
+        assert!(!host.join("a.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(host.join("src/b.txt")).unwrap(),
+            "b"
+        );
+        assert!(watcher.pass().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pass_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (host, ws) = (tmp.path().join("host"), tmp.path().join("ws"));
+        repo(&host);
+        repo(&ws);
+        std::fs::write(host.join("same.txt"), "same").unwrap();
+        std::fs::write(ws.join("same.txt"), "same").unwrap();
+        std::fs::write(host.join("c.txt"), "host").unwrap();
+        std::fs::write(ws.join("c.txt"), "workspace").unwrap();
+        touch(&ws.join("c.txt"), 10);
+
+        // Identical files aren't conflicts; otherwise the newer copy wins
+        let mut watcher = Watcher::new(&host, &ws, Conflict::Newer);
+        let synced = watcher.pass().unwrap();
+        assert_eq!(synced, vec![(Direction::ToHost, "c.txt".to_string())]);
+        assert_eq!(
+            std::fs::read_to_string(host.join("c.txt")).unwrap(),
+            "workspace"
+        );
+
+        std::fs::write(host.join("c.txt"), "host again").unwrap();
+        std::fs::write(ws.join("c.txt"), "workspace again").unwrap();
+        touch(&ws.join("c.txt"), 20);
+        let mut watcher_host = Watcher::new(&host, &ws, Conflict::Host);
+        watcher_host.pass().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(ws.join("c.txt")).unwrap(),
+            "host again"
+        );
+
+        // An edit beats a deletion on the other side
+        watcher.pass().unwrap();
+        std::fs::remove_file(host.join("c.txt")).unwrap();
+        std::fs::write(ws.join("c.txt"), "edited").unwrap();
+        touch(&ws.join("c.txt"), 30);
+        watcher.pass().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(host.join("c.txt")).unwrap(),
+            "edited"
+        );
+    }
+}
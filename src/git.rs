@@ -1,4 +1,8 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::io::Write;
 use std::path::Path;
+use std::process::Command;
 
 pub fn is_repo(dir: &Path) -> bool {
     dir.join(".git").exists()
@@ -18,10 +22,571 @@ pub fn find_root(dir: &Path) -> Option<&Path> {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub branch: String,
+    /// `git status --porcelain` lines for modified and untracked files.
+    pub changes: Vec<String>,
+}
+
+/// Branch and working tree changes of the repository at `dir`, or `None` if it isn't one.
+pub fn status(dir: &Path) -> Option<Status> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--porcelain", "--branch"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let branch = lines
+        .next()
+        .and_then(|l| l.strip_prefix("## "))
+        .map(|l| l.split("...").next().unwrap_or(l).to_string())
+        .unwrap_or_default();
+    Some(Status {
+        branch,
+        changes: lines.map(|l| l.to_string()).collect(),
+    })
+}
+
+/// How a session's workspace differs from the host repo it came from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncState {
+    /// Uncommitted or untracked files in the workspace.
+    pub dirty: bool,
+    /// Workspace commits the host's `HEAD` doesn't have.
+    pub ahead: usize,
+    /// Host commits the workspace's `HEAD` doesn't have.
+    pub behind: usize,
+}
+
+impl SyncState {
+    /// Whether deleting the workspace would lose work the host doesn't have.
+    pub fn unsynced(&self) -> bool {
+        self.dirty || self.ahead > 0
+    }
+
+    /// Compact indicator, e.g. `* ↑2 ↓1`; empty when in sync.
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if self.dirty {
+            parts.push("*".to_string());
+        }
+        if self.ahead > 0 {
+            parts.push(format!("\u{2191}{}", self.ahead));
+        }
+        if self.behind > 0 {
+            parts.push(format!("\u{2193}{}", self.behind));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Compare the workspace repo at `workspace` with `HEAD` of the host repo at
+/// `host`. The host's objects are borrowed read-only through
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES`, so neither repository is modified.
+/// `None` if either isn't a repository with commits.
+pub fn sync_state(workspace: &Path, host: &Path) -> Option<SyncState> {
+    let host_head = run(host, &["rev-parse", "HEAD"]).ok()?;
+    let objects = objects_dir(host).ok()?;
+    let dirty = !status(workspace)?.changes.is_empty();
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(workspace)
+            .env("GIT_ALTERNATE_OBJECT_DIRECTORIES", &objects)
+            .args(args)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // Unrelated histories (e.g. the host dir isn't the repo the workspace
+    // came from) have nothing meaningful to count
+    git(&["merge-base", "HEAD", &host_head])?;
+    let counts = git(&[
+        "rev-list",
+        "--left-right",
+        "--count",
+        &format!("HEAD...{}", host_head),
+    ])?;
+    let mut counts = counts.split_whitespace().map(|n| n.parse().ok());
+    Some(SyncState {
+        dirty,
+        ahead: counts.next()??,
+        behind: counts.next()??,
+    })
+}
+
+/// Absolute path of the object store of the repository at `dir`.
+fn objects_dir(dir: &Path) -> Result<String> {
+    run(
+        dir,
+        &[
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-path",
+            "objects",
+        ],
+    )
+}
+
+/// A workspace's changes relative to `HEAD` of the host repo, as patches.
+pub struct WorkspacePatches {
+    /// `git format-patch` mailbox of the workspace commits the host lacks.
+    pub commits: Vec<u8>,
+    /// Number of commits in `commits`.
+    pub count: usize,
+    /// Binary diff of uncommitted changes, untracked files included.
+    pub uncommitted: Vec<u8>,
+}
+
+/// Build patches that bring the host repo at `host` up to the workspace at
+/// `workspace`. Like `sync_state`, the host's objects are only borrowed, and
+/// untracked files are staged in a throwaway index, so neither repository
+/// is modified.
+pub fn workspace_patches(workspace: &Path, host: &Path) -> Result<WorkspacePatches> {
+    let host_head = run(host, &["rev-parse", "HEAD"])?;
+    let objects = objects_dir(host)?;
+    let alternates = [("GIT_ALTERNATE_OBJECT_DIRECTORIES", objects.as_str())];
+    let base = match output(workspace, &["merge-base", "HEAD", &host_head], &alternates) {
+        Ok(base) => String::from_utf8_lossy(&base).trim().to_string(),
+        Err(_) => bail!("The workspace and the host repo share no history."),
+    };
+    let range = format!("{}..HEAD", base);
+    let count = run(workspace, &["rev-list", "--count", &range])?
+        .parse()
+        .unwrap_or(0);
+    let commits = if count > 0 {
+        output(
+            workspace,
+            &["format-patch", "--stdout", "--binary", &range],
+            &[],
+        )?
+    } else {
+        Vec::new()
+    };
+
+    let index = run(
+        workspace,
+        &["rev-parse", "--path-format=absolute", "--git-path", "index"],
+    )?;
+    let scratch = std::env::temp_dir().join(format!("box-apply-{}.index", std::process::id()));
+    if Path::new(&index).exists() {
+        std::fs::copy(&index, &scratch)?;
+    }
+    let scratch_env = [("GIT_INDEX_FILE", scratch.to_str().unwrap_or_default())];
+    let uncommitted = output(workspace, &["add", "--all"], &scratch_env).and_then(|_| {
+        output(
+            workspace,
+            &["diff", "--cached", "--binary", "HEAD"],
+            &scratch_env,
+        )
+    });
+    let _ = std::fs::remove_file(&scratch);
+    Ok(WorkspacePatches {
+        commits,
+        count,
+        uncommitted: uncommitted?,
+    })
+}
+
+/// Apply a `git format-patch` mailbox to the repository at `dir` as commits,
+/// falling back to a 3-way merge. `false` when `git am` stops on a conflict.
+pub fn am(dir: &Path, mbox: &[u8]) -> Result<bool> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["am", "--3way"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("piped stdin").write_all(mbox)?;
+    Ok(child.wait()?.success())
+}
+
+/// Apply `patch` to the working tree of the repository at `dir`.
+pub fn apply(dir: &Path, patch: &[u8]) -> Result<()> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["apply", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("piped stdin").write_all(patch)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "git apply failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Create branch `name` at the current `HEAD` of `dir` and switch to it.
+pub fn switch_create(dir: &Path, name: &str) -> Result<()> {
+    run(dir, &["switch", "--quiet", "--create", name])?;
+    Ok(())
+}
+
+/// Run git in `dir` with extra environment and return its raw stdout,
+/// failing with git's stderr.
+fn output(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .envs(env.iter().copied())
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Run git in `dir` and return its trimmed stdout, failing with git's stderr.
+fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Fetch `HEAD` of the repository at `from` into `repo` and return the fetched commit.
+/// The commit is left in `repo`'s `FETCH_HEAD`.
+pub fn fetch_head(repo: &Path, from: &Path) -> Result<String> {
+    run(repo, &["fetch", "--quiet", &from.to_string_lossy(), "HEAD"])?;
+    run(repo, &["rev-parse", "FETCH_HEAD"])
+}
+
+/// Create branch `name` in `repo` pointing at `commit`.
+pub fn create_branch(repo: &Path, name: &str, commit: &str) -> Result<()> {
+    run(repo, &["branch", name, commit])?;
+    Ok(())
+}
+
+/// Fast-forward the current branch of `repo` to `commit`.
+pub fn merge_ff_only(repo: &Path, commit: &str) -> Result<()> {
+    run(repo, &["merge", "--ff-only", "--quiet", commit])?;
+    Ok(())
+}
+
+/// One-line summaries of the commits in `range` (e.g. `a..b`), newest first.
+pub fn log_oneline(repo: &Path, range: &str) -> Result<Vec<String>> {
+    let out = run(repo, &["log", "--oneline", range])?;
+    Ok(out.lines().map(|l| l.to_string()).collect())
+}
+
+/// The commit `rev` (a branch, tag or commit) names in the repository at `dir`.
+pub fn resolve_commit(dir: &Path, rev: &str) -> Result<String> {
+    run(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )
+}
+
+/// Whether `name` is a local branch of the repository at `dir`.
+pub fn is_branch(dir: &Path, name: &str) -> bool {
+    run(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{}", name),
+        ],
+    )
+    .is_ok()
+}
+
+/// Whether any file in the checkout at `dir` is stored with Git LFS, per its
+/// `.gitattributes`.
+pub fn uses_lfs(dir: &Path) -> bool {
+    run(dir, &["ls-files", "--", ":(attr:filter=lfs)"])
+        .map(|out| !out.is_empty())
+        .unwrap_or(false)
+}
+
+/// Whether the checkout at `dir` declares submodules in `.gitmodules`.
+pub fn has_submodules(dir: &Path) -> bool {
+    dir.join(".gitmodules").is_file()
+}
+
+/// Untracked files (ignored ones included) in the repository at `dir` that
+/// match any of `globs`, relative to its root.
+pub fn untracked_matching(dir: &Path, globs: &[String]) -> Result<Vec<String>> {
+    if globs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let pathspecs: Vec<String> = globs.iter().map(|g| format!(":(glob){}", g)).collect();
+    let mut args = vec!["ls-files", "-z", "--others", "--"];
+    args.extend(pathspecs.iter().map(|p| p.as_str()));
+    let out = run(dir, &args)?;
+    Ok(out
+        .split('\0')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_string())
+        .collect())
+}
+
+/// Whether the `git lfs` extension is installed on the host.
+pub fn lfs_installed() -> bool {
+    Command::new("git")
+        .args(["lfs", "version"])
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// URL of the `origin` remote of the repository at `dir`, if any.
+pub fn remote_url(dir: &Path) -> Option<String> {
+    if !is_repo(dir) {
+        return None;
+    }
+    run(dir, &["remote", "get-url", "origin"]).ok()
+}
+
+/// The commit `HEAD` points at, and the current branch unless detached.
+pub fn head(dir: &Path) -> Result<(String, Option<String>)> {
+    let commit = run(dir, &["rev-parse", "HEAD"])?;
+    let branch = run(dir, &["symbolic-ref", "--quiet", "--short", "HEAD"]).ok();
+    Ok((commit, branch))
+}
+
+/// Whether `commit` is contained in a remote-tracking branch, i.e. can be
+/// fetched from a remote.
+pub fn is_pushed(dir: &Path, commit: &str) -> bool {
+    run(dir, &["branch", "--remotes", "--contains", commit])
+        .map(|out| !out.is_empty())
+        .unwrap_or(false)
+}
+
+/// Clone the tip of `url` into `dir`.
+pub fn clone_shallow(url: &str, dir: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .args(["clone", "--quiet", "--depth", "1", url])
+        .arg(dir)
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "git clone failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Fast-forward the repository at `dir` from its upstream.
+pub fn pull_ff_only(dir: &Path) -> Result<()> {
+    run(dir, &["pull", "--ff-only", "--quiet"])?;
+    Ok(())
+}
+
+/// Diff the working tree of `workspace` against `HEAD` of `host`, writing to the terminal.
+/// The host's HEAD is fetched into the workspace first so it can be compared directly.
+pub fn diff_against_host(workspace: &Path, host: &Path, args: &[&str]) -> Result<i32> {
+    run(
+        workspace,
+        &[
+            "fetch",
+            "--quiet",
+            "--no-tags",
+            &host.to_string_lossy(),
+            "HEAD",
+        ],
+    )?;
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .arg("diff")
+        .args(args)
+        .arg("FETCH_HEAD")
+        .status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Whether `url` reaches its remote over SSH, either as `ssh://` or in
+/// scp-like `user@host:path` form.
+pub fn is_ssh_url(url: &str) -> bool {
+    if let Some((scheme, _)) = url.split_once("://") {
+        return scheme == "ssh" || scheme == "git+ssh";
+    }
+    // scp-like syntax has a colon before any slash; local paths don't
+    match url.find(':') {
+        Some(colon) => !url[..colon].contains('/'),
+        None => false,
+    }
+}
+
+/// Username and password (or token) for an HTTPS remote.
+#[derive(Debug, PartialEq)]
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+/// Host of an `http(s)://` remote URL, without user info or port.
+pub fn https_host(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then_some(host)
+}
+
+/// Credentials for the HTTPS remote `url` from the host's credential helpers,
+/// never prompting. Falls back to `gh auth token` for hosts gh is logged in to.
+pub fn credential(url: &str) -> Result<Credential> {
+    let Some(host) = https_host(url) else {
+        bail!("'{}' is not an HTTPS remote.", url);
+    };
+    if let Some(credential) = credential_fill(url) {
+        return Ok(credential);
+    }
+    let token = Command::new("gh")
+        .args(["auth", "token", "--hostname", host])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|t| !t.is_empty());
+    match token {
+        Some(password) => Ok(Credential {
+            username: "x-access-token".to_string(),
+            password,
+        }),
+        None => bail!(
+            "No stored credentials for {}. Configure a git credential helper or run `gh auth login`.",
+            host
+        ),
+    }
+}
+
+/// `git credential fill` for `url` with prompts disabled.
+fn credential_fill(url: &str) -> Option<Credential> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env_remove("GIT_ASKPASS")
+        .env_remove("SSH_ASKPASS")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .ok()?;
+    {
+        let mut stdin = child.stdin.take()?;
+        writeln!(stdin, "url={}\n", url).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_credential(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Username and password from `git credential` output.
+fn parse_credential(output: &str) -> Option<Credential> {
+    let field = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .filter(|v| !v.is_empty())
+            .map(String::from)
+    };
+    Some(Credential {
+        username: field("username")?,
+        password: field("password")?,
+    })
+}
+
+/// Push `refspec` from the repository at `dir` to `origin`, writing git's
+/// progress to the terminal.
+pub fn push(dir: &Path, refspec: &str, set_upstream: bool) -> Result<i32> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(dir).arg("push");
+    if set_upstream {
+        cmd.arg("--set-upstream");
+    }
+    let status = cmd.args(["origin", refspec]).status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Create a repo at `dir` with a single empty commit.
+    fn init_repo(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        run(dir, &["init", "-q", "-b", "main"]).unwrap();
+        commit(dir, "init");
+    }
+
+    fn commit(dir: &Path, msg: &str) {
+        run(
+            dir,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                msg,
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_clone_shallow_and_pull() {
+        let tmp = tempfile::tempdir().unwrap();
+        let upstream = tmp.path().join("upstream");
+        init_repo(&upstream);
+        let url = format!("file://{}", upstream.display());
+
+        let clone = tmp.path().join("clone");
+        clone_shallow(&url, &clone).unwrap();
+        assert_eq!(remote_url(&clone).as_deref(), Some(url.as_str()));
+        assert_eq!(remote_url(&upstream), None);
+
+        commit(&upstream, "second");
+        pull_ff_only(&clone).unwrap();
+        assert_eq!(
+            run(&clone, &["rev-parse", "HEAD"]).unwrap(),
+            run(&upstream, &["rev-parse", "HEAD"]).unwrap()
+        );
+    }
+
     #[test]
     fn test_is_repo_true() {
         let tmp = tempfile::tempdir().unwrap();
@@ -86,4 +651,319 @@ mod tests {
         std::fs::create_dir_all(&sub).unwrap();
         assert_eq!(find_root(&sub), None);
     }
+
+    #[test]
+    fn test_status() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(status(tmp.path()).is_none());
+
+        Command::new("git")
+            .args(["init", "-q", "-b", "main", tmp.path().to_str().unwrap()])
+            .status()
+            .unwrap();
+        std::fs::write(tmp.path().join("new.txt"), "x").unwrap();
+
+        let st = status(tmp.path()).unwrap();
+        assert!(st.branch.contains("main"));
+        assert_eq!(st.changes, vec!["?? new.txt"]);
+    }
+
+    #[test]
+    fn test_resolve_commit_and_is_branch() {
+        let tmp = tempfile::tempdir().unwrap();
+        init_repo(tmp.path());
+        let head = run(tmp.path(), &["rev-parse", "HEAD"]).unwrap();
+        assert_eq!(resolve_commit(tmp.path(), "main").unwrap(), head);
+        assert_eq!(resolve_commit(tmp.path(), &head).unwrap(), head);
+        assert!(resolve_commit(tmp.path(), "nope").is_err());
+        assert!(is_branch(tmp.path(), "main"));
+        assert!(!is_branch(tmp.path(), &head));
+    }
+
+    #[test]
+    fn test_uses_lfs_and_has_submodules() {
+        let tmp = tempfile::tempdir().unwrap();
+        init_repo(tmp.path());
+        assert!(!uses_lfs(tmp.path()));
+        assert!(!has_submodules(tmp.path()));
+
+        std::fs::write(
+            tmp.path().join(".gitattributes"),
+            "*.bin filter=lfs diff=lfs merge=lfs -text\n",
+        )
+        .unwrap();
+        std::fs::write(tmp.path().join("notes.txt"), "text").unwrap();
+        run(tmp.path(), &["add", ".gitattributes", "notes.txt"]).unwrap();
+        assert!(!uses_lfs(tmp.path()));
+        std::fs::write(tmp.path().join("model.bin"), "blob").unwrap();
+        run(tmp.path(), &["add", "model.bin"]).unwrap();
+        assert!(uses_lfs(tmp.path()));
+
+        std::fs::write(tmp.path().join(".gitmodules"), "").unwrap();
+        assert!(has_submodules(tmp.path()));
+    }
+
+    #[test]
+    fn test_untracked_matching() {
+        let tmp = tempfile::tempdir().unwrap();
+        init_repo(tmp.path());
+        std::fs::write(tmp.path().join(".gitignore"), ".env\nlocal/\n").unwrap();
+        std::fs::write(tmp.path().join("tracked.env"), "").unwrap();
+        run(tmp.path(), &["add", ".gitignore", "tracked.env"]).unwrap();
+        std::fs::write(tmp.path().join(".env"), "A=1").unwrap();
+        std::fs::write(tmp.path().join("notes.txt"), "").unwrap();
+        std::fs::create_dir_all(tmp.path().join("local/nested")).unwrap();
+        std::fs::write(tmp.path().join("local/nested/settings.json"), "{}").unwrap();
+        std::fs::create_dir_all(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("sub/.env"), "").unwrap();
+
+        let globs = [".env", "*.env", "local/**"].map(String::from);
+        let mut found = untracked_matching(tmp.path(), &globs).unwrap();
+        found.sort();
+        assert_eq!(found, vec![".env", "local/nested/settings.json"]);
+    }
+
+    #[test]
+    fn test_is_ssh_url() {
+        for url in [
+            "git@github.com:owner/repo.git",
+            "ssh://git@github.com/owner/repo.git",
+            "git+ssh://host/repo",
+            "host:repo",
+        ] {
+            assert!(is_ssh_url(url), "{}", url);
+        }
+        for url in [
+            "https://github.com/owner/repo.git",
+            "file:///tmp/repo",
+            "/tmp/repo",
+            "./a:b",
+        ] {
+            assert!(!is_ssh_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_push() {
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = tmp.path().join("remote.git");
+        let repo = tmp.path().join("repo");
+        std::fs::create_dir_all(&remote).unwrap();
+        run(&remote, &["init", "-q", "--bare"]).unwrap();
+        init_repo(&repo);
+        run(
+            &repo,
+            &["remote", "add", "origin", &remote.to_string_lossy()],
+        )
+        .unwrap();
+
+        assert_eq!(push(&repo, "main", true).unwrap(), 0);
+        assert_eq!(
+            run(&repo, &["rev-parse", "--abbrev-ref", "main@{upstream}"]).unwrap(),
+            "origin/main"
+        );
+        assert_eq!(push(&repo, "HEAD:refs/heads/other", false).unwrap(), 0);
+        assert_eq!(
+            run(&remote, &["rev-parse", "other"]).unwrap(),
+            run(&repo, &["rev-parse", "HEAD"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_workspace_patches_apply_to_host() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (host, ws) = (tmp.path().join("host"), tmp.path().join("ws"));
+        init_repo(&host);
+        run(&host, &["config", "user.name", "t"]).unwrap();
+        run(&host, &["config", "user.email", "t@t"]).unwrap();
+        std::fs::write(host.join("a.txt"), "a\n").unwrap();
+        run(&host, &["add", "a.txt"]).unwrap();
+        commit(&host, "add a");
+        run(
+            tmp.path(),
+            &[
+                "clone",
+                "-q",
+                &host.to_string_lossy(),
+                &ws.to_string_lossy(),
+            ],
+        )
+        .unwrap();
+
+        std::fs::write(ws.join("b.txt"), "b\n").unwrap();
+        run(&ws, &["add", "b.txt"]).unwrap();
+        commit(&ws, "add b");
+        std::fs::write(ws.join("a.txt"), "a\nmore\n").unwrap();
+        std::fs::write(ws.join("c.txt"), "c\n").unwrap();
+        // The host moved on meanwhile
+        std::fs::write(host.join("d.txt"), "d\n").unwrap();
+        run(&host, &["add", "d.txt"]).unwrap();
+        commit(&host, "add d");
+
+        let patches = workspace_patches(&ws, &host).unwrap();
+        assert_eq!(patches.count, 1);
+        let diff = String::from_utf8_lossy(&patches.uncommitted);
+        assert!(diff.contains("a.txt") && diff.contains("c.txt"));
+        // The workspace's own index is left alone
+        assert_eq!(
+            run(&ws, &["status", "--porcelain"]).unwrap(),
+            "M a.txt\n?? c.txt"
+        );
+
+        assert!(am(&host, &patches.commits).unwrap());
+        apply(&host, &patches.uncommitted).unwrap();
+        assert_eq!(run(&host, &["log", "-1", "--format=%s"]).unwrap(), "add b");
+        assert_eq!(
+            std::fs::read_to_string(host.join("a.txt")).unwrap(),
+            "a\nmore\n"
+        );
+        assert!(host.join("c.txt").is_file() && host.join("d.txt").is_file());
+
+        let other = tmp.path().join("other");
+        std::fs::create_dir_all(&other).unwrap();
+        run(&other, &["init", "-q"]).unwrap();
+        commit(&other, "unrelated root");
+        assert!(workspace_patches(&ws, &other).is_err());
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let tmp = tempfile::tempdir().unwrap();
+        let host = tmp.path().join("host");
+        init_repo(&host);
+        run(
+            tmp.path(),
+            &["clone", "-q", "--local", host.to_str().unwrap(), "ws"],
+        )
+        .unwrap();
+        let ws = tmp.path().join("ws");
+        assert_eq!(sync_state(&ws, &host), Some(SyncState::default()));
+
+        commit(&ws, "box work");
+        commit(&ws, "more box work");
+        commit(&host, "host work");
+        std::fs::write(ws.join("new.txt"), "x").unwrap();
+        let state = sync_state(&ws, &host).unwrap();
+        assert_eq!(
+            state,
+            SyncState {
+                dirty: true,
+                ahead: 2,
+                behind: 1
+            }
+        );
+        assert!(state.unsynced());
+        assert_eq!(state.label(), "* \u{2191}2 \u{2193}1");
+        // Reading the host's objects doesn't fetch them into the workspace
+        assert!(run(&ws, &["cat-file", "-e", "FETCH_HEAD"]).is_err());
+
+        assert_eq!(sync_state(&tmp.path().join("missing"), &host), None);
+
+        let other = tmp.path().join("other");
+        std::fs::create_dir_all(&other).unwrap();
+        run(&other, &["init", "-q", "-b", "main"]).unwrap();
+        commit(&other, "unrelated root");
+        assert_eq!(sync_state(&ws, &other), None);
+    }
+
+    #[test]
+    fn test_sync_state_label_in_sync() {
+        let state = SyncState {
+            dirty: false,
+            ahead: 0,
+            behind: 3,
+        };
+        assert!(!state.unsynced());
+        assert_eq!(state.label(), "\u{2193}3");
+        assert_eq!(SyncState::default().label(), "");
+    }
+
+    #[test]
+    fn test_fetch_head_branch_and_ff() {
+        let tmp = tempfile::tempdir().unwrap();
+        let host = tmp.path().join("host");
+        let ws = tmp.path().join("ws");
+        init_repo(&host);
+        run(
+            tmp.path(),
+            &["clone", "-q", "--local", host.to_str().unwrap(), "ws"],
+        )
+        .unwrap();
+        commit(&ws, "work in box");
+
+        let fetched = fetch_head(&host, &ws).unwrap();
+        assert_eq!(fetched, run(&ws, &["rev-parse", "HEAD"]).unwrap());
+        assert_eq!(
+            log_oneline(&host, &format!("HEAD..{}", fetched))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        create_branch(&host, "box/ws", &fetched).unwrap();
+        assert_eq!(run(&host, &["rev-parse", "box/ws"]).unwrap(), fetched);
+        assert!(create_branch(&host, "box/ws", &fetched).is_err());
+
+        merge_ff_only(&host, &fetched).unwrap();
+        assert_eq!(run(&host, &["rev-parse", "HEAD"]).unwrap(), fetched);
+    }
+
+    #[test]
+    fn test_run_reports_git_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let err = run(tmp.path(), &["rev-parse", "HEAD"]).unwrap_err();
+        assert!(err.to_string().starts_with("git rev-parse failed"));
+    }
+
+    #[test]
+    fn test_diff_against_host_fetches_host_head() {
+        let tmp = tempfile::tempdir().unwrap();
+        let host = tmp.path().join("host");
+        init_repo(&host);
+        run(
+            tmp.path(),
+            &["clone", "-q", "--local", host.to_str().unwrap(), "ws"],
+        )
+        .unwrap();
+        let ws = tmp.path().join("ws");
+        // Host moves on after the workspace was cloned
+        commit(&host, "host work");
+
+        let code = diff_against_host(&ws, &host, &["--quiet"]).unwrap();
+        assert_eq!(code, 0);
+        assert_eq!(
+            run(&ws, &["rev-parse", "FETCH_HEAD"]).unwrap(),
+            run(&host, &["rev-parse", "HEAD"]).unwrap()
+        );
+
+        std::fs::write(ws.join("new.txt"), "x").unwrap();
+        run(&ws, &["add", "new.txt"]).unwrap();
+        assert_eq!(diff_against_host(&ws, &host, &["--quiet"]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_https_host() {
+        assert_eq!(
+            https_host("https://github.com/org/repo.git"),
+            Some("github.com")
+        );
+        assert_eq!(
+            https_host("https://me@git.example.com:8443/org/repo"),
+            Some("git.example.com")
+        );
+        assert_eq!(https_host("git@github.com:org/repo.git"), None);
+    }
+
+    #[test]
+    fn test_parse_credential() {
+        let output = "protocol=https\nhost=github.com\nusername=me\npassword=s3cret\n";
+        assert_eq!(
+            parse_credential(output),
+            Some(Credential {
+                username: "me".to_string(),
+                password: "s3cret".to_string(),
+            })
+        );
+        assert_eq!(parse_credential("protocol=https\nhost=github.com\n"), None);
+    }
 }
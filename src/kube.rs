@@ -0,0 +1,353 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::git;
+use crate::session::Session;
+
+/// Image of the init container that clones the repository into the workspace.
+const CLONE_IMAGE: &str = "alpine/git";
+
+/// Size requested for a session's workspace volume claim.
+const WORKSPACE_SIZE: &str = "10Gi";
+
+/// How long to wait for a new pod to clone the repo and start.
+const READY_TIMEOUT: &str = "300s";
+
+/// Pod (and workspace claim) name of session `name`.
+pub fn pod_name(name: &str) -> String {
+    format!("box-{}", name)
+}
+
+pub fn check() -> Result<()> {
+    let ok = Command::new("kubectl")
+        .args(["version", "--client"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !ok {
+        bail!("kubectl is not installed. See https://kubernetes.io/docs/tasks/tools/");
+    }
+    Ok(())
+}
+
+/// Phase of the session's pod (`Pending`, `Running`, `Succeeded`, ...), or
+/// `None` if there is no pod.
+pub fn pod_phase(name: &str) -> Option<String> {
+    let output = Command::new("kubectl")
+        .args([
+            "get",
+            "pod",
+            &pod_name(name),
+            "--ignore-not-found",
+            "-o",
+            "jsonpath={.status.phase}",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let phase = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !phase.is_empty()).then_some(phase)
+}
+
+pub fn pod_is_running(name: &str) -> bool {
+    pod_phase(name).as_deref() == Some("Running")
+}
+
+/// Create the session's workspace claim (if missing) and pod, and wait until
+/// it is running. The init container clones the project's `origin` at the
+/// current commit, so that commit has to be pushed.
+pub fn create_pod(sess: &Session) -> Result<()> {
+    let project = Path::new(&sess.project_dir);
+    let Some(origin) = git::remote_url(project) else {
+        bail!("The project has no `origin` remote for the pod to clone from.");
+    };
+    let (commit, branch) = git::head(project)?;
+    if !git::is_pushed(project, &commit) {
+        bail!(
+            "HEAD ({}) isn't on any remote branch. Push it first; the pod clones from {}.",
+            &commit[..commit.len().min(12)],
+            origin
+        );
+    }
+
+    let manifest = json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "items": [
+            workspace_claim(&sess.name),
+            pod_manifest(sess, &origin, &commit, branch.as_deref()),
+        ],
+    });
+    // The claim already exists after the first create; only the pod is new.
+    kubectl_apply(&manifest)?;
+
+    eprintln!("\x1b[2mwaiting for pod:\x1b[0m {}", pod_name(&sess.name));
+    let status = Command::new("kubectl")
+        .args([
+            "wait",
+            "--for=condition=Ready",
+            &format!("pod/{}", pod_name(&sess.name)),
+            &format!("--timeout={}", READY_TIMEOUT),
+        ])
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!(
+            "Pod {} did not become ready. Check `kubectl describe pod {}`.",
+            pod_name(&sess.name),
+            pod_name(&sess.name)
+        );
+    }
+    Ok(())
+}
+
+fn kubectl_apply(manifest: &Value) -> Result<()> {
+    let mut child = Command::new("kubectl")
+        .args(["apply", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(manifest.to_string().as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("kubectl apply failed");
+    }
+    Ok(())
+}
+
+fn labels(name: &str) -> Value {
+    json!({
+        "app.kubernetes.io/managed-by": "box",
+        "box.session": name,
+    })
+}
+
+fn workspace_claim(name: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "PersistentVolumeClaim",
+        "metadata": { "name": pod_name(name), "labels": labels(name) },
+        "spec": {
+            "accessModes": ["ReadWriteOnce"],
+            "resources": { "requests": { "storage": WORKSPACE_SIZE } },
+        },
+    })
+}
+
+/// The pod for a session: an init container clones the repo into the
+/// workspace claim the first time, then the session's image runs with a
+/// terminal like `docker run -it`.
+fn pod_manifest(sess: &Session, origin: &str, commit: &str, branch: Option<&str>) -> Value {
+    // `git init` + fetch instead of clone: a fresh volume may already hold
+    // `lost+found`.
+    let clone = "[ -d /workspace/.git ] && exit 0; \
+                 cd /workspace \
+                 && git init --quiet \
+                 && git remote add origin \"$1\" \
+                 && git fetch --quiet origin \
+                 && if [ -n \"$3\" ]; then git checkout --quiet -B \"$3\" \"$2\"; \
+                    else git checkout --quiet \"$2\"; fi \
+                 && chmod 777 /workspace";
+
+    let mut container = json!({
+        "name": "box",
+        "image": sess.image,
+        "stdin": true,
+        "tty": true,
+        "workingDir": sess.mount_path,
+        "env": env_vars(&sess.env),
+        "volumeMounts": [{ "name": "workspace", "mountPath": sess.mount_path }],
+    });
+    if !sess.command.is_empty() {
+        container["args"] = json!(sess.command);
+    }
+    let mut limits = serde_json::Map::new();
+    if let Some(cpus) = &sess.cpus {
+        limits.insert("cpu".into(), json!(cpus));
+    }
+    if let Some(memory) = &sess.memory {
+        limits.insert("memory".into(), json!(memory_quantity(memory)));
+    }
+    if !limits.is_empty() {
+        container["resources"] = json!({ "limits": limits });
+    }
+
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": pod_name(&sess.name), "labels": labels(&sess.name) },
+        "spec": {
+            "restartPolicy": "Never",
+            "initContainers": [{
+                "name": "clone",
+                "image": CLONE_IMAGE,
+                "command": ["sh", "-c", clone, "sh", origin, commit, branch.unwrap_or("")],
+                "volumeMounts": [{ "name": "workspace", "mountPath": "/workspace" }],
+            }],
+            "containers": [container],
+            "volumes": [{
+                "name": "workspace",
+                "persistentVolumeClaim": { "claimName": pod_name(&sess.name) },
+            }],
+        },
+    })
+}
+
+/// Session env entries as pod env vars. A bare `KEY` takes the host's value,
+/// like `docker run -e KEY`, and is dropped when unset.
+fn env_vars(env: &[String]) -> Vec<Value> {
+    env.iter()
+        .filter_map(|entry| {
+            let (key, value) = match entry.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => (entry.as_str(), std::env::var(entry).ok()?),
+            };
+            Some(json!({ "name": key, "value": value }))
+        })
+        .collect()
+}
+
+/// Convert a docker memory size (`512m`, `4g`) to a Kubernetes quantity.
+fn memory_quantity(docker: &str) -> String {
+    let digits = docker.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    match docker[digits.len()..].to_ascii_lowercase().as_str() {
+        "k" => format!("{}Ki", digits),
+        "m" => format!("{}Mi", digits),
+        "g" => format!("{}Gi", digits),
+        _ => digits.to_string(),
+    }
+}
+
+pub fn attach(name: &str) -> Result<i32> {
+    let status = Command::new("kubectl")
+        .args(["attach", "-it", &pod_name(name), "-c", "box"])
+        .status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn exec(name: &str, cmd: &[String]) -> Result<i32> {
+    let status = Command::new("kubectl")
+        .args(["exec", "-it", &pod_name(name), "-c", "box", "--"])
+        .args(cmd)
+        .status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn logs(name: &str, follow: bool, tail: Option<&str>, since: Option<&str>) -> Result<i32> {
+    let mut cmd = Command::new("kubectl");
+    cmd.args(["logs", &pod_name(name), "-c", "box"]);
+    if follow {
+        cmd.arg("--follow");
+    }
+    if let Some(tail) = tail {
+        cmd.arg(format!("--tail={}", tail));
+    }
+    if let Some(since) = since {
+        cmd.arg(format!("--since={}", since));
+    }
+    Ok(cmd.status()?.code().unwrap_or(1))
+}
+
+/// Delete the session's pod; the workspace claim is kept for the next resume.
+/// `grace_period` overrides the seconds the pod gets to shut down.
+pub fn delete_pod(name: &str, grace_period: Option<u32>) -> Result<()> {
+    match grace_period {
+        Some(secs) => delete(&["pod", &pod_name(name), &format!("--grace-period={}", secs)]),
+        None => delete(&["pod", &pod_name(name)]),
+    }
+}
+
+/// Delete the session's pod and workspace claim.
+pub fn delete_all(name: &str) -> Result<()> {
+    delete(&["pod,pvc", &pod_name(name)])
+}
+
+fn delete(what: &[&str]) -> Result<()> {
+    let output = Command::new("kubectl")
+        .arg("delete")
+        .args(what)
+        .arg("--ignore-not-found")
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "kubectl delete failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            name: "dev".into(),
+            project_dir: "/tmp/project".into(),
+            image: "ubuntu:24.04".into(),
+            mount_path: "/workspace/project".into(),
+            command: vec!["bash".into()],
+            env: vec!["FOO=bar".into(), "BOX_KUBE_TEST_UNSET".into()],
+            cpus: Some("2".into()),
+            memory: Some("4g".into()),
+            kube: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pod_manifest() {
+        let pod = pod_manifest(
+            &session(),
+            "https://example.com/repo.git",
+            "abc123",
+            Some("main"),
+        );
+        assert_eq!(pod["metadata"]["name"], "box-dev");
+        assert_eq!(pod["metadata"]["labels"]["box.session"], "dev");
+
+        let init = &pod["spec"]["initContainers"][0];
+        assert_eq!(init["image"], CLONE_IMAGE);
+        let args = init["command"].as_array().unwrap();
+        assert_eq!(
+            args[3..],
+            ["sh", "https://example.com/repo.git", "abc123", "main"]
+        );
+
+        let box_container = &pod["spec"]["containers"][0];
+        assert_eq!(box_container["image"], "ubuntu:24.04");
+        assert_eq!(box_container["tty"], true);
+        assert_eq!(box_container["args"], json!(["bash"]));
+        assert_eq!(box_container["workingDir"], "/workspace/project");
+        assert_eq!(
+            box_container["env"],
+            json!([{ "name": "FOO", "value": "bar" }])
+        );
+        assert_eq!(
+            box_container["resources"]["limits"],
+            json!({ "cpu": "2", "memory": "4Gi" })
+        );
+        assert_eq!(
+            pod["spec"]["volumes"][0]["persistentVolumeClaim"]["claimName"],
+            "box-dev"
+        );
+    }
+
+    #[test]
+    fn test_memory_quantity() {
+        assert_eq!(memory_quantity("512m"), "512Mi");
+        assert_eq!(memory_quantity("4G"), "4Gi");
+        assert_eq!(memory_quantity("1024k"), "1024Ki");
+        assert_eq!(memory_quantity("1073741824"), "1073741824");
+        assert_eq!(memory_quantity("100b"), "100");
+    }
+}
@@ -1,21 +1,31 @@
 mod config;
 mod docker;
+mod doctor;
+mod engine;
+mod events;
+mod filesync;
 mod git;
+mod kube;
+mod registry;
 mod session;
+mod snapshot;
+mod storage;
 mod tui;
 
 use anyhow::{bail, Result};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Parser)]
 #[command(
     name = "box",
     about = "Sandboxed Docker environments for git repos",
-    after_help = "Examples:\n  box                                         # interactive session manager\n  box my-feature                               # shortcut for `box create my-feature`\n  box create my-feature                        # create a new session\n  box create my-feature --image ubuntu -- bash # create with options\n  box resume my-feature                        # resume a session\n  box resume my-feature -d                     # resume in background\n  box stop my-feature                          # stop a running session\n  box exec my-feature -- ls -la                # run a command in a session\n  box list                                     # list all sessions\n  box list -q --running                        # names of running sessions\n  box remove my-feature                        # remove a session\n  box cd my-feature                            # print project directory\n  box path my-feature                          # print workspace path\n  box upgrade                                  # self-update"
+    after_help = "Examples:\n  box                                         # interactive session manager\n  box my-feature                               # create my-feature, or resume it if it exists\n  box create my-feature                        # create a new session\n  box create my-feature --image ubuntu -- bash # create with options\n  box run -- ./untrusted.sh                    # run once in a throwaway box\n  box resume my-feature                        # resume a session\n  box resume my-feature -d                     # resume in background\n  box attach my-feature                        # attach to a running session\n  box stop my-feature                          # stop a running session\n  box stop --all                               # stop every running session\n  box stop my-feature -t 60                    # allow a minute to shut down\n  box kill my-feature                          # kill a hung session\n  box pause my-feature                         # freeze a running session\n  box exec my-feature -- ls -la                # run a command in a session\n  box shell my-feature                         # open another shell in a session\n  box logs my-feature -f                       # follow a session's output\n  box wait my-feature                          # block until it exits, with its exit code\n  box ssh my-feature                           # connect over ssh\n  box code my-feature                          # open VS Code in the container\n  box clone my-feature my-feature-2            # duplicate a session\n  box commit my-feature --use                  # snapshot installed tools into the image\n  box pull my-feature                          # refresh the session's image\n  box list                                     # list all sessions\n  box list -q --running                        # names of running sessions\n  box list --json                              # sessions as JSON\n  box inspect my-feature                       # detailed session status\n  box note my-feature \"try the new parser\"      # describe what a session is for\n  box edit my-feature                          # change a session's stored settings\n  box diff my-feature --stat                   # review workspace changes\n  box sync my-feature --branch my-feature      # land workspace commits on a host branch\n  box sync my-feature --watch                  # mirror file edits between host and box\n  box push my-feature -u                       # push the workspace branch to origin\n  box apply my-feature --branch my-feature     # replay workspace changes onto a host branch\n  box snapshot my-feature before-agent         # save the workspace before a risky run\n  box restore my-feature before-agent          # roll the workspace back\n  box remove my-feature                        # remove a session\n  box prune                                    # clean up stale sessions\n  box gc --dry-run                             # show reclaimable disk space\n  box doctor                                   # diagnose setup problems\n  box init                                     # scaffold .box.toml for this repo\n  box migrate                                  # move data from older versions\n  box autostop                                 # stop idle sessions\n  box template save rust --from my-feature     # save a session as a template\n  box create api --template rust               # create from a template\n  box template add org/templates               # share templates from a git repo\n  box cd my-feature                            # print project directory\n  box path my-feature                          # print workspace path\n  box cp my-feature:target/app ./app           # copy a build artifact out\n  box upgrade                                  # self-update"
 )]
 struct Cli {
     #[command(subcommand)]
@@ -25,28 +35,110 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Create a new session
-    Create(CreateArgs),
+    Create(Box<CreateArgs>),
+    /// Run a command once in a throwaway workspace and container
+    Run(RunArgs),
     /// Resume an existing session
     Resume(ResumeArgs),
+    /// Attach to a running session's terminal
+    Attach(SessionArgs),
     /// Remove a session (must be stopped first)
     Remove(RemoveArgs),
     /// Stop a running session
     Stop(StopArgs),
+    /// Kill running sessions immediately, without a grace period
+    Kill(KillArgs),
+    /// Freeze all processes in a running session
+    Pause(SessionArgs),
+    /// Resume a paused session's processes
+    Unpause(SessionArgs),
     /// Run a command in a running session
     Exec(ExecArgs),
+    /// Open an additional shell in a session, starting it if needed
+    Shell(SessionArgs),
+    /// Show a session's container output
+    Logs(LogsArgs),
+    /// Block until a session's container exits and return its exit code
+    Wait(SessionArgs),
+    /// Connect to a session over ssh (for remote editors)
+    Ssh(SshArgs),
+    /// Open VS Code attached to a session's container
+    Code {
+        /// Session name
+        #[arg(value_name = "SESSION")]
+        name: String,
+    },
+    /// Duplicate a session (metadata and workspace)
+    Clone(CloneArgs),
+    /// Snapshot a session's container as an image
+    Commit(CommitArgs),
+    /// Pull the latest version of a session's image
+    Pull {
+        /// Session name
+        #[arg(value_name = "SESSION")]
+        name: String,
+    },
+    /// Remove stale sessions and orphaned workspaces/containers
+    Prune(PruneArgs),
+    /// Move data left by older versions in ~/.box or ~/.realm into the data directory
+    Migrate,
+    /// Stop sessions that have been idle longer than their idle timeout
+    Autostop(AutostopArgs),
+    /// Reclaim disk space: exited containers, dangling snapshot images, old orphaned workspaces
+    Gc(GcArgs),
+    /// Check docker, SSH agent forwarding and storage, and suggest fixes
+    Doctor,
+    /// Write a commented .box.toml with detected defaults to the repository root
+    Init(InitArgs),
+    /// Show detailed status of a session
+    Inspect(InspectArgs),
+    /// Show or edit a session's note
+    Note(NoteArgs),
+    /// Edit a session's stored settings in $EDITOR (applied on next resume)
+    Edit(SessionArgs),
+    /// Fetch workspace commits into the host repo, or mirror files both ways
+    Sync(SyncArgs),
+    /// Push the workspace's current branch to origin
+    Push(PushArgs),
+    /// Apply a session's workspace commits and uncommitted changes to the host repo as patches
+    Apply(ApplyArgs),
+    /// Save a copy of a session's workspace, or list and delete saved copies
+    Snapshot(SnapshotArgs),
+    /// Roll a session's workspace back to a snapshot
+    Restore(RestoreArgs),
+    /// Show or change a session's CPU and memory limits
+    Limits(LimitsArgs),
+    /// Show or change a session's published ports
+    Ports(PortsArgs),
+    /// Show or change a session's network mode
+    Network(NetworkArgs),
+    /// Show or change a session's environment variables
+    Env(EnvArgs),
+    /// Show or change which secrets are mounted into a session
+    Secrets(SecretsArgs),
+    /// Show changes in a session's workspace relative to the host repo's HEAD
+    Diff(DiffArgs),
     /// List sessions
     #[command(alias = "ls")]
     List(ListArgs),
-    /// Print the host project directory for a session
+    /// Change into a session's workspace (prints the path without shell integration)
     Cd {
         /// Session name
+        #[arg(value_name = "SESSION")]
         name: String,
     },
-    /// Print workspace path for a session
+    /// Print workspace path for a session, or copy the workspace out with --export
     Path {
         /// Session name
+        #[arg(value_name = "SESSION")]
         name: String,
+
+        /// Copy the workspace to DIR on the host (for volume workspaces)
+        #[arg(long, value_name = "DIR")]
+        export: Option<std::path::PathBuf>,
     },
+    /// Copy files between the host and a session (NAME:PATH)
+    Cp(CpArgs),
     /// Self-update to the latest version
     Upgrade,
     /// Output shell configuration (e.g. eval "$(box config zsh)")
@@ -54,24 +146,224 @@ enum Commands {
         #[command(subcommand)]
         shell: ConfigShell,
     },
-    /// Shortcut: `box <name>` is equivalent to `box create <name>`
+    /// Print session names for shell completion
+    #[command(name = "__complete-sessions", hide = true)]
+    CompleteSessions,
+    /// Print template names for shell completion
+    #[command(name = "__complete-templates", hide = true)]
+    CompleteTemplates,
+    /// Manage reusable session templates
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
+    /// Shortcut: `box <name>` resumes session <name> if it exists, else runs `box create <name>`
     #[command(external_subcommand)]
     External(Vec<OsString>),
 }
 
+impl Commands {
+    /// The existing session a command operates on, used to select its docker
+    /// context before anything talks to docker.
+    fn session_name(&self) -> Option<&str> {
+        let name = match self {
+            Commands::Resume(ResumeArgs { name, .. })
+            | Commands::Attach(SessionArgs { name })
+            | Commands::Pause(SessionArgs { name })
+            | Commands::Unpause(SessionArgs { name })
+            | Commands::Exec(ExecArgs { name, .. })
+            | Commands::Shell(SessionArgs { name })
+            | Commands::Logs(LogsArgs { name, .. })
+            | Commands::Wait(SessionArgs { name })
+            | Commands::Ssh(SshArgs { name, .. })
+            | Commands::Code { name }
+            | Commands::Clone(CloneArgs { src: name, .. })
+            | Commands::Commit(CommitArgs { name, .. })
+            | Commands::Pull { name }
+            | Commands::Inspect(InspectArgs { name, .. })
+            | Commands::Note(NoteArgs { name, .. })
+            | Commands::Edit(SessionArgs { name })
+            | Commands::Sync(SyncArgs { name, .. })
+            | Commands::Push(PushArgs { name, .. })
+            | Commands::Apply(ApplyArgs { name, .. })
+            | Commands::Snapshot(SnapshotArgs { name, .. })
+            | Commands::Restore(RestoreArgs { name, .. })
+            | Commands::Limits(LimitsArgs { name, .. })
+            | Commands::Network(NetworkArgs { name, .. })
+            | Commands::Env(EnvArgs { name, .. })
+            | Commands::Secrets(SecretsArgs { name, .. })
+            | Commands::Diff(DiffArgs { name, .. })
+            | Commands::Path { name, .. } => name,
+            Commands::Stop(StopArgs { names, .. })
+            | Commands::Kill(KillArgs { names })
+            | Commands::Remove(RemoveArgs { names, .. }) => names.first()?,
+            Commands::Ports(PortsArgs { action, name }) => match action {
+                Some(PortsAction::Add { name, .. } | PortsAction::Remove { name, .. }) => name,
+                None => name.as_ref()?,
+            },
+            Commands::Cp(args) => return args.session().map(|(name, _)| name),
+            Commands::External(args) => return args.first()?.to_str(),
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Expand session name prefixes and fuzzy matches to the full name for
+    /// the commands that accept them. Commands that stop or delete sessions
+    /// take only unique prefixes, so a typo can't pick another session.
+    fn resolve_session_names(&mut self) -> Result<()> {
+        let (names, fuzzy): (Vec<&mut String>, bool) = match self {
+            Commands::Resume(ResumeArgs { name, .. }) | Commands::Path { name, .. } => {
+                (vec![name], true)
+            }
+            Commands::Stop(StopArgs { names, .. })
+            | Commands::Kill(KillArgs { names })
+            | Commands::Remove(RemoveArgs { names, .. }) => (names.iter_mut().collect(), false),
+            _ => return Ok(()),
+        };
+        for name in names {
+            let resolved = session::resolve_name(name, fuzzy)?;
+            if resolved != *name {
+                eprintln!("\x1b[2msession:\x1b[0m {}", resolved);
+                *name = resolved;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set up for the existing session `cmd` operates on: select its docker
+/// context, and refuse commands the Kubernetes backend doesn't handle.
+fn prepare_session(cmd: &Commands) -> Result<()> {
+    let Some(name) = cmd.session_name() else {
+        return Ok(());
+    };
+    let Some(sess) = select_context(name) else {
+        return Ok(());
+    };
+    let kube_supported = matches!(
+        cmd,
+        Commands::Resume(_)
+            | Commands::Attach(_)
+            | Commands::Remove(_)
+            | Commands::Stop(_)
+            | Commands::Kill(_)
+            | Commands::Exec(_)
+            | Commands::Shell(_)
+            | Commands::Logs(_)
+            | Commands::Inspect(_)
+            | Commands::Note(_)
+            | Commands::Edit(_)
+            | Commands::Env(_)
+            | Commands::Limits(_)
+            | Commands::External(_)
+    );
+    if sess.kube && !kube_supported {
+        bail!(
+            "Session '{}' runs on Kubernetes, which doesn't support this command yet.",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Point docker at the context session `name` was created in.
+fn select_context(name: &str) -> Option<session::Session> {
+    session::validate_name(name).ok()?;
+    let sess = session::load(name).ok()?;
+    docker::use_context(sess.context.as_deref());
+    Some(sess)
+}
+
+#[derive(clap::Args, Debug)]
+struct CpArgs {
+    /// Source: a host path, or NAME:PATH in a session
+    #[arg(value_name = "SRC")]
+    src: String,
+
+    /// Destination: a host path, or NAME:PATH in a session
+    #[arg(value_name = "DEST")]
+    dest: String,
+}
+
+impl CpArgs {
+    /// The session side of the copy as `(name, path)`, if exactly one side
+    /// names a session.
+    fn session(&self) -> Option<(&str, &str)> {
+        match (session_path(&self.src), session_path(&self.dest)) {
+            (Some(side), None) | (None, Some(side)) => Some(side),
+            _ => None,
+        }
+    }
+}
+
+/// Split `NAME:PATH` into a session name and a container path. Anything that
+/// doesn't start with a valid session name and a colon is a host path.
+fn session_path(arg: &str) -> Option<(&str, &str)> {
+    let (name, path) = arg.split_once(':')?;
+    session::validate_name(name).ok()?;
+    Some((name, path))
+}
+
+/// Absolute container path for `path`; relative paths are taken from the
+/// workspace, the container's working directory.
+fn container_path(mount_path: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else if path.is_empty() || path == "." {
+        mount_path.to_string()
+    } else {
+        format!("{}/{}", mount_path.trim_end_matches('/'), path)
+    }
+}
+
+/// Where `container_path` lives in a bind-mounted workspace on the host, or
+/// `None` if it is outside the mount.
+fn workspace_host_path(
+    workspace: &Path,
+    mount_path: &str,
+    container_path: &str,
+) -> Option<std::path::PathBuf> {
+    let rel = Path::new(container_path).strip_prefix(mount_path).ok()?;
+    if rel
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        return None;
+    }
+    let mut host = workspace.join(rel);
+    // Keep `dir/.` meaning "the contents of dir", as with cp and docker cp
+    if container_path.ends_with("/.") {
+        host.push(".");
+    }
+    Some(host)
+}
+
 #[derive(clap::Args, Debug)]
 struct CreateArgs {
-    /// Session name
-    name: String,
+    /// Session name (prompted for when omitted)
+    name: Option<String>,
+
+    /// Prompt for the name, image and command interactively
+    #[arg(short, long)]
+    interactive: bool,
 
     /// Run container in the background (detached)
     #[arg(short = 'd')]
     detach: bool,
 
-    /// Docker image to use (default: $BOX_DEFAULT_IMAGE or alpine:latest)
+    /// Docker image to use (default: $BOX_DEFAULT_IMAGE, else one matching the project type)
     #[arg(long)]
     image: Option<String>,
 
+    /// Resolve the image to its sha256 digest now and keep using that digest on resumes
+    #[arg(long = "pin-digest")]
+    pin_digest: bool,
+
+    /// Free-text note describing what the session is for
+    #[arg(long)]
+    note: Option<String>,
+
     /// Extra Docker flags (e.g. -e KEY=VALUE, -v /host:/container, --network host).
     /// Overrides $BOX_DOCKER_ARGS when provided.
     #[arg(long = "docker-args", allow_hyphen_values = true)]
@@ -81,14 +373,152 @@ struct CreateArgs {
     #[arg(long = "no-ssh")]
     no_ssh: bool,
 
+    /// Enable SSH agent forwarding, overriding `ssh = false` in the global config
+    #[arg(long, conflicts_with = "no_ssh")]
+    ssh: bool,
+
+    /// Create the workspace with `git worktree add` instead of `git clone --local`
+    #[arg(long)]
+    worktree: bool,
+
+    /// Branch, tag or commit to check out in the workspace instead of the current HEAD
+    #[arg(long, value_name = "REF")]
+    branch: Option<String>,
+
+    /// Clone this remote into the workspace instead of the current repository,
+    /// so the session can be created outside one
+    #[arg(long, conflicts_with_all = ["worktree", "volume"])]
+    url: Option<String>,
+
+    /// Clone only the last N commits into the workspace (0 for full history)
+    #[arg(long, value_name = "N", conflicts_with = "worktree")]
+    depth: Option<u32>,
+
+    /// Partial clone filter for the workspace (e.g. blob:none), fetching the rest on demand
+    #[arg(long, value_name = "SPEC", value_parser = parse_clone_filter, conflicts_with = "worktree")]
+    filter: Option<String>,
+
+    /// Keep the workspace in a Docker volume instead of a bind mount (faster on macOS)
+    #[arg(long, conflicts_with = "worktree")]
+    volume: bool,
+
+    /// Run as your host uid:gid so files in the workspace stay owned by you
+    #[arg(long = "user-map")]
+    user_map: bool,
+
+    /// Network mode, kept across resumes: none (no network), bridge, host, or a docker network name
+    #[arg(long, value_parser = parse_network)]
+    network: Option<String>,
+
+    /// Sandbox untrusted code: drop all capabilities, no privilege escalation, read-only root
+    /// filesystem (tmpfs /tmp), and no network unless --network is given
+    #[arg(long)]
+    hardened: bool,
+
+    /// Forward the host's gpg-agent so commits can be signed in the container
+    #[arg(long)]
+    gpg: bool,
+
+    /// Forward the host's git credentials (credential helper or `gh auth token`) for HTTPS remotes
+    #[arg(long = "git-auth")]
+    git_auth: bool,
+
+    /// Mount the host's Docker socket so the box can run containers (gives it control of the host daemon)
+    #[arg(long = "docker-socket", conflicts_with_all = ["dind", "hardened"])]
+    docker_socket: bool,
+
+    /// Run privileged so a nested Docker daemon can run inside (weakens the sandbox)
+    #[arg(long, conflicts_with = "hardened")]
+    dind: bool,
+
+    /// Docker context to run the session in (e.g. a remote host), kept across resumes
+    #[arg(long, value_parser = parse_context)]
+    context: Option<String>,
+
+    /// Run the session as a pod in kubectl's current cluster and namespace (experimental)
+    #[arg(
+        long,
+        conflicts_with_all = ["worktree", "branch", "url", "depth", "filter", "volume", "user_map", "network", "context", "publish", "secret", "cache", "hardened", "gpg", "git_auth", "docker_socket", "dind", "pin_digest"]
+    )]
+    kube: bool,
+
+    /// Stop the session after this many idle minutes under `box autostop` (0 disables)
+    #[arg(long = "idle-timeout", value_name = "MINUTES")]
+    idle_timeout: Option<u64>,
+
+    /// CPU limit (e.g. 2 or 1.5), kept across resumes
+    #[arg(long, value_parser = parse_cpus)]
+    cpus: Option<String>,
+
+    /// Memory limit (e.g. 4g or 512m), kept across resumes
+    #[arg(long, value_parser = parse_memory)]
+    memory: Option<String>,
+
+    /// Publish a container port ([ip:]host:container[/proto]), kept across resumes
+    #[arg(short = 'p', long = "publish", value_parser = parse_publish)]
+    publish: Vec<String>,
+
+    /// Environment variable (KEY=VALUE, or KEY to pass through the host's value), kept across resumes
+    #[arg(short = 'e', long = "env", value_parser = parse_env)]
+    env: Vec<String>,
+
+    /// Mount a host file read-only at /run/secrets/NAME (NAME=/path/on/host)
+    #[arg(long = "secret", value_parser = parse_secret)]
+    secret: Vec<String>,
+
+    /// Mount a shared cache volume (cargo, npm, pip, go, maven, gradle, or NAME:/container/path)
+    #[arg(long = "cache", value_parser = parse_cache)]
+    cache: Vec<String>,
+
+    /// Skip installing the configured dotfiles
+    #[arg(long = "no-dotfiles")]
+    no_dotfiles: bool,
+
+    /// Start from a saved template (see `box template save`)
+    #[arg(long, short = 't')]
+    template: Option<String>,
+
     /// Command to run in container (default: $BOX_DEFAULT_CMD if set)
     #[arg(last = true)]
     cmd: Vec<String>,
 }
 
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Docker image to use (default: $BOX_DEFAULT_IMAGE, else one matching the project type)
+    #[arg(long)]
+    image: Option<String>,
+
+    /// Branch, tag or commit to check out in the workspace instead of the current HEAD
+    #[arg(long, value_name = "REF")]
+    branch: Option<String>,
+
+    /// Environment variable (KEY=VALUE, or KEY to pass through the host's value)
+    #[arg(short = 'e', long = "env", value_parser = parse_env)]
+    env: Vec<String>,
+
+    /// Network mode: none (no network), bridge, host, or a docker network name
+    #[arg(long, value_parser = parse_network)]
+    network: Option<String>,
+
+    /// Drop all capabilities, no privilege escalation, read-only root filesystem
+    /// (tmpfs /tmp), and no network unless --network is given
+    #[arg(long)]
+    hardened: bool,
+
+    /// Extra Docker flags (e.g. -v /host:/container). Overrides $BOX_DOCKER_ARGS when provided.
+    #[arg(long = "docker-args", allow_hyphen_values = true)]
+    docker_args: Option<String>,
+
+    /// Command to run in the container
+    #[arg(last = true, required = true)]
+    cmd: Vec<String>,
+}
+
 #[derive(clap::Args, Debug)]
 struct ResumeArgs {
     /// Session name
+    #[arg(value_name = "SESSION")]
     name: String,
 
     /// Run container in the background (detached)
@@ -99,23 +529,64 @@ struct ResumeArgs {
     /// Overrides $BOX_DOCKER_ARGS when provided.
     #[arg(long = "docker-args", allow_hyphen_values = true)]
     docker_args: Option<String>,
+
+    /// Turn SSH agent forwarding off for this session (recreates the container)
+    #[arg(long = "no-ssh")]
+    no_ssh: bool,
+
+    /// Turn SSH agent forwarding on for this session (recreates the container)
+    #[arg(long, conflicts_with = "no_ssh")]
+    ssh: bool,
 }
 
 #[derive(clap::Args, Debug)]
 struct RemoveArgs {
-    /// Session name
-    name: String,
+    /// Session names
+    #[arg(value_name = "SESSION", required = true)]
+    names: Vec<String>,
+
+    /// Stop running sessions first instead of refusing to remove them
+    #[arg(short, long)]
+    force: bool,
 }
 
 #[derive(clap::Args, Debug)]
 struct StopArgs {
+    /// Session names
+    #[arg(
+        value_name = "SESSION",
+        required_unless_present = "all",
+        conflicts_with = "all"
+    )]
+    names: Vec<String>,
+
+    /// Stop every running session
+    #[arg(long)]
+    all: bool,
+
+    /// Seconds to wait for the session to exit before killing it
+    #[arg(short = 't', long, value_name = "SECONDS")]
+    timeout: Option<u32>,
+}
+
+#[derive(clap::Args, Debug)]
+struct KillArgs {
+    /// Session names
+    #[arg(value_name = "SESSION", required = true)]
+    names: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SessionArgs {
     /// Session name
+    #[arg(value_name = "SESSION")]
     name: String,
 }
 
 #[derive(clap::Args, Debug)]
 struct ExecArgs {
     /// Session name
+    #[arg(value_name = "SESSION")]
     name: String,
 
     /// Command to run in the container
@@ -124,1016 +595,6120 @@ struct ExecArgs {
 }
 
 #[derive(clap::Args, Debug)]
-struct ListArgs {
-    /// Show only running sessions
-    #[arg(long, short)]
-    running: bool,
-    /// Show only stopped sessions
-    #[arg(long, short)]
-    stopped: bool,
-    /// Only print session names
-    #[arg(long, short)]
-    quiet: bool,
-}
+struct LogsArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-#[derive(Subcommand, Debug)]
-enum ConfigShell {
-    /// Output Zsh completions
-    Zsh,
-    /// Output Bash completions
-    Bash,
+    /// Follow log output
+    #[arg(short, long)]
+    follow: bool,
+
+    /// Number of lines to show from the end (or "all")
+    #[arg(short = 'n', long)]
+    tail: Option<String>,
+
+    /// Show logs since a timestamp (e.g. 2024-01-02T13:23:37Z) or relative time (e.g. 10m)
+    #[arg(long)]
+    since: Option<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(clap::Args, Debug)]
+struct CloneArgs {
+    /// Session to copy
+    #[arg(value_name = "SESSION")]
+    src: String,
 
-    let result = match cli.command {
-        Some(Commands::Create(args)) => {
-            let docker_args = args
-                .docker_args
-                .or_else(|| std::env::var("BOX_DOCKER_ARGS").ok())
-                .unwrap_or_default();
-            let cmd = if args.cmd.is_empty() {
-                None
-            } else {
-                Some(args.cmd)
-            };
-            cmd_create(
-                &args.name,
-                args.image,
-                &docker_args,
-                cmd,
-                !args.no_ssh,
-                args.detach,
-            )
-        }
-        Some(Commands::Resume(args)) => {
-            let docker_args = args
-                .docker_args
-                .or_else(|| std::env::var("BOX_DOCKER_ARGS").ok())
-                .unwrap_or_default();
-            cmd_resume(&args.name, &docker_args, args.detach)
-        }
-        Some(Commands::Remove(args)) => cmd_remove(&args.name),
-        Some(Commands::Stop(args)) => cmd_stop(&args.name),
-        Some(Commands::Exec(args)) => cmd_exec(&args.name, &args.cmd),
-        Some(Commands::List(args)) => cmd_list_sessions(&args),
-        Some(Commands::Cd { name }) => cmd_cd(&name),
-        Some(Commands::Path { name }) => cmd_path(&name),
-        Some(Commands::Upgrade) => cmd_upgrade(),
-        Some(Commands::Config { shell }) => match shell {
-            ConfigShell::Zsh => cmd_config_zsh(),
-            ConfigShell::Bash => cmd_config_bash(),
-        },
-        Some(Commands::External(args)) => {
-            let name = args[0].to_string_lossy().to_string();
-            let docker_args = std::env::var("BOX_DOCKER_ARGS").unwrap_or_default();
-            if session::session_exists(&name).unwrap_or(false) {
-                cmd_resume(&name, &docker_args, false)
-            } else {
-                let cmd: Vec<String> = args[1..]
-                    .iter()
-                    .skip_while(|a| *a != "--")
-                    .skip(1)
-                    .map(|a| a.to_string_lossy().to_string())
-                    .collect();
-                let cmd = if cmd.is_empty() { None } else { Some(cmd) };
-                cmd_create(&name, None, &docker_args, cmd, true, false)
-            }
-        }
-        None => cmd_list(),
-    };
+    /// Name of the new session
+    dst: String,
 
-    match result {
-        Ok(code) => std::process::exit(code),
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }
-    }
-}
+    /// Snapshot the source container's filesystem and use it as the new session's image
+    #[arg(long)]
+    commit: bool,
 
-fn output_cd_path(path: &str) {
-    if let Ok(cd_file) = std::env::var("BOX_CD_FILE") {
-        let _ = fs::write(cd_file, path);
-    } else {
-        println!("{}", path);
-    }
+    /// Re-clone the workspace from the project instead of copying the source workspace
+    #[arg(long)]
+    fresh: bool,
 }
 
-fn cmd_list() -> Result<i32> {
-    let mut sessions = session::list()?;
+#[derive(clap::Args, Debug)]
+struct CommitArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    docker::check()?;
-    let running = docker::running_sessions();
-    for s in &mut sessions {
-        s.running = running.contains(&s.name);
-    }
+    /// Image tag (default: box-<name>:<timestamp>)
+    tag: Option<String>,
 
-    let delete_fn = |name: &str| -> Result<()> {
-        docker::remove_container(name);
-        docker::remove_workspace(name);
-        session::remove_dir(name)?;
-        Ok(())
-    };
+    /// Make the snapshot the session's image, used when its container is next recreated
+    #[arg(long = "use")]
+    use_image: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    let docker_args = std::env::var("BOX_DOCKER_ARGS").unwrap_or_default();
+    /// Show a diffstat
+    #[arg(long)]
+    stat: bool,
 
-    match tui::session_manager(&sessions, delete_fn)? {
-        tui::TuiAction::Resume(name) => cmd_resume(&name, &docker_args, false),
-        tui::TuiAction::New {
-            name,
-            image,
-            command,
-        } => cmd_create(&name, image, &docker_args, command, true, false),
-        tui::TuiAction::Cd(name) => cmd_cd(&name),
-        tui::TuiAction::Quit => Ok(0),
-    }
+    /// Show the patch (default unless --stat is given)
+    #[arg(long, short)]
+    patch: bool,
 }
 
-fn cmd_list_sessions(args: &ListArgs) -> Result<i32> {
-    let mut sessions = session::list()?;
+#[derive(clap::Args, Debug)]
+struct NoteArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    docker::check()?;
-    let running = docker::running_sessions();
-    for s in &mut sessions {
-        s.running = running.contains(&s.name);
-    }
+    /// New note text (opens $EDITOR when omitted on a terminal)
+    #[arg(conflicts_with = "clear")]
+    text: Option<String>,
 
-    if args.running {
-        sessions.retain(|s| s.running);
-    }
-    if args.stopped {
-        sessions.retain(|s| !s.running);
-    }
+    /// Remove the note
+    #[arg(long)]
+    clear: bool,
+}
 
-    if args.quiet {
-        for s in &sessions {
-            println!("{}", s.name);
-        }
-        return Ok(0);
-    }
+#[derive(clap::Args, Debug)]
+struct NetworkArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    if sessions.is_empty() {
-        println!("No sessions found.");
-        return Ok(0);
-    }
+    /// New mode: none, bridge, host, or a docker network name (applied on next resume)
+    #[arg(value_parser = parse_network)]
+    mode: Option<String>,
+}
 
-    let home = config::home_dir().unwrap_or_default();
+#[derive(clap::Args, Debug)]
+struct LimitsArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    // Compute column widths
-    let name_w = sessions
-        .iter()
-        .map(|s| s.name.len())
-        .max()
-        .unwrap_or(0)
-        .max(4);
-    let status_w = 7; // "running" or "stopped"
-    let image_w = sessions
-        .iter()
-        .map(|s| s.image.len())
-        .max()
-        .unwrap_or(0)
-        .max(5);
+    /// CPU limit (e.g. 2 or 1.5); 0 removes the limit
+    #[arg(long, value_parser = parse_cpus)]
+    cpus: Option<String>,
 
-    let shorten_home = |p: &str| -> String {
-        if !home.is_empty() {
-            if let Some(rest) = p.strip_prefix(&home) {
-                return format!("~{}", rest);
-            }
-        }
-        p.to_string()
-    };
+    /// Memory limit (e.g. 4g or 512m); 0 removes the limit
+    #[arg(long, value_parser = parse_memory)]
+    memory: Option<String>,
+}
 
-    let project_w = sessions
-        .iter()
-        .map(|s| shorten_home(&s.project_dir).len())
-        .max()
-        .unwrap_or(0)
-        .max(7);
-    let command_w = sessions
-        .iter()
-        .map(|s| s.command.len())
-        .max()
-        .unwrap_or(0)
-        .max(7);
+#[derive(clap::Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct PortsArgs {
+    #[command(subcommand)]
+    action: Option<PortsAction>,
 
-    println!(
-        "{:<name_w$}  {:<status_w$}  {:<image_w$}  {:<project_w$}  {:<command_w$}  CREATED",
-        "NAME", "STATUS", "IMAGE", "PROJECT", "COMMAND",
-    );
+    /// Session name
+    #[arg(value_name = "SESSION", required = true)]
+    name: Option<String>,
+}
 
-    for s in &sessions {
-        let status = if s.running { "running" } else { "stopped" };
-        let project = shorten_home(&s.project_dir);
-        println!(
-            "{:<name_w$}  {:<status_w$}  {:<image_w$}  {:<project_w$}  {:<command_w$}  {}",
-            s.name, status, s.image, project, s.command, s.created_at,
-        );
-    }
+#[derive(Subcommand, Debug)]
+enum PortsAction {
+    /// Publish ports (applied on next resume)
+    Add {
+        /// Session name
+        #[arg(value_name = "SESSION")]
+        name: String,
+        /// Port specs ([ip:]host:container[/proto])
+        #[arg(required = true, value_parser = parse_publish)]
+        specs: Vec<String>,
+    },
+    /// Stop publishing ports (applied on next resume)
+    Remove {
+        /// Session name
+        #[arg(value_name = "SESSION")]
+        name: String,
+        /// Port specs as shown by `box ports <name>`
+        #[arg(required = true)]
+        specs: Vec<String>,
+    },
+}
 
-    Ok(0)
+#[derive(clap::Args, Debug)]
+struct EnvArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
+
+    #[command(subcommand)]
+    action: Option<EnvAction>,
 }
 
-fn cmd_create(
-    name: &str,
-    image: Option<String>,
-    docker_args: &str,
-    cmd: Option<Vec<String>>,
-    ssh: bool,
-    detach: bool,
-) -> Result<i32> {
-    session::validate_name(name)?;
+#[derive(Subcommand, Debug)]
+enum EnvAction {
+    /// List the session's environment variables (default)
+    List,
+    /// Set variables (applied on next resume)
+    Set {
+        /// KEY=VALUE, or KEY to pass through the host's value
+        #[arg(required = true, value_parser = parse_env)]
+        vars: Vec<String>,
+    },
+    /// Remove variables (applied on next resume)
+    Unset {
+        /// Variable names
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
 
-    if session::session_exists(name)? {
-        bail!(
-            "Session '{}' already exists. Use `box resume {}` to resume it.",
-            name,
-            name
-        );
+/// Accept a `docker run --env` entry: `KEY=VALUE` or a bare `KEY`.
+fn parse_env(s: &str) -> Result<String, String> {
+    let key = env_key(s);
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return Err(format!(
+            "invalid environment variable '{}' (e.g. FOO=bar)",
+            s
+        ));
     }
+    Ok(s.to_string())
+}
 
-    let cwd =
-        fs::canonicalize(".").map_err(|_| anyhow::anyhow!("Cannot resolve current directory."))?;
-
-    let project_dir = git::find_root(&cwd)
-        .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a git repository.", cwd.display()))?
-        .to_string_lossy()
-        .to_string();
+#[derive(clap::Args, Debug)]
+struct SecretsArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    docker::check()?;
+    #[command(subcommand)]
+    action: Option<SecretsAction>,
+}
 
-    let cfg = config::resolve(config::BoxConfigInput {
-        name: name.to_string(),
-        image,
-        mount_path: None,
-        project_dir,
-        command: cmd,
-        env: vec![],
-        ssh,
-    })?;
+#[derive(Subcommand, Debug)]
+enum SecretsAction {
+    /// List the session's secrets (default)
+    List,
+    /// Mount host files as secrets (applied on next resume)
+    Add {
+        /// NAME=/path/on/host
+        #[arg(required = true, value_parser = parse_secret)]
+        secrets: Vec<String>,
+    },
+    /// Stop mounting secrets (applied on next resume)
+    Remove {
+        /// Secret names
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+}
 
-    eprintln!("\x1b[2msession:\x1b[0m {}", cfg.name);
-    eprintln!("\x1b[2mimage:\x1b[0m {}", cfg.image);
-    eprintln!("\x1b[2mmount:\x1b[0m {}", cfg.mount_path);
-    if cfg.ssh {
-        eprintln!("\x1b[2mssh:\x1b[0m true");
-    }
-    if !cfg.command.is_empty() {
-        eprintln!("\x1b[2mcommand:\x1b[0m {}", shell_words::join(&cfg.command));
+/// Accept a secret mount: `NAME=/path/on/host`, where NAME becomes the file
+/// name under /run/secrets.
+fn parse_secret(s: &str) -> Result<String, String> {
+    let err = || format!("invalid secret '{}' (e.g. npmrc=~/.npmrc)", s);
+    let (name, path) = s.split_once('=').ok_or_else(err)?;
+    let valid_name = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid_name || path.is_empty() {
+        return Err(err());
     }
-    if !docker_args.is_empty() {
-        eprintln!("\x1b[2mdocker args:\x1b[0m {}", docker_args);
-    }
-    eprintln!();
+    Ok(s.to_string())
+}
 
-    let sess = session::Session::from(cfg);
-    session::save(&sess)?;
+fn parse_cache(s: &str) -> Result<String, String> {
+    config::resolve_cache(s)
+        .map(|_| s.to_string())
+        .map_err(|e| e.to_string())
+}
 
-    let home = config::home_dir()?;
-    let docker_args_opt = if docker_args.is_empty() {
-        None
-    } else {
-        Some(docker_args)
+/// Turn the host path of a `NAME=path` secret into an absolute one, failing
+/// if it doesn't exist.
+fn resolve_secret(spec: &str) -> Result<String> {
+    let (name, path) = spec.split_once('=').unwrap_or((spec, ""));
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => Path::new(&config::home_dir()?).join(rest),
+        None => Path::new(path).to_path_buf(),
     };
-
-    docker::remove_container(name);
-    docker::run_container(&docker::DockerRunConfig {
-        name,
-        project_dir: &sess.project_dir,
-        image: &sess.image,
-        mount_path: &sess.mount_path,
-        cmd: &sess.command,
-        env: &sess.env,
-        home: &home,
-        docker_args: docker_args_opt,
-        ssh: sess.ssh,
-        detach,
-    })
+    let path = fs::canonicalize(&path)
+        .map_err(|_| anyhow::anyhow!("Secret '{}': '{}' not found.", name, path.display()))?;
+    Ok(format!("{}={}", name, path.display()))
 }
 
-fn cmd_resume(name: &str, docker_args: &str, detach: bool) -> Result<i32> {
-    session::validate_name(name)?;
+fn secret_name(entry: &str) -> &str {
+    entry.split_once('=').map_or(entry, |(name, _)| name)
+}
 
-    let sess = session::load(name)?;
+fn env_key(entry: &str) -> &str {
+    entry.split_once('=').map_or(entry, |(key, _)| key)
+}
 
-    if !Path::new(&sess.project_dir).is_dir() {
-        bail!("Project directory '{}' no longer exists.", sess.project_dir);
+/// Accept a `docker run --publish` spec: `[ip:][host:]container[/proto]`,
+/// where ports may be ranges like `8000-8010`.
+fn parse_publish(s: &str) -> Result<String, String> {
+    let err = || {
+        format!(
+            "invalid port spec '{}' (e.g. 8080:80, 127.0.0.1:5432:5432)",
+            s
+        )
+    };
+    let (ports, proto) = match s.split_once('/') {
+        Some((ports, proto)) => (ports, Some(proto)),
+        None => (s, None),
+    };
+    if !matches!(proto, None | Some("tcp") | Some("udp") | Some("sctp")) {
+        return Err(err());
     }
-
-    docker::check()?;
-
-    if docker::container_is_running(name) {
-        if detach {
-            println!("Session '{}' is already running.", name);
-            return Ok(0);
+    let is_port = |p: &str| {
+        let mut bounds = p.splitn(2, '-');
+        bounds.all(|b| b.parse::<u16>().is_ok_and(|n| n > 0))
+    };
+    let mut parts = ports.rsplitn(3, ':');
+    let container = parts.next().unwrap_or("");
+    if !is_port(container) {
+        return Err(err());
+    }
+    if let Some(host) = parts.next() {
+        // An empty host port lets docker pick one (e.g. `127.0.0.1::80`)
+        let has_ip = parts.next().is_some_and(|ip| !ip.is_empty());
+        let valid = is_port(host) || (host.is_empty() && has_ip);
+        if !valid {
+            return Err(err());
         }
-        return docker::attach_container(name);
     }
+    Ok(s.to_string())
+}
 
-    println!("Resuming session '{}'...", name);
-    session::touch_resumed_at(name)?;
+/// Accept a non-negative decimal number of CPUs.
+fn parse_cpus(s: &str) -> Result<String, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Ok(s.to_string()),
+        _ => Err(format!("invalid CPU count '{}'", s)),
+    }
+}
 
-    if docker::container_exists(name) {
-        if detach {
-            docker::start_container_detached(name)
-        } else {
-            docker::start_container(name)
-        }
+/// Accept a docker context name.
+fn parse_context(s: &str) -> Result<String, String> {
+    let valid = s.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'));
+    if valid {
+        Ok(s.to_string())
     } else {
-        let home = config::home_dir()?;
-        let docker_args_opt = if docker_args.is_empty() {
-            None
-        } else {
-            Some(docker_args)
-        };
+        Err(format!("invalid docker context name '{}'", s))
+    }
+}
 
-        docker::remove_container(name);
-        docker::run_container(&docker::DockerRunConfig {
-            name,
-            project_dir: &sess.project_dir,
-            image: &sess.image,
-            mount_path: &sess.mount_path,
-            cmd: &sess.command,
-            env: &sess.env,
-            home: &home,
-            docker_args: docker_args_opt,
-            ssh: sess.ssh,
-            detach,
-        })
+/// Accept a `git clone --filter` spec such as `blob:none`, `blob:limit=1m`
+/// or `tree:0`.
+fn parse_clone_filter(s: &str) -> Result<String, String> {
+    let valid = s.split_once(':').is_some_and(|(kind, arg)| {
+        !kind.is_empty()
+            && kind.chars().all(|c| c.is_ascii_lowercase())
+            && !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '=' | '.' | '-' | '_'))
+    });
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "invalid filter '{}': expected a git clone filter such as blob:none",
+            s
+        ))
     }
 }
 
-fn cmd_remove(name: &str) -> Result<i32> {
-    session::validate_name(name)?;
+/// Accept a network mode: `none`, `bridge`, `host`, or the name of a
+/// user-defined docker network.
+fn parse_network(s: &str) -> Result<String, String> {
+    let valid = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "invalid network '{}' (none, bridge, host, or a docker network name)",
+            s
+        ))
+    }
+}
 
-    if !session::session_exists(name)? {
-        bail!("Session '{}' not found.", name);
+/// Accept a docker memory size: a number with an optional b/k/m/g suffix.
+fn parse_memory(s: &str) -> Result<String, String> {
+    let digits = s.trim_end_matches(['b', 'k', 'm', 'g', 'B', 'K', 'M', 'G']);
+    if !digits.is_empty()
+        && s.len() - digits.len() <= 1
+        && digits.chars().all(|c| c.is_ascii_digit())
+    {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid memory size '{}' (e.g. 512m, 4g)", s))
     }
+}
 
-    docker::check()?;
+#[derive(clap::Args, Debug)]
+struct SyncArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    if docker::container_is_running(name) {
-        bail!(
-            "Session '{}' is still running. Stop it first with `box stop {}`.",
-            name,
-            name
-        );
-    }
+    /// Create a branch in the host repo at the workspace HEAD
+    #[arg(long, short)]
+    branch: Option<String>,
 
-    docker::remove_container(name);
-    docker::remove_workspace(name);
-    session::remove_dir(name)?;
+    /// Fast-forward the host repo's current branch to the workspace HEAD
+    #[arg(long, conflicts_with = "branch")]
+    ff: bool,
 
-    println!("Session '{}' removed.", name);
-    Ok(0)
-}
+    /// Keep the host checkout and the workspace's files in sync both ways until interrupted
+    #[arg(long, conflicts_with_all = ["branch", "ff"])]
+    watch: bool,
 
-fn cmd_stop(name: &str) -> Result<i32> {
-    session::validate_name(name)?;
+    /// With --watch, which side wins when a file changed on both: newer, host or workspace
+    #[arg(long, value_name = "POLICY", value_parser = parse_conflict, default_value = "newer", requires = "watch")]
+    conflict: filesync::Conflict,
 
-    if !session::session_exists(name)? {
-        bail!("Session '{}' not found.", name);
-    }
+    /// With --watch, seconds between checks for changes
+    #[arg(long, value_name = "SECS", default_value_t = 1, requires = "watch")]
+    interval: u64,
+}
 
-    docker::check()?;
+#[derive(clap::Args, Debug)]
+struct PushArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    if !docker::container_is_running(name) {
-        bail!("Session '{}' is not running.", name);
-    }
+    /// Make the pushed branch the upstream of the workspace branch
+    #[arg(long, short = 'u')]
+    set_upstream: bool,
 
-    docker::stop_container(name)
+    /// Push HEAD to this branch on origin instead of the current branch
+    #[arg(long, short)]
+    branch: Option<String>,
 }
 
-fn cmd_exec(name: &str, cmd: &[String]) -> Result<i32> {
-    session::validate_name(name)?;
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    if !session::session_exists(name)? {
-        bail!("Session '{}' not found.", name);
-    }
+    /// Create this branch in the host repo and apply the changes there
+    #[arg(long, short)]
+    branch: Option<String>,
+}
 
-    docker::check()?;
+#[derive(clap::Args, Debug)]
+struct SnapshotArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    if !docker::container_is_running(name) {
-        bail!("Session '{}' is not running.", name);
-    }
+    /// Snapshot name (default: the current date and time)
+    #[arg(value_name = "SNAPSHOT", conflicts_with_all = ["list", "remove"])]
+    snapshot: Option<String>,
 
-    docker::exec_container(name, cmd)
+    /// List the session's snapshots
+    #[arg(long, short)]
+    list: bool,
+
+    /// Delete this snapshot
+    #[arg(long, value_name = "SNAPSHOT", conflicts_with = "list")]
+    remove: Option<String>,
 }
 
-fn cmd_cd(name: &str) -> Result<i32> {
-    session::validate_name(name)?;
-    if !session::session_exists(name)? {
-        bail!("Session '{}' not found.", name);
-    }
-    let home = config::home_dir()?;
-    let path = Path::new(&home).join(".box").join("workspaces").join(name);
-    output_cd_path(&path.to_string_lossy());
-    Ok(0)
+#[derive(clap::Args, Debug)]
+struct RestoreArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
+
+    /// Snapshot to restore
+    #[arg(value_name = "SNAPSHOT")]
+    snapshot: String,
 }
 
-fn cmd_path(name: &str) -> Result<i32> {
-    session::validate_name(name)?;
-    if !session::session_exists(name)? {
-        bail!("Session '{}' not found.", name);
-    }
-    let home = config::home_dir()?;
-    let path = Path::new(&home).join(".box").join("workspaces").join(name);
-    println!("{}", path.display());
-    Ok(0)
+fn parse_conflict(s: &str) -> Result<filesync::Conflict, String> {
+    filesync::Conflict::parse(s).ok_or_else(|| {
+        format!(
+            "invalid conflict policy '{}' (expected newer, host or workspace)",
+            s
+        )
+    })
 }
 
-fn cmd_config_zsh() -> Result<i32> {
-    print!(
-        r#"__box_sessions() {{
-    local -a sessions
-    if [[ -d "$HOME/.box/sessions" ]]; then
-        for s in "$HOME/.box/sessions"/*(N:t); do
-            local desc=""
-            if [[ -f "$HOME/.box/sessions/$s/project_dir" ]]; then
-                desc=$(< "$HOME/.box/sessions/$s/project_dir")
-                desc=${{desc/#$HOME/\~}}
-            fi
-            sessions+=("$s:[$desc]")
-        done
-    fi
-    if (( ${{#sessions}} )); then
-        _describe 'session' sessions
-    fi
-}}
+#[derive(clap::Args, Debug)]
+struct SshArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-_box() {{
-    local curcontext="$curcontext" state line
-    typeset -A opt_args
+    /// Print the ssh command instead of running it
+    #[arg(long)]
+    print: bool,
+}
 
-    _arguments -C \
-        '1: :->subcmd' \
-        '*:: :->args'
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    /// Session name
+    #[arg(value_name = "SESSION")]
+    name: String,
 
-    case $state in
-        subcmd)
-            __box_sessions
-            ;;
-        args)
-            case $words[1] in
-                create)
-                    _arguments \
-                        '-d[Run container in the background]' \
-                        '--image=[Docker image to use]:image' \
-                        '--docker-args=[Extra Docker flags]:args' \
-                        '--no-ssh[Disable SSH agent forwarding]' \
-                        '1:session name:' \
-                        '*:command:'
-                    ;;
-                resume)
-                    _arguments \
-                        '-d[Run container in the background]' \
-                        '--docker-args=[Extra Docker flags]:args' \
-                        '1:session name:__box_sessions'
-                    ;;
-                exec)
-                    _arguments \
-                        '1:session name:__box_sessions' \
-                        '*:command:'
-                    ;;
-                list|ls)
-                    _arguments \
-                        '--running[Show only running sessions]' \
-                        '-r[Show only running sessions]' \
-                        '--stopped[Show only stopped sessions]' \
-                        '-s[Show only stopped sessions]' \
-                        '--quiet[Only print session names]' \
-                        '-q[Only print session names]'
-                    ;;
-                remove|stop|path|cd)
-                    if (( CURRENT == 2 )); then
-                        __box_sessions
-                    fi
-                    ;;
-                config)
-                    if (( CURRENT == 2 )); then
-                        local -a shells
-                        shells=('zsh:Zsh completion script' 'bash:Bash completion script')
-                        _describe 'shell' shells
-                    fi
-                    ;;
-            esac
-            ;;
-    esac
-}}
-compdef _box box
+    /// Output as JSON
+    #[arg(long)]
+    json: bool,
+}
 
-box() {{
-    local __box_cd_file
-    __box_cd_file=$(mktemp "/tmp/.box-cd.XXXXXX")
-    BOX_CD_FILE="$__box_cd_file" command box "$@"
-    local __box_exit=$?
-    if [[ -s "$__box_cd_file" ]]; then
-        local __box_dir
-        __box_dir=$(<"$__box_cd_file")
-        cd "$__box_dir"
-    fi
-    rm -f "$__box_cd_file"
-    return $__box_exit
-}}
-"#
-    );
-    Ok(0)
+#[derive(clap::Args, Debug)]
+struct PruneArgs {
+    /// Remove without asking for confirmation
+    #[arg(short, long)]
+    force: bool,
 }
 
-fn cmd_config_bash() -> Result<i32> {
-    print!(
-        r#"_box() {{
-    local cur prev words cword
-    _init_completion || return
-
-    local subcommands="create resume remove stop exec list cd path upgrade config"
-    local session_cmds="resume remove stop exec cd path"
-
-    if [[ $cword -eq 1 ]]; then
-        local sessions=""
-        if [[ -d "$HOME/.box/sessions" ]]; then
-            sessions=$(command ls "$HOME/.box/sessions" 2>/dev/null)
-        fi
-        COMPREPLY=($(compgen -W "$sessions" -- "$cur"))
-        return
-    fi
+#[derive(clap::Args, Debug)]
+struct GcArgs {
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long = "dry-run")]
+    dry_run: bool,
+
+    /// Remove orphaned workspaces not modified for this many days (default: 7, or `gc_older_than` in the global config)
+    #[arg(long = "older-than", value_name = "DAYS")]
+    older_than: Option<u64>,
+}
 
-    local subcmd="${{words[1]}}"
+#[derive(clap::Args, Debug)]
+struct InitArgs {
+    /// Overwrite an existing .box.toml
+    #[arg(long, short)]
+    force: bool,
+}
 
-    case "$subcmd" in
-        create)
-            case "$cur" in
-                -*)
-                    COMPREPLY=($(compgen -W "-d --image --docker-args --no-ssh" -- "$cur"))
-                    ;;
-            esac
-            ;;
-        resume)
-            case "$cur" in
-                -*)
-                    COMPREPLY=($(compgen -W "-d --docker-args" -- "$cur"))
-                    ;;
-                *)
-                    if [[ $cword -eq 2 ]]; then
-                        local sessions=""
-                        if [[ -d "$HOME/.box/sessions" ]]; then
-                            sessions=$(command ls "$HOME/.box/sessions" 2>/dev/null)
-                        fi
-                        COMPREPLY=($(compgen -W "$sessions" -- "$cur"))
-                    fi
-                    ;;
-            esac
-            ;;
-        exec)
-            if [[ $cword -eq 2 ]]; then
-                local sessions=""
-                if [[ -d "$HOME/.box/sessions" ]]; then
-                    sessions=$(command ls "$HOME/.box/sessions" 2>/dev/null)
-                fi
-                COMPREPLY=($(compgen -W "$sessions" -- "$cur"))
-            fi
-            ;;
-        list|ls)
-            case "$cur" in
-                -*)
-                    COMPREPLY=($(compgen -W "--running -r --stopped -s --quiet -q" -- "$cur"))
-                    ;;
-            esac
-            ;;
-        remove|stop|path|cd)
-            if [[ $cword -eq 2 ]]; then
-                local sessions=""
-                if [[ -d "$HOME/.box/sessions" ]]; then
-                    sessions=$(command ls "$HOME/.box/sessions" 2>/dev/null)
-                fi
-                COMPREPLY=($(compgen -W "$sessions" -- "$cur"))
-            fi
-            ;;
-        config)
-            if [[ $cword -eq 2 ]]; then
-                COMPREPLY=($(compgen -W "zsh bash" -- "$cur"))
-            fi
-            ;;
-    esac
-}}
-complete -F _box box
+#[derive(clap::Args, Debug)]
+struct AutostopArgs {
+    /// Seconds between activity checks
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    interval: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    /// Show only running sessions
+    #[arg(long, short)]
+    running: bool,
+    /// Show only stopped sessions
+    #[arg(long, short)]
+    stopped: bool,
+    /// Only print session names
+    #[arg(long, short)]
+    quiet: bool,
+    /// Print sessions as JSON
+    #[arg(long, conflicts_with = "quiet")]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplateAction {
+    /// Save a template from flags and/or an existing session
+    Save(TemplateSaveArgs),
+    /// List saved templates
+    #[command(alias = "ls")]
+    List,
+    /// Remove a saved template, or a template registry with --registry
+    Remove {
+        /// Template name
+        #[arg(value_name = "TEMPLATE", required_unless_present = "registry")]
+        name: Option<String>,
+
+        /// Remove the template registry with this name and its cache
+        #[arg(long, conflicts_with = "name")]
+        registry: Option<String>,
+    },
+    /// Add a shared template registry from a git URL, HTTPS URL, or org/repo on GitHub
+    Add {
+        /// Git URL, HTTPS base URL, or org/repo
+        source: String,
+
+        /// Registry name used in `--template <registry>/<template>` [default: the repo owner]
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Refresh cached templates from registries
+    Update {
+        /// Registry name [default: all registries]
+        registry: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct TemplateSaveArgs {
+    /// Template name
+    name: String,
+
+    /// Copy image, mount path, env, and command from an existing session
+    #[arg(long, value_name = "SESSION")]
+    from: Option<String>,
+
+    /// Docker image
+    #[arg(long)]
+    image: Option<String>,
+
+    /// Mount path inside the container
+    #[arg(long = "mount-path")]
+    mount_path: Option<String>,
+
+    /// Environment variable (KEY=VALUE), may be repeated
+    #[arg(long, short = 'e')]
+    env: Vec<String>,
+
+    /// Extra Docker flags
+    #[arg(long = "docker-args", allow_hyphen_values = true)]
+    docker_args: Option<String>,
+
+    /// Command to run in container
+    #[arg(last = true)]
+    cmd: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigShell {
+    /// Output Zsh completions
+    Zsh,
+    /// Output Bash completions
+    Bash,
+    /// Output Fish completions
+    Fish,
+    /// Output a Nushell completion module
+    Nu,
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    let prepared = cli.command.as_mut().map(|cmd| {
+        cmd.resolve_session_names()?;
+        prepare_session(cmd)
+    });
+    if let Some(Err(e)) = prepared {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    let result = match cli.command {
+        Some(Commands::Create(args)) => {
+            let cmd = if args.cmd.is_empty() {
+                None
+            } else {
+                Some(args.cmd)
+            };
+            let ssh = if args.no_ssh {
+                Some(false)
+            } else if args.ssh {
+                Some(true)
+            } else {
+                None
+            };
+            let interactive = args.interactive || args.name.is_none();
+            let name = args.name.unwrap_or_default();
+            let opts = CreateOptions {
+                image: args.image,
+                docker_args: args.docker_args,
+                cmd,
+                ssh,
+                template: args.template,
+                worktree: args.worktree.then_some(true),
+                branch: args.branch,
+                url: args.url,
+                clone_depth: args.depth,
+                clone_filter: args.filter,
+                volume: args.volume.then_some(true),
+                user_map: args.user_map.then_some(true),
+                network: args.network,
+                hardened: args.hardened,
+                gpg: args.gpg,
+                git_auth: args.git_auth,
+                docker_socket: args.docker_socket,
+                dind: args.dind,
+                pin_digest: args.pin_digest,
+                note: args.note,
+                idle_timeout: args.idle_timeout,
+                context: args.context,
+                kube: args.kube,
+                cpus: args.cpus,
+                memory: args.memory,
+                ports: args.publish,
+                env: args.env,
+                secrets: args.secret,
+                caches: args.cache,
+                no_dotfiles: args.no_dotfiles,
+                detach: args.detach,
+            };
+            if interactive {
+                cmd_create_interactive(&name, opts)
+            } else {
+                cmd_create(&name, opts)
+            }
+        }
+        Some(Commands::Resume(args)) => {
+            let ssh = if args.no_ssh {
+                Some(false)
+            } else if args.ssh {
+                Some(true)
+            } else {
+                None
+            };
+            cmd_resume(&args.name, args.docker_args, ssh, args.detach)
+        }
+        Some(Commands::Remove(args)) => {
+            for_each_session(&args.names, |name| cmd_remove(name, args.force))
+        }
+        Some(Commands::Attach(args)) => cmd_attach(&args.name),
+        Some(Commands::Stop(args)) => cmd_stop_many(&args),
+        Some(Commands::Kill(args)) => for_each_session(&args.names, cmd_kill),
+        Some(Commands::Pause(args)) => cmd_pause(&args.name, true),
+        Some(Commands::Unpause(args)) => cmd_pause(&args.name, false),
+        Some(Commands::Exec(args)) => cmd_exec(&args.name, &args.cmd),
+        Some(Commands::Logs(args)) => cmd_logs(&args),
+        Some(Commands::Shell(args)) => cmd_shell(&args.name),
+        Some(Commands::Wait(args)) => cmd_wait(&args.name),
+        Some(Commands::Run(args)) => cmd_run(args),
+        Some(Commands::Ssh(args)) => cmd_ssh(&args),
+        Some(Commands::Code { name }) => cmd_code(&name),
+        Some(Commands::Clone(args)) => cmd_clone(&args),
+        Some(Commands::Commit(args)) => cmd_commit(&args),
+        Some(Commands::Pull { name }) => cmd_pull(&name),
+        Some(Commands::Prune(args)) => cmd_prune(args.force),
+        Some(Commands::Migrate) => cmd_migrate(),
+        Some(Commands::Autostop(args)) => cmd_autostop(args.interval),
+        Some(Commands::Gc(args)) => cmd_gc(&args),
+        Some(Commands::Doctor) => cmd_doctor(),
+        Some(Commands::Init(args)) => cmd_init(args.force),
+        Some(Commands::Inspect(args)) => cmd_inspect(&args.name, args.json),
+        Some(Commands::Sync(args)) => cmd_sync(&args),
+        Some(Commands::Push(args)) => cmd_push(&args),
+        Some(Commands::Apply(args)) => cmd_apply(&args),
+        Some(Commands::Snapshot(args)) => cmd_snapshot(&args),
+        Some(Commands::Restore(args)) => cmd_restore(&args),
+        Some(Commands::Limits(args)) => cmd_limits(args),
+        Some(Commands::Ports(args)) => cmd_ports(args),
+        Some(Commands::Network(args)) => cmd_network(&args),
+        Some(Commands::Note(args)) => cmd_note(args),
+        Some(Commands::Edit(args)) => cmd_edit(&args.name),
+        Some(Commands::Env(args)) => cmd_env(args),
+        Some(Commands::Secrets(args)) => cmd_secrets(args),
+        Some(Commands::Diff(args)) => cmd_diff(&args),
+        Some(Commands::List(args)) => cmd_list_sessions(&args),
+        Some(Commands::Cd { name }) => cmd_cd(&name),
+        Some(Commands::Path { name, export }) => cmd_path(&name, export.as_deref()),
+        Some(Commands::Cp(args)) => cmd_cp(&args),
+        Some(Commands::Upgrade) => cmd_upgrade(),
+        Some(Commands::Template { action }) => cmd_template(action),
+        Some(Commands::CompleteSessions) => cmd_config_complete_sessions(),
+        Some(Commands::CompleteTemplates) => cmd_config_complete_templates(),
+        Some(Commands::Config { shell }) => match shell {
+            ConfigShell::Zsh => cmd_config_zsh(),
+            ConfigShell::Bash => cmd_config_bash(),
+            ConfigShell::Fish => cmd_config_fish(),
+            ConfigShell::Nu => cmd_config_nu(),
+        },
+        Some(Commands::External(args)) => {
+            let name = args[0].to_string_lossy().to_string();
+            if session::session_exists(&name).unwrap_or(false) {
+                cmd_resume(&name, None, None, false)
+            } else {
+                let cmd: Vec<String> = args[1..]
+                    .iter()
+                    .skip_while(|a| *a != "--")
+                    .skip(1)
+                    .map(|a| a.to_string_lossy().to_string())
+                    .collect();
+                let cmd = if cmd.is_empty() { None } else { Some(cmd) };
+                cmd_create(
+                    &name,
+                    CreateOptions {
+                        cmd,
+                        ..CreateOptions::default()
+                    },
+                )
+            }
+        }
+        None => cmd_list(),
+    };
+
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn output_cd_path(path: &str) {
+    if let Ok(cd_file) = std::env::var("BOX_CD_FILE") {
+        let _ = fs::write(cd_file, path);
+    } else {
+        println!("{}", path);
+    }
+}
+
+fn cmd_list() -> Result<i32> {
+    // Container status is filled in by the session manager once docker answers
+    let sessions = session::list()?;
+
+    let delete_fn = |name: &str| -> Result<()> {
+        let _lock = session::lock(name)?;
+        let sess = session::load(name).ok();
+        docker::remove_container(name);
+        docker::remove_workspace(name);
+        session::remove_dir(name)?;
+        if let Some(sess) = &sess {
+            events::emit(events::Event::Removed, sess);
+        }
+        Ok(())
+    };
+
+    match tui::session_manager(&sessions, delete_fn)? {
+        tui::TuiAction::Resume(name) => {
+            select_context(&name);
+            cmd_resume(&name, None, None, false)
+        }
+        tui::TuiAction::New {
+            name,
+            image,
+            command,
+            template,
+            docker_args,
+            env,
+            ssh,
+        } => cmd_create(
+            &name,
+            CreateOptions {
+                image,
+                cmd: command,
+                template,
+                docker_args,
+                env,
+                ssh,
+                ..CreateOptions::default()
+            },
+        ),
+        tui::TuiAction::Cd(name) => cmd_cd(&name),
+        tui::TuiAction::Quit => Ok(0),
+    }
+}
+
+/// `box create` without a name (or with `-i`): ask for the name, template,
+/// image, command, docker args, env and SSH forwarding with the session
+/// manager's prompts. Other options from the command line still apply.
+fn cmd_create_interactive(name: &str, opts: CreateOptions) -> Result<i32> {
+    if !std::io::stdin().is_terminal() {
+        bail!("Session name is required.");
+    }
+    match tui::create_wizard(name)? {
+        tui::TuiAction::New {
+            name,
+            image,
+            command,
+            template,
+            docker_args,
+            env,
+            ssh,
+        } => cmd_create(
+            &name,
+            CreateOptions {
+                image: image.or(opts.image),
+                cmd: command.or(opts.cmd),
+                template: template.or(opts.template),
+                docker_args: docker_args.or(opts.docker_args),
+                env: opts.env.into_iter().chain(env).collect(),
+                ssh: ssh.or(opts.ssh),
+                ..opts
+            },
+        ),
+        _ => Ok(0),
+    }
+}
+
+fn cmd_list_sessions(args: &ListArgs) -> Result<i32> {
+    let mut sessions = session::list()?;
+
+    docker::check()?;
+    docker::refresh_status(&mut sessions);
+
+    if args.running {
+        sessions.retain(|s| s.running);
+    }
+    if args.stopped {
+        sessions.retain(|s| !s.running);
+    }
+
+    if args.quiet {
+        for s in &sessions {
+            println!("{}", s.name);
+        }
+        return Ok(0);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&sessions)?);
+        return Ok(0);
+    }
+
+    if sessions.is_empty() {
+        println!("No sessions found.");
+        return Ok(0);
+    }
+
+    let home = config::home_dir().unwrap_or_default();
+
+    // Compute column widths
+    let name_w = sessions
+        .iter()
+        .map(|s| s.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let statuses: Vec<String> = sessions
+        .iter()
+        .map(|s| container_status(s).unwrap_or_else(|| "stopped".to_string()))
+        .collect();
+    let status_w = statuses.iter().map(|s| s.len()).max().unwrap_or(0).max(6);
+    let image_w = sessions
+        .iter()
+        .map(|s| s.image.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    let shorten_home = |p: &str| -> String {
+        if !home.is_empty() {
+            if let Some(rest) = p.strip_prefix(&home) {
+                return format!("~{}", rest);
+            }
+        }
+        p.to_string()
+    };
+
+    let project_w = sessions
+        .iter()
+        .map(|s| shorten_home(&s.project_dir).len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+    let command_w = sessions
+        .iter()
+        .map(|s| s.command.len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+
+    // Unsynced work: dirty tree, and commits ahead of/behind the host repo
+    let git_states: Vec<String> = sessions
+        .iter()
+        .map(|s| {
+            storage::workspace_dir(&s.name)
+                .ok()
+                .filter(|dir| dir.is_dir())
+                .and_then(|dir| git::sync_state(&dir, Path::new(&s.project_dir)))
+                .map(|state| state.label())
+                .unwrap_or_else(|| "-".to_string())
+        })
+        .collect();
+    let git_w = git_states
+        .iter()
+        .map(|g| g.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let created_w = sessions
+        .iter()
+        .map(|s| s.created_at.len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+
+    println!(
+        "{:<name_w$}  {:<status_w$}  {:<image_w$}  {:<project_w$}  {:<command_w$}  {:<git_w$}  {:<created_w$}  LAST USED",
+        "NAME", "STATUS", "IMAGE", "PROJECT", "COMMAND", "GIT", "CREATED",
+    );
+
+    for ((s, status), git_state) in sessions.iter().zip(&statuses).zip(&git_states) {
+        let project = shorten_home(&s.project_dir);
+        println!(
+            "{:<name_w$}  {:<status_w$}  {:<image_w$}  {:<project_w$}  {:<command_w$}  {:<git_w$}  {:<created_w$}  {}",
+            s.name,
+            status,
+            s.image,
+            project,
+            s.command,
+            git_state,
+            s.created_at,
+            s.last_used(),
+        );
+    }
+
+    Ok(0)
+}
+
+/// Settings for `cmd_create`; `None` fields fall back to config resolution.
+#[derive(Default)]
+struct CreateOptions {
+    image: Option<String>,
+    docker_args: Option<String>,
+    cmd: Option<Vec<String>>,
+    ssh: Option<bool>,
+    template: Option<String>,
+    worktree: Option<bool>,
+    branch: Option<String>,
+    url: Option<String>,
+    clone_depth: Option<u32>,
+    clone_filter: Option<String>,
+    volume: Option<bool>,
+    user_map: Option<bool>,
+    network: Option<String>,
+    hardened: bool,
+    gpg: bool,
+    git_auth: bool,
+    docker_socket: bool,
+    dind: bool,
+    pin_digest: bool,
+    note: Option<String>,
+    idle_timeout: Option<u64>,
+    context: Option<String>,
+    kube: bool,
+    cpus: Option<String>,
+    memory: Option<String>,
+    ports: Vec<String>,
+    env: Vec<String>,
+    secrets: Vec<String>,
+    caches: Vec<String>,
+    no_dotfiles: bool,
+    detach: bool,
+}
+
+fn cmd_create(name: &str, opts: CreateOptions) -> Result<i32> {
+    session::validate_name(name)?;
+    let template = opts
+        .template
+        .map(|t| config::Template::load(&t))
+        .transpose()?;
+    let template_caches = template
+        .as_ref()
+        .map(|t| t.caches.clone())
+        .unwrap_or_default();
+
+    // Held until the container is up, so a concurrent create can't pass the
+    // existence check too and a remove can't delete the session while its
+    // workspace and container are set up.
+    let lock = session::lock(name)?;
+    if session::session_exists(name)? {
+        bail!(
+            "Session '{}' already exists. Use `box resume {}` to resume it.",
+            name,
+            name
+        );
+    }
+
+    let cwd =
+        fs::canonicalize(".").map_err(|_| anyhow::anyhow!("Cannot resolve current directory."))?;
+
+    // A session cloned from a URL has no host repo; it belongs to the
+    // directory it was created in.
+    let project_dir = if opts.url.is_some() {
+        cwd.to_string_lossy().to_string()
+    } else {
+        git::find_root(&cwd)
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a git repository.", cwd.display()))?
+            .to_string_lossy()
+            .to_string()
+    };
+    if let (Some(branch), None) = (&opts.branch, &opts.url) {
+        if git::resolve_commit(Path::new(&project_dir), branch).is_err() {
+            bail!("'{}' is not a branch or commit in {}.", branch, project_dir);
+        }
+    }
+
+    if let Some(context) = &opts.context {
+        if !docker::context_exists(context) {
+            bail!(
+                "Docker context '{}' not found. See `docker context ls`.",
+                context
+            );
+        }
+    }
+    docker::use_context(opts.context.as_deref());
+    if opts.kube {
+        kube::check()?;
+    } else {
+        docker::check()?;
+    }
+    let remote = !opts.kube && docker::is_remote();
+
+    let cfg = config::resolve(config::BoxConfigInput {
+        name: name.to_string(),
+        image: opts.image,
+        mount_path: None,
+        project_dir,
+        command: opts.cmd,
+        env: opts.env,
+        ssh: opts.ssh,
+        docker_args: opts.docker_args,
+        template,
+        // There's no local repository to add a worktree to
+        worktree: if opts.url.is_some() {
+            Some(false)
+        } else {
+            opts.worktree
+        },
+    })?;
+
+    eprintln!("\x1b[2msession:\x1b[0m {}", cfg.name);
+    eprintln!("\x1b[2mimage:\x1b[0m {}", cfg.image);
+    eprintln!("\x1b[2mmount:\x1b[0m {}", cfg.mount_path);
+    if remote {
+        if cfg.worktree {
+            bail!("A worktree workspace needs a local Docker daemon; drop --worktree for a remote host.");
+        }
+        if opts.url.is_some() {
+            bail!("--url clones on this machine and needs a local Docker daemon.");
+        }
+        eprintln!(
+            "\x1b[2mcontext:\x1b[0m {} (remote; workspace is copied into a volume)",
+            opts.context.as_deref().unwrap_or("current")
+        );
+    } else if let Some(context) = &opts.context {
+        eprintln!("\x1b[2mcontext:\x1b[0m {}", context);
+    }
+    if opts.kube {
+        eprintln!("\x1b[2mbackend:\x1b[0m kubernetes (experimental)");
+    }
+    let ssh = cfg.ssh && !remote && !opts.kube;
+    if ssh {
+        eprintln!("\x1b[2mssh:\x1b[0m true");
+    }
+    if cfg.worktree {
+        eprintln!("\x1b[2mworktree:\x1b[0m true");
+    }
+    if let Some(url) = &opts.url {
+        eprintln!("\x1b[2murl:\x1b[0m {}", url);
+    }
+    if let Some(branch) = &opts.branch {
+        eprintln!("\x1b[2mbranch:\x1b[0m {}", branch);
+    }
+    let volume = !opts.kube
+        && opts.url.is_none()
+        && (remote || (!cfg.worktree && opts.volume.unwrap_or_else(config::volume_workspace)));
+    if volume {
+        eprintln!("\x1b[2mvolume:\x1b[0m {}", docker::workspace_volume(name));
+    }
+    let user_map = !opts.kube && opts.user_map.unwrap_or_else(config::user_map);
+    if user_map {
+        eprintln!("\x1b[2muser:\x1b[0m {}", docker::host_user());
+    }
+    let network = if opts.kube {
+        None
+    } else if opts.hardened && opts.network.is_none() {
+        Some("none".to_string())
+    } else {
+        config::network(&cfg.project_dir, opts.network)?
+    };
+    if opts.hardened {
+        eprintln!("\x1b[2mhardened:\x1b[0m true");
+    }
+    if opts.gpg {
+        eprintln!("\x1b[2mgpg agent:\x1b[0m forwarded");
+    }
+    if opts.git_auth {
+        eprintln!("\x1b[2mgit credentials:\x1b[0m forwarded");
+    }
+    if opts.docker_socket {
+        eprintln!("\x1b[2mdocker:\x1b[0m host socket");
+        eprintln!(
+            "\x1b[2mwarning:\x1b[0m the box can control the host's Docker daemon, which is equivalent to root on the host"
+        );
+    }
+    if opts.dind {
+        eprintln!(
+            "\x1b[2mdocker:\x1b[0m nested daemon ({})",
+            docker::dind_volume(name)
+        );
+        eprintln!(
+            "\x1b[2mwarning:\x1b[0m the container runs --privileged and is no longer isolated from the host kernel"
+        );
+    }
+    if let Some(network) = &network {
+        eprintln!("\x1b[2mnetwork:\x1b[0m {}", network);
+    }
+    if !cfg.command.is_empty() {
+        eprintln!("\x1b[2mcommand:\x1b[0m {}", shell_words::join(&cfg.command));
+    }
+    if !cfg.docker_args.is_empty() {
+        eprintln!("\x1b[2mdocker args:\x1b[0m {}", cfg.docker_args);
+    }
+    let (clone_depth, clone_filter) = if opts.kube || cfg.worktree {
+        (None, None)
+    } else {
+        config::clone_options(&cfg.project_dir, opts.clone_depth, opts.clone_filter)?
+    };
+    // The remote host gets the repository as a bundle, which can't be shallow
+    let (clone_depth, clone_filter) = if remote && (clone_depth.is_some() || clone_filter.is_some())
+    {
+        eprintln!("\x1b[2mclone:\x1b[0m full (shallow and partial clones need a local daemon)");
+        (None, None)
+    } else {
+        (clone_depth, clone_filter)
+    };
+    if let Some(depth) = clone_depth {
+        eprintln!("\x1b[2mclone depth:\x1b[0m {}", depth);
+    }
+    if let Some(filter) = &clone_filter {
+        eprintln!("\x1b[2mclone filter:\x1b[0m {}", filter);
+    }
+    let caches = if opts.kube {
+        Vec::new()
+    } else {
+        config::caches(&cfg.project_dir, &template_caches, &opts.caches)?
+    };
+    for cache in &caches {
+        eprintln!("\x1b[2mcache:\x1b[0m {}", cache);
+    }
+    eprintln!();
+
+    // The cluster pulls images for Kubernetes sessions
+    if !opts.kube {
+        docker::check_image(&cfg.image)?;
+    }
+    let image = if opts.pin_digest {
+        let pinned = docker::pin_digest(&cfg.image)?;
+        eprintln!("\x1b[2mpinned:\x1b[0m {}", pinned);
+        pinned
+    } else {
+        cfg.image.clone()
+    };
+
+    // `docker cp` can't write into a read-only root filesystem
+    let dotfiles = if opts.no_dotfiles || opts.kube || opts.hardened {
+        None
+    } else {
+        config::dotfiles()
+    };
+    let dotfiles_dir = dotfiles.as_ref().map(prepare_dotfiles).transpose()?;
+    let hooks = config::hooks(&cfg.project_dir)?;
+
+    let docker_args = cfg.docker_args.clone();
+    let mut sess = session::Session::from(cfg);
+    sess.image = image;
+    sess.cpus = opts.cpus.filter(|v| !is_zero(v));
+    sess.memory = opts.memory.filter(|v| !is_zero(v));
+    sess.ports = opts.ports;
+    sess.secrets = opts
+        .secrets
+        .iter()
+        .map(|s| resolve_secret(s))
+        .collect::<Result<_>>()?;
+    sess.caches = caches;
+    sess.volume = volume;
+    sess.user_map = user_map;
+    sess.network = network;
+    sess.hardened = opts.hardened;
+    sess.gpg = opts.gpg;
+    sess.git_auth = opts.git_auth;
+    sess.docker_socket = opts.docker_socket;
+    sess.dind = opts.dind;
+    sess.branch = opts.branch;
+    sess.url = opts.url;
+    sess.clone_depth = clone_depth;
+    sess.clone_filter = clone_filter;
+    sess.note = opts
+        .note
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty());
+    sess.idle_timeout = opts.idle_timeout;
+    sess.context = opts.context;
+    sess.kube = opts.kube;
+    sess.ssh = ssh;
+    session::save(&sess)?;
+    events::emit(events::Event::Created, &sess);
+
+    if sess.kube {
+        return start_kube_session(&sess, opts.detach, lock);
+    }
+
+    let home = config::home_dir()?;
+    let workspace = storage::workspace_dir(name)?.to_string_lossy().to_string();
+    let docker_args_opt = if docker_args.is_empty() {
+        None
+    } else {
+        Some(docker_args.as_str())
+    };
+
+    // With dotfiles or a post_create hook, start in the background first so
+    // they can run before attaching.
+    let setup = dotfiles_dir.is_some() || hooks.post_create.is_some();
+    let detach_keys = config::detach_keys();
+    let user = sess.user_map.then(docker::host_user);
+    docker::remove_container(name);
+    let code = docker::run_container(
+        &docker::DockerRunConfig {
+            name,
+            project_dir: &sess.project_dir,
+            image: &sess.image,
+            mount_path: &sess.mount_path,
+            cmd: &sess.command,
+            env: &sess.env,
+            home: &home,
+            workspace: &workspace,
+            docker_args: docker_args_opt,
+            ssh: sess.ssh,
+            worktree: sess.worktree,
+            branch: sess.branch.as_deref(),
+            url: sess.url.as_deref(),
+            clone: docker::CloneOptions {
+                depth: sess.clone_depth,
+                filter: sess.clone_filter.as_deref(),
+            },
+            cpus: sess.cpus.as_deref(),
+            memory: sess.memory.as_deref(),
+            ports: &sess.ports,
+            secrets: &sess.secrets,
+            caches: &sess.caches,
+            volume: sess.volume,
+            user: user.as_deref(),
+            network: sess.network.as_deref(),
+            hardened: sess.hardened,
+            gpg: sess.gpg,
+            git_auth: sess.git_auth,
+            docker_socket: sess.docker_socket,
+            dind: sess.dind,
+            remote,
+            security: docker::host_security(),
+            detach: opts.detach || setup,
+            tty: !opts.detach && setup,
+            detach_keys: detach_keys.as_deref(),
+            rm: false,
+        },
+        || {
+            drop(lock);
+            events::emit(events::Event::Started, &sess);
+        },
+    )?;
+
+    if !setup || code != 0 {
+        return Ok(code);
+    }
+    if let (Some(dir), Some(dotfiles)) = (dotfiles_dir, dotfiles) {
+        eprintln!("\x1b[2minstalling dotfiles:\x1b[0m {}", dotfiles.source);
+        if let Err(e) = docker::install_dotfiles(name, &dir, dotfiles.install.as_deref()) {
+            eprintln!("\x1b[2mwarning:\x1b[0m {}", e);
+        }
+    }
+    if let Some(hook) = &hooks.post_create {
+        let target = hook.target(config::HookTarget::Container);
+        if let Err(e) = run_hook("post_create", hook, target, &sess) {
+            eprintln!("\x1b[2mwarning:\x1b[0m {}", e);
+        }
+    }
+    if opts.detach {
+        Ok(0)
+    } else {
+        docker::attach_container(name)
+    }
+}
+
+/// Run a lifecycle hook in the session's container, or on the host from the
+/// session's workspace (its project directory if there is no workspace yet).
+fn run_hook(
+    stage: &str,
+    hook: &config::Hook,
+    target: config::HookTarget,
+    sess: &session::Session,
+) -> Result<()> {
+    eprintln!("\x1b[2m{} hook:\x1b[0m {}", stage, hook.command());
+    let ok = match target {
+        config::HookTarget::Container => docker::exec_script(&sess.name, hook.command())?,
+        config::HookTarget::Host => {
+            let workspace = storage::workspace_dir(&sess.name)?;
+            let dir = if workspace.is_dir() {
+                workspace
+            } else {
+                std::path::PathBuf::from(&sess.project_dir)
+            };
+            std::process::Command::new("sh")
+                .args(["-c", hook.command()])
+                .current_dir(dir)
+                .env("BOX_SESSION", &sess.name)
+                .status()?
+                .success()
+        }
+    };
+    if !ok {
+        bail!("{} hook failed: {}", stage, hook.command());
+    }
+    Ok(())
+}
+
+/// Run a hook that only makes sense on the host because the container isn't
+/// running at that point.
+fn run_host_hook(stage: &str, hook: &config::Hook, sess: &session::Session) -> Result<()> {
+    if hook.target(config::HookTarget::Host) == config::HookTarget::Container {
+        bail!(
+            "{} hooks run on the host; the container isn't running then.",
+            stage
+        );
+    }
+    run_hook(stage, hook, config::HookTarget::Host, sess)
+}
+
+/// Host directory holding the dotfiles: the configured directory, or a
+/// cached clone of the configured git URL (updated on each use).
+fn prepare_dotfiles(dotfiles: &config::Dotfiles) -> Result<std::path::PathBuf> {
+    if let Some(dir) = dotfiles.local_path()? {
+        if !dir.is_dir() {
+            bail!("Dotfiles directory '{}' does not exist.", dir.display());
+        }
+        return Ok(dir);
+    }
+    let dir = storage::dotfiles_cache_dir()?;
+    if git::remote_url(&dir).as_deref() == Some(dotfiles.source.as_str()) {
+        if let Err(e) = git::pull_ff_only(&dir) {
+            eprintln!(
+                "\x1b[2mwarning:\x1b[0m could not update dotfiles, using cached copy: {}",
+                e
+            );
+        }
+    } else {
+        let _ = fs::remove_dir_all(&dir);
+        eprintln!("\x1b[2mcloning dotfiles:\x1b[0m {}", dotfiles.source);
+        git::clone_shallow(&dotfiles.source, &dir)?;
+    }
+    Ok(dir)
+}
+
+/// (Re)create the pod of a Kubernetes session and attach to it.
+/// Start the pod of `sess`. `lock` is released once the pod is created.
+fn start_kube_session(
+    sess: &session::Session,
+    detach: bool,
+    lock: session::SessionLock,
+) -> Result<i32> {
+    kube::delete_pod(&sess.name, None)?;
+    kube::create_pod(sess)?;
+    drop(lock);
+    events::emit(events::Event::Started, sess);
+    if detach {
+        Ok(0)
+    } else {
+        kube::attach(&sess.name)
+    }
+}
+
+/// Run `cmd` once in a fresh clone of the current repository, in a
+/// container that is removed when it exits, and return its exit code. The
+/// workspace is deleted afterwards and no session is saved.
+fn cmd_run(args: RunArgs) -> Result<i32> {
+    let cwd =
+        fs::canonicalize(".").map_err(|_| anyhow::anyhow!("Cannot resolve current directory."))?;
+    let project_dir = git::find_root(&cwd)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a git repository.", cwd.display()))?
+        .to_string_lossy()
+        .to_string();
+    if let Some(branch) = &args.branch {
+        if git::resolve_commit(Path::new(&project_dir), branch).is_err() {
+            bail!("'{}' is not a branch or commit in {}.", branch, project_dir);
+        }
+    }
+    docker::check()?;
+    let remote = docker::is_remote();
+
+    // Unique per invocation, so parallel runs don't collide; `box prune`
+    // cleans up after a run that was killed
+    let name = format!(
+        "run-{}-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    );
+    let cfg = config::resolve(config::BoxConfigInput {
+        name: name.clone(),
+        image: args.image,
+        mount_path: None,
+        project_dir,
+        command: Some(args.cmd),
+        env: args.env,
+        ssh: Some(false),
+        docker_args: args.docker_args,
+        template: None,
+        worktree: Some(false),
+    })?;
+    let network = if args.hardened && args.network.is_none() {
+        Some("none".to_string())
+    } else {
+        config::network(&cfg.project_dir, args.network)?
+    };
+    eprintln!("\x1b[2mimage:\x1b[0m {}", cfg.image);
+    if let Some(network) = &network {
+        eprintln!("\x1b[2mnetwork:\x1b[0m {}", network);
+    }
+
+    let home = config::home_dir()?;
+    let workspace = storage::workspace_dir(&name)?.to_string_lossy().to_string();
+    let docker_args = (!cfg.docker_args.is_empty()).then_some(cfg.docker_args.as_str());
+    let result = docker::run_container(
+        &docker::DockerRunConfig {
+            name: &name,
+            project_dir: &cfg.project_dir,
+            image: &cfg.image,
+            mount_path: &cfg.mount_path,
+            cmd: &cfg.command,
+            env: &cfg.env,
+            home: &home,
+            workspace: &workspace,
+            docker_args,
+            ssh: false,
+            worktree: false,
+            branch: args.branch.as_deref(),
+            url: None,
+            clone: docker::CloneOptions::default(),
+            cpus: None,
+            memory: None,
+            ports: &[],
+            secrets: &[],
+            caches: &[],
+            volume: remote,
+            user: None,
+            network: network.as_deref(),
+            hardened: args.hardened,
+            gpg: false,
+            git_auth: false,
+            docker_socket: false,
+            dind: false,
+            remote,
+            security: docker::host_security(),
+            detach: false,
+            tty: io::stdin().is_terminal() && io::stdout().is_terminal(),
+            detach_keys: None,
+            rm: true,
+        },
+        || {},
+    );
+    docker::remove_workspace(&name);
+    result
+}
+
+fn cmd_resume(
+    name: &str,
+    docker_args: Option<String>,
+    ssh: Option<bool>,
+    detach: bool,
+) -> Result<i32> {
+    session::validate_name(name)?;
+
+    // Held until the container is up, so a concurrent remove can't delete
+    // the session while its workspace and container are set up.
+    let lock = session::lock(name)?;
+    let mut sess = session::load(name)?;
+    // --ssh/--no-ssh change the stored setting, which needs a new container
+    if let Some(ssh) = ssh.filter(|&ssh| ssh != sess.ssh) {
+        if sess.kube && ssh {
+            bail!("SSH agent forwarding isn't available for Kubernetes sessions.");
+        }
+        sess.ssh = ssh;
+        session::save_ssh(&sess)?;
+        session::mark_recreate(name)?;
+    }
+    // --docker-args for this resume, else what the session was created with
+    let docker_args = docker_args
+        .or_else(|| sess.docker_args.clone())
+        .unwrap_or_else(config::default_docker_args);
+    let recreate = session::needs_recreate(name)?;
+
+    if !Path::new(&sess.project_dir).is_dir() {
+        bail!("Project directory '{}' no longer exists.", sess.project_dir);
+    }
+
+    if sess.kube {
+        kube::check()?;
+        if kube::pod_is_running(name) && !recreate {
+            if detach {
+                println!("Session '{}' is already running.", name);
+                return Ok(0);
+            }
+            drop(lock);
+            return kube::attach(name);
+        }
+        println!("Resuming session '{}'...", name);
+        session::touch_resumed_at(name)?;
+        session::clear_recreate(name)?;
+        return start_kube_session(&sess, detach, lock);
+    }
+
+    docker::check()?;
+
+    if docker::container_is_running(name) {
+        if docker::container_is_paused(name) {
+            eprintln!("\x1b[2munpausing session:\x1b[0m {}", name);
+            docker::unpause_container(name)?;
+        }
+        if recreate {
+            eprintln!(
+                "\x1b[2mnote:\x1b[0m settings changed; stop and resume '{}' to apply them",
+                name
+            );
+        }
+        if detach {
+            println!("Session '{}' is already running.", name);
+            return Ok(0);
+        }
+        drop(lock);
+        return docker::attach_container(name);
+    }
+
+    println!("Resuming session '{}'...", name);
+    if let Some(hook) = config::hooks(&sess.project_dir)?.pre_resume {
+        run_host_hook("pre_resume", &hook, &sess)?;
+    }
+    session::touch_resumed_at(name)?;
+    let started = || {
+        drop(lock);
+        events::emit(events::Event::Started, &sess);
+    };
+
+    if docker::container_exists(name) && !recreate {
+        // The forwarded socket's permissions reset when the VM restarts
+        if sess.ssh && !docker::is_remote() {
+            docker::repair_ssh_socket(&sess.image);
+        }
+        // Tokens expire; hand the container fresh ones
+        if sess.git_auth {
+            docker::refresh_git_auth(name, &sess.project_dir, sess.url.as_deref())?;
+        }
+        if detach {
+            let code = docker::start_container_detached(name)?;
+            started();
+            Ok(code)
+        } else {
+            docker::start_container(name, started)
+        }
+    } else {
+        let home = config::home_dir()?;
+        let workspace = storage::workspace_dir(name)?.to_string_lossy().to_string();
+        let docker_args_opt = if docker_args.is_empty() {
+            None
+        } else {
+            Some(docker_args.as_str())
+        };
+
+        if recreate {
+            eprintln!("\x1b[2mrecreating container to apply changed settings\x1b[0m");
+            session::clear_recreate(name)?;
+        }
+        let detach_keys = config::detach_keys();
+        let user = sess.user_map.then(docker::host_user);
+        docker::remove_container(name);
+        docker::run_container(
+            &docker::DockerRunConfig {
+                name,
+                project_dir: &sess.project_dir,
+                image: &sess.image,
+                mount_path: &sess.mount_path,
+                cmd: &sess.command,
+                env: &sess.env,
+                home: &home,
+                workspace: &workspace,
+                docker_args: docker_args_opt,
+                ssh: sess.ssh,
+                worktree: sess.worktree,
+                branch: sess.branch.as_deref(),
+                url: sess.url.as_deref(),
+                clone: docker::CloneOptions {
+                    depth: sess.clone_depth,
+                    filter: sess.clone_filter.as_deref(),
+                },
+                cpus: sess.cpus.as_deref(),
+                memory: sess.memory.as_deref(),
+                ports: &sess.ports,
+                secrets: &sess.secrets,
+                caches: &sess.caches,
+                volume: sess.volume,
+                user: user.as_deref(),
+                network: sess.network.as_deref(),
+                hardened: sess.hardened,
+                gpg: sess.gpg,
+                git_auth: sess.git_auth,
+                docker_socket: sess.docker_socket,
+                dind: sess.dind,
+                remote: docker::is_remote(),
+                security: docker::host_security(),
+                detach,
+                tty: false,
+                detach_keys: detach_keys.as_deref(),
+                rm: false,
+            },
+            started,
+        )
+    }
+}
+
+fn cmd_remove(name: &str, force: bool) -> Result<i32> {
+    session::validate_name(name)?;
+
+    let _lock = session::lock(name)?;
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    let sess = session::load(name).ok();
+    if sess.as_ref().is_some_and(|sess| sess.kube) {
+        kube::check()?;
+        if kube::pod_is_running(name) && !force {
+            bail!(
+                "Session '{}' is still running. Stop it first with `box stop {}`, or pass --force.",
+                name,
+                name
+            );
+        }
+        kube::delete_all(name)?;
+        session::remove_dir(name)?;
+        if let Some(sess) = &sess {
+            events::emit(events::Event::Removed, sess);
+        }
+        println!("Session '{}' removed.", name);
+        return Ok(0);
+    }
+
+    docker::check()?;
+
+    if docker::container_is_running(name) {
+        if !force {
+            bail!(
+                "Session '{}' is still running. Stop it first with `box stop {}`, or pass --force.",
+                name,
+                name
+            );
+        }
+        eprintln!("\x1b[2mstopping session:\x1b[0m {}", name);
+        docker::stop_session(name, None)?;
+        if let Some(sess) = &sess {
+            events::emit(events::Event::Stopped, sess);
+        }
+    }
+
+    docker::remove_container(name);
+    docker::remove_workspace(name);
+    let _ = std::fs::remove_dir_all(storage::snapshots_dir(name)?);
+    session::remove_dir(name)?;
+    if let Some(sess) = &sess {
+        events::emit(events::Event::Removed, sess);
+    }
+
+    println!("Session '{}' removed.", name);
+    Ok(0)
+}
+
+fn cmd_stop_many(args: &StopArgs) -> Result<i32> {
+    let names = if args.all {
+        docker::check()?;
+        let mut running: Vec<String> = docker::running_sessions().into_iter().collect();
+        running.sort();
+        if running.is_empty() {
+            println!("No running sessions.");
+            return Ok(0);
+        }
+        running
+    } else {
+        args.names.clone()
+    };
+    for_each_session(&names, |name| cmd_stop(name, args.timeout))
+}
+
+/// Run `f` for each of `names`. A single session behaves exactly like `f`;
+/// with several, failures are reported and the rest still run, and the exit
+/// code is non-zero if any failed.
+fn for_each_session(names: &[String], f: impl Fn(&str) -> Result<i32>) -> Result<i32> {
+    if let [name] = names {
+        return f(name);
+    }
+    let mut code = 0;
+    for name in names {
+        select_context(name);
+        match f(name) {
+            Ok(0) => {}
+            Ok(c) => code = c,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                code = 1;
+            }
+        }
+    }
+    Ok(code)
+}
+
+/// Stop a session, giving it `timeout` seconds to exit before it is killed.
+fn cmd_stop(name: &str, timeout: Option<u32>) -> Result<i32> {
+    end_session(name, timeout, false)
+}
+
+/// Kill a session right away.
+fn cmd_kill(name: &str) -> Result<i32> {
+    end_session(name, None, true)
+}
+
+fn end_session(name: &str, timeout: Option<u32>, kill: bool) -> Result<i32> {
+    session::validate_name(name)?;
+
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    if session::load(name).is_ok_and(|sess| sess.kube) {
+        kube::check()?;
+        if kube::pod_phase(name).is_none() {
+            bail!("Session '{}' is not running.", name);
+        }
+        // The workspace claim outlives the pod. 1 is kubectl's immediate shutdown.
+        kube::delete_pod(name, if kill { Some(1) } else { timeout })?;
+        if let Ok(sess) = session::load(name) {
+            events::emit(events::Event::Stopped, &sess);
+        }
+        println!(
+            "Session '{}' {}.",
+            name,
+            if kill { "killed" } else { "stopped" }
+        );
+        return Ok(0);
+    }
+
+    docker::check()?;
+
+    if !docker::container_is_running(name) {
+        bail!("Session '{}' is not running.", name);
+    }
+
+    let code = if kill {
+        docker::kill_container(name)?
+    } else {
+        docker::stop_container(name, timeout)?
+    };
+    if let (0, Ok(sess)) = (code, session::load(name)) {
+        events::emit(events::Event::Stopped, &sess);
+        if let Some(hook) = config::hooks(&sess.project_dir)?.post_stop {
+            run_host_hook("post_stop", &hook, &sess)?;
+        }
+    }
+    Ok(code)
+}
+
+fn cmd_pause(name: &str, pause: bool) -> Result<i32> {
+    session::validate_name(name)?;
+
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    docker::check()?;
+
+    if !docker::container_is_running(name) {
+        bail!("Session '{}' is not running.", name);
+    }
+
+    let paused = docker::container_is_paused(name);
+    if pause {
+        if paused {
+            bail!("Session '{}' is already paused.", name);
+        }
+        docker::pause_container(name)?;
+        println!("Session '{}' paused.", name);
+    } else {
+        if !paused {
+            bail!("Session '{}' is not paused.", name);
+        }
+        docker::unpause_container(name)?;
+        println!("Session '{}' unpaused.", name);
+    }
+    Ok(0)
+}
+
+/// Block until the session's container exits and return its exit code. A
+/// session that has already stopped returns right away.
+fn cmd_wait(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    docker::check()?;
+
+    if !docker::container_exists(name) {
+        bail!(
+            "Session '{}' has no container. Start it with `box resume {}`.",
+            name,
+            name
+        );
+    }
+    docker::wait_container(name)
+}
+
+/// Attach to a running session as `docker attach` would, without the
+/// checks, hooks and metadata updates of `box resume`.
+fn cmd_attach(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    if session::load(name).is_ok_and(|sess| sess.kube) {
+        kube::check()?;
+        if !kube::pod_is_running(name) {
+            bail!(
+                "Session '{}' is not running. Start it with `box resume {}`.",
+                name,
+                name
+            );
+        }
+        return kube::attach(name);
+    }
+
+    docker::check()?;
+
+    if !docker::container_is_running(name) {
+        bail!(
+            "Session '{}' is not running. Start it with `box resume {}`.",
+            name,
+            name
+        );
+    }
+
+    if docker::container_is_paused(name) {
+        bail!(
+            "Session '{}' is paused. Run `box unpause {}` first.",
+            name,
+            name
+        );
+    }
+
+    docker::attach_container(name)
+}
+
+fn cmd_exec(name: &str, cmd: &[String]) -> Result<i32> {
+    session::validate_name(name)?;
+
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+
+    if session::load(name).is_ok_and(|sess| sess.kube) {
+        kube::check()?;
+        if !kube::pod_is_running(name) {
+            bail!("Session '{}' is not running.", name);
+        }
+        return kube::exec(name, cmd);
+    }
+
+    docker::check()?;
+
+    if !docker::container_is_running(name) {
+        bail!("Session '{}' is not running.", name);
+    }
+
+    if docker::container_is_paused(name) {
+        bail!(
+            "Session '{}' is paused. Run `box unpause {}` first.",
+            name,
+            name
+        );
+    }
+
+    docker::exec_container(name, cmd)
+}
+
+/// Open an interactive shell in the session at its mount path, alongside the
+/// session's own command. A stopped session is started in the background first.
+fn cmd_shell(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+    let sess = session::load(name)?;
+    let shell = config::shell();
+
+    if sess.kube {
+        kube::check()?;
+        if !kube::pod_is_running(name) {
+            let code = cmd_resume(name, None, None, true)?;
+            if code != 0 {
+                return Ok(code);
+            }
+        }
+        return kube::exec(name, &docker::shell_command(shell.as_deref()));
+    }
+
+    docker::check()?;
+    if !docker::container_is_running(name) {
+        let code = cmd_resume(name, None, None, true)?;
+        if code != 0 {
+            return Ok(code);
+        }
+        if !docker::container_is_running(name) {
+            bail!(
+                "Session '{}' exited after starting. Its command must keep running to open a shell.",
+                name
+            );
+        }
+    } else if docker::container_is_paused(name) {
+        bail!(
+            "Session '{}' is paused. Run `box unpause {}` first.",
+            name,
+            name
+        );
+    }
+
+    docker::shell_container(name, &sess.mount_path, shell.as_deref())
+}
+
+fn cmd_ssh(args: &SshArgs) -> Result<i32> {
+    let name = args.name.as_str();
+    session::validate_name(name)?;
+    let mut sess = session::load(name)?;
+
+    if !sess.ports.iter().any(|p| is_ssh_port_spec(p)) {
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))?
+            .local_addr()?
+            .port();
+        let spec = format!("127.0.0.1:{}:22", port);
+        eprintln!("\x1b[2mpublishing ssh port:\x1b[0m {}", spec);
+        sess.ports.push(spec);
+        session::save_ports(&sess)?;
+        session::mark_recreate(name)?;
+    }
+
+    let key = ssh_public_key(&config::home_dir()?)?;
+
+    docker::check()?;
+    if docker::container_is_running(name) {
+        if docker::container_is_paused(name) {
+            bail!(
+                "Session '{}' is paused. Run `box unpause {}` first.",
+                name,
+                name
+            );
+        }
+        if session::needs_recreate(name)? {
+            bail!(
+                "Session '{}' must be restarted to publish its ssh port. Run `box stop {}` and try again.",
+                name,
+                name
+            );
+        }
+    } else {
+        let code = cmd_resume(name, None, None, true)?;
+        if code != 0 {
+            return Ok(code);
+        }
+        if !docker::container_is_running(name) {
+            bail!(
+                "Session '{}' exited after starting. Its command must keep running for ssh access.",
+                name
+            );
+        }
+    }
+
+    let port = ssh_host_port(&docker::published_ports(name))
+        .ok_or_else(|| anyhow::anyhow!("Session '{}' has no published ssh port.", name))?;
+    eprintln!("\x1b[2mstarting sshd\x1b[0m");
+    let user = docker::ensure_sshd(name, &key)?;
+
+    let ssh_args = [
+        "-p".to_string(),
+        port,
+        "-o".to_string(),
+        "NoHostAuthenticationForLocalhost=yes".to_string(),
+        format!("{}@localhost", user),
+    ];
+    if args.print {
+        println!("ssh {}", shell_words::join(&ssh_args));
+        return Ok(0);
+    }
+    let status = std::process::Command::new("ssh")
+        .args(&ssh_args)
+        .status()
+        .map_err(|e| anyhow::anyhow!("Failed to run ssh: {}", e))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+fn cmd_code(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+    let sess = session::load(name)?;
+
+    docker::check()?;
+    if !docker::container_is_running(name) {
+        let code = cmd_resume(name, None, None, true)?;
+        if code != 0 {
+            return Ok(code);
+        }
+        if !docker::container_is_running(name) {
+            bail!(
+                "Session '{}' exited after starting. Its command must keep running for VS Code to attach.",
+                name
+            );
+        }
+    } else if docker::container_is_paused(name) {
+        bail!(
+            "Session '{}' is paused. Run `box unpause {}` first.",
+            name,
+            name
+        );
+    }
+
+    let uri = vscode_attach_uri(name, &sess.mount_path);
+    let status = std::process::Command::new("code")
+        .args(["--folder-uri", &uri])
+        .status()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => anyhow::anyhow!(
+                "VS Code's `code` command was not found on PATH. Install it from the Command Palette (Shell Command: Install 'code' command in PATH)."
+            ),
+            _ => anyhow::anyhow!("Failed to run code: {}", e),
+        })?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Folder URI that opens `mount_path` in VS Code attached to the session's
+/// container (Dev Containers extension). The authority is the hex-encoded
+/// container name.
+fn vscode_attach_uri(name: &str, mount_path: &str) -> String {
+    let hex: String = format!("box-{}", name)
+        .bytes()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("vscode-remote://attached-container+{}{}", hex, mount_path)
+}
+
+/// Whether a `--publish` spec targets container port 22.
+fn is_ssh_port_spec(spec: &str) -> bool {
+    spec.rsplit(':').next().map(|p| p.trim_end_matches("/tcp")) == Some("22")
+}
+
+/// Host port bound to container port 22, from `docker port` style lines.
+fn ssh_host_port(published: &[String]) -> Option<String> {
+    published
+        .iter()
+        .find_map(|line| line.strip_prefix("22/tcp -> "))
+        .and_then(|host| host.rsplit(':').next())
+        .map(|port| port.to_string())
+}
+
+/// The first of the usual ssh public keys found in `~/.ssh`.
+fn ssh_public_key(home: &str) -> Result<String> {
+    let dir = Path::new(home).join(".ssh");
+    for file in ["id_ed25519.pub", "id_ecdsa.pub", "id_rsa.pub"] {
+        if let Ok(key) = fs::read_to_string(dir.join(file)) {
+            return Ok(key.trim().to_string());
+        }
+    }
+    bail!(
+        "No ssh public key found in {}. Create one with `ssh-keygen -t ed25519`.",
+        dir.display()
+    )
+}
+
+fn cmd_logs(args: &LogsArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+
+    if !session::session_exists(&args.name)? {
+        bail!("Session '{}' not found.", args.name);
+    }
+
+    if session::load(&args.name).is_ok_and(|sess| sess.kube) {
+        kube::check()?;
+        return kube::logs(
+            &args.name,
+            args.follow,
+            args.tail.as_deref(),
+            args.since.as_deref(),
+        );
+    }
+
+    docker::check()?;
+
+    if !docker::container_exists(&args.name) {
+        bail!("Session '{}' has no container yet.", args.name);
+    }
+
+    docker::logs(
+        &args.name,
+        &docker::LogsOptions {
+            follow: args.follow,
+            tail: args.tail.as_deref(),
+            since: args.since.as_deref(),
+        },
+    )
+}
+
+fn cmd_clone(args: &CloneArgs) -> Result<i32> {
+    session::validate_name(&args.src)?;
+    session::validate_name(&args.dst)?;
+
+    let mut sess = session::load(&args.src)?;
+    let _lock = session::lock(&args.dst)?;
+    if session::session_exists(&args.dst)? {
+        bail!("Session '{}' already exists.", args.dst);
+    }
+
+    if args.commit {
+        docker::check()?;
+        if !docker::container_exists(&args.src) {
+            bail!(
+                "Session '{}' has no container to commit. Resume it once first.",
+                args.src
+            );
+        }
+        let tag = format!("box-{}:clone", args.dst.to_lowercase());
+        eprintln!(
+            "\x1b[2mcommitting container:\x1b[0m box-{} -> {}",
+            args.src, tag
+        );
+        docker::commit_container(&args.src, &tag, &args.dst)?;
+        sess.image = tag;
+    }
+
+    if sess.worktree && !args.fresh {
+        eprintln!(
+            "\x1b[2mworktree session:\x1b[0m '{}' will get its own worktree on first run",
+            args.dst
+        );
+    } else if sess.volume && !args.fresh {
+        docker::check()?;
+        eprintln!(
+            "\x1b[2mcopying workspace:\x1b[0m {}",
+            docker::workspace_volume(&args.dst)
+        );
+        docker::copy_workspace_volume(&args.src, &args.dst)?;
+    } else if !args.fresh {
+        let src_ws = storage::workspace_dir(&args.src)?;
+        if src_ws.is_dir() {
+            let dst_ws = storage::workspace_dir(&args.dst)?;
+            eprintln!("\x1b[2mcopying workspace:\x1b[0m {}", dst_ws.display());
+            docker::copy_workspace(&src_ws, &dst_ws)?;
+        }
+    }
+
+    sess.name = args.dst.clone();
+    session::save(&sess)?;
+
+    println!(
+        "Session '{}' cloned from '{}'. Run `box resume {}` to start it.",
+        args.dst, args.src, args.dst
+    );
+    Ok(0)
+}
+
+fn cmd_diff(args: &DiffArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let sess = session::load(&args.name)?;
+    require_host_repo(&sess)?;
+    let workspace = host_workspace(&sess)?;
+    if !git::is_repo(&workspace) {
+        bail!(
+            "Session '{}' has no workspace yet. Resume it once first.",
+            args.name
+        );
+    }
+
+    let mut diff_args = Vec::new();
+    if args.stat {
+        diff_args.push("--stat");
+    }
+    if args.patch || !args.stat {
+        diff_args.push("--patch");
+    }
+    let code = git::diff_against_host(&workspace, Path::new(&sess.project_dir), &diff_args)?;
+
+    // git diff ignores untracked files; list them so nothing is missed
+    if let Some(status) = git::status(&workspace) {
+        let untracked: Vec<&str> = status
+            .changes
+            .iter()
+            .filter_map(|l| l.strip_prefix("?? "))
+            .collect();
+        if !untracked.is_empty() {
+            println!("\nUntracked files:");
+            for path in untracked {
+                println!("  {}", path);
+            }
+        }
+    }
+    Ok(code)
+}
+
+/// `0` (in any spelling such as `0.0` or `0g`) means "no limit".
+fn is_zero(value: &str) -> bool {
+    value
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+        .parse::<f64>()
+        .map(|v| v == 0.0)
+        .unwrap_or(false)
+}
+
+fn cmd_commit(args: &CommitArgs) -> Result<i32> {
+    let name = args.name.as_str();
+    session::validate_name(name)?;
+    let mut sess = session::load(name)?;
+
+    docker::check()?;
+    if !docker::container_exists(name) {
+        bail!(
+            "Session '{}' has no container to commit. Resume it once first.",
+            name
+        );
+    }
+
+    let tag = args.tag.clone().unwrap_or_else(|| {
+        format!(
+            "box-{}:{}",
+            name.to_lowercase(),
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        )
+    });
+    eprintln!(
+        "\x1b[2mcommitting container:\x1b[0m box-{} -> {}",
+        name, tag
+    );
+    docker::commit_container(name, &tag, name)?;
+
+    if args.use_image {
+        sess.image = tag.clone();
+        session::save_image(&sess)?;
+        session::mark_recreate(name)?;
+        println!(
+            "Committed '{}' as {}. It becomes the session's image on the next `box resume {}`.",
+            name, tag, name
+        );
+    } else {
+        println!("Committed '{}' as {}.", name, tag);
+    }
+    Ok(0)
+}
+
+fn cmd_pull(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+    let sess = session::load(name)?;
+    docker::check()?;
+
+    let before = docker::image_id(&sess.image);
+    docker::pull_image(&sess.image)?;
+    if before.is_some() && docker::image_id(&sess.image) != before {
+        if docker::container_exists(name) {
+            session::mark_recreate(name)?;
+            println!(
+                "Image '{}' updated. It will be used from the next `box resume {}`.",
+                sess.image, name
+            );
+        } else {
+            println!("Image '{}' updated.", sess.image);
+        }
+    } else {
+        println!("Image '{}' is up to date.", sess.image);
+    }
+    Ok(0)
+}
+
+fn cmd_limits(args: LimitsArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let mut sess = session::load(&args.name)?;
+
+    if args.cpus.is_none() && args.memory.is_none() {
+        println!("cpus:   {}", sess.cpus.as_deref().unwrap_or("unlimited"));
+        println!("memory: {}", sess.memory.as_deref().unwrap_or("unlimited"));
+        return Ok(0);
+    }
+
+    if let Some(cpus) = &args.cpus {
+        sess.cpus = Some(cpus.clone()).filter(|v| !is_zero(v));
+    }
+    if let Some(memory) = &args.memory {
+        sess.memory = Some(memory.clone()).filter(|v| !is_zero(v));
+    }
+    session::save_limits(&sess)?;
+
+    docker::check()?;
+    if docker::container_exists(&args.name) {
+        // docker update has no way to clear a memory limit; that needs a new container
+        let memory = args.memory.as_deref().filter(|v| !is_zero(v));
+        if let Err(e) = docker::update_limits(&args.name, args.cpus.as_deref(), memory) {
+            eprintln!(
+                "Warning: {}. Limits will apply when the container is recreated.",
+                e
+            );
+        } else if args.memory.as_deref().is_some_and(is_zero) {
+            eprintln!("Memory limit removal applies when the container is recreated.");
+        }
+    }
+    println!("Limits updated for session '{}'.", args.name);
+    Ok(0)
+}
+
+fn cmd_ports(args: PortsArgs) -> Result<i32> {
+    let (name, action) = match args.action {
+        Some(PortsAction::Add { name, specs }) => (name, Some((true, specs))),
+        Some(PortsAction::Remove { name, specs }) => (name, Some((false, specs))),
+        None => (args.name.unwrap_or_default(), None),
+    };
+    session::validate_name(&name)?;
+    let mut sess = session::load(&name)?;
+
+    let Some((add, specs)) = action else {
+        if sess.ports.is_empty() {
+            println!("No ports configured.");
+        } else {
+            println!("Configured:");
+            for port in &sess.ports {
+                println!("  {}", port);
+            }
+        }
+        docker::check()?;
+        let live = docker::published_ports(&name);
+        if !live.is_empty() {
+            println!("Published:");
+            for line in &live {
+                println!("  {}", line);
+            }
+        }
+        return Ok(0);
+    };
+
+    if add {
+        for spec in specs {
+            if !sess.ports.contains(&spec) {
+                sess.ports.push(spec);
+            }
+        }
+    } else {
+        for spec in &specs {
+            if !sess.ports.contains(spec) {
+                bail!("Port '{}' is not configured for session '{}'.", spec, name);
+            }
+        }
+        sess.ports.retain(|p| !specs.contains(p));
+    }
+    session::save_ports(&sess)?;
+    session::mark_recreate(&name)?;
+    println!(
+        "Ports updated for session '{}'. They take effect on the next `box resume {}`.",
+        name, name
+    );
+    Ok(0)
+}
+
+fn cmd_network(args: &NetworkArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let mut sess = session::load(&args.name)?;
+
+    let Some(mode) = &args.mode else {
+        println!("{}", sess.network.as_deref().unwrap_or("bridge"));
+        return Ok(0);
+    };
+    sess.network = Some(mode.clone());
+    session::save_network(&sess)?;
+    session::mark_recreate(&args.name)?;
+    println!(
+        "Network set to '{}' for session '{}'. It takes effect on the next `box resume {}`.",
+        mode, args.name, args.name
+    );
+    Ok(0)
+}
+
+fn cmd_note(args: NoteArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let mut sess = session::load(&args.name)?;
+
+    let note = if args.clear {
+        None
+    } else if let Some(text) = args.text {
+        Some(text)
+    } else if std::io::stdin().is_terminal() {
+        Some(edit_text(sess.note.as_deref().unwrap_or(""), "txt")?)
+    } else {
+        if let Some(note) = &sess.note {
+            println!("{}", note);
+        }
+        return Ok(0);
+    };
+    sess.note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    session::save_note(&sess)?;
+    match &sess.note {
+        Some(_) => println!("Note updated for session '{}'.", args.name),
+        None => println!("Note cleared for session '{}'.", args.name),
+    }
+    Ok(0)
+}
+
+/// Open `initial` in $VISUAL/$EDITOR (falling back to vi) as a file with
+/// extension `ext` and return the edited text.
+fn edit_text(initial: &str, ext: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let path = std::env::temp_dir().join(format!("box-edit-{}.{}", std::process::id(), ext));
+    fs::write(&path, initial)?;
+    // Run through the shell so editors configured with flags (e.g. "code --wait") work.
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let text = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status.map_err(|e| anyhow::anyhow!("Failed to run editor '{}': {}", editor, e))?;
+    if !status.success() {
+        bail!(
+            "Editor '{}' exited with {}; nothing changed.",
+            editor,
+            status
+        );
+    }
+    Ok(text?)
+}
+
+/// Edit the session's stored settings as TOML in the editor. Invalid input
+/// reopens the editor with the error on top; saving it unchanged gives up.
+fn cmd_edit(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+    let mut sess = session::load(name)?;
+    let current = sess.settings();
+
+    let header = format!(
+        "# Settings of session '{}', applied on the next `box resume {}`.\n\
+         # Also: cpus, memory, network, idle_timeout, note. Delete a line to reset it.\n\n",
+        name, name
+    );
+    let mut text = header.clone() + &toml::to_string(&current)?;
+    let settings = loop {
+        let edited = edit_text(&text, "toml")?;
+        let error = match parse_settings(&edited) {
+            Ok(settings) => break settings,
+            Err(e) => e,
+        };
+        if edited == text {
+            return Err(error);
+        }
+        let body: String = edited
+            .lines()
+            .skip_while(|l| l.starts_with("# Error:"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+        text = format!("# Error: {:#}\n{}", error, body);
+    };
+
+    if settings == current {
+        println!("No changes to session '{}'.", name);
+        return Ok(0);
+    }
+    sess.apply(settings)?;
+    session::save_settings(&sess)?;
+    session::mark_recreate(name)?;
+    println!(
+        "Session '{}' updated. Changes take effect on the next `box resume {}`.",
+        name, name
+    );
+    Ok(0)
+}
+
+/// Parse and validate the settings written by `box edit`.
+fn parse_settings(text: &str) -> Result<session::Settings> {
+    let settings: session::Settings = toml::from_str(text)?;
+    let check = |r: Result<String, String>| r.map(|_| ()).map_err(|e| anyhow::anyhow!(e));
+    if settings.image.trim().is_empty() {
+        bail!("image must not be empty");
+    }
+    if !settings.mount_path.starts_with('/') {
+        bail!("mount_path must be an absolute path");
+    }
+    shell_words::split(&settings.command).map_err(|e| anyhow::anyhow!("command: {}", e))?;
+    shell_words::split(&settings.docker_args).map_err(|e| anyhow::anyhow!("docker_args: {}", e))?;
+    for env in &settings.env {
+        check(parse_env(env))?;
+    }
+    for port in &settings.ports {
+        check(parse_publish(port))?;
+    }
+    if let Some(cpus) = &settings.cpus {
+        check(parse_cpus(cpus))?;
+    }
+    if let Some(memory) = &settings.memory {
+        check(parse_memory(memory))?;
+    }
+    if let Some(network) = &settings.network {
+        check(parse_network(network))?;
+    }
+    Ok(settings)
+}
+
+fn cmd_env(args: EnvArgs) -> Result<i32> {
+    let name = args.name;
+    session::validate_name(&name)?;
+    let mut sess = session::load(&name)?;
+
+    match args.action.unwrap_or(EnvAction::List) {
+        EnvAction::List => {
+            if sess.env.is_empty() {
+                println!("No environment variables set.");
+            }
+            for entry in &sess.env {
+                println!("{}", entry);
+            }
+            return Ok(0);
+        }
+        EnvAction::Set { vars } => {
+            for var in vars {
+                sess.env.retain(|e| env_key(e) != env_key(&var));
+                sess.env.push(var);
+            }
+        }
+        EnvAction::Unset { keys } => {
+            for key in &keys {
+                if !sess.env.iter().any(|e| env_key(e) == key) {
+                    bail!("Variable '{}' is not set for session '{}'.", key, name);
+                }
+            }
+            sess.env.retain(|e| !keys.iter().any(|k| k == env_key(e)));
+        }
+    }
+    session::save_env(&sess)?;
+    session::mark_recreate(&name)?;
+    println!(
+        "Environment updated for session '{}'. It takes effect on the next `box resume {}`.",
+        name, name
+    );
+    Ok(0)
+}
+
+fn cmd_secrets(args: SecretsArgs) -> Result<i32> {
+    let name = args.name;
+    session::validate_name(&name)?;
+    let mut sess = session::load(&name)?;
+
+    match args.action.unwrap_or(SecretsAction::List) {
+        SecretsAction::List => {
+            if sess.secrets.is_empty() {
+                println!("No secrets configured.");
+            }
+            for secret in &sess.secrets {
+                let (secret, path) = secret.split_once('=').unwrap_or((secret, ""));
+                println!("{}/{}  <- {}", docker::SECRETS_DIR, secret, path);
+            }
+            return Ok(0);
+        }
+        SecretsAction::Add { secrets } => {
+            for spec in secrets {
+                let spec = resolve_secret(&spec)?;
+                sess.secrets
+                    .retain(|s| secret_name(s) != secret_name(&spec));
+                sess.secrets.push(spec);
+            }
+        }
+        SecretsAction::Remove { names } => {
+            for secret in &names {
+                if !sess.secrets.iter().any(|s| secret_name(s) == secret) {
+                    bail!(
+                        "Secret '{}' is not configured for session '{}'.",
+                        secret,
+                        name
+                    );
+                }
+            }
+            sess.secrets
+                .retain(|s| !names.iter().any(|n| n == secret_name(s)));
+        }
+    }
+    session::save_secrets(&sess)?;
+    session::mark_recreate(&name)?;
+    println!(
+        "Secrets updated for session '{}'. They take effect on the next `box resume {}`.",
+        name, name
+    );
+    Ok(0)
+}
+
+/// Refuse commands that compare a workspace with its host repo for sessions
+/// cloned from a URL, which don't have one.
+fn require_host_repo(sess: &session::Session) -> Result<()> {
+    if let Some(url) = &sess.url {
+        bail!(
+            "Session '{}' was cloned from {} and has no host repository; publish its commits with `box push` instead.",
+            sess.name,
+            url
+        );
+    }
+    Ok(())
+}
+
+fn cmd_sync(args: &SyncArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let sess = session::load(&args.name)?;
+    require_host_repo(&sess)?;
+    let project = Path::new(&sess.project_dir);
+    let workspace = host_workspace(&sess)?;
+    if !git::is_repo(&workspace) {
+        bail!(
+            "Session '{}' has no workspace yet. Resume it once first.",
+            args.name
+        );
+    }
+
+    if args.watch {
+        eprintln!(
+            "Syncing files between {} and {} (Ctrl-C to stop)...",
+            project.display(),
+            workspace.display()
+        );
+        let mut watcher = filesync::Watcher::new(project, &workspace, args.conflict);
+        watcher.run(std::time::Duration::from_secs(args.interval.max(1)))?;
+        return Ok(0);
+    }
+
+    if let Some(status) = git::status(&workspace) {
+        if !status.changes.is_empty() {
+            eprintln!(
+                "\x1b[2mnote:\x1b[0m workspace has {} uncommitted change(s) that won't be synced",
+                status.changes.len()
+            );
+        }
+    }
+
+    let commit = git::fetch_head(project, &workspace)?;
+    let short = &commit[..commit.len().min(12)];
+    let new_commits = git::log_oneline(project, &format!("HEAD..{}", commit))?;
+    if new_commits.is_empty() {
+        println!("Fetched {} (already in the host's HEAD).", short);
+    } else {
+        println!(
+            "Fetched {} ({} commit(s) not in the host's HEAD):",
+            short,
+            new_commits.len()
+        );
+        for line in &new_commits {
+            println!("  {}", line);
+        }
+    }
+
+    if let Some(branch) = &args.branch {
+        git::create_branch(project, branch, &commit)?;
+        println!("Created branch '{}' at {}.", branch, short);
+    } else if args.ff {
+        git::merge_ff_only(project, &commit)?;
+        println!("Fast-forwarded host HEAD to {}.", short);
+    } else if !new_commits.is_empty() {
+        println!("Available as FETCH_HEAD. Use --branch <name> or --ff to land it.");
+    }
+    Ok(0)
+}
+
+fn cmd_apply(args: &ApplyArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let sess = session::load(&args.name)?;
+    require_host_repo(&sess)?;
+    let project = Path::new(&sess.project_dir);
+    let workspace = host_workspace(&sess)?;
+    if !git::is_repo(&workspace) {
+        bail!(
+            "Session '{}' has no workspace yet. Resume it once first.",
+            args.name
+        );
+    }
+    // git am refuses a dirty index, and patching over local edits would mix them in
+    if let Some(status) = git::status(project) {
+        if status.changes.iter().any(|l| !l.starts_with("??")) {
+            bail!(
+                "The host repo at {} has uncommitted changes. Commit or stash them first.",
+                project.display()
+            );
+        }
+    }
+
+    let patches = git::workspace_patches(&workspace, project)?;
+    if patches.count == 0 && patches.uncommitted.is_empty() {
+        println!("Nothing to apply; the host's HEAD already has the workspace's changes.");
+        return Ok(0);
+    }
+
+    if let Some(branch) = &args.branch {
+        git::switch_create(project, branch)?;
+        println!("Switched to new branch '{}'.", branch);
+    }
+    if patches.count > 0 {
+        if !git::am(project, &patches.commits)? {
+            eprintln!(
+                "git am stopped on a conflict. Resolve it and run `git am --continue`, or `git am --abort` to undo."
+            );
+            if !patches.uncommitted.is_empty() {
+                eprintln!("The workspace's uncommitted changes were not applied.");
+            }
+            return Ok(1);
+        }
+        println!("Applied {} commit(s).", patches.count);
+    }
+    if !patches.uncommitted.is_empty() {
+        git::apply(project, &patches.uncommitted)?;
+        println!("Applied uncommitted changes to the working tree.");
+    }
+    Ok(0)
+}
+
+/// The host workspace of a session that can be snapshotted. A worktree's
+/// HEAD and index live in the host repo, out of a snapshot's reach.
+fn snapshot_workspace(sess: &session::Session) -> Result<std::path::PathBuf> {
+    if sess.worktree {
+        bail!(
+            "Session '{}' uses a git worktree, whose git state lives in the host repo; snapshots only cover cloned workspaces.",
+            sess.name
+        );
+    }
+    let workspace = host_workspace(sess)?;
+    if !workspace.is_dir() {
+        bail!(
+            "Session '{}' has no workspace yet. Resume it once first.",
+            sess.name
+        );
+    }
+    Ok(workspace)
+}
+
+fn cmd_snapshot(args: &SnapshotArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let sess = session::load(&args.name)?;
+    let dir = storage::snapshots_dir(&args.name)?;
+
+    if args.list {
+        let snapshots = snapshot::list(&dir)?;
+        if snapshots.is_empty() {
+            println!("No snapshots.");
+            return Ok(0);
+        }
+        let name_w = snapshots
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("SNAPSHOT".len());
+        println!(
+            "{:<name_w$}  {:<16}  {:>9}  TYPE",
+            "SNAPSHOT", "CREATED", "SIZE"
+        );
+        for s in &snapshots {
+            let created: chrono::DateTime<chrono::Local> = s.created.into();
+            let (size, kind) = if s.reflink {
+                (docker::workspace_size(&s.path), "reflink")
+            } else {
+                (
+                    std::fs::metadata(&s.path).map(|m| m.len()).unwrap_or(0),
+                    "tar",
+                )
+            };
+            println!(
+                "{:<name_w$}  {:<16}  {:>9}  {}",
+                s.name,
+                created.format("%Y-%m-%d %H:%M"),
+                format_bytes(size),
+                kind
+            );
+        }
+        return Ok(0);
+    }
+
+    if let Some(name) = &args.remove {
+        snapshot::remove(&dir, name)?;
+        println!("Snapshot '{}' removed.", name);
+        return Ok(0);
+    }
+
+    let workspace = snapshot_workspace(&sess)?;
+    let name = args.snapshot.clone().unwrap_or_else(snapshot::default_name);
+    let snap = snapshot::create(&dir, &workspace, &name)?;
+    println!(
+        "Saved snapshot '{}' of session '{}'{}.",
+        snap.name,
+        args.name,
+        if snap.reflink { " (copy-on-write)" } else { "" }
+    );
+    Ok(0)
+}
+
+fn cmd_restore(args: &RestoreArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let _lock = session::lock(&args.name)?;
+    let sess = session::load(&args.name)?;
+    let workspace = snapshot_workspace(&sess)?;
+    // A running container keeps the replaced directory mounted
+    if docker::check().is_ok() && docker::container_is_running(&args.name) {
+        bail!(
+            "Session '{}' is running. Stop it first with `box stop {}`.",
+            args.name,
+            args.name
+        );
+    }
+    snapshot::restore(
+        &storage::snapshots_dir(&args.name)?,
+        &workspace,
+        &args.snapshot,
+    )?;
+    println!(
+        "Restored session '{}' from snapshot '{}'.",
+        args.name, args.snapshot
+    );
+    Ok(0)
+}
+
+fn cmd_push(args: &PushArgs) -> Result<i32> {
+    session::validate_name(&args.name)?;
+    let sess = session::load(&args.name)?;
+    if sess.volume {
+        bail!(
+            "Session '{}' keeps its workspace in a Docker volume; push from inside it with `box exec {} -- git push`.",
+            sess.name,
+            sess.name
+        );
+    }
+    let workspace = host_workspace(&sess)?;
+    if !git::is_repo(&workspace) {
+        bail!(
+            "Session '{}' has no workspace yet. Resume it once first.",
+            args.name
+        );
+    }
+    let Some(origin) = git::remote_url(&workspace) else {
+        bail!("Session '{}' workspace has no origin remote.", args.name);
+    };
+
+    let (_, current) = git::head(&workspace)?;
+    let refspec = match (&args.branch, current) {
+        (Some(branch), _) => format!("HEAD:refs/heads/{}", branch),
+        (None, Some(current)) => current,
+        (None, None) => bail!(
+            "Session '{}' workspace is on a detached HEAD. Name the branch to push to with --branch <name>.",
+            args.name
+        ),
+    };
+
+    // The push runs on the host, so it authenticates with the host's agent
+    if git::is_ssh_url(&origin) {
+        for finding in doctor::check_ssh_agent(false) {
+            if finding.level != doctor::Level::Ok {
+                eprintln!("{}", finding.render());
+            }
+        }
+    }
+    eprintln!("\x1b[2mpushing to:\x1b[0m {} ({})", origin, refspec);
+    git::push(&workspace, &refspec, args.set_upstream)
+}
+
+fn cmd_inspect(name: &str, json: bool) -> Result<i32> {
+    session::validate_name(name)?;
+    let sess = session::load(name)?;
+    let summary = session::list()?.into_iter().find(|s| s.name == name);
+    let (created_at, resumed_at) = summary
+        .map(|s| (s.created_at, s.resumed_at))
+        .unwrap_or_default();
+
+    let state = if sess.kube {
+        kube::check()?;
+        kube::pod_phase(name).map(|phase| serde_json::json!({ "Status": phase }))
+    } else {
+        docker::check()?;
+        docker::container_state(name)
+    };
+
+    let workspace = storage::workspace_dir(name)?;
+    let workspace_exists = workspace.is_dir();
+    let disk_usage = if workspace_exists {
+        Some(docker::workspace_size(&workspace))
+    } else {
+        None
+    };
+    let git_status = if workspace_exists {
+        git::status(&workspace)
+    } else {
+        None
+    };
+
+    if json {
+        let value = serde_json::json!({
+            "session": sess,
+            "created_at": created_at,
+            "resumed_at": resumed_at,
+            "container": state,
+            "workspace": {
+                "path": workspace,
+                "exists": workspace_exists,
+                "disk_usage_bytes": disk_usage,
+                "git": git_status,
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(0);
+    }
+
+    let row = |key: &str, value: &str| println!("{:<12} {}", key, value);
+    row("name", &sess.name);
+    if let Some(note) = &sess.note {
+        row("note", note);
+    }
+    row("project", &sess.project_dir);
+    row("image", &sess.image);
+    row("mount", &sess.mount_path);
+    row("command", &shell_words::join(&sess.command));
+    for env in &sess.env {
+        row("env", env);
+    }
+    row("ssh", if sess.ssh { "yes" } else { "no" });
+    row("worktree", if sess.worktree { "yes" } else { "no" });
+    if let Some(url) = &sess.url {
+        row("url", url);
+    }
+    if let Some(branch) = &sess.branch {
+        row("ref", branch);
+    }
+    if let Some(depth) = sess.clone_depth {
+        row("clone depth", &depth.to_string());
+    }
+    if let Some(filter) = &sess.clone_filter {
+        row("clone filter", filter);
+    }
+    row("network", sess.network.as_deref().unwrap_or("bridge"));
+    if let Some(context) = &sess.context {
+        row("context", context);
+    }
+    if sess.kube {
+        row("backend", "kubernetes");
+    }
+    if sess.hardened {
+        row("hardened", "yes");
+    }
+    if sess.gpg {
+        row("gpg agent", "forwarded");
+    }
+    if sess.git_auth {
+        row("git credentials", "forwarded");
+    }
+    if sess.docker_socket {
+        row("docker", "host socket");
+    }
+    if sess.dind {
+        row("docker", "nested daemon (privileged)");
+    }
+    if sess.user_map {
+        row("user", &docker::host_user());
+    }
+    match sess.idle_timeout.or_else(config::idle_timeout) {
+        Some(minutes) if minutes > 0 => row("idle timeout", &format!("{} min", minutes)),
+        _ => {}
+    }
+    row("cpus", sess.cpus.as_deref().unwrap_or("unlimited"));
+    row("memory", sess.memory.as_deref().unwrap_or("unlimited"));
+    for port in &sess.ports {
+        row("port", port);
+    }
+    for secret in &sess.secrets {
+        row("secret", secret);
+    }
+    for cache in &sess.caches {
+        row("cache", cache);
+    }
+    row("created", &created_at);
+    if !resumed_at.is_empty() {
+        row("resumed", &resumed_at);
+    }
+
+    match &state {
+        Some(state) => {
+            let status = state["Status"].as_str().unwrap_or("unknown");
+            row("container", &format!("box-{} ({})", name, status));
+            if state["Running"].as_bool().unwrap_or(false) {
+                if let Some(uptime) = state["StartedAt"].as_str().and_then(elapsed_since) {
+                    row("uptime", &uptime);
+                }
+            } else if let Some(code) = state["ExitCode"].as_i64() {
+                row("exit code", &code.to_string());
+            }
+        }
+        None => row("container", "none"),
+    }
+
+    if sess.volume {
+        row(
+            "workspace",
+            &format!("volume {}", docker::workspace_volume(name)),
+        );
+    } else if workspace_exists {
+        row("workspace", &workspace.display().to_string());
+        row("disk usage", &format_bytes(disk_usage.unwrap_or(0)));
+    } else {
+        row("workspace", &format!("{} (missing)", workspace.display()));
+    }
+    if let Some(git_status) = &git_status {
+        row("branch", &git_status.branch);
+        if git_status.changes.is_empty() {
+            row("changes", "clean");
+        } else {
+            row("changes", &format!("{} file(s)", git_status.changes.len()));
+            for line in &git_status.changes {
+                println!("{:<12}   {}", "", line);
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Container status for listings: uptime while running, or the exit code and
+/// time since exit once stopped. `None` when docker reported no container.
+fn container_status(s: &session::SessionSummary) -> Option<String> {
+    if s.paused {
+        return Some("paused".to_string());
+    }
+    if s.running {
+        return Some(match elapsed_since(&s.started_at) {
+            Some(uptime) => format!("up {}", uptime),
+            None => "running".to_string(),
+        });
+    }
+    let code = s.exit_code?;
+    Some(match elapsed_since(&s.finished_at) {
+        Some(ago) => format!("exit {}, {} ago", code, ago),
+        None => format!("exit {}", code),
+    })
+}
+
+/// Human-readable time since an RFC 3339 timestamp, e.g. `2h 5m`.
+fn elapsed_since(timestamp: &str) -> Option<String> {
+    let started = chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+    let secs = (chrono::Utc::now() - started.with_timezone(&chrono::Utc)).num_seconds();
+    Some(format_duration(secs.max(0) as u64))
+}
+
+fn format_duration(secs: u64) -> String {
+    let (d, h, m, s) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    if d > 0 {
+        format!("{}d {}h", d, h)
+    } else if h > 0 {
+        format!("{}h {}m", h, m)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Resources `box prune` would remove.
+#[derive(Debug, Default, PartialEq)]
+struct PruneCandidates {
+    /// Sessions whose project directory no longer exists.
+    stale_sessions: Vec<String>,
+    /// Workspace directories without session metadata.
+    orphan_workspaces: Vec<String>,
+    /// `box-*` containers without session metadata.
+    orphan_containers: Vec<String>,
+}
+
+impl PruneCandidates {
+    fn is_empty(&self) -> bool {
+        self.stale_sessions.is_empty()
+            && self.orphan_workspaces.is_empty()
+            && self.orphan_containers.is_empty()
+    }
+}
+
+fn find_prune_candidates(
+    sessions: &[session::SessionSummary],
+    workspaces: &[String],
+    containers: &[String],
+) -> PruneCandidates {
+    let known: std::collections::HashSet<&str> = sessions.iter().map(|s| s.name.as_str()).collect();
+    let mut candidates = PruneCandidates {
+        stale_sessions: sessions
+            .iter()
+            .filter(|s| !Path::new(&s.project_dir).is_dir())
+            .map(|s| s.name.clone())
+            .collect(),
+        orphan_workspaces: workspaces
+            .iter()
+            .filter(|w| !known.contains(w.as_str()))
+            .cloned()
+            .collect(),
+        orphan_containers: containers
+            .iter()
+            .filter(|c| !known.contains(c.as_str()))
+            .cloned()
+            .collect(),
+    };
+    candidates.stale_sessions.sort();
+    candidates.orphan_workspaces.sort();
+    candidates.orphan_containers.sort();
+    candidates
+}
+
+fn cmd_prune(force: bool) -> Result<i32> {
+    docker::check()?;
+
+    let sessions = session::list()?;
+    let workspaces_dir = storage::workspaces_dir()?;
+    let workspaces: Vec<String> = match fs::read_dir(&workspaces_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let containers: Vec<String> = docker::all_session_containers().into_iter().collect();
+
+    let mut candidates = find_prune_candidates(&sessions, &workspaces, &containers);
+    let running = docker::running_sessions();
+    candidates.stale_sessions.retain(|name| {
+        if running.contains(name) {
+            eprintln!(
+                "\x1b[2mskipping running session:\x1b[0m {} (stop it first)",
+                name
+            );
+            false
+        } else {
+            true
+        }
+    });
+
+    if candidates.is_empty() {
+        println!("Nothing to prune.");
+        return Ok(0);
+    }
+
+    for name in &candidates.stale_sessions {
+        println!("session    {} (project directory missing)", name);
+    }
+    for name in &candidates.orphan_workspaces {
+        println!("workspace  {}", workspaces_dir.join(name).display());
+    }
+    for name in &candidates.orphan_containers {
+        println!("container  box-{}", name);
+    }
+
+    if !force {
+        eprint!("Remove these? [y/N] ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted.");
+            return Ok(1);
+        }
+    }
+
+    for name in &candidates.stale_sessions {
+        let _lock = session::lock(name)?;
+        let sess = session::load(name).ok();
+        docker::remove_container(name);
+        docker::remove_workspace(name);
+        session::remove_dir(name)?;
+        if let Some(sess) = &sess {
+            events::emit(events::Event::Removed, sess);
+        }
+    }
+    for name in &candidates.orphan_workspaces {
+        docker::remove_workspace(name);
+    }
+    for name in &candidates.orphan_containers {
+        docker::remove_container(name);
+    }
+
+    let total = candidates.stale_sessions.len()
+        + candidates.orphan_workspaces.len()
+        + candidates.orphan_containers.len();
+    println!("Pruned {} item(s).", total);
+    Ok(0)
+}
+
+/// Workspaces without session metadata that haven't been modified for
+/// `max_age`, sorted by name.
+fn old_orphan_workspaces(
+    sessions: &[session::SessionSummary],
+    workspaces: &[(String, SystemTime)],
+    now: SystemTime,
+    max_age: Duration,
+) -> Vec<String> {
+    let known: HashSet<&str> = sessions.iter().map(|s| s.name.as_str()).collect();
+    let mut old: Vec<String> = workspaces
+        .iter()
+        .filter(|(name, _)| !known.contains(name.as_str()))
+        .filter(|(_, modified)| {
+            now.duration_since(*modified)
+                .is_ok_and(|age| age >= max_age)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    old.sort();
+    old
+}
+
+fn cmd_gc(args: &GcArgs) -> Result<i32> {
+    docker::check()?;
+
+    let days = args.older_than.unwrap_or_else(config::gc_older_than);
+    let sessions = session::list()?;
+    let workspaces_dir = storage::workspaces_dir()?;
+    let workspaces: Vec<(String, SystemTime)> = match fs::read_dir(&workspaces_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| {
+                let modified = e.metadata().ok()?.modified().ok()?;
+                Some((e.file_name().to_string_lossy().to_string(), modified))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let workspaces = old_orphan_workspaces(
+        &sessions,
+        &workspaces,
+        SystemTime::now(),
+        Duration::from_secs(days * 24 * 60 * 60),
+    );
+    let mut containers: Vec<String> = docker::exited_sessions().into_iter().collect();
+    containers.sort();
+    let images = docker::dangling_session_images();
+
+    if containers.is_empty() && images.is_empty() && workspaces.is_empty() {
+        println!("Nothing to collect.");
+        return Ok(0);
+    }
+
+    let mut reclaimed = 0;
+    let mut removed = 0;
+    for name in &containers {
+        let size = docker::container_size(name);
+        let note = if sessions.iter().any(|s| &s.name == name) {
+            ", recreated on next resume"
+        } else {
+            ""
+        };
+        println!("container  box-{} ({}{})", name, format_bytes(size), note);
+        if !args.dry_run {
+            let _lock = session::lock(name)?;
+            docker::remove_container(name);
+        }
+        reclaimed += size;
+        removed += 1;
+    }
+    for (id, size) in &images {
+        let short = id.strip_prefix("sha256:").unwrap_or(id);
+        println!(
+            "image      {} ({})",
+            &short[..short.len().min(12)],
+            format_bytes(*size)
+        );
+        if !args.dry_run {
+            if let Err(e) = docker::remove_image(id) {
+                eprintln!("\x1b[2mskipped:\x1b[0m {}", e);
+                continue;
+            }
+        }
+        reclaimed += size;
+        removed += 1;
+    }
+    for name in &workspaces {
+        let path = workspaces_dir.join(name);
+        let size = docker::workspace_size(&path);
+        println!("workspace  {} ({})", path.display(), format_bytes(size));
+        if !args.dry_run {
+            docker::remove_workspace(name);
+        }
+        reclaimed += size;
+        removed += 1;
+    }
+
+    if args.dry_run {
+        println!(
+            "Would reclaim {} from {} item(s).",
+            format_bytes(reclaimed),
+            removed
+        );
+    } else {
+        println!(
+            "Reclaimed {} from {} item(s).",
+            format_bytes(reclaimed),
+            removed
+        );
+    }
+    Ok(0)
+}
+
+fn cmd_doctor() -> Result<i32> {
+    let status = docker::docker_status();
+    let docker_ready = matches!(status, docker::DockerStatus::Ready { .. });
+    let mut findings = vec![doctor::check_docker(&status)];
+    findings.extend(doctor::check_ssh_agent(docker_ready));
+    findings.extend(doctor::check_storage());
+    findings.push(check_orphans(docker_ready)?);
+
+    for finding in &findings {
+        println!("{}", finding.render());
+    }
+    let failed = findings.iter().any(|f| f.level == doctor::Level::Error);
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// Leftovers `box prune` would clean up. Containers are only checked when
+/// docker is reachable.
+fn check_orphans(docker_ready: bool) -> Result<doctor::Finding> {
+    let sessions = session::list()?;
+    let workspaces: Vec<String> = match fs::read_dir(storage::workspaces_dir()?) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let containers: Vec<String> = if docker_ready {
+        docker::all_session_containers().into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    let candidates = find_prune_candidates(&sessions, &workspaces, &containers);
+    if candidates.is_empty() {
+        return Ok(doctor::Finding::ok(
+            "orphans",
+            format!("{} session(s), nothing stale", sessions.len()),
+        ));
+    }
+    Ok(doctor::Finding::warn(
+        "orphans",
+        format!(
+            "{} stale session(s), {} orphaned workspace(s), {} orphaned container(s)",
+            candidates.stale_sessions.len(),
+            candidates.orphan_workspaces.len(),
+            candidates.orphan_containers.len()
+        ),
+        "run `box prune` to review and remove them",
+    ))
+}
+
+fn cmd_init(force: bool) -> Result<i32> {
+    let cwd =
+        fs::canonicalize(".").map_err(|_| anyhow::anyhow!("Cannot resolve current directory."))?;
+    let root = git::find_root(&cwd)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a git repository.", cwd.display()))?;
+    let path = root.join(config::REPO_CONFIG_FILE);
+    if path.exists() && !force {
+        bail!(
+            "{} already exists. Use --force to overwrite it.",
+            path.display()
+        );
+    }
+    let content = config::init_config(&root.to_string_lossy());
+    fs::write(&path, content)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+    println!("Wrote {}.", path.display());
+    println!(
+        "Review it, then commit it to share these defaults with everyone using box on this repo."
+    );
+    Ok(0)
+}
+
+fn cmd_migrate() -> Result<i32> {
+    let migration = storage::migrate()?;
+    for (from, to) in &migration.moved {
+        println!("moved     {} -> {}", from.display(), to.display());
+    }
+    for path in &migration.linked {
+        println!(
+            "linked    {} -> {}",
+            path.display(),
+            storage::data_dir()?.display()
+        );
+    }
+    // Containers of sessions moved out of a directory that couldn't be
+    // replaced by a symlink still bind-mount the old paths.
+    for name in migration.relocated_sessions() {
+        session::mark_recreate(&name)?;
+    }
+    for path in &migration.conflicts {
+        eprintln!(
+            "\x1b[2mskipped:\x1b[0m {} (already exists in {})",
+            path.display(),
+            storage::data_dir()?.display()
+        );
+    }
+
+    if migration.moved.is_empty() && migration.conflicts.is_empty() {
+        println!("Nothing to migrate.");
+        return Ok(0);
+    }
+    Ok(if migration.conflicts.is_empty() { 0 } else { 1 })
+}
+
+/// CPU usage (percent) below which a session without an attached terminal
+/// counts as idle.
+const IDLE_CPU_PERCENT: f64 = 1.0;
+
+fn cmd_autostop(interval: u64) -> Result<i32> {
+    docker::check()?;
+    eprintln!(
+        "Checking for idle sessions every {}s (Ctrl-C to quit).",
+        interval
+    );
+    let mut tracker = IdleTracker::default();
+    loop {
+        let usage = docker::session_cpu_usage();
+        // Paused sessions were frozen on purpose; leave them alone.
+        let paused = docker::paused_sessions();
+        let running: HashSet<String> = docker::running_sessions()
+            .into_iter()
+            .filter(|name| !paused.contains(name))
+            .collect();
+        let attached = docker::attached_sessions();
+        let default_timeout = config::idle_timeout();
+        let now = Instant::now();
+        tracker.retain(&running);
+
+        let mut names: Vec<&String> = running.iter().collect();
+        names.sort();
+        for name in names {
+            let Ok(sess) = session::load(name) else {
+                continue;
+            };
+            let minutes = match sess.idle_timeout.or(default_timeout) {
+                Some(minutes) if minutes > 0 => minutes,
+                _ => continue,
+            };
+            let active = attached.contains(name)
+                || usage.get(name).is_none_or(|&cpu| cpu >= IDLE_CPU_PERCENT);
+            let timeout = Duration::from_secs(minutes * 60);
+            if tracker.observe(name, active, now, timeout) {
+                println!("Session '{}' idle for {} min, stopping.", name, minutes);
+                if let Err(e) = cmd_stop(name, None) {
+                    eprintln!("Failed to stop session '{}': {}", name, e);
+                }
+                tracker.forget(name);
+            }
+        }
+        std::thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Remembers since when each running session has been idle.
+#[derive(Default)]
+struct IdleTracker {
+    idle_since: HashMap<String, Instant>,
+}
+
+impl IdleTracker {
+    /// Record one observation of `name`; returns true once it has been idle
+    /// for at least `timeout`.
+    fn observe(&mut self, name: &str, active: bool, now: Instant, timeout: Duration) -> bool {
+        if active {
+            self.idle_since.remove(name);
+            return false;
+        }
+        let since = *self.idle_since.entry(name.to_string()).or_insert(now);
+        now.duration_since(since) >= timeout
+    }
+
+    fn forget(&mut self, name: &str) {
+        self.idle_since.remove(name);
+    }
+
+    /// Drop sessions that are no longer running.
+    fn retain(&mut self, running: &HashSet<String>) {
+        self.idle_since.retain(|name, _| running.contains(name));
+    }
+}
+
+fn cmd_template(action: TemplateAction) -> Result<i32> {
+    match action {
+        TemplateAction::Save(args) => {
+            let mut template = config::Template::default();
+            if let Some(src) = &args.from {
+                session::validate_name(src)?;
+                let sess = session::load(src)?;
+                template.image = Some(sess.image);
+                template.mount_path = Some(sess.mount_path);
+                template.env = sess.env;
+                template.caches = sess.caches;
+                if !sess.command.is_empty() {
+                    template.command = Some(shell_words::join(&sess.command));
+                }
+            }
+            if args.image.is_some() {
+                template.image = args.image;
+            }
+            if args.mount_path.is_some() {
+                template.mount_path = args.mount_path;
+            }
+            template.env.extend(args.env);
+            if args.docker_args.is_some() {
+                template.docker_args = args.docker_args;
+            }
+            if !args.cmd.is_empty() {
+                template.command = Some(shell_words::join(&args.cmd));
+            }
+            if template == config::Template::default() {
+                bail!("Nothing to save. Pass --from <session> or at least one setting.");
+            }
+            template.save(&args.name)?;
+            println!(
+                "Template '{}' saved to {}.",
+                args.name,
+                config::Template::path(&args.name)?.display()
+            );
+        }
+        TemplateAction::List => {
+            for name in config::Template::list()? {
+                println!("{}", name);
+            }
+        }
+        TemplateAction::Remove {
+            name,
+            registry: Some(registry),
+        } => {
+            debug_assert!(name.is_none());
+            registry::remove(&registry)?;
+            println!("Template registry '{}' removed.", registry);
+        }
+        TemplateAction::Remove { name, .. } => {
+            let name = name.expect("clap requires a template name");
+            config::Template::remove(&name)?;
+            println!("Template '{}' removed.", name);
+        }
+        TemplateAction::Add { source, name } => {
+            let source = registry::expand_source(&source);
+            let name = match name.or_else(|| registry::default_name(&source)) {
+                Some(name) => name,
+                None => bail!(
+                    "Can't derive a registry name from '{}'. Pass --name.",
+                    source
+                ),
+            };
+            let added = registry::add(&name, &source)?;
+            println!(
+                "Template registry '{}' added from {}.",
+                added.name, added.source
+            );
+            println!(
+                "Use its templates with `box create --template {}/<template>`.",
+                added.name
+            );
+        }
+        TemplateAction::Update { registry: name } => {
+            let registries = match name {
+                Some(name) => vec![registry::get(&name)?],
+                None => registry::list()?,
+            };
+            if registries.is_empty() {
+                println!("No template registries. Add one with `box template add <source>`.");
+            }
+            for r in registries {
+                registry::update(&r)?;
+                println!("Template registry '{}' updated.", r.name);
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn cmd_cd(name: &str) -> Result<i32> {
+    session::validate_name(name)?;
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+    let path = host_workspace(&session::load(name)?)?;
+    output_cd_path(&path.to_string_lossy());
+    Ok(0)
+}
+
+fn cmd_path(name: &str, export: Option<&Path>) -> Result<i32> {
+    session::validate_name(name)?;
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+    let sess = session::load(name)?;
+    let Some(dest) = export else {
+        println!("{}", host_workspace(&sess)?.display());
+        return Ok(0);
+    };
+
+    if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+        bail!("'{}' is not empty.", dest.display());
+    }
+    if sess.volume {
+        docker::check()?;
+        docker::export_workspace_volume(name, dest)?;
+    } else {
+        let _ = fs::remove_dir(dest);
+        docker::copy_workspace(&storage::workspace_dir(name)?, dest)?;
+    }
+    println!("Workspace of '{}' exported to {}.", name, dest.display());
+    Ok(0)
+}
+
+fn cmd_cp(args: &CpArgs) -> Result<i32> {
+    let to_host = match (session_path(&args.src), session_path(&args.dest)) {
+        (Some(_), None) => true,
+        (None, Some(_)) => false,
+        (Some(_), Some(_)) => {
+            bail!("Copying between two sessions isn't supported; copy through the host.")
+        }
+        (None, None) => bail!("One side must be a session path like NAME:PATH."),
+    };
+    let (name, path) = args.session().expect("one side is a session");
+    if !session::session_exists(name)? {
+        bail!("Session '{}' not found.", name);
+    }
+    let sess = session::load(name)?;
+    let path = container_path(&sess.mount_path, path);
+    let host = Path::new(if to_host { &args.dest } else { &args.src });
+
+    // Files in a bind-mounted workspace are on the host already, so copy
+    // them directly; that works without a container too.
+    let workspace = (!sess.volume)
+        .then(|| storage::workspace_dir(name))
+        .transpose()?
+        .and_then(|ws| workspace_host_path(&ws, &sess.mount_path, &path));
+    match workspace {
+        Some(ws) if to_host => docker::copy_path(&ws, host)?,
+        Some(ws) => docker::copy_path(host, &ws)?,
+        None => {
+            docker::check()?;
+            docker::copy_container_path(name, &path, host, to_host)?;
+        }
+    }
+    Ok(0)
+}
+
+/// Host directory of a session's workspace. Volume workspaces have none, so
+/// point at `box path --export` instead.
+fn host_workspace(sess: &session::Session) -> Result<std::path::PathBuf> {
+    if sess.volume {
+        bail!(
+            "Session '{}' keeps its workspace in the Docker volume {}. Copy it out with `box path {} --export <dir>`.",
+            sess.name,
+            docker::workspace_volume(&sess.name),
+            sess.name
+        );
+    }
+    storage::workspace_dir(&sess.name)
+}
+
+/// Subcommand paths (e.g. `ports add`) whose first positional argument is
+/// named `value_name`. Drives the dynamic parts of the completion scripts.
+fn completion_paths(cmd: &clap::Command, value_name: &str) -> Vec<String> {
+    fn walk(
+        cmd: &clap::Command,
+        prefix: &mut Vec<String>,
+        value_name: &str,
+        out: &mut Vec<String>,
+    ) {
+        for sub in cmd.get_subcommands() {
+            prefix.push(sub.get_name().to_string());
+            let takes_value = sub
+                .get_positionals()
+                .next()
+                .and_then(|arg| arg.get_value_names())
+                .is_some_and(|names| names.iter().any(|n| n == value_name));
+            if takes_value {
+                out.push(prefix.join(" "));
+            }
+            walk(sub, prefix, value_name, out);
+            prefix.pop();
+        }
+    }
+    let mut out = Vec::new();
+    walk(cmd, &mut Vec::new(), value_name, &mut out);
+    out
+}
+
+/// The command tree completion scripts are generated from. Hidden subcommands
+/// are left out, and session/template arguments get no static completion
+/// because the scripts fill those in from `box __complete-*`.
+fn completion_command() -> clap::Command {
+    fn prepare(cmd: clap::Command) -> clap::Command {
+        let mut cmd = cmd.mut_args(|arg| {
+            let dynamic = arg
+                .get_value_names()
+                .is_some_and(|names| names.iter().any(|n| n == "SESSION" || n == "TEMPLATE"));
+            if dynamic {
+                arg.value_hint(clap::ValueHint::Other)
+            } else {
+                arg
+            }
+        });
+        let names: Vec<String> = cmd
+            .get_subcommands()
+            .map(|sub| sub.get_name().to_string())
+            .collect();
+        for name in names {
+            cmd = cmd.mut_subcommand(name, prepare);
+        }
+        cmd
+    }
+    let cli = Cli::command();
+    let visible = cli
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .cloned();
+    prepare(clap::Command::new("box").subcommands(visible))
+}
+
+/// Generate the static part of a completion script from the clap definition.
+fn generate_completions(shell: clap_complete::Shell) -> String {
+    let mut buf = Vec::new();
+    clap_complete::generate(shell, &mut completion_command(), "box", &mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+fn cmd_config_complete_sessions() -> Result<i32> {
+    let home = config::home_dir()?;
+    for s in session::list()? {
+        let project = match s.project_dir.strip_prefix(&home) {
+            Some(rest) => format!("~{}", rest),
+            None => s.project_dir,
+        };
+        println!("{}\t{}", s.name, project);
+    }
+    Ok(0)
+}
+
+fn cmd_config_complete_templates() -> Result<i32> {
+    for name in config::Template::list()? {
+        println!("{}", name);
+    }
+    Ok(0)
+}
+
+fn cmd_config_zsh() -> Result<i32> {
+    let cmd = completion_command();
+    let quote = |paths: Vec<String>| {
+        paths
+            .iter()
+            .map(|p| format!("'{}'", p))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    print!("{}", generate_completions(clap_complete::Shell::Zsh));
+    print!(
+        r#"
+__box_sessions() {{
+    local -a sessions
+    sessions=(${{(f)"$(command box __complete-sessions 2>/dev/null)"}})
+    sessions=(${{sessions//$'\t'/:}})
+    if (( ${{#sessions}} )); then
+        _describe 'session' sessions
+    fi
+}}
+
+__box_templates() {{
+    local -a templates
+    templates=(${{(f)"$(command box __complete-templates 2>/dev/null)"}})
+    if (( ${{#templates}} )); then
+        _describe 'template' templates
+    fi
+}}
+
+# Session and template names come from box itself; everything else from _box.
+_box_dynamic() {{
+    local -a session_cmds template_cmds args
+    session_cmds=({sessions})
+    template_cmds=({templates})
+    args=(${{words[2,CURRENT-1]:#-*}})
+    local cmdpath="${{(j: :)args}}"
+    if [[ $words[CURRENT-1] == (-t|--template) ]]; then
+        __box_templates
+        return
+    elif [[ $words[CURRENT-1] == --from ]]; then
+        __box_sessions
+        return
+    fi
+    _box "$@"
+    [[ $PREFIX == -* ]] && return
+    # No arguments yet: sessions for the `box <name>` shortcut
+    if (( ${{#args}} == 0 || ${{session_cmds[(Ie)$cmdpath]}} )); then
+        __box_sessions
+    elif (( ${{template_cmds[(Ie)$cmdpath]}} )); then
+        __box_templates
+    fi
+}}
+compdef _box_dynamic box
+
+box() {{
+    local __box_cd_file
+    __box_cd_file=$(mktemp "${{TMPDIR:-/tmp}}/.box-cd.XXXXXX") || return
+    BOX_CD_FILE="$__box_cd_file" command box "$@"
+    local __box_exit=$?
+    if [[ -s "$__box_cd_file" ]]; then
+        local __box_dir
+        __box_dir=$(<"$__box_cd_file")
+        cd -- "$__box_dir"
+    fi
+    rm -f "$__box_cd_file"
+    return $__box_exit
+}}
+"#,
+        sessions = quote(completion_paths(&cmd, "SESSION")),
+        templates = quote(completion_paths(&cmd, "TEMPLATE")),
+    );
+    Ok(0)
+}
+
+fn cmd_config_bash() -> Result<i32> {
+    let cmd = completion_command();
+    let pattern = |paths: Vec<String>| {
+        paths
+            .iter()
+            .map(|p| format!("\"{}\"", p))
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+    print!("{}", generate_completions(clap_complete::Shell::Bash));
+    print!(
+        r#"
+# Session and template names come from box itself; everything else from _box.
+__box_dynamic() {{
+    _box "$@"
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    [[ $cur == -* ]] && return
+    local args=() w
+    for w in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        [[ $w == -* ]] || args+=("$w")
+    done
+    local values
+    if [[ $prev == -t || $prev == --template ]]; then
+        values=$(command box __complete-templates 2>/dev/null)
+        COMPREPLY=($(compgen -W "$values" -- "$cur"))
+        return
+    elif [[ $prev == --from ]]; then
+        values=$(command box __complete-sessions 2>/dev/null | cut -f1)
+        COMPREPLY=($(compgen -W "$values" -- "$cur"))
+        return
+    fi
+    case "${{args[*]}}" in
+        # No arguments yet: sessions for the `box <name>` shortcut
+        ""|{sessions})
+            values=$(command box __complete-sessions 2>/dev/null | cut -f1)
+            ;;
+        {templates})
+            values=$(command box __complete-templates 2>/dev/null)
+            ;;
+        *)
+            return
+            ;;
+    esac
+    # Keep subcommands offered by _box (e.g. `ports add`), drop its options
+    local keep=() c
+    for c in "${{COMPREPLY[@]}}"; do
+        [[ $c == -* ]] || keep+=("$c")
+    done
+    COMPREPLY=("${{keep[@]}}" $(compgen -W "$values" -- "$cur"))
+}}
+complete -F __box_dynamic -o bashdefault -o default box
+
+box() {{
+    local __box_cd_file
+    __box_cd_file=$(mktemp "${{TMPDIR:-/tmp}}/.box-cd.XXXXXX") || return
+    BOX_CD_FILE="$__box_cd_file" command box "$@"
+    local __box_exit=$?
+    if [[ -s "$__box_cd_file" ]]; then
+        local __box_dir
+        __box_dir=$(<"$__box_cd_file")
+        cd -- "$__box_dir"
+    fi
+    rm -f "$__box_cd_file"
+    return $__box_exit
+}}
+"#,
+        sessions = pattern(completion_paths(&cmd, "SESSION")),
+        templates = pattern(completion_paths(&cmd, "TEMPLATE")),
+    );
+    Ok(0)
+}
+
+fn cmd_config_fish() -> Result<i32> {
+    let cmd = completion_command();
+    let quote = |paths: Vec<String>| {
+        paths
+            .iter()
+            .map(|p| format!("\"{}\"", p))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    print!("{}", generate_completions(clap_complete::Shell::Fish));
+    print!(
+        r#"
+complete -c box -f
+
+# Succeeds when the subcommand path typed so far is one of the arguments
+function __box_completing
+    set -l tokens (commandline -opc)
+    set -e tokens[1]
+    set -l args (string match -v -- '-*' $tokens)
+    contains -- "$args" $argv
+end
+
+complete -c box -n __fish_box_needs_command -f -a '(command box __complete-sessions 2>/dev/null)'
+complete -c box -n '__box_completing {sessions}' -f -a '(command box __complete-sessions 2>/dev/null)'
+complete -c box -n '__box_completing {templates}' -f -a '(command box __complete-templates 2>/dev/null)'
+complete -c box -n '__fish_box_using_subcommand create' -s t -l template -x -a '(command box __complete-templates 2>/dev/null)'
+complete -c box -n '__fish_box_using_subcommand template' -l from -x -a '(command box __complete-sessions 2>/dev/null)'
+
+function box
+    set -l tmpdir /tmp
+    set -q TMPDIR; and set tmpdir $TMPDIR
+    set -l cd_file (mktemp $tmpdir/.box-cd.XXXXXX); or return
+    BOX_CD_FILE=$cd_file command box $argv
+    set -l exit_status $status
+    if test -s $cd_file
+        cd (cat $cd_file)
+    end
+    rm -f $cd_file
+    return $exit_status
+end
+"#,
+        sessions = quote(completion_paths(&cmd, "SESSION")),
+        templates = quote(completion_paths(&cmd, "TEMPLATE")),
+    );
+    Ok(0)
+}
+
+fn cmd_config_nu() -> Result<i32> {
+    print!(
+        r#"module box-completions {{
+    def "nu-complete box sessions" [] {{
+        ^box __complete-sessions | lines | split column "\t" value description
+    }}
+
+    def "nu-complete box templates" [] {{
+        ^box __complete-templates | lines
+    }}
+
+    def "nu-complete box ports action" [] {{ ["add" "remove"] }}
+    def "nu-complete box network modes" [] {{ ["none" "bridge" "host"] }}
+    def "nu-complete box docker contexts" [] {{ ^docker context ls --format "{{{{.Name}}}}" | lines }}
+    def "nu-complete box env action" [] {{ ["list" "set" "unset"] }}
+    def "nu-complete box secrets action" [] {{ ["list" "add" "remove"] }}
+    def "nu-complete box template action" [] {{ ["save" "list" "remove" "add" "update"] }}
+    def "nu-complete box shells" [] {{ ["zsh" "bash" "fish" "nu"] }}
+
+    # Session manager, or `box <name>` to create or resume a session
+    export extern "box" [
+        name?: string@"nu-complete box sessions"
+    ]
+
+    # Create a new session
+    export extern "box create" [
+        name?: string
+        --interactive(-i)                 # Prompt for the session's settings
+        -d                                # Run container in the background
+        --image: string                   # Docker image to use
+        --pin-digest                      # Pin the image to its sha256 digest
+        --note: string                    # Note describing the session
+        --docker-args: string             # Extra Docker flags
+        --no-ssh                          # Disable SSH agent forwarding
+        --ssh                             # Enable SSH agent forwarding
+        --worktree                        # Use git worktree for the workspace
+        --branch: string                  # Branch or commit to check out
+        --url: string                     # Clone this remote instead of the current repo
+        --depth: int                      # Clone only the last N commits
+        --filter: string                  # Partial clone filter (e.g. blob:none)
+        --volume                          # Keep the workspace in a Docker volume
+        --user-map                        # Run as your host uid:gid
+        --network: string@"nu-complete box network modes" # Network mode (none, bridge, host)
+        --hardened                        # Drop capabilities, read-only root, no network
+        --gpg                             # Forward the host's gpg-agent
+        --git-auth                        # Forward host git credentials for HTTPS remotes
+        --docker-socket                   # Mount the host's Docker socket
+        --dind                            # Run privileged for a nested Docker daemon
+        --idle-timeout: int               # Idle minutes before `box autostop` stops it
+        --context: string@"nu-complete box docker contexts" # Docker context (e.g. a remote host)
+        --kube                            # Run as a Kubernetes pod (experimental)
+        --template(-t): string@"nu-complete box templates" # Start from a saved template
+        --cpus: string                    # CPU limit
+        --memory: string                  # Memory limit
+        --publish(-p): string             # Publish a container port
+        --env(-e): string                 # Environment variable (KEY=VALUE)
+        --secret: string                  # Mount a host file at /run/secrets/NAME (NAME=PATH)
+        --cache: string                   # Mount a shared cache volume (cargo, npm, pip, ...)
+        --no-dotfiles                     # Skip installing the configured dotfiles
+        ...cmd: string                    # Command to run after --
+    ]
+
+    # Run a command once in a throwaway workspace and container
+    export extern "box run" [
+        --image: string                   # Docker image to use
+        --branch: string                  # Branch or commit to check out
+        --env(-e): string                 # Environment variable (KEY=VALUE)
+        --network: string@"nu-complete box network modes" # Network mode (none, bridge, host)
+        --hardened                        # Drop capabilities, read-only root, no network
+        --docker-args: string             # Extra Docker flags
+        ...cmd: string                    # Command to run after --
+    ]
+
+    # Resume an existing session
+    export extern "box resume" [
+        name: string@"nu-complete box sessions"
+        -d                                # Run container in the background
+        --docker-args: string             # Extra Docker flags
+        --no-ssh                          # Turn SSH agent forwarding off
+        --ssh                             # Turn SSH agent forwarding on
+    ]
+
+    # Remove a session
+    export extern "box remove" [
+        ...names: string@"nu-complete box sessions"
+        --force(-f)                       # Stop running sessions first
+    ]
+
+    # Stop a running session
+    export extern "box stop" [
+        ...names: string@"nu-complete box sessions"
+        --all                             # Stop every running session
+        --timeout(-t): int                # Seconds to wait before killing the session
+    ]
+
+    # Kill running sessions immediately
+    export extern "box kill" [...names: string@"nu-complete box sessions"]
+
+    # Attach to a running session
+    export extern "box attach" [name: string@"nu-complete box sessions"]
+
+    # Freeze a running session
+    export extern "box pause" [name: string@"nu-complete box sessions"]
+
+    # Resume a paused session
+    export extern "box unpause" [name: string@"nu-complete box sessions"]
+
+    # Run a command in a running session
+    export extern "box exec" [
+        name: string@"nu-complete box sessions"
+        ...cmd: string                    # Command to run after --
+    ]
+
+    # Show a session's container output
+    export extern "box logs" [
+        name: string@"nu-complete box sessions"
+        --follow(-f)                      # Follow log output
+        --tail(-n): string                # Number of lines from the end
+        --since: string                   # Show logs since timestamp or relative time
+    ]
+
+    # Open an additional shell in a session, starting it if needed
+    export extern "box shell" [name: string@"nu-complete box sessions"]
+
+    # Block until a session's container exits and return its exit code
+    export extern "box wait" [name: string@"nu-complete box sessions"]
+
+    # Connect to a session over ssh
+    export extern "box ssh" [
+        name: string@"nu-complete box sessions"
+        --print                           # Print the ssh command instead of running it
+    ]
+
+    # Open VS Code attached to a session's container
+    export extern "box code" [name: string@"nu-complete box sessions"]
+
+    # Duplicate a session
+    export extern "box clone" [
+        src: string@"nu-complete box sessions"
+        dst: string
+        --commit                          # Snapshot the source container as the new image
+        --fresh                           # Re-clone the workspace from the project
+    ]
+
+    # Snapshot a session's container as an image
+    export extern "box commit" [
+        name: string@"nu-complete box sessions"
+        tag?: string                      # Image tag (default: box-<name>:<timestamp>)
+        --use                             # Make the snapshot the session's image
+    ]
+
+    # Remove stale sessions and orphaned resources
+    export extern "box prune" [
+        --force(-f)                       # Remove without confirmation
+    ]
+
+    # Pull the latest version of a session's image
+    export extern "box pull" [name: string@"nu-complete box sessions"]
+
+    # Move data from older versions into the data directory
+    export extern "box migrate" []
+
+    # Reclaim disk space from exited containers, dangling images and old workspaces
+    export extern "box gc" [
+        --dry-run(-n)                     # Show what would be removed
+        --older-than: int                 # Minimum age in days for orphaned workspaces
+    ]
+
+    # Check docker, SSH agent forwarding and storage
+    export extern "box doctor" []
+
+    # Write a commented .box.toml with detected defaults to the repository root
+    export extern "box init" [
+        --force(-f)                       # Overwrite an existing .box.toml
+    ]
+
+    # Stop sessions that have been idle longer than their idle timeout
+    export extern "box autostop" [
+        --interval: int                   # Seconds between activity checks
+    ]
+
+    # Show detailed status of a session
+    export extern "box inspect" [
+        name: string@"nu-complete box sessions"
+        --json                            # Output as JSON
+    ]
+
+    # Show workspace changes vs. the host repo
+    export extern "box diff" [
+        name: string@"nu-complete box sessions"
+        --stat                            # Show a diffstat
+        --patch(-p)                       # Show the patch
+    ]
+
+    # Fetch workspace commits into the host repo, or mirror files both ways
+    export extern "box sync" [
+        name: string@"nu-complete box sessions"
+        --branch(-b): string              # Create a branch at the workspace HEAD
+        --ff                              # Fast-forward the host branch
+        --watch                           # Keep files in sync both ways until interrupted
+        --conflict: string                # With --watch: newer, host or workspace
+        --interval: int                   # With --watch: seconds between checks
+    ]
+
+    # Push the workspace's current branch to origin
+    export extern "box push" [
+        name: string@"nu-complete box sessions"
+        --set-upstream(-u)                # Make it the workspace branch's upstream
+        --branch(-b): string              # Push HEAD to this branch instead
+    ]
+
+    # Apply workspace changes to the host repo as patches
+    export extern "box apply" [
+        name: string@"nu-complete box sessions"
+        --branch(-b): string              # Apply on a new host branch
+    ]
+
+    # Save, list or delete snapshots of a session's workspace
+    export extern "box snapshot" [
+        name: string@"nu-complete box sessions"
+        snapshot?: string                 # Snapshot name (default: current time)
+        --list(-l)                        # List the session's snapshots
+        --remove: string                  # Delete this snapshot
+    ]
+
+    # Roll a session's workspace back to a snapshot
+    export extern "box restore" [
+        name: string@"nu-complete box sessions"
+        snapshot: string                  # Snapshot to restore
+    ]
+
+    # Show or change resource limits
+    export extern "box limits" [
+        name: string@"nu-complete box sessions"
+        --cpus: string                    # CPU limit
+        --memory: string                  # Memory limit
+    ]
+
+    # Show published ports
+    export extern "box ports" [name: string@"nu-complete box sessions"]
+
+    # Show or edit a session's note (opens $EDITOR without text)
+    export extern "box note" [
+        name: string@"nu-complete box sessions"
+        text?: string
+        --clear                           # Remove the note
+    ]
+
+    # Edit a session's stored settings in $EDITOR (applied on next resume)
+    export extern "box edit" [name: string@"nu-complete box sessions"]
+
+    # Show or change the network mode (applied on next resume)
+    export extern "box network" [
+        name: string@"nu-complete box sessions"
+        mode?: string@"nu-complete box network modes"
+    ]
+
+    # Publish ports (applied on next resume)
+    export extern "box ports add" [
+        name: string@"nu-complete box sessions"
+        ...ports: string
+    ]
+
+    # Stop publishing ports (applied on next resume)
+    export extern "box ports remove" [
+        name: string@"nu-complete box sessions"
+        ...ports: string
+    ]
+
+    # Show or change environment variables (applied on next resume)
+    export extern "box env" [
+        name: string@"nu-complete box sessions"
+        action?: string@"nu-complete box env action"
+        ...vars: string
+    ]
+
+    # Show or change mounted secrets (applied on next resume)
+    export extern "box secrets" [
+        name: string@"nu-complete box sessions"
+        action?: string@"nu-complete box secrets action"
+        ...secrets: string
+    ]
+
+    # Manage session templates
+    export extern "box template" [action: string@"nu-complete box template action"]
+
+    # Remove a template
+    export extern "box template remove" [
+        name?: string@"nu-complete box templates"
+        --registry: string                # Remove a template registry instead
+    ]
+
+    # Add a shared template registry (git URL, HTTPS URL, or org/repo)
+    export extern "box template add" [
+        source: string
+        --name: string                    # Registry name (default: the repo owner)
+    ]
+
+    # Refresh cached templates from registries
+    export extern "box template update" [registry?: string]
+
+    # List sessions
+    export extern "box list" [
+        --running(-r)                     # Show only running sessions
+        --stopped(-s)                     # Show only stopped sessions
+        --quiet(-q)                       # Only print session names
+        --json                            # Print sessions as JSON
+    ]
+
+    # Print a session's workspace path
+    export extern "box cd" [name: string@"nu-complete box sessions"]
+
+    # Print workspace path
+    export extern "box path" [
+        name: string@"nu-complete box sessions"
+        --export: path                    # Copy the workspace to a host directory
+    ]
+
+    # Copy files between the host and a session (NAME:PATH)
+    export extern "box cp" [
+        src: string
+        dest: string
+    ]
+
+    # Upgrade to latest version
+    export extern "box upgrade" []
+
+    # Output shell integration
+    export extern "box config" [shell: string@"nu-complete box shells"]
+}}
+
+export use box-completions *
+"#
+    );
+    Ok(0)
+}
+
+fn cmd_upgrade() -> Result<i32> {
+    let current_version = env!("CARGO_PKG_VERSION");
+    println!("Current version: {}", current_version);
+
+    println!("Checking for updates...");
+    let releases = self_update::backends::github::ReleaseList::configure()
+        .repo_owner("yusukeshib")
+        .repo_name("box")
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to build release list: {}", e))?
+        .fetch()
+        .map_err(|e| anyhow::anyhow!("Failed to fetch releases: {}", e))?;
+
+    let latest = releases
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No releases found"))?;
+    let latest_version = latest.version.trim_start_matches('v');
+
+    println!("Latest version: {}", latest_version);
+
+    if current_version == latest_version {
+        println!("Already at latest version.");
+        return Ok(0);
+    }
+
+    let asset_name = upgrade_asset_name()?;
+    println!("Looking for asset: {}", asset_name);
+
+    let asset_exists = latest.assets.iter().any(|a| a.name == asset_name);
+    if !asset_exists {
+        bail!(
+            "Asset '{}' not found for this platform. Available assets: {}",
+            asset_name,
+            latest
+                .assets
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let download_url = format!(
+        "https://github.com/yusukeshib/box/releases/download/v{}/{}",
+        latest_version, asset_name
+    );
+
+    let expected = upgrade_expected_checksum(latest, latest_version, &asset_name)?;
+
+    println!("Downloading new version...");
+    let tmp_path = upgrade_download(&download_url)?;
+    let _guard = UpgradeTempGuard(tmp_path.clone());
+
+    println!("Verifying checksum...");
+    let actual = sha256_hex(&fs::read(&tmp_path)?);
+    if actual != expected {
+        bail!(
+            "Checksum mismatch for {}: expected {}, got {}. Refusing to install.",
+            asset_name,
+            expected,
+            actual
+        );
+    }
+
+    println!("Installing update...");
+    self_update::self_replace::self_replace(&tmp_path).map_err(|e| {
+        let msg = e.to_string();
+        if msg.to_lowercase().contains("permission denied") {
+            anyhow::anyhow!(
+                "Permission denied. Try running with elevated privileges (e.g., sudo box upgrade)."
+            )
+        } else {
+            anyhow::anyhow!("{}", msg)
+        }
+    })?;
+
+    println!("Upgraded from {} to {}.", current_version, latest_version);
+    Ok(0)
+}
+
+/// Fetch the published SHA-256 for the release asset. Prefers the combined
+/// `SHA256SUMS` file and falls back to the per-asset `<asset>.sha256`.
+fn upgrade_expected_checksum(
+    release: &self_update::update::Release,
+    version: &str,
+    asset_name: &str,
+) -> Result<String> {
+    let per_asset = format!("{}.sha256", asset_name);
+    for file in ["SHA256SUMS", per_asset.as_str()] {
+        if !release.assets.iter().any(|a| a.name == file) {
+            continue;
+        }
+        let url = format!(
+            "https://github.com/yusukeshib/box/releases/download/v{}/{}",
+            version, file
+        );
+        let mut body = Vec::new();
+        self_update::Download::from_url(&url)
+            .download_to(&mut body)
+            .map_err(|e| anyhow::anyhow!("Failed to download {}: {}", file, e))?;
+        let contents = String::from_utf8_lossy(&body);
+        return parse_checksum(&contents, asset_name)
+            .ok_or_else(|| anyhow::anyhow!("No checksum for '{}' in {}", asset_name, file));
+    }
+    bail!(
+        "No checksum published for '{}'. Refusing to install an unverified binary.",
+        asset_name
+    )
+}
+
+/// Find the hash for `asset_name` in `sha256sum`-style output
+/// (`<hex>  <name>` or `<hex> *<name>` per line).
+fn parse_checksum(contents: &str, asset_name: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (hash, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        let valid = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+        (valid && name == asset_name).then(|| hash.to_ascii_lowercase())
+    })
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// RAII guard that removes the temp file on drop.
+struct UpgradeTempGuard(std::path::PathBuf);
+
+impl Drop for UpgradeTempGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn upgrade_asset_name() -> Result<String> {
+    let arch = std::env::consts::ARCH;
+    let os_name = match std::env::consts::OS {
+        "macos" => "darwin",
+        "linux" => "linux",
+        other => bail!("Unsupported platform: {}", other),
+    };
+    Ok(format!("box-{}-{}", arch, os_name))
+}
+
+fn upgrade_download(url: &str) -> Result<std::path::PathBuf> {
+    let tmp_path = std::env::temp_dir().join(format!("box-update-{}", std::process::id()));
+    let mut tmp_file = fs::File::create(&tmp_path)?;
+
+    self_update::Download::from_url(url)
+        .download_to(&mut tmp_file)
+        .map_err(|e| anyhow::anyhow!("Download failed: {}", e))?;
+
+    tmp_file.flush()?;
+    drop(tmp_file);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&tmp_path)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&tmp_path, perms)?;
+    }
+
+    Ok(tmp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Cli {
+        let mut full_args = vec!["box"];
+        full_args.extend_from_slice(args);
+        Cli::try_parse_from(full_args).unwrap()
+    }
+
+    fn try_parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let mut full_args = vec!["box"];
+        full_args.extend_from_slice(args);
+        Cli::try_parse_from(full_args)
+    }
+
+    #[test]
+    fn test_resume_waits_for_session_lock() {
+        session::tests::with_temp_home(|tmp| {
+            session::save(&session::Session {
+                name: "racing".to_string(),
+                project_dir: tmp.display().to_string(),
+                image: "alpine".to_string(),
+                mount_path: "/workspace".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+            // A delete on another thread holds the lock while resume starts
+            let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+            let remover = std::thread::spawn(move || {
+                let _lock = session::lock("racing").unwrap();
+                locked_tx.send(()).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                session::remove_dir("racing").unwrap();
+            });
+            locked_rx.recv().unwrap();
+
+            let err = cmd_resume("racing", None, None, true).unwrap_err();
+            assert_eq!(err.to_string(), "Session 'racing' not found.");
+            remover.join().unwrap();
+            assert!(!storage::workspace_dir("racing").unwrap().exists());
+        });
+    }
+
+    // -- No args = TUI --
+
+    #[test]
+    fn test_no_args_launches_tui() {
+        let cli = parse(&[]);
+        assert!(cli.command.is_none());
+    }
+
+    // -- create subcommand --
+
+    #[test]
+    fn test_create_name_only() {
+        let cli = parse(&["create", "my-session"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+                assert!(!args.detach);
+                assert!(args.image.is_none());
+                assert!(args.docker_args.is_none());
+                assert!(!args.no_ssh);
+                assert!(args.cmd.is_empty());
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_with_all_options() {
+        let cli = parse(&[
+            "create",
+            "full-session",
+            "-d",
+            "--image",
+            "python:3.11",
+            "--docker-args",
+            "-e FOO=bar --network host",
+            "--no-ssh",
+            "--",
+            "python",
+            "main.py",
+        ]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("full-session"));
+                assert!(args.detach);
+                assert_eq!(args.image.as_deref(), Some("python:3.11"));
+                assert_eq!(
+                    args.docker_args.as_deref(),
+                    Some("-e FOO=bar --network host")
+                );
+                assert!(args.no_ssh);
+                assert_eq!(args.cmd, vec!["python", "main.py"]);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_with_image() {
+        let cli = parse(&["create", "my-session", "--image", "ubuntu:latest"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+                assert_eq!(args.image.as_deref(), Some("ubuntu:latest"));
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_with_command() {
+        let cli = parse(&["create", "my-session", "--", "bash", "-c", "echo hi"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+                assert_eq!(args.cmd, vec!["bash", "-c", "echo hi"]);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_detach() {
+        let cli = parse(&["create", "my-session", "-d"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+                assert!(args.detach);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_ssh_flag() {
+        let cli = parse(&["create", "my-session", "--ssh"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert!(args.ssh);
+                assert!(!args.no_ssh);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_ssh_conflicts_with_no_ssh() {
+        let result = try_parse(&["create", "my-session", "--ssh", "--no-ssh"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_create_without_name_is_interactive() {
+        match parse(&["create"]).command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name, None);
+                assert!(!args.interactive);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+        match parse(&["create", "-i", "my-session"]).command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+                assert!(args.interactive);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    // -- resume subcommand --
+
+    #[test]
+    fn test_resume_name_only() {
+        let cli = parse(&["resume", "my-session"]);
+        match cli.command {
+            Some(Commands::Resume(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(!args.detach);
+                assert!(args.docker_args.is_none());
+            }
+            other => panic!("expected Resume, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resume_detach() {
+        let cli = parse(&["resume", "my-session", "-d"]);
+        match cli.command {
+            Some(Commands::Resume(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.detach);
+            }
+            other => panic!("expected Resume, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resume_with_docker_args() {
+        let cli = parse(&["resume", "my-session", "--docker-args", "-e KEY=val"]);
+        match cli.command {
+            Some(Commands::Resume(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.docker_args.as_deref(), Some("-e KEY=val"));
+            }
+            other => panic!("expected Resume, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resume_requires_name() {
+        let result = try_parse(&["resume"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resume_rejects_image() {
+        let result = try_parse(&["resume", "my-session", "--image", "ubuntu"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resume_ssh_flags() {
+        match parse(&["resume", "my-session", "--no-ssh"]).command {
+            Some(Commands::Resume(args)) => {
+                assert!(args.no_ssh);
+                assert!(!args.ssh);
+            }
+            other => panic!("expected Resume, got {:?}", other),
+        }
+        assert!(try_parse(&["resume", "my-session", "--ssh", "--no-ssh"]).is_err());
+    }
+
+    // -- remove subcommand --
+
+    #[test]
+    fn test_remove_parses() {
+        let cli = parse(&["remove", "my-session"]);
+        match cli.command {
+            Some(Commands::Remove(args)) => {
+                assert_eq!(args.names, ["my-session"]);
+                assert!(!args.force);
+            }
+            other => panic!("expected Remove, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remove_many_force() {
+        match parse(&["remove", "-f", "a", "b"]).command {
+            Some(ref cmd @ Commands::Remove(ref args)) => {
+                assert_eq!(args.names, ["a", "b"]);
+                assert!(args.force);
+                assert_eq!(cmd.session_name(), Some("a"));
+            }
+            other => panic!("expected Remove, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remove_requires_name() {
+        let result = try_parse(&["remove"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_remove_rejects_flags() {
+        let result = try_parse(&["remove", "my-session", "-d"]);
+        assert!(result.is_err());
+    }
+
+    // -- stop subcommand --
+
+    #[test]
+    fn test_stop_parses() {
+        let cli = parse(&["stop", "my-session"]);
+        match cli.command {
+            Some(Commands::Stop(args)) => {
+                assert_eq!(args.names, ["my-session"]);
+                assert!(!args.all);
+            }
+            other => panic!("expected Stop, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stop_many_and_all() {
+        match parse(&["stop", "a", "b"]).command {
+            Some(ref cmd @ Commands::Stop(ref args)) => {
+                assert_eq!(args.names, ["a", "b"]);
+                assert_eq!(cmd.session_name(), Some("a"));
+            }
+            other => panic!("expected Stop, got {:?}", other),
+        }
+        match parse(&["stop", "--all"]).command {
+            Some(ref cmd @ Commands::Stop(ref args)) => {
+                assert!(args.all && args.names.is_empty());
+                assert_eq!(cmd.session_name(), None);
+            }
+            other => panic!("expected Stop, got {:?}", other),
+        }
+        assert!(try_parse(&["stop", "a", "--all"]).is_err());
+    }
+
+    #[test]
+    fn test_stop_timeout() {
+        match parse(&["stop", "a", "-t", "30"]).command {
+            Some(Commands::Stop(args)) => assert_eq!(args.timeout, Some(30)),
+            other => panic!("expected Stop, got {:?}", other),
+        }
+        match parse(&["stop", "--all", "--timeout", "0"]).command {
+            Some(Commands::Stop(args)) => assert_eq!(args.timeout, Some(0)),
+            other => panic!("expected Stop, got {:?}", other),
+        }
+        assert!(try_parse(&["stop", "a", "-t", "soon"]).is_err());
+    }
+
+    #[test]
+    fn test_kill_parses() {
+        match parse(&["kill", "a", "b"]).command {
+            Some(ref cmd @ Commands::Kill(ref args)) => {
+                assert_eq!(args.names, ["a", "b"]);
+                assert_eq!(cmd.session_name(), Some("a"));
+            }
+            other => panic!("expected Kill, got {:?}", other),
+        }
+        assert!(try_parse(&["kill"]).is_err());
+    }
+
+    #[test]
+    fn test_stop_requires_name() {
+        let result = try_parse(&["stop"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_stop_rejects_flags() {
+        let result = try_parse(&["stop", "my-session", "-d"]);
+        assert!(result.is_err());
+    }
+
+    // -- exec subcommand --
+
+    #[test]
+    fn test_attach_parses() {
+        match parse(&["attach", "my-session"]).command {
+            Some(Commands::Attach(SessionArgs { ref name })) => assert_eq!(name, "my-session"),
+            other => panic!("expected Attach, got {:?}", other),
+        }
+        assert!(try_parse(&["attach"]).is_err());
+    }
+
+    #[test]
+    fn test_exec_parses() {
+        let cli = parse(&["exec", "my-session", "--", "ls", "-la"]);
+        match cli.command {
+            Some(Commands::Exec(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.cmd, vec!["ls", "-la"]);
+            }
+            other => panic!("expected Exec, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_exec_requires_name() {
+        let result = try_parse(&["exec"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_exec_requires_command() {
+        let result = try_parse(&["exec", "my-session"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_create_worktree_flag() {
+        let cli = parse(&["create", "my-session", "--worktree"]);
+        match cli.command {
+            Some(Commands::Create(args)) => assert!(args.worktree),
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_template_flag() {
+        let cli = parse(&["create", "my-session", "--template", "rust"]);
+        match cli.command {
+            Some(Commands::Create(args)) => assert_eq!(args.template.as_deref(), Some("rust")),
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    // -- template subcommand --
+
+    #[test]
+    fn test_template_save_parses() {
+        let cli = parse(&[
+            "template",
+            "save",
+            "rust",
+            "--from",
+            "my-session",
+            "--image",
+            "rust:latest",
+            "-e",
+            "A=1",
+            "-e",
+            "B=2",
+            "--docker-args",
+            "--network host",
+            "--",
+            "cargo",
+            "watch",
+        ]);
+        match cli.command {
+            Some(Commands::Template {
+                action: TemplateAction::Save(args),
+            }) => {
+                assert_eq!(args.name, "rust");
+                assert_eq!(args.from.as_deref(), Some("my-session"));
+                assert_eq!(args.image.as_deref(), Some("rust:latest"));
+                assert_eq!(args.env, vec!["A=1", "B=2"]);
+                assert_eq!(args.docker_args.as_deref(), Some("--network host"));
+                assert_eq!(args.cmd, vec!["cargo", "watch"]);
+            }
+            other => panic!("expected Template Save, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_template_list_and_remove_parse() {
+        assert!(matches!(
+            parse(&["template", "ls"]).command,
+            Some(Commands::Template {
+                action: TemplateAction::List
+            })
+        ));
+        match parse(&["template", "remove", "rust"]).command {
+            Some(Commands::Template {
+                action: TemplateAction::Remove { name, registry },
+            }) => {
+                assert_eq!(name.as_deref(), Some("rust"));
+                assert_eq!(registry, None);
+            }
+            other => panic!("expected Template Remove, got {:?}", other),
+        }
+        assert!(try_parse(&["template", "remove"]).is_err());
+        assert!(try_parse(&["template", "remove", "rust", "--registry", "org"]).is_err());
+    }
+
+    #[test]
+    fn test_template_registry_parse() {
+        match parse(&["template", "add", "org/templates", "--name", "acme"]).command {
+            Some(Commands::Template {
+                action: TemplateAction::Add { source, name },
+            }) => {
+                assert_eq!(source, "org/templates");
+                assert_eq!(name.as_deref(), Some("acme"));
+            }
+            other => panic!("expected Template Add, got {:?}", other),
+        }
+        match parse(&["template", "update"]).command {
+            Some(Commands::Template {
+                action: TemplateAction::Update { registry },
+            }) => assert_eq!(registry, None),
+            other => panic!("expected Template Update, got {:?}", other),
+        }
+        match parse(&["template", "remove", "--registry", "org"]).command {
+            Some(Commands::Template {
+                action: TemplateAction::Remove { name, registry },
+            }) => {
+                assert_eq!(name, None);
+                assert_eq!(registry.as_deref(), Some("org"));
+            }
+            other => panic!("expected Template Remove, got {:?}", other),
+        }
+    }
+
+    // -- pause/unpause subcommands --
+
+    #[test]
+    fn test_pause_and_unpause_parse() {
+        match parse(&["pause", "my-session"]).command {
+            Some(Commands::Pause(args)) => assert_eq!(args.name, "my-session"),
+            other => panic!("expected Pause, got {:?}", other),
+        }
+        match parse(&["unpause", "my-session"]).command {
+            Some(Commands::Unpause(args)) => assert_eq!(args.name, "my-session"),
+            other => panic!("expected Unpause, got {:?}", other),
+        }
+        assert!(try_parse(&["pause"]).is_err());
+    }
+
+    // -- logs subcommand --
+
+    #[test]
+    fn test_logs_parses() {
+        let cli = parse(&["logs", "my-session", "-f", "--tail", "50", "--since", "1h"]);
+        match cli.command {
+            Some(Commands::Logs(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.follow);
+                assert_eq!(args.tail.as_deref(), Some("50"));
+                assert_eq!(args.since.as_deref(), Some("1h"));
+            }
+            other => panic!("expected Logs, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_logs_requires_name() {
+        assert!(try_parse(&["logs"]).is_err());
+    }
+
+    // -- run subcommand --
+
+    #[test]
+    fn test_run_parses() {
+        let cli = parse(&[
+            "run",
+            "--hardened",
+            "-e",
+            "CI=1",
+            "--",
+            "sh",
+            "-c",
+            "make test",
+        ]);
+        match cli.command {
+            Some(Commands::Run(args)) => {
+                assert!(args.hardened);
+                assert_eq!(args.env, ["CI=1"]);
+                assert_eq!(args.cmd, ["sh", "-c", "make test"]);
+            }
+            other => panic!("expected Run, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_requires_command() {
+        assert!(try_parse(&["run"]).is_err());
+        assert!(try_parse(&["run", "--image", "ubuntu"]).is_err());
+    }
+
+    // -- shell subcommand --
+
+    #[test]
+    fn test_shell_parses() {
+        match parse(&["shell", "my-session"]).command {
+            Some(Commands::Shell(args)) => assert_eq!(args.name, "my-session"),
+            other => panic!("expected Shell, got {:?}", other),
+        }
+        assert!(try_parse(&["shell"]).is_err());
+    }
+
+    // -- wait subcommand --
+
+    #[test]
+    fn test_wait_parses() {
+        match parse(&["wait", "job"]).command {
+            Some(Commands::Wait(args)) => assert_eq!(args.name, "job"),
+            other => panic!("expected Wait, got {:?}", other),
+        }
+        assert!(try_parse(&["wait"]).is_err());
+    }
+
+    // -- clone subcommand --
+
+    #[test]
+    fn test_clone_parses() {
+        let cli = parse(&["clone", "src-session", "dst-session"]);
+        match cli.command {
+            Some(Commands::Clone(args)) => {
+                assert_eq!(args.src, "src-session");
+                assert_eq!(args.dst, "dst-session");
+                assert!(!args.commit);
+                assert!(!args.fresh);
+            }
+            other => panic!("expected Clone, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_clone_with_flags() {
+        let cli = parse(&["clone", "a", "b", "--commit", "--fresh"]);
+        match cli.command {
+            Some(Commands::Clone(args)) => {
+                assert!(args.commit);
+                assert!(args.fresh);
+            }
+            other => panic!("expected Clone, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_commit_parses() {
+        match parse(&["commit", "my-session"]).command {
+            Some(Commands::Commit(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.tag, None);
+                assert!(!args.use_image);
+            }
+            other => panic!("expected Commit, got {:?}", other),
+        }
+        match parse(&["commit", "my-session", "mydev:v2", "--use"]).command {
+            Some(Commands::Commit(args)) => {
+                assert_eq!(args.tag.as_deref(), Some("mydev:v2"));
+                assert!(args.use_image);
+            }
+            other => panic!("expected Commit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_clone_requires_both_names() {
+        let result = try_parse(&["clone", "a"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_create_limits() {
+        let cli = parse(&["create", "s", "--cpus", "1.5", "--memory", "4g"]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.cpus.as_deref(), Some("1.5"));
+                assert_eq!(args.memory.as_deref(), Some("4g"));
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_rejects_invalid_limits() {
+        assert!(try_parse(&["create", "s", "--cpus", "lots"]).is_err());
+        assert!(try_parse(&["create", "s", "--cpus", "-1"]).is_err());
+        assert!(try_parse(&["create", "s", "--memory", "4gb"]).is_err());
+        assert!(try_parse(&["create", "s", "--memory", "g"]).is_err());
+    }
+
+    #[test]
+    fn test_create_publish() {
+        let cli = parse(&[
+            "create",
+            "s",
+            "-p",
+            "8080:80",
+            "--publish",
+            "127.0.0.1::53/udp",
+        ]);
+        match cli.command {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.publish, vec!["8080:80", "127.0.0.1::53/udp"]);
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_publish() {
+        for ok in [
+            "80",
+            "8080:80",
+            "8000-8010:8000-8010",
+            "127.0.0.1:5432:5432",
+            "127.0.0.1::80",
+            "53:53/udp",
+        ] {
+            assert_eq!(parse_publish(ok).as_deref(), Ok(ok), "{}", ok);
+        }
+        for bad in ["", "http", "8080:", ":80", "0:80", "80/icmp", "70000:80"] {
+            assert!(parse_publish(bad).is_err(), "{}", bad);
+        }
+    }
+
+    // -- ports subcommand --
+
+    #[test]
+    fn test_ports_list_parses() {
+        let cli = parse(&["ports", "my-session"]);
+        match cli.command {
+            Some(Commands::Ports(args)) => {
+                assert!(args.action.is_none());
+                assert_eq!(args.name.as_deref(), Some("my-session"));
+            }
+            other => panic!("expected Ports, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ports_add_and_remove_parse() {
+        match parse(&["ports", "add", "s", "8080:80", "9000:9000"]).command {
+            Some(Commands::Ports(PortsArgs {
+                action: Some(PortsAction::Add { name, specs }),
+                ..
+            })) => {
+                assert_eq!(name, "s");
+                assert_eq!(specs, vec!["8080:80", "9000:9000"]);
+            }
+            other => panic!("expected Ports Add, got {:?}", other),
+        }
+        assert!(matches!(
+            parse(&["ports", "remove", "s", "8080:80"]).command,
+            Some(Commands::Ports(PortsArgs {
+                action: Some(PortsAction::Remove { .. }),
+                ..
+            }))
+        ));
+        assert!(try_parse(&["ports"]).is_err());
+        assert!(try_parse(&["ports", "add", "s", "nope"]).is_err());
+    }
+
+    // -- limits subcommand --
+
+    #[test]
+    fn test_limits_parses() {
+        let cli = parse(&["limits", "s", "--memory", "512m"]);
+        match cli.command {
+            Some(Commands::Limits(args)) => {
+                assert_eq!(args.name, "s");
+                assert_eq!(args.cpus, None);
+                assert_eq!(args.memory.as_deref(), Some("512m"));
+            }
+            other => panic!("expected Limits, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_is_zero() {
+        assert!(is_zero("0"));
+        assert!(is_zero("0.0"));
+        assert!(is_zero("0g"));
+        assert!(!is_zero("0.5"));
+        assert!(!is_zero("4g"));
+    }
+
+    // -- diff subcommand --
+
+    #[test]
+    fn test_diff_parses() {
+        let cli = parse(&["diff", "my-session", "--stat"]);
+        match cli.command {
+            Some(Commands::Diff(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.stat);
+                assert!(!args.patch);
+            }
+            other => panic!("expected Diff, got {:?}", other),
+        }
+    }
 
-box() {{
-    local __box_cd_file
-    __box_cd_file=$(mktemp "/tmp/.box-cd.XXXXXX")
-    BOX_CD_FILE="$__box_cd_file" command box "$@"
-    local __box_exit=$?
-    if [[ -s "$__box_cd_file" ]]; then
-        local __box_dir
-        __box_dir=$(<"$__box_cd_file")
-        cd "$__box_dir"
-    fi
-    rm -f "$__box_cd_file"
-    return $__box_exit
-}}
-"#
-    );
-    Ok(0)
-}
+    #[test]
+    fn test_diff_stat_and_patch() {
+        let cli = parse(&["diff", "s", "--stat", "-p"]);
+        match cli.command {
+            Some(Commands::Diff(args)) => assert!(args.stat && args.patch),
+            other => panic!("expected Diff, got {:?}", other),
+        }
+    }
 
-fn cmd_upgrade() -> Result<i32> {
-    let current_version = env!("CARGO_PKG_VERSION");
-    println!("Current version: {}", current_version);
+    // -- sync subcommand --
 
-    println!("Checking for updates...");
-    let releases = self_update::backends::github::ReleaseList::configure()
-        .repo_owner("yusukeshib")
-        .repo_name("box")
-        .build()
-        .map_err(|e| anyhow::anyhow!("Failed to build release list: {}", e))?
-        .fetch()
-        .map_err(|e| anyhow::anyhow!("Failed to fetch releases: {}", e))?;
+    #[test]
+    fn test_sync_parses() {
+        let cli = parse(&["sync", "my-session", "--branch", "feature/x"]);
+        match cli.command {
+            Some(Commands::Sync(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.branch.as_deref(), Some("feature/x"));
+                assert!(!args.ff);
+            }
+            other => panic!("expected Sync, got {:?}", other),
+        }
+    }
 
-    let latest = releases
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("No releases found"))?;
-    let latest_version = latest.version.trim_start_matches('v');
+    #[test]
+    fn test_sync_branch_conflicts_with_ff() {
+        assert!(try_parse(&["sync", "s", "--branch", "b", "--ff"]).is_err());
+    }
 
-    println!("Latest version: {}", latest_version);
+    #[test]
+    fn test_push_parses() {
+        let cli = parse(&["push", "my-session", "-u", "--branch", "feature/x"]);
+        match cli.command {
+            Some(Commands::Push(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.set_upstream);
+                assert_eq!(args.branch.as_deref(), Some("feature/x"));
+            }
+            other => panic!("expected Push, got {:?}", other),
+        }
+    }
 
-    if current_version == latest_version {
-        println!("Already at latest version.");
-        return Ok(0);
+    #[test]
+    fn test_apply_parses() {
+        let cli = parse(&["apply", "my-session", "-b", "from-box"]);
+        match cli.command {
+            Some(Commands::Apply(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.branch.as_deref(), Some("from-box"));
+            }
+            other => panic!("expected Apply, got {:?}", other),
+        }
     }
 
-    let asset_name = upgrade_asset_name()?;
-    println!("Looking for asset: {}", asset_name);
+    #[test]
+    fn test_snapshot_parses() {
+        let cli = parse(&["snapshot", "my-session", "before"]);
+        match cli.command {
+            Some(Commands::Snapshot(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert_eq!(args.snapshot.as_deref(), Some("before"));
+                assert!(!args.list);
+            }
+            other => panic!("expected Snapshot, got {:?}", other),
+        }
+        match parse(&["snapshot", "s", "--remove", "before"]).command {
+            Some(Commands::Snapshot(args)) => assert_eq!(args.remove.as_deref(), Some("before")),
+            other => panic!("expected Snapshot, got {:?}", other),
+        }
+        assert!(try_parse(&["snapshot", "s", "before", "--list"]).is_err());
+        assert!(try_parse(&["snapshot", "s", "--list", "--remove", "x"]).is_err());
 
-    let asset_exists = latest.assets.iter().any(|a| a.name == asset_name);
-    if !asset_exists {
-        bail!(
-            "Asset '{}' not found for this platform. Available assets: {}",
-            asset_name,
-            latest
-                .assets
-                .iter()
-                .map(|a| a.name.as_str())
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
+        match parse(&["restore", "s", "before"]).command {
+            Some(Commands::Restore(args)) => assert_eq!(args.snapshot, "before"),
+            other => panic!("expected Restore, got {:?}", other),
+        }
+        assert!(try_parse(&["restore", "s"]).is_err());
     }
 
-    let download_url = format!(
-        "https://github.com/yusukeshib/box/releases/download/v{}/{}",
-        latest_version, asset_name
-    );
+    #[test]
+    fn test_sync_watch_parses() {
+        let cli = parse(&[
+            "sync",
+            "s",
+            "--watch",
+            "--conflict",
+            "host",
+            "--interval",
+            "5",
+        ]);
+        match cli.command {
+            Some(Commands::Sync(args)) => {
+                assert!(args.watch);
+                assert_eq!(args.conflict, filesync::Conflict::Host);
+                assert_eq!(args.interval, 5);
+            }
+            other => panic!("expected Sync, got {:?}", other),
+        }
+        assert!(try_parse(&["sync", "s", "--watch", "--ff"]).is_err());
+        assert!(try_parse(&["sync", "s", "--conflict", "host"]).is_err());
+        assert!(try_parse(&["sync", "s", "--watch", "--conflict", "ours"]).is_err());
+    }
 
-    println!("Downloading new version...");
-    let tmp_path = upgrade_download(&download_url)?;
-    let _guard = UpgradeTempGuard(tmp_path.clone());
+    // -- inspect subcommand --
 
-    println!("Installing update...");
-    self_update::self_replace::self_replace(&tmp_path).map_err(|e| {
-        let msg = e.to_string();
-        if msg.to_lowercase().contains("permission denied") {
-            anyhow::anyhow!(
-                "Permission denied. Try running with elevated privileges (e.g., sudo box upgrade)."
-            )
-        } else {
-            anyhow::anyhow!("{}", msg)
+    #[test]
+    fn test_inspect_parses() {
+        let cli = parse(&["inspect", "my-session", "--json"]);
+        match cli.command {
+            Some(Commands::Inspect(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.json);
+            }
+            other => panic!("expected Inspect, got {:?}", other),
         }
-    })?;
+    }
 
-    println!("Upgraded from {} to {}.", current_version, latest_version);
-    Ok(0)
-}
+    #[test]
+    fn test_inspect_requires_name() {
+        assert!(try_parse(&["inspect"]).is_err());
+    }
 
-/// RAII guard that removes the temp file on drop.
-struct UpgradeTempGuard(std::path::PathBuf);
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
 
-impl Drop for UpgradeTempGuard {
-    fn drop(&mut self) {
-        let _ = fs::remove_file(&self.0);
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(125), "2m 5s");
+        assert_eq!(format_duration(7500), "2h 5m");
+        assert_eq!(format_duration(90000), "1d 1h");
     }
-}
 
-fn upgrade_asset_name() -> Result<String> {
-    let arch = std::env::consts::ARCH;
-    let os_name = match std::env::consts::OS {
-        "macos" => "darwin",
-        "linux" => "linux",
-        other => bail!("Unsupported platform: {}", other),
-    };
-    Ok(format!("box-{}-{}", arch, os_name))
-}
+    #[test]
+    fn test_container_status() {
+        let mut s = summary("dev", "/p");
+        assert_eq!(container_status(&s), None);
+
+        s.exit_code = Some(137);
+        s.finished_at = "not a time".to_string();
+        assert_eq!(container_status(&s).as_deref(), Some("exit 137"));
+        s.finished_at = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+        assert!(container_status(&s).unwrap().starts_with("exit 137, 5m"));
+
+        s.running = true;
+        s.started_at = (chrono::Utc::now() - chrono::Duration::hours(2)).to_rfc3339();
+        assert!(container_status(&s).unwrap().starts_with("up 2h"));
+
+        s.paused = true;
+        assert_eq!(container_status(&s).as_deref(), Some("paused"));
+    }
 
-fn upgrade_download(url: &str) -> Result<std::path::PathBuf> {
-    let tmp_path = std::env::temp_dir().join(format!("box-update-{}", std::process::id()));
-    let mut tmp_file = fs::File::create(&tmp_path)?;
+    // -- prune subcommand --
 
-    self_update::Download::from_url(url)
-        .download_to(&mut tmp_file)
-        .map_err(|e| anyhow::anyhow!("Download failed: {}", e))?;
+    #[test]
+    fn test_prune_parses() {
+        let cli = parse(&["prune"]);
+        match cli.command {
+            Some(Commands::Prune(args)) => assert!(!args.force),
+            other => panic!("expected Prune, got {:?}", other),
+        }
+    }
 
-    tmp_file.flush()?;
-    drop(tmp_file);
+    #[test]
+    fn test_prune_force_flag() {
+        for flag in ["-f", "--force"] {
+            let cli = parse(&["prune", flag]);
+            match cli.command {
+                Some(Commands::Prune(args)) => assert!(args.force),
+                other => panic!("expected Prune, got {:?}", other),
+            }
+        }
+    }
 
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&tmp_path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&tmp_path, perms)?;
+    fn summary(name: &str, project_dir: &str) -> session::SessionSummary {
+        session::SessionSummary {
+            name: name.to_string(),
+            project_dir: project_dir.to_string(),
+            image: String::new(),
+            command: String::new(),
+            created_at: String::new(),
+            resumed_at: String::new(),
+            running: false,
+            paused: false,
+            started_at: String::new(),
+            finished_at: String::new(),
+            exit_code: None,
+        }
     }
 
-    Ok(tmp_path)
-}
+    #[test]
+    fn test_find_prune_candidates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live = tmp.path().to_string_lossy().to_string();
+        let sessions = vec![summary("alive", &live), summary("gone", "/nonexistent/box")];
+        let workspaces = vec!["alive".to_string(), "leftover".to_string()];
+        let containers = vec!["gone".to_string(), "stray".to_string()];
+
+        let candidates = find_prune_candidates(&sessions, &workspaces, &containers);
+        assert_eq!(
+            candidates,
+            PruneCandidates {
+                stale_sessions: vec!["gone".to_string()],
+                orphan_workspaces: vec!["leftover".to_string()],
+                orphan_containers: vec!["stray".to_string()],
+            }
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::Parser;
+    #[test]
+    fn test_find_prune_candidates_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live = tmp.path().to_string_lossy().to_string();
+        let sessions = vec![summary("alive", &live)];
+        let candidates =
+            find_prune_candidates(&sessions, &["alive".to_string()], &["alive".to_string()]);
+        assert!(candidates.is_empty());
+    }
 
-    fn parse(args: &[&str]) -> Cli {
-        let mut full_args = vec!["box"];
-        full_args.extend_from_slice(args);
-        Cli::try_parse_from(full_args).unwrap()
+    // -- path subcommand --
+
+    #[test]
+    fn test_cp_subcommand() {
+        match parse(&["cp", "s:target/app", "./app"]).command {
+            Some(Commands::Cp(args)) => {
+                assert_eq!(args.session(), Some(("s", "target/app")));
+            }
+            other => panic!("expected Cp, got {:?}", other),
+        }
+        match parse(&["cp", "notes.txt", "s:/tmp/notes.txt"]).command {
+            Some(ref cmd @ Commands::Cp(_)) => assert_eq!(cmd.session_name(), Some("s")),
+            other => panic!("expected Cp, got {:?}", other),
+        }
+        assert!(try_parse(&["cp", "s:a"]).is_err());
     }
 
-    fn try_parse(args: &[&str]) -> Result<Cli, clap::Error> {
-        let mut full_args = vec!["box"];
-        full_args.extend_from_slice(args);
-        Cli::try_parse_from(full_args)
+    #[test]
+    fn test_session_path() {
+        assert_eq!(
+            session_path("dev:src/main.rs"),
+            Some(("dev", "src/main.rs"))
+        );
+        assert_eq!(session_path("dev:"), Some(("dev", "")));
+        assert_eq!(session_path("./dev:x"), None);
+        assert_eq!(session_path("/tmp/a:b"), None);
+        assert_eq!(session_path("plain"), None);
     }
 
-    // -- No args = TUI --
+    #[test]
+    fn test_workspace_host_path() {
+        let ws = Path::new("/data/workspaces/dev");
+        assert_eq!(
+            container_path("/workspace", "target/app"),
+            "/workspace/target/app"
+        );
+        assert_eq!(container_path("/workspace", ""), "/workspace");
+        assert_eq!(container_path("/workspace", "/etc/hosts"), "/etc/hosts");
+        assert_eq!(
+            workspace_host_path(ws, "/workspace", "/workspace/target/app"),
+            Some(ws.join("target/app"))
+        );
+        assert_eq!(
+            workspace_host_path(ws, "/workspace", "/workspace"),
+            Some(ws.to_path_buf())
+        );
+        assert_eq!(
+            workspace_host_path(ws, "/workspace", "/workspace/dist/."),
+            Some(ws.join("dist/."))
+        );
+        assert_eq!(workspace_host_path(ws, "/workspace", "/etc/hosts"), None);
+        assert_eq!(
+            workspace_host_path(ws, "/workspace", "/workspace/../etc"),
+            None
+        );
+        assert_eq!(workspace_host_path(ws, "/workspace", "/workspacefoo"), None);
+    }
 
     #[test]
-    fn test_no_args_launches_tui() {
-        let cli = parse(&[]);
-        assert!(cli.command.is_none());
+    fn test_path_subcommand_parses() {
+        let cli = parse(&["path", "my-session"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Path { ref name, export: None }) if name == "my-session"
+        ));
     }
 
-    // -- create subcommand --
+    #[test]
+    fn test_path_export_parses() {
+        match parse(&["path", "my-session", "--export", "out"]).command {
+            Some(Commands::Path { name, export }) => {
+                assert_eq!(name, "my-session");
+                assert_eq!(export, Some(std::path::PathBuf::from("out")));
+            }
+            other => panic!("expected Path, got {:?}", other),
+        }
+    }
 
     #[test]
-    fn test_create_name_only() {
-        let cli = parse(&["create", "my-session"]);
-        match cli.command {
-            Some(Commands::Create(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert!(!args.detach);
-                assert!(args.image.is_none());
-                assert!(args.docker_args.is_none());
-                assert!(!args.no_ssh);
-                assert!(args.cmd.is_empty());
+    fn test_note_subcommand() {
+        match parse(&["note", "s"]).command {
+            Some(Commands::Note(args)) => {
+                assert_eq!(args.name, "s");
+                assert_eq!(args.text, None);
+                assert!(!args.clear);
             }
+            other => panic!("expected Note, got {:?}", other),
+        }
+        match parse(&["note", "s", "fix the parser"]).command {
+            Some(Commands::Note(args)) => assert_eq!(args.text.as_deref(), Some("fix the parser")),
+            other => panic!("expected Note, got {:?}", other),
+        }
+        match parse(&["create", "s", "--note", "spike"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.note.as_deref(), Some("spike")),
             other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["note", "s", "text", "--clear"]).is_err());
     }
 
     #[test]
-    fn test_create_with_all_options() {
-        let cli = parse(&[
-            "create",
-            "full-session",
-            "-d",
-            "--image",
-            "python:3.11",
-            "--docker-args",
-            "-e FOO=bar --network host",
-            "--no-ssh",
-            "--",
-            "python",
-            "main.py",
-        ]);
-        match cli.command {
-            Some(Commands::Create(args)) => {
-                assert_eq!(args.name, "full-session");
-                assert!(args.detach);
-                assert_eq!(args.image.as_deref(), Some("python:3.11"));
-                assert_eq!(
-                    args.docker_args.as_deref(),
-                    Some("-e FOO=bar --network host")
-                );
-                assert!(args.no_ssh);
-                assert_eq!(args.cmd, vec!["python", "main.py"]);
+    fn test_edit_subcommand() {
+        match parse(&["edit", "s"]).command {
+            Some(ref cmd @ Commands::Edit(ref args)) => {
+                assert_eq!(args.name, "s");
+                assert_eq!(cmd.session_name(), Some("s"));
             }
+            other => panic!("expected Edit, got {:?}", other),
+        }
+        assert!(try_parse(&["edit"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let base = "image = \"python:3.12\"\nmount_path = \"/app\"\n";
+        let settings = parse_settings(&format!(
+            "# comment\n{}command = \"python -m http.server\"\nports = [\"8000:8000\"]\ncpus = \"2\"\n",
+            base
+        ))
+        .unwrap();
+        assert_eq!(settings.image, "python:3.12");
+        assert_eq!(settings.ports, ["8000:8000"]);
+        assert_eq!(settings.cpus.as_deref(), Some("2"));
+        assert_eq!(settings.memory, None);
+
+        assert!(parse_settings("image = \"\"\nmount_path = \"/app\"\n").is_err());
+        assert!(parse_settings("image = \"alpine\"\nmount_path = \"app\"\n").is_err());
+        assert!(parse_settings(&format!("{}ports = [\"http\"]\n", base)).is_err());
+        assert!(parse_settings(&format!("{}memory = \"lots\"\n", base)).is_err());
+        assert!(parse_settings(&format!("{}command = \"echo 'oops\"\n", base)).is_err());
+        assert!(parse_settings(&format!("{}kube = true\n", base)).is_err());
+    }
+
+    #[test]
+    fn test_network_subcommand() {
+        match parse(&["network", "s"]).command {
+            Some(Commands::Network(args)) => assert_eq!(args.mode, None),
+            other => panic!("expected Network, got {:?}", other),
+        }
+        match parse(&["network", "s", "none"]).command {
+            Some(Commands::Network(args)) => assert_eq!(args.mode.as_deref(), Some("none")),
+            other => panic!("expected Network, got {:?}", other),
+        }
+        match parse(&["create", "s", "--network", "host"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.network.as_deref(), Some("host")),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--network", "a b"]).is_err());
+    }
+
+    #[test]
+    fn test_create_branch_flag() {
+        match parse(&["create", "s", "--branch", "feature/x"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.branch.as_deref(), Some("feature/x")),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--branch", "main", "--kube"]).is_err());
+    }
+
+    #[test]
+    fn test_create_url_flag() {
+        let url = "git@github.com:org/repo.git";
+        match parse(&["create", "s", "--url", url]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.url.as_deref(), Some(url)),
             other => panic!("expected Create, got {:?}", other),
         }
+        for flag in ["--worktree", "--volume", "--kube"] {
+            assert!(try_parse(&["create", "s", "--url", url, flag]).is_err());
+        }
     }
 
     #[test]
-    fn test_create_with_image() {
-        let cli = parse(&["create", "my-session", "--image", "ubuntu:latest"]);
-        match cli.command {
+    fn test_create_clone_flags() {
+        match parse(&["create", "s", "--depth", "1", "--filter", "blob:none"]).command {
             Some(Commands::Create(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert_eq!(args.image.as_deref(), Some("ubuntu:latest"));
+                assert_eq!(args.depth, Some(1));
+                assert_eq!(args.filter.as_deref(), Some("blob:none"));
             }
             other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--depth", "1", "--worktree"]).is_err());
+        assert!(try_parse(&["create", "s", "--filter", "blob:none", "--kube"]).is_err());
     }
 
     #[test]
-    fn test_create_with_command() {
-        let cli = parse(&["create", "my-session", "--", "bash", "-c", "echo hi"]);
-        match cli.command {
-            Some(Commands::Create(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert_eq!(args.cmd, vec!["bash", "-c", "echo hi"]);
-            }
+    fn test_parse_clone_filter() {
+        for ok in ["blob:none", "blob:limit=1m", "tree:0"] {
+            assert_eq!(parse_clone_filter(ok).as_deref(), Ok(ok));
+        }
+        for bad in ["", "blob", ":none", "blob:", "blob:none;rm", "Blob:none"] {
+            assert!(parse_clone_filter(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_create_user_map_flag() {
+        match parse(&["create", "s", "--user-map"]).command {
+            Some(Commands::Create(args)) => assert!(args.user_map),
             other => panic!("expected Create, got {:?}", other),
         }
     }
 
     #[test]
-    fn test_create_detach() {
-        let cli = parse(&["create", "my-session", "-d"]);
-        match cli.command {
+    fn test_create_hardened_flag() {
+        match parse(&["create", "s", "--hardened"]).command {
             Some(Commands::Create(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert!(args.detach);
+                assert!(args.hardened);
+                assert_eq!(args.network, None);
             }
             other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--hardened", "--kube"]).is_err());
     }
 
     #[test]
-    fn test_create_requires_name() {
-        let result = try_parse(&["create"]);
-        assert!(result.is_err());
+    fn test_create_gpg_flag() {
+        match parse(&["create", "s", "--gpg"]).command {
+            Some(Commands::Create(args)) => assert!(args.gpg),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--gpg", "--kube"]).is_err());
     }
 
-    // -- resume subcommand --
+    #[test]
+    fn test_create_git_auth_flag() {
+        match parse(&["create", "s", "--git-auth"]).command {
+            Some(Commands::Create(args)) => assert!(args.git_auth),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--git-auth", "--kube"]).is_err());
+    }
 
     #[test]
-    fn test_resume_name_only() {
-        let cli = parse(&["resume", "my-session"]);
-        match cli.command {
-            Some(Commands::Resume(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert!(!args.detach);
-                assert!(args.docker_args.is_none());
+    fn test_create_docker_access_flags() {
+        match parse(&["create", "s", "--docker-socket"]).command {
+            Some(Commands::Create(args)) => {
+                assert!(args.docker_socket);
+                assert!(!args.dind);
             }
-            other => panic!("expected Resume, got {:?}", other),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        match parse(&["create", "s", "--dind"]).command {
+            Some(Commands::Create(args)) => assert!(args.dind),
+            other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--docker-socket", "--dind"]).is_err());
+        assert!(try_parse(&["create", "s", "--dind", "--hardened"]).is_err());
+        assert!(try_parse(&["create", "s", "--docker-socket", "--hardened"]).is_err());
+        assert!(try_parse(&["create", "s", "--dind", "--kube"]).is_err());
     }
 
     #[test]
-    fn test_resume_detach() {
-        let cli = parse(&["resume", "my-session", "-d"]);
-        match cli.command {
-            Some(Commands::Resume(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert!(args.detach);
-            }
-            other => panic!("expected Resume, got {:?}", other),
+    fn test_create_pin_digest_flag() {
+        match parse(&["create", "s", "--image", "python:3.12", "--pin-digest"]).command {
+            Some(Commands::Create(args)) => assert!(args.pin_digest),
+            other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--pin-digest", "--kube"]).is_err());
     }
 
     #[test]
-    fn test_resume_with_docker_args() {
-        let cli = parse(&["resume", "my-session", "--docker-args", "-e KEY=val"]);
-        match cli.command {
-            Some(Commands::Resume(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert_eq!(args.docker_args.as_deref(), Some("-e KEY=val"));
-            }
-            other => panic!("expected Resume, got {:?}", other),
+    fn test_create_volume_conflicts_with_worktree() {
+        match parse(&["create", "s", "--volume"]).command {
+            Some(Commands::Create(args)) => assert!(args.volume),
+            other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--volume", "--worktree"]).is_err());
     }
 
     #[test]
-    fn test_resume_requires_name() {
-        let result = try_parse(&["resume"]);
+    fn test_path_requires_name() {
+        let result = try_parse(&["path"]);
         assert!(result.is_err());
     }
 
+    // -- cd subcommand --
+
     #[test]
-    fn test_resume_rejects_image() {
-        let result = try_parse(&["resume", "my-session", "--image", "ubuntu"]);
-        assert!(result.is_err());
+    fn test_cd_subcommand_parses() {
+        let cli = parse(&["cd", "my-session"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Cd { ref name }) if name == "my-session"
+        ));
     }
 
     #[test]
-    fn test_resume_rejects_no_ssh() {
-        let result = try_parse(&["resume", "my-session", "--no-ssh"]);
+    fn test_cd_requires_name() {
+        let result = try_parse(&["cd"]);
         assert!(result.is_err());
     }
 
-    // -- remove subcommand --
+    // -- upgrade subcommand --
 
     #[test]
-    fn test_remove_parses() {
-        let cli = parse(&["remove", "my-session"]);
-        match cli.command {
-            Some(Commands::Remove(args)) => {
-                assert_eq!(args.name, "my-session");
-            }
-            other => panic!("expected Remove, got {:?}", other),
-        }
+    fn test_upgrade_subcommand_parses() {
+        let cli = parse(&["upgrade"]);
+        assert!(matches!(cli.command, Some(Commands::Upgrade)));
     }
 
+    // -- pull subcommand --
+
     #[test]
-    fn test_remove_requires_name() {
-        let result = try_parse(&["remove"]);
-        assert!(result.is_err());
+    fn test_pull_subcommand_parses() {
+        assert!(matches!(
+            parse(&["pull", "my-session"]).command,
+            Some(Commands::Pull { ref name }) if name == "my-session"
+        ));
+        assert!(try_parse(&["pull"]).is_err());
     }
 
+    // -- migrate subcommand --
+
     #[test]
-    fn test_remove_rejects_flags() {
-        let result = try_parse(&["remove", "my-session", "-d"]);
-        assert!(result.is_err());
+    fn test_migrate_subcommand_parses() {
+        let cli = parse(&["migrate"]);
+        assert!(matches!(cli.command, Some(Commands::Migrate)));
     }
 
-    // -- stop subcommand --
+    // -- gc subcommand --
 
     #[test]
-    fn test_stop_parses() {
-        let cli = parse(&["stop", "my-session"]);
-        match cli.command {
-            Some(Commands::Stop(args)) => {
-                assert_eq!(args.name, "my-session");
+    fn test_gc_subcommand() {
+        match parse(&["gc"]).command {
+            Some(Commands::Gc(args)) => {
+                assert!(!args.dry_run);
+                assert_eq!(args.older_than, None);
             }
-            other => panic!("expected Stop, got {:?}", other),
+            other => panic!("expected Gc, got {:?}", other),
+        }
+        match parse(&["gc", "-n", "--older-than", "30"]).command {
+            Some(Commands::Gc(args)) => {
+                assert!(args.dry_run);
+                assert_eq!(args.older_than, Some(30));
+            }
+            other => panic!("expected Gc, got {:?}", other),
         }
     }
 
     #[test]
-    fn test_stop_requires_name() {
-        let result = try_parse(&["stop"]);
-        assert!(result.is_err());
+    fn test_old_orphan_workspaces() {
+        let now = SystemTime::now();
+        let days = |n: u64| now - Duration::from_secs(n * 24 * 60 * 60);
+        let sessions = vec![summary("kept", "/tmp")];
+        let workspaces = vec![
+            ("kept".to_string(), days(30)),
+            ("stale".to_string(), days(10)),
+            ("recent".to_string(), days(1)),
+            ("edge".to_string(), days(7)),
+        ];
+        assert_eq!(
+            old_orphan_workspaces(
+                &sessions,
+                &workspaces,
+                now,
+                Duration::from_secs(7 * 24 * 60 * 60)
+            ),
+            vec!["edge".to_string(), "stale".to_string()]
+        );
     }
 
+    // -- context option --
+
     #[test]
-    fn test_stop_rejects_flags() {
-        let result = try_parse(&["stop", "my-session", "-d"]);
-        assert!(result.is_err());
+    fn test_create_context() {
+        match parse(&["create", "s", "--context", "build-box"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.context.as_deref(), Some("build-box")),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--context", "-bad"]).is_err());
+        assert!(try_parse(&["create", "s", "--context", "a b"]).is_err());
     }
 
-    // -- exec subcommand --
+    #[test]
+    fn test_session_name_for_context() {
+        let name = |args: &[&str]| {
+            parse(args)
+                .command
+                .as_ref()
+                .and_then(Commands::session_name)
+                .map(String::from)
+        };
+        assert_eq!(name(&["resume", "s"]).as_deref(), Some("s"));
+        assert_eq!(name(&["clone", "a", "b"]).as_deref(), Some("a"));
+        assert_eq!(
+            name(&["ports", "add", "s", "8080:80"]).as_deref(),
+            Some("s")
+        );
+        assert_eq!(name(&["ports", "s"]).as_deref(), Some("s"));
+        assert_eq!(name(&["s"]).as_deref(), Some("s"));
+        assert_eq!(name(&["create", "s"]), None);
+        assert_eq!(name(&["list"]), None);
+    }
 
     #[test]
-    fn test_exec_parses() {
-        let cli = parse(&["exec", "my-session", "--", "ls", "-la"]);
-        match cli.command {
-            Some(Commands::Exec(args)) => {
-                assert_eq!(args.name, "my-session");
-                assert_eq!(args.cmd, vec!["ls", "-la"]);
-            }
-            other => panic!("expected Exec, got {:?}", other),
+    fn test_create_kube() {
+        match parse(&["create", "s", "--kube", "--cpus", "2"]).command {
+            Some(Commands::Create(args)) => assert!(args.kube),
+            other => panic!("expected Create, got {:?}", other),
         }
+        assert!(try_parse(&["create", "s", "--kube", "--volume"]).is_err());
+        assert!(try_parse(&["create", "s", "--kube", "-p", "8080:80"]).is_err());
     }
 
+    // -- doctor subcommand --
+
     #[test]
-    fn test_exec_requires_name() {
-        let result = try_parse(&["exec"]);
-        assert!(result.is_err());
+    fn test_doctor_subcommand_parses() {
+        assert!(matches!(parse(&["doctor"]).command, Some(Commands::Doctor)));
+        assert!(matches!(
+            parse(&["init", "--force"]).command,
+            Some(Commands::Init(InitArgs { force: true }))
+        ));
+        assert!(try_parse(&["doctor", "extra"]).is_err());
     }
 
+    // -- autostop subcommand --
+
     #[test]
-    fn test_exec_requires_command() {
-        let result = try_parse(&["exec", "my-session"]);
-        assert!(result.is_err());
+    fn test_autostop_subcommand() {
+        match parse(&["autostop"]).command {
+            Some(Commands::Autostop(args)) => assert_eq!(args.interval, 60),
+            other => panic!("expected Autostop, got {:?}", other),
+        }
+        match parse(&["autostop", "--interval", "5"]).command {
+            Some(Commands::Autostop(args)) => assert_eq!(args.interval, 5),
+            other => panic!("expected Autostop, got {:?}", other),
+        }
+        assert!(try_parse(&["autostop", "--interval", "0"]).is_err());
+        match parse(&["create", "s", "--idle-timeout", "30"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.idle_timeout, Some(30)),
+            other => panic!("expected Create, got {:?}", other),
+        }
     }
 
-    // -- path subcommand --
+    #[test]
+    fn test_idle_tracker() {
+        let mut tracker = IdleTracker::default();
+        let start = Instant::now();
+        let timeout = Duration::from_secs(600);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(!tracker.observe("s", false, at(0), timeout));
+        assert!(!tracker.observe("s", false, at(599), timeout));
+        assert!(tracker.observe("s", false, at(600), timeout));
+
+        // Activity resets the idle clock
+        assert!(!tracker.observe("s", true, at(700), timeout));
+        assert!(!tracker.observe("s", false, at(800), timeout));
+        assert!(!tracker.observe("s", false, at(1300), timeout));
+        assert!(tracker.observe("s", false, at(1400), timeout));
+
+        // Sessions that stopped running are forgotten
+        tracker.retain(&HashSet::new());
+        assert!(!tracker.observe("s", false, at(1500), timeout));
+    }
+
+    // -- ssh subcommand --
 
     #[test]
-    fn test_path_subcommand_parses() {
-        let cli = parse(&["path", "my-session"]);
+    fn test_ssh_subcommand() {
+        match parse(&["ssh", "my-session", "--print"]).command {
+            Some(Commands::Ssh(args)) => {
+                assert_eq!(args.name, "my-session");
+                assert!(args.print);
+            }
+            other => panic!("expected Ssh, got {:?}", other),
+        }
+    }
+
+    // -- env subcommand --
+
+    #[test]
+    fn test_env_subcommand() {
         assert!(matches!(
-            cli.command,
-            Some(Commands::Path { ref name }) if name == "my-session"
+            parse(&["env", "my-session"]).command,
+            Some(Commands::Env(EnvArgs { ref name, action: None })) if name == "my-session"
         ));
+        match parse(&["env", "my-session", "set", "A=1", "B"]).command {
+            Some(Commands::Env(EnvArgs {
+                action: Some(EnvAction::Set { vars }),
+                ..
+            })) => assert_eq!(vars, vec!["A=1", "B"]),
+            other => panic!("expected env set, got {:?}", other),
+        }
+        match parse(&["env", "my-session", "unset", "A"]).command {
+            Some(Commands::Env(EnvArgs {
+                action: Some(EnvAction::Unset { keys }),
+                ..
+            })) => assert_eq!(keys, vec!["A"]),
+            other => panic!("expected env unset, got {:?}", other),
+        }
+        assert!(try_parse(&["env", "my-session", "set"]).is_err());
+        assert!(try_parse(&["env", "my-session", "set", "=x"]).is_err());
+        assert!(try_parse(&["env", "my-session", "set", "A B=1"]).is_err());
     }
 
+    // -- secrets subcommand --
+
     #[test]
-    fn test_path_requires_name() {
-        let result = try_parse(&["path"]);
-        assert!(result.is_err());
+    fn test_secrets_subcommand() {
+        match parse(&["create", "s", "--secret", "npmrc=~/.npmrc"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.secret, vec!["npmrc=~/.npmrc"]),
+            other => panic!("expected Create, got {:?}", other),
+        }
+        match parse(&["secrets", "s", "add", "token=/tmp/token"]).command {
+            Some(Commands::Secrets(SecretsArgs {
+                action: Some(SecretsAction::Add { secrets }),
+                ..
+            })) => assert_eq!(secrets, vec!["token=/tmp/token"]),
+            other => panic!("expected secrets add, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--secret", "/tmp/token"]).is_err());
+        assert!(try_parse(&["create", "s", "--secret", "a/b=/tmp/token"]).is_err());
+        assert!(try_parse(&["create", "s", "--secret", "..=/tmp/token"]).is_err());
+        assert!(try_parse(&["create", "s", "--secret", "token="]).is_err());
     }
 
-    // -- cd subcommand --
+    #[test]
+    fn test_resolve_secret() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("token");
+        fs::write(&file, "s3cret").unwrap();
+        let canonical = fs::canonicalize(&file).unwrap();
+        assert_eq!(
+            resolve_secret(&format!("token={}", file.display())).unwrap(),
+            format!("token={}", canonical.display())
+        );
+        let err = resolve_secret("token=/nonexistent/token").unwrap_err();
+        assert!(err.to_string().contains("not found"));
+    }
 
     #[test]
-    fn test_cd_subcommand_parses() {
-        let cli = parse(&["cd", "my-session"]);
+    fn test_create_env_flag() {
+        match parse(&["create", "s", "-e", "A=1", "--env", "B=x=y"]).command {
+            Some(Commands::Create(args)) => assert_eq!(args.env, vec!["A=1", "B=x=y"]),
+            other => panic!("expected Create, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_cache_flag() {
+        match parse(&[
+            "create",
+            "s",
+            "--cache",
+            "cargo",
+            "--cache",
+            "ccache:/root/.ccache",
+        ])
+        .command
+        {
+            Some(Commands::Create(args)) => {
+                assert_eq!(args.cache, vec!["cargo", "ccache:/root/.ccache"])
+            }
+            other => panic!("expected Create, got {:?}", other),
+        }
+        assert!(try_parse(&["create", "s", "--cache", "nope"]).is_err());
+    }
+
+    #[test]
+    fn test_code_subcommand() {
         assert!(matches!(
-            cli.command,
-            Some(Commands::Cd { ref name }) if name == "my-session"
+            parse(&["code", "my-session"]).command,
+            Some(Commands::Code { ref name }) if name == "my-session"
         ));
     }
 
     #[test]
-    fn test_cd_requires_name() {
-        let result = try_parse(&["cd"]);
-        assert!(result.is_err());
+    fn test_vscode_attach_uri() {
+        assert_eq!(
+            vscode_attach_uri("ab", "/workspace"),
+            "vscode-remote://attached-container+626f782d6162/workspace"
+        );
     }
 
-    // -- upgrade subcommand --
+    #[test]
+    fn test_ssh_port_helpers() {
+        assert!(is_ssh_port_spec("127.0.0.1:2222:22"));
+        assert!(is_ssh_port_spec("2222:22/tcp"));
+        assert!(is_ssh_port_spec("22"));
+        assert!(!is_ssh_port_spec("2222:2222"));
+        assert!(!is_ssh_port_spec("22:80"));
+
+        let published = vec![
+            "80/tcp -> 0.0.0.0:8080".to_string(),
+            "22/tcp -> 127.0.0.1:40123".to_string(),
+        ];
+        assert_eq!(ssh_host_port(&published).as_deref(), Some("40123"));
+        assert_eq!(ssh_host_port(&published[..1]), None);
+    }
 
     #[test]
-    fn test_upgrade_subcommand_parses() {
-        let cli = parse(&["upgrade"]);
-        assert!(matches!(cli.command, Some(Commands::Upgrade)));
+    fn test_parse_checksum() {
+        let hash = "A".repeat(64);
+        let sums = format!(
+            "{}  box-x86_64-linux\n{} *box-aarch64-darwin\n",
+            "b".repeat(64),
+            hash
+        );
+        assert_eq!(
+            parse_checksum(&sums, "box-aarch64-darwin"),
+            Some("a".repeat(64))
+        );
+        assert_eq!(
+            parse_checksum(&sums, "box-x86_64-linux"),
+            Some("b".repeat(64))
+        );
+        assert_eq!(parse_checksum(&sums, "box-aarch64-linux"), None);
+        assert_eq!(
+            parse_checksum("nothex  box-x86_64-linux", "box-x86_64-linux"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
     }
 
     #[test]
@@ -1166,6 +6741,54 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_config_fish_subcommand_parses() {
+        let cli = parse(&["config", "fish"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Config {
+                shell: ConfigShell::Fish
+            })
+        ));
+    }
+
+    #[test]
+    fn test_config_nu_subcommand_parses() {
+        let cli = parse(&["config", "nu"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Config {
+                shell: ConfigShell::Nu
+            })
+        ));
+    }
+
+    #[test]
+    fn test_complete_sessions_is_hidden() {
+        assert!(matches!(
+            parse(&["__complete-sessions"]).command,
+            Some(Commands::CompleteSessions)
+        ));
+        for shell in [
+            clap_complete::Shell::Zsh,
+            clap_complete::Shell::Bash,
+            clap_complete::Shell::Fish,
+        ] {
+            assert!(!generate_completions(shell).contains("__complete-sessions"));
+        }
+    }
+
+    #[test]
+    fn test_completion_paths() {
+        let cmd = completion_command();
+        let sessions = completion_paths(&cmd, "SESSION");
+        for path in ["resume", "exec", "clone", "ports", "ports add", "cd"] {
+            assert!(sessions.contains(&path.to_string()), "missing {}", path);
+        }
+        assert!(!sessions.contains(&"create".to_string()));
+        assert_eq!(completion_paths(&cmd, "TEMPLATE"), ["template remove"]);
+    }
+
     #[test]
     fn test_config_requires_shell() {
         let result = try_parse(&["config"]);
@@ -1248,6 +6871,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_list_json_flag() {
+        let cli = parse(&["list", "--json"]);
+        match cli.command {
+            Some(Commands::List(args)) => {
+                assert!(args.json);
+                assert!(!args.quiet);
+            }
+            other => panic!("expected List, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_list_json_conflicts_with_quiet() {
+        let result = try_parse(&["list", "--json", "-q"]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_list_alias_ls() {
         let cli = parse(&["ls"]);
@@ -1279,5 +6920,10 @@ mod tests {
             }
             other => panic!("expected External, got {:?}", other),
         }
+        assert_eq!(cli_session_name(&["my-session"]), Some("my-session".into()));
+    }
+
+    fn cli_session_name(args: &[&str]) -> Option<String> {
+        parse(args).command?.session_name().map(String::from)
     }
 }
@@ -0,0 +1,280 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::{self, Template};
+use crate::git;
+use crate::storage;
+
+/// File in a registry's cache directory recording where it comes from.
+const SOURCE_FILE: &str = ".box-source";
+
+/// A shared set of templates: a git repository with `<name>.toml` files at
+/// its root, or an HTTPS endpoint serving them as `<url>/<name>.toml`.
+#[derive(Debug, PartialEq)]
+pub struct Registry {
+    pub name: String,
+    pub source: String,
+}
+
+impl Registry {
+    /// Git sources are cloned; other HTTPS URLs are fetched a file at a time.
+    pub fn is_git(&self) -> bool {
+        is_git_url(&self.source)
+    }
+
+    fn dir(&self) -> Result<PathBuf> {
+        Ok(storage::registries_dir()?.join(&self.name))
+    }
+
+    /// Cache file of `template`, downloading it from an HTTPS registry the
+    /// first time it's used.
+    fn template_path(&self, template: &str) -> Result<PathBuf> {
+        let path = self.dir()?.join(format!("{}.toml", template));
+        if !path.is_file() && !self.is_git() {
+            fetch(&self.template_url(template), &path)?;
+        }
+        Ok(path)
+    }
+
+    fn template_url(&self, template: &str) -> String {
+        format!("{}/{}.toml", self.source.trim_end_matches('/'), template)
+    }
+}
+
+fn is_git_url(url: &str) -> bool {
+    url.ends_with(".git") || url.starts_with("git@") || url.starts_with("ssh://")
+}
+
+/// Expand `org/repo` to its GitHub URL; anything else is taken as given.
+pub fn expand_source(source: &str) -> String {
+    let shorthand = !source.contains(':')
+        && source.split('/').count() == 2
+        && source.split('/').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        });
+    if shorthand {
+        format!("https://github.com/{}.git", source.trim_end_matches(".git"))
+    } else {
+        source.to_string()
+    }
+}
+
+/// Name a registry after the owner in its source: `org` for `org/templates`,
+/// `https://github.com/org/templates.git` or `git@github.com:org/templates.git`.
+pub fn default_name(source: &str) -> Option<String> {
+    let path = match source.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?.1,
+        None => source.split_once(':').map_or(source, |(_, path)| path),
+    };
+    let owner = path.split('/').find(|part| !part.is_empty())?;
+    let owner = owner.trim_end_matches(".git");
+    validate_name(owner).ok()?;
+    Some(owner.to_string())
+}
+
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!(
+            "Invalid registry name '{}'. Use only letters, digits, dots, hyphens, and underscores.",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Register `source` as `name` and cache its templates.
+pub fn add(name: &str, source: &str) -> Result<Registry> {
+    validate_name(name)?;
+    let registry = Registry {
+        name: name.to_string(),
+        source: source.to_string(),
+    };
+    let dir = registry.dir()?;
+    if dir.exists() {
+        bail!(
+            "Template registry '{}' already exists. Remove it first with `box template remove --registry {}`.",
+            name,
+            name
+        );
+    }
+    if registry.is_git() {
+        git::clone_shallow(source, &dir)?;
+    } else if !source.starts_with("https://") && !source.starts_with("http://") {
+        bail!(
+            "'{}' is not a git or HTTPS URL (or an org/repo on GitHub).",
+            source
+        );
+    } else {
+        fs::create_dir_all(&dir).context("Failed to create registry directory")?;
+    }
+    fs::write(dir.join(SOURCE_FILE), source)?;
+    Ok(registry)
+}
+
+/// Refresh the cached templates of a registry: pull the clone, or download
+/// the templates used so far again.
+pub fn update(registry: &Registry) -> Result<()> {
+    let dir = registry.dir()?;
+    if registry.is_git() {
+        return git::pull_ff_only(&dir);
+    }
+    for template in cached(&dir)? {
+        fetch(
+            &registry.template_url(&template),
+            &dir.join(format!("{}.toml", template)),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    let registry = get(name)?;
+    fs::remove_dir_all(registry.dir()?)
+        .with_context(|| format!("Failed to remove template registry '{}'", name))
+}
+
+pub fn get(name: &str) -> Result<Registry> {
+    validate_name(name)?;
+    let dir = storage::registries_dir()?.join(name);
+    let source = fs::read_to_string(dir.join(SOURCE_FILE))
+        .map_err(|_| anyhow::anyhow!("Template registry '{}' not found.", name))?;
+    Ok(Registry {
+        name: name.to_string(),
+        source: source.trim().to_string(),
+    })
+}
+
+/// All registered registries, sorted by name.
+pub fn list() -> Result<Vec<Registry>> {
+    let dir = storage::registries_dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    Ok(names.iter().filter_map(|name| get(name).ok()).collect())
+}
+
+/// Cached templates of every registry, as `registry/template`.
+pub fn templates() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for registry in list()? {
+        for template in cached(&registry.dir()?)? {
+            names.push(format!("{}/{}", registry.name, template));
+        }
+    }
+    Ok(names)
+}
+
+/// Load `template` from registry `name`.
+pub fn load(name: &str, template: &str) -> Result<Template> {
+    // Same naming rules as local templates
+    Template::path(template)?;
+    let registry = get(name)?;
+    let path = registry.template_path(template)?;
+    if !path.is_file() {
+        bail!("Template '{}/{}' not found.", name, template);
+    }
+    config::load_toml(&path)
+}
+
+/// Names of the `*.toml` files in `dir`, sorted.
+fn cached(dir: &std::path::Path) -> Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_suffix(".toml").map(|n| n.to_string())
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Download `url` to `path` with curl. The file is only replaced once the
+/// download has finished.
+fn fetch(url: &str, path: &std::path::Path) -> Result<()> {
+    let tmp = path.with_extension("toml.part");
+    let output = Command::new("curl")
+        .args(["-fsSL", "--proto", "=https,http", "-o"])
+        .arg(&tmp)
+        .arg(url)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&tmp);
+        bail!(
+            "Failed to fetch {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_source() {
+        assert_eq!(
+            expand_source("org/templates"),
+            "https://github.com/org/templates.git"
+        );
+        assert_eq!(
+            expand_source("git@github.com:org/templates.git"),
+            "git@github.com:org/templates.git"
+        );
+        assert_eq!(
+            expand_source("https://example.com/box"),
+            "https://example.com/box"
+        );
+    }
+
+    #[test]
+    fn test_default_name() {
+        assert_eq!(
+            default_name("https://github.com/org/templates.git").as_deref(),
+            Some("org")
+        );
+        assert_eq!(
+            default_name("git@github.com:org/templates.git").as_deref(),
+            Some("org")
+        );
+        assert_eq!(
+            default_name("https://example.com/box/").as_deref(),
+            Some("box")
+        );
+        assert_eq!(default_name("https://example.com"), None);
+    }
+
+    #[test]
+    fn test_is_git() {
+        let registry = |source: &str| Registry {
+            name: "org".to_string(),
+            source: source.to_string(),
+        };
+        assert!(registry("https://github.com/org/templates.git").is_git());
+        assert!(registry("git@github.com:org/templates.git").is_git());
+        assert!(!registry("https://example.com/box").is_git());
+        assert_eq!(
+            registry("https://example.com/box/").template_url("rust-dev"),
+            "https://example.com/box/rust-dev.toml"
+        );
+    }
+}
@@ -1,11 +1,15 @@
 use anyhow::{bail, Context, Result};
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::fs;
-use std::path::PathBuf;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
 
 use crate::config;
+use crate::storage;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct Session {
     pub name: String,
     pub project_dir: String,
@@ -14,6 +18,58 @@ pub struct Session {
     pub command: Vec<String>,
     pub env: Vec<String>,
     pub ssh: bool,
+    pub worktree: bool,
+    /// `docker run --cpus` limit, e.g. `2` or `1.5`.
+    pub cpus: Option<String>,
+    /// `docker run --memory` limit, e.g. `4g`.
+    pub memory: Option<String>,
+    /// Extra docker flags given at create time. `None` for sessions created
+    /// before they were stored.
+    pub docker_args: Option<String>,
+    /// `docker run --publish` specs.
+    pub ports: Vec<String>,
+    /// Secrets as `NAME=/host/path`, mounted at `/run/secrets/NAME`. Only the
+    /// host path is stored, never the contents.
+    pub secrets: Vec<String>,
+    /// Shared cache volumes as `NAME:/container/path`, mounted from the
+    /// `box-cache-NAME` volume.
+    pub caches: Vec<String>,
+    /// The workspace lives in the `box-ws-<name>` Docker volume instead of a
+    /// host directory.
+    pub volume: bool,
+    /// Run the container as the host user's uid:gid.
+    pub user_map: bool,
+    /// `docker run --network` mode; `None` uses docker's default bridge.
+    pub network: Option<String>,
+    /// Minutes without activity before `box autostop` stops the container.
+    /// `None` falls back to the global `idle_timeout`; `Some(0)` disables it.
+    pub idle_timeout: Option<u64>,
+    /// Docker context the session runs in; `None` uses the current one.
+    pub context: Option<String>,
+    /// Runs as a Kubernetes pod instead of a local container (experimental).
+    pub kube: bool,
+    /// No capabilities, no privilege escalation and a read-only root filesystem.
+    pub hardened: bool,
+    /// Forward the host's gpg-agent so commits can be signed in the container.
+    pub gpg: bool,
+    /// Answer git's HTTPS credential prompts with the host's credentials.
+    pub git_auth: bool,
+    /// Mount the host's Docker socket, giving the container control of the
+    /// host daemon.
+    pub docker_socket: bool,
+    /// Run privileged so a nested Docker daemon can run inside.
+    pub dind: bool,
+    /// Free-text note describing what the session is for.
+    pub note: Option<String>,
+    /// Branch or commit checked out when the workspace was created.
+    pub branch: Option<String>,
+    /// Remote the workspace was cloned from, for sessions created outside a
+    /// repository with `--url`.
+    pub url: Option<String>,
+    /// `git clone --depth` for the workspace; `None` clones full history.
+    pub clone_depth: Option<u32>,
+    /// `git clone --filter` for the workspace, e.g. `blob:none`.
+    pub clone_filter: Option<String>,
 }
 
 impl From<config::BoxConfig> for Session {
@@ -26,28 +82,212 @@ impl From<config::BoxConfig> for Session {
             command: cfg.command,
             env: cfg.env,
             ssh: cfg.ssh,
+            worktree: cfg.worktree,
+            docker_args: Some(cfg.docker_args),
+            ..Default::default()
         }
     }
 }
 
-#[derive(Clone)]
+/// The stored settings `box edit` can change, as written to the editor.
+/// They apply when the container is next recreated.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub image: String,
+    pub mount_path: String,
+    /// Shell-quoted command; empty runs the image's default.
+    pub command: String,
+    pub env: Vec<String>,
+    pub ssh: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    pub docker_args: String,
+    pub ports: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Session {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            image: self.image.clone(),
+            mount_path: self.mount_path.clone(),
+            command: shell_words::join(&self.command),
+            env: self.env.clone(),
+            ssh: self.ssh,
+            cpus: self.cpus.clone(),
+            memory: self.memory.clone(),
+            docker_args: self.docker_args.clone().unwrap_or_default(),
+            ports: self.ports.clone(),
+            network: self.network.clone(),
+            idle_timeout: self.idle_timeout,
+            note: self.note.clone(),
+        }
+    }
+
+    /// Take over `settings`. The command must be valid shell words.
+    pub fn apply(&mut self, settings: Settings) -> Result<()> {
+        self.command = shell_words::split(&settings.command)
+            .map_err(|e| anyhow::anyhow!("Invalid command: {}", e))?;
+        self.image = settings.image;
+        self.mount_path = settings.mount_path;
+        self.env = settings.env;
+        self.ssh = settings.ssh;
+        self.cpus = settings.cpus;
+        self.memory = settings.memory;
+        // Sessions that never stored docker args keep the default unless given some
+        if self.docker_args.is_some() || !settings.docker_args.is_empty() {
+            self.docker_args = Some(settings.docker_args);
+        }
+        self.ports = settings.ports;
+        self.network = settings.network;
+        self.idle_timeout = settings.idle_timeout;
+        self.note = settings.note;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct SessionSummary {
     pub name: String,
     pub project_dir: String,
     pub image: String,
     pub command: String,
     pub created_at: String,
+    pub resumed_at: String,
     pub running: bool,
+    pub paused: bool,
+    /// When the container started (RFC 3339); empty unless it's running.
+    pub started_at: String,
+    /// When the container last exited (RFC 3339); empty unless it's stopped.
+    pub finished_at: String,
+    /// Exit code of the last run of a stopped container.
+    pub exit_code: Option<i64>,
+}
+
+impl SessionSummary {
+    /// When the session was last resumed, or created if it never was.
+    pub fn last_used(&self) -> &str {
+        if self.resumed_at.is_empty() {
+            &self.created_at
+        } else {
+            &self.resumed_at
+        }
+    }
+}
+
+thread_local! {
+    /// Names locked by this thread, so nested `lock` calls don't deadlock on
+    /// our own `flock`. Per thread, so other threads still block on the lock.
+    static HELD_LOCKS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Exclusive advisory lock on a session, released on drop.
+///
+/// Serializes create/remove/metadata updates across `box` processes. Lock
+/// files live in `locks/` under the data directory and are never deleted, so every process
+/// contends on the same inode even across remove/create cycles.
+pub struct SessionLock {
+    name: String,
+    file: Option<fs::File>,
+}
+
+pub fn lock(name: &str) -> Result<SessionLock> {
+    if HELD_LOCKS.with(|held| held.borrow().iter().any(|n| n == name)) {
+        return Ok(SessionLock {
+            name: name.to_string(),
+            file: None,
+        });
+    }
+
+    let dir = storage::locks_dir()?;
+    fs::create_dir_all(&dir).context("Failed to create lock directory")?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{}.lock", name)))?;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err).context(format!("Failed to lock session '{}'", name));
+        }
+    }
+
+    HELD_LOCKS.with(|held| held.borrow_mut().push(name.to_string()));
+    Ok(SessionLock {
+        name: name.to_string(),
+        file: Some(file),
+    })
 }
 
-pub fn sessions_dir() -> Result<PathBuf> {
-    Ok(PathBuf::from(config::home_dir()?)
-        .join(".box")
-        .join("sessions"))
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        // Closing the file releases the flock.
+        if self.file.take().is_some() {
+            HELD_LOCKS.with(|held| held.borrow_mut().retain(|n| n != &self.name));
+        }
+    }
 }
 
 const RESERVED_NAMES: &[&str] = &[
-    "create", "resume", "remove", "stop", "exec", "upgrade", "path", "config", "list", "ls",
+    "create",
+    "resume",
+    "attach",
+    "remove",
+    "stop",
+    "exec",
+    "upgrade",
+    "path",
+    "cp",
+    "config",
+    "list",
+    "ls",
+    "clone",
+    "commit",
+    "migrate",
+    "pull",
+    "network",
+    "autostop",
+    "gc",
+    "doctor",
+    "init",
+    "prune",
+    "inspect",
+    "note",
+    "template",
+    "sync",
+    "push",
+    "apply",
+    "snapshot",
+    "restore",
+    "diff",
+    "limits",
+    "ports",
+    "env",
+    "secrets",
+    "logs",
+    "edit",
+    "kill",
+    "shell",
+    "wait",
+    "run",
+    "ssh",
+    "code",
+    "pause",
+    "unpause",
+    "__complete-sessions",
+    "__complete-templates",
 ];
 
 pub fn validate_name(name: &str) -> Result<()> {
@@ -73,167 +313,492 @@ pub fn validate_name(name: &str) -> Result<()> {
 }
 
 pub fn session_exists(name: &str) -> Result<bool> {
-    Ok(sessions_dir()?.join(name).is_dir())
+    Ok(storage::sessions_dir()?.join(name).is_dir())
 }
 
-pub fn save(session: &Session) -> Result<()> {
-    let dir = sessions_dir()?.join(&session.name);
-    fs::create_dir_all(&dir).context("Failed to create session directory")?;
+/// Resolve `query` to an existing session name: the name itself, else the
+/// only session it is a prefix of, else (with `fuzzy`) the only one
+/// containing its characters in order. A query matching nothing is returned
+/// unchanged so callers report it as not found.
+pub fn resolve_name(query: &str, fuzzy: bool) -> Result<String> {
+    if validate_name(query).is_err() || session_exists(query)? {
+        return Ok(query.to_string());
+    }
+    let dir = storage::sessions_dir()?;
+    let mut names: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    match_name(query, &names, fuzzy)
+}
 
-    fs::write(dir.join("project_dir"), &session.project_dir)?;
-    fs::write(dir.join("image"), &session.image)?;
-    fs::write(dir.join("mount_path"), &session.mount_path)?;
-    fs::write(
-        dir.join("created_at"),
-        Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string(),
-    )?;
-    if !session.command.is_empty() {
-        let content: Vec<&str> = session.command.iter().map(|s| s.as_str()).collect();
-        fs::write(dir.join("command"), content.join("\0"))?;
+fn match_name(query: &str, names: &[String], fuzzy: bool) -> Result<String> {
+    let prefixed: Vec<&String> = names.iter().filter(|n| n.starts_with(query)).collect();
+    let candidates = if prefixed.is_empty() && fuzzy {
+        names.iter().filter(|n| is_subsequence(query, n)).collect()
     } else {
-        let _ = fs::remove_file(dir.join("command"));
+        prefixed
+    };
+    match candidates[..] {
+        [] => Ok(query.to_string()),
+        [name] => Ok(name.clone()),
+        _ => bail!(
+            "Session '{}' is ambiguous: {}.",
+            query,
+            candidates
+                .iter()
+                .map(|n| n.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
     }
-    if !session.env.is_empty() {
-        let content: Vec<&str> = session.env.iter().map(|s| s.as_str()).collect();
-        fs::write(dir.join("env"), content.join("\0"))?;
-    } else {
-        let _ = fs::remove_file(dir.join("env"));
+}
+
+fn is_subsequence(query: &str, name: &str) -> bool {
+    let mut chars = name.chars();
+    query.chars().all(|q| chars.any(|c| c == q))
+}
+
+/// Version of the `session.json` schema written by this build.
+const SCHEMA_VERSION: u32 = 1;
+
+const METADATA_FILE: &str = "session.json";
+
+/// Per-field files used before `session.json`; migrated on first read.
+const LEGACY_FILES: &[&str] = &[
+    "project_dir",
+    "image",
+    "mount_path",
+    "created_at",
+    "resumed_at",
+    "command",
+    "env",
+    "ssh",
+    "worktree",
+    "cpus",
+    "memory",
+    "docker_args",
+    "ports",
+    "recreate",
+];
+
+/// On-disk contents of `sessions/<name>/session.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Metadata {
+    version: u32,
+    project_dir: String,
+    image: String,
+    mount_path: String,
+    #[serde(default)]
+    created_at: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    resumed_at: String,
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    env: Vec<String>,
+    #[serde(default)]
+    ssh: bool,
+    #[serde(default)]
+    worktree: bool,
+    #[serde(default)]
+    cpus: Option<String>,
+    #[serde(default)]
+    memory: Option<String>,
+    #[serde(default)]
+    docker_args: Option<String>,
+    #[serde(default)]
+    ports: Vec<String>,
+    #[serde(default)]
+    secrets: Vec<String>,
+    #[serde(default)]
+    caches: Vec<String>,
+    #[serde(default)]
+    volume: bool,
+    #[serde(default)]
+    user_map: bool,
+    #[serde(default)]
+    network: Option<String>,
+    #[serde(default)]
+    idle_timeout: Option<u64>,
+    #[serde(default)]
+    context: Option<String>,
+    #[serde(default)]
+    kube: bool,
+    #[serde(default)]
+    hardened: bool,
+    #[serde(default)]
+    gpg: bool,
+    #[serde(default)]
+    git_auth: bool,
+    #[serde(default)]
+    docker_socket: bool,
+    #[serde(default)]
+    dind: bool,
+    #[serde(default)]
+    note: Option<String>,
+    #[serde(default)]
+    branch: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    clone_depth: Option<u32>,
+    #[serde(default)]
+    clone_filter: Option<String>,
+    #[serde(default)]
+    recreate: bool,
+}
+
+fn now() -> String {
+    Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// Read a session's metadata, migrating the legacy per-field layout if that
+/// is all there is. Returns `None` when the directory holds no metadata.
+fn read_metadata(dir: &Path) -> Result<Option<Metadata>> {
+    let path = dir.join(METADATA_FILE);
+    if path.exists() {
+        let content = fs::read_to_string(&path)?;
+        let meta: Metadata = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if meta.version > SCHEMA_VERSION {
+            bail!(
+                "{} was written by a newer version of box (schema {}). Run `box upgrade`.",
+                path.display(),
+                meta.version
+            );
+        }
+        return Ok(Some(meta));
     }
-    if session.ssh {
-        fs::write(dir.join("ssh"), "true")?;
-    } else {
-        let _ = fs::remove_file(dir.join("ssh"));
+
+    let Some(meta) = read_legacy(dir) else {
+        return Ok(None);
+    };
+    // Best effort: a failed migration still leaves the legacy files readable.
+    if write_metadata(dir, &meta).is_ok() {
+        for file in LEGACY_FILES {
+            let _ = fs::remove_file(dir.join(file));
+        }
     }
+    Ok(Some(meta))
+}
+
+fn read_legacy(dir: &Path) -> Option<Metadata> {
+    let project_dir = fs::read_to_string(dir.join("project_dir"))
+        .ok()?
+        .trim()
+        .to_string();
+    let read = |file: &str| {
+        fs::read_to_string(dir.join(file))
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    let read_list = |file: &str| {
+        fs::read_to_string(dir.join(file))
+            .map(|s| {
+                s.split('\0')
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
 
+    Some(Metadata {
+        version: SCHEMA_VERSION,
+        image: read("image").unwrap_or_else(|| config::DEFAULT_IMAGE.to_string()),
+        mount_path: read("mount_path").unwrap_or_else(|| config::derive_mount_path(&project_dir)),
+        project_dir,
+        created_at: read("created_at").unwrap_or_default(),
+        resumed_at: read("resumed_at").unwrap_or_default(),
+        command: read_list("command"),
+        env: read_list("env"),
+        ssh: dir.join("ssh").exists(),
+        worktree: dir.join("worktree").exists(),
+        cpus: read("cpus").filter(|s| !s.is_empty()),
+        memory: read("memory").filter(|s| !s.is_empty()),
+        docker_args: read("docker_args"),
+        ports: read("ports")
+            .map(|s| {
+                s.lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        recreate: dir.join("recreate").exists(),
+        ..Default::default()
+    })
+}
+
+/// Write `session.json` atomically: a temp file in the same directory is
+/// renamed over the old one, so readers never see a partial file.
+fn write_metadata(dir: &Path, meta: &Metadata) -> Result<()> {
+    let json = serde_json::to_string_pretty(meta)?;
+    let tmp = dir.join(format!(".{}.{}.tmp", METADATA_FILE, std::process::id()));
+    fs::write(&tmp, json + "\n")?;
+    fs::rename(&tmp, dir.join(METADATA_FILE)).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
     Ok(())
 }
 
-pub fn load(name: &str) -> Result<Session> {
-    let dir = sessions_dir()?.join(name);
-    if !dir.is_dir() {
-        bail!("Session '{}' not found.", name);
-    }
+/// Read-modify-write the metadata of an existing session.
+fn update_metadata(name: &str, f: impl FnOnce(&mut Metadata)) -> Result<()> {
+    let _lock = lock(name)?;
+    let dir = storage::sessions_dir()?.join(name);
+    let mut meta =
+        read_metadata(&dir)?.ok_or_else(|| anyhow::anyhow!("Session '{}' not found.", name))?;
+    f(&mut meta);
+    write_metadata(&dir, &meta)
+}
 
-    let project_dir_path = dir.join("project_dir");
-    if !project_dir_path.exists() {
-        bail!("Session '{}' is missing project directory metadata.", name);
-    }
-    let project_dir = fs::read_to_string(&project_dir_path)?.trim().to_string();
+pub fn save(session: &Session) -> Result<()> {
+    let _lock = lock(&session.name)?;
+    let dir = storage::sessions_dir()?.join(&session.name);
+    fs::create_dir_all(&dir).context("Failed to create session directory")?;
 
-    let image = fs::read_to_string(dir.join("image"))
-        .map(|s| s.trim().to_string())
-        .unwrap_or_else(|_| config::DEFAULT_IMAGE.to_string());
+    let existing = read_metadata(&dir).ok().flatten().unwrap_or_default();
+    let meta = Metadata {
+        version: SCHEMA_VERSION,
+        project_dir: session.project_dir.clone(),
+        image: session.image.clone(),
+        mount_path: session.mount_path.clone(),
+        created_at: now(),
+        resumed_at: existing.resumed_at,
+        command: session.command.clone(),
+        env: session.env.clone(),
+        ssh: session.ssh,
+        worktree: session.worktree,
+        cpus: session.cpus.clone(),
+        memory: session.memory.clone(),
+        docker_args: session.docker_args.clone(),
+        ports: session.ports.clone(),
+        secrets: session.secrets.clone(),
+        caches: session.caches.clone(),
+        volume: session.volume,
+        user_map: session.user_map,
+        network: session.network.clone(),
+        idle_timeout: session.idle_timeout,
+        context: session.context.clone(),
+        kube: session.kube,
+        hardened: session.hardened,
+        gpg: session.gpg,
+        git_auth: session.git_auth,
+        docker_socket: session.docker_socket,
+        dind: session.dind,
+        note: session.note.clone(),
+        branch: session.branch.clone(),
+        url: session.url.clone(),
+        clone_depth: session.clone_depth,
+        clone_filter: session.clone_filter.clone(),
+        recreate: existing.recreate,
+    };
+    write_metadata(&dir, &meta)
+}
 
-    let mount_path = fs::read_to_string(dir.join("mount_path"))
-        .map(|s| s.trim().to_string())
-        .unwrap_or_else(|_| config::derive_mount_path(&project_dir));
+/// Persist only the published ports of an existing session.
+pub fn save_ports(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.ports = session.ports.clone())
+}
 
-    let command = fs::read_to_string(dir.join("command"))
-        .map(|s| {
-            s.split('\0')
-                .filter(|l| !l.is_empty())
-                .map(|l| l.to_string())
-                .collect()
-        })
-        .unwrap_or_default();
+/// Persist only the network mode of an existing session.
+pub fn save_network(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.network = session.network.clone())
+}
 
-    let env = fs::read_to_string(dir.join("env"))
-        .map(|s| {
-            s.split('\0')
-                .filter(|l| !l.is_empty())
-                .map(|l| l.to_string())
-                .collect()
-        })
-        .unwrap_or_default();
+/// Persist only the note of an existing session.
+pub fn save_note(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.note = session.note.clone())
+}
+
+/// Persist only the image of an existing session.
+pub fn save_image(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.image = session.image.clone())
+}
+
+/// Persist only the secret mounts of an existing session.
+pub fn save_secrets(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.secrets = session.secrets.clone())
+}
+
+/// Persist only the SSH agent forwarding setting of an existing session.
+pub fn save_ssh(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.ssh = session.ssh)
+}
+
+/// Persist only the environment variables of an existing session.
+pub fn save_env(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| m.env = session.env.clone())
+}
+
+/// Persist the settings `box edit` can change of an existing session.
+pub fn save_settings(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| {
+        m.image = session.image.clone();
+        m.mount_path = session.mount_path.clone();
+        m.command = session.command.clone();
+        m.env = session.env.clone();
+        m.ssh = session.ssh;
+        m.cpus = session.cpus.clone();
+        m.memory = session.memory.clone();
+        m.docker_args = session.docker_args.clone();
+        m.ports = session.ports.clone();
+        m.network = session.network.clone();
+        m.idle_timeout = session.idle_timeout;
+        m.note = session.note.clone();
+    })
+}
 
-    let ssh = dir.join("ssh").exists();
+/// Mark the session so its container is recreated on the next resume, picking up
+/// settings that `docker start` can't change (ports, env, ...).
+pub fn mark_recreate(name: &str) -> Result<()> {
+    update_metadata(name, |m| m.recreate = true)
+}
+
+pub fn needs_recreate(name: &str) -> Result<bool> {
+    let dir = storage::sessions_dir()?.join(name);
+    Ok(read_metadata(&dir)?.is_some_and(|m| m.recreate))
+}
+
+pub fn clear_recreate(name: &str) -> Result<()> {
+    update_metadata(name, |m| m.recreate = false)
+}
+
+/// Persist only the resource limits of an existing session.
+pub fn save_limits(session: &Session) -> Result<()> {
+    update_metadata(&session.name, |m| {
+        m.cpus = session.cpus.clone();
+        m.memory = session.memory.clone();
+    })
+}
+
+pub fn load(name: &str) -> Result<Session> {
+    let dir = storage::sessions_dir()?.join(name);
+    if !dir.is_dir() {
+        bail!("Session '{}' not found.", name);
+    }
+
+    let Some(meta) = read_metadata(&dir)? else {
+        bail!("Session '{}' is missing project directory metadata.", name);
+    };
 
     Ok(Session {
         name: name.to_string(),
-        project_dir,
-        image,
-        mount_path,
-        command,
-        env,
-        ssh,
+        project_dir: meta.project_dir,
+        image: meta.image,
+        mount_path: meta.mount_path,
+        command: meta.command,
+        env: meta.env,
+        ssh: meta.ssh,
+        worktree: meta.worktree,
+        cpus: meta.cpus,
+        memory: meta.memory,
+        docker_args: meta.docker_args,
+        ports: meta.ports,
+        secrets: meta.secrets,
+        caches: meta.caches,
+        volume: meta.volume,
+        user_map: meta.user_map,
+        network: meta.network,
+        idle_timeout: meta.idle_timeout,
+        context: meta.context,
+        kube: meta.kube,
+        hardened: meta.hardened,
+        gpg: meta.gpg,
+        git_auth: meta.git_auth,
+        docker_socket: meta.docker_socket,
+        dind: meta.dind,
+        note: meta.note,
+        branch: meta.branch,
+        url: meta.url,
+        clone_depth: meta.clone_depth,
+        clone_filter: meta.clone_filter,
     })
 }
 
 pub fn list() -> Result<Vec<SessionSummary>> {
-    let dir = sessions_dir()?;
+    let dir = storage::sessions_dir()?;
     if !dir.is_dir() {
         return Ok(Vec::new());
     }
 
-    let mut sessions = Vec::new();
     let mut entries: Vec<_> = fs::read_dir(&dir)?
         .filter_map(|e| e.ok())
         .filter(|e| e.path().is_dir())
         .collect();
     entries.sort_by_key(|e| e.file_name());
-
-    for entry in entries {
-        let name = entry.file_name().to_string_lossy().to_string();
-        let session_path = entry.path();
-
-        let project_dir = fs::read_to_string(session_path.join("project_dir"))
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        let image = fs::read_to_string(session_path.join("image"))
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        let created_at = fs::read_to_string(session_path.join("created_at"))
-            .map(|s| s.trim().to_string())
-            .unwrap_or_default();
-        let command = fs::read_to_string(session_path.join("command"))
-            .map(|s| {
-                s.split('\0')
-                    .filter(|l| !l.is_empty())
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            })
-            .unwrap_or_default();
-
-        sessions.push(SessionSummary {
-            name,
-            project_dir,
-            image,
-            command,
-            created_at,
-            running: false,
-        });
+    if entries.is_empty() {
+        return Ok(Vec::new());
     }
 
+    // Read metadata files on a few threads; chunks keep the sorted order
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let sessions = std::thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(entries.len().div_ceil(threads))
+            .map(|chunk| scope.spawn(move || chunk.iter().map(summary).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_default())
+            .collect()
+    });
     Ok(sessions)
 }
 
+fn summary(entry: &fs::DirEntry) -> SessionSummary {
+    let name = entry.file_name().to_string_lossy().to_string();
+    let meta = read_metadata(&entry.path())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+
+    SessionSummary {
+        name,
+        project_dir: meta.project_dir,
+        image: meta.image,
+        command: meta.command.join(" "),
+        created_at: meta.created_at,
+        resumed_at: meta.resumed_at,
+        running: false,
+        paused: false,
+        started_at: String::new(),
+        finished_at: String::new(),
+        exit_code: None,
+    }
+}
+
 pub fn remove_dir(name: &str) -> Result<()> {
-    let dir = sessions_dir()?.join(name);
+    let _lock = lock(name)?;
+    let dir = storage::sessions_dir()?.join(name);
     fs::remove_dir_all(&dir).context(format!("Failed to remove session directory for '{}'", name))
 }
 
 pub fn touch_resumed_at(name: &str) -> Result<()> {
-    let dir = sessions_dir()?.join(name);
-    fs::write(
-        dir.join("resumed_at"),
-        Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string(),
-    )?;
-    Ok(())
+    update_metadata(name, |m| m.resumed_at = now())
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::sync::Mutex;
 
     // Serialize tests that mutate HOME env var
     static ENV_LOCK: Mutex<()> = Mutex::new(());
 
-    fn with_temp_home<F: FnOnce(&std::path::Path)>(f: F) {
+    pub(crate) fn with_temp_home<F: FnOnce(&std::path::Path)>(f: F) {
         let _lock = ENV_LOCK.lock().unwrap();
         let tmp = tempfile::tempdir().unwrap();
         let old_home = std::env::var("HOME").ok();
         std::env::set_var("HOME", tmp.path());
+        std::env::remove_var("XDG_DATA_HOME");
         f(tmp.path());
         match old_home {
             Some(h) => std::env::set_var("HOME", h),
@@ -241,6 +806,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_match_name() {
+        let names: Vec<String> = ["api", "my-feature-branch-sandbox", "my-fix", "web"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            match_name("my-fe", &names, true).unwrap(),
+            "my-feature-branch-sandbox"
+        );
+        assert_eq!(
+            match_name("mfbs", &names, true).unwrap(),
+            "my-feature-branch-sandbox"
+        );
+        assert_eq!(match_name("we", &names, true).unwrap(), "web");
+        assert_eq!(match_name("nope", &names, true).unwrap(), "nope");
+        let err = match_name("my-f", &names, true).unwrap_err().to_string();
+        assert!(err.contains("my-feature-branch-sandbox, my-fix"));
+        // A unique prefix wins over other fuzzy matches
+        assert_eq!(match_name("a", &names, true).unwrap(), "api");
+    }
+
+    #[test]
+    fn test_match_name_without_fuzzy() {
+        let names: Vec<String> = ["api", "my-feature-branch-sandbox", "my-fix"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            match_name("my-fe", &names, false).unwrap(),
+            "my-feature-branch-sandbox"
+        );
+        // Only exact names and unique prefixes resolve
+        assert_eq!(match_name("mfbs", &names, false).unwrap(), "mfbs");
+        assert!(match_name("my-", &names, false).is_err());
+    }
+
     #[test]
     fn test_validate_name_valid() {
         assert!(validate_name("my-session").is_ok());
@@ -293,8 +893,8 @@ mod tests {
     #[test]
     fn test_sessions_dir() {
         with_temp_home(|tmp| {
-            let dir = sessions_dir().unwrap();
-            assert_eq!(dir, tmp.join(".box").join("sessions"));
+            let dir = storage::sessions_dir().unwrap();
+            assert_eq!(dir, tmp.join(".local/share/box/sessions"));
         });
     }
 
@@ -306,9 +906,7 @@ mod tests {
                 project_dir: "/tmp/myproject".to_string(),
                 image: "ubuntu:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
@@ -334,8 +932,7 @@ mod tests {
                     "-c".to_string(),
                     "echo hello".to_string(),
                 ],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
@@ -352,21 +949,23 @@ mod tests {
                 project_dir: "/tmp/p".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
-            let dir = sessions_dir().unwrap().join("meta-test");
-            assert!(dir.join("project_dir").exists());
-            assert!(dir.join("image").exists());
-            assert!(dir.join("mount_path").exists());
-            assert!(dir.join("created_at").exists());
-            assert!(!dir.join("command").exists());
-
-            let created = fs::read_to_string(dir.join("created_at")).unwrap();
-            assert!(created.ends_with("UTC"));
+            let dir = storage::sessions_dir().unwrap().join("meta-test");
+            let names: Vec<_> = fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            assert_eq!(names, ["session.json"]);
+
+            let json: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(dir.join("session.json")).unwrap())
+                    .unwrap();
+            assert_eq!(json["version"], SCHEMA_VERSION);
+            assert_eq!(json["image"], "alpine:latest");
+            assert!(json["created_at"].as_str().unwrap().ends_with("UTC"));
         });
     }
 
@@ -381,7 +980,7 @@ mod tests {
     #[test]
     fn test_load_missing_project_dir() {
         with_temp_home(|_| {
-            let dir = sessions_dir().unwrap().join("broken");
+            let dir = storage::sessions_dir().unwrap().join("broken");
             fs::create_dir_all(&dir).unwrap();
             // Don't write project_dir file
 
@@ -395,7 +994,7 @@ mod tests {
     #[test]
     fn test_load_defaults_when_optional_files_missing() {
         with_temp_home(|_| {
-            let dir = sessions_dir().unwrap().join("minimal");
+            let dir = storage::sessions_dir().unwrap().join("minimal");
             fs::create_dir_all(&dir).unwrap();
             fs::write(dir.join("project_dir"), "/tmp/project").unwrap();
             // Don't write image or mount_path
@@ -416,9 +1015,7 @@ mod tests {
                 project_dir: "/tmp/p".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
             assert!(session_exists("exists-test").unwrap());
@@ -442,9 +1039,7 @@ mod tests {
                     project_dir: format!("/tmp/{}", name),
                     image: "alpine:latest".to_string(),
                     mount_path: "/workspace".to_string(),
-                    command: vec![],
-                    env: vec![],
-                    ssh: false,
+                    ..Default::default()
                 };
                 save(&sess).unwrap();
             }
@@ -466,9 +1061,7 @@ mod tests {
                 project_dir: "/home/user/project".to_string(),
                 image: "ubuntu:22.04".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
@@ -488,9 +1081,7 @@ mod tests {
                 project_dir: "/tmp/p".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
             assert!(session_exists("to-remove").unwrap());
@@ -508,6 +1099,29 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_list_reads_resumed_at() {
+        with_temp_home(|_| {
+            let sess = Session {
+                name: "list-resumed".to_string(),
+                project_dir: "/tmp/p".to_string(),
+                image: "alpine:latest".to_string(),
+                mount_path: "/workspace".to_string(),
+                ..Default::default()
+            };
+            save(&sess).unwrap();
+
+            let sessions = list().unwrap();
+            assert!(sessions[0].resumed_at.is_empty());
+            assert_eq!(sessions[0].last_used(), sessions[0].created_at);
+
+            touch_resumed_at("list-resumed").unwrap();
+            let sessions = list().unwrap();
+            assert!(sessions[0].resumed_at.ends_with("UTC"));
+            assert_eq!(sessions[0].last_used(), sessions[0].resumed_at);
+        });
+    }
+
     #[test]
     fn test_touch_resumed_at() {
         with_temp_home(|_| {
@@ -516,24 +1130,23 @@ mod tests {
                 project_dir: "/tmp/p".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
             touch_resumed_at("resume-test").unwrap();
 
-            let dir = sessions_dir().unwrap().join("resume-test");
-            let content = fs::read_to_string(dir.join("resumed_at")).unwrap();
-            assert!(content.ends_with("UTC"));
+            let dir = storage::sessions_dir().unwrap().join("resume-test");
+            let meta = read_metadata(&dir).unwrap().unwrap();
+            assert!(meta.resumed_at.ends_with("UTC"));
+            assert_eq!(meta.image, "alpine:latest");
         });
     }
 
     #[test]
     fn test_save_trims_whitespace_on_load() {
         with_temp_home(|_| {
-            let dir = sessions_dir().unwrap().join("trim-test");
+            let dir = storage::sessions_dir().unwrap().join("trim-test");
             fs::create_dir_all(&dir).unwrap();
             fs::write(dir.join("project_dir"), "  /tmp/project  \n").unwrap();
             fs::write(dir.join("image"), " ubuntu:latest \n").unwrap();
@@ -555,14 +1168,18 @@ mod tests {
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
                 command: vec!["bash".to_string(), "-c".to_string(), "echo hi".to_string()],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
-            let dir = sessions_dir().unwrap().join("cmd-format");
-            let raw = fs::read_to_string(dir.join("command")).unwrap();
-            assert_eq!(raw, "bash\0-c\0echo hi");
+            let dir = storage::sessions_dir().unwrap().join("cmd-format");
+            let json: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(dir.join("session.json")).unwrap())
+                    .unwrap();
+            assert_eq!(
+                json["command"],
+                serde_json::json!(["bash", "-c", "echo hi"])
+            );
         });
     }
 
@@ -574,18 +1191,13 @@ mod tests {
                 project_dir: "/tmp/project".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
                 env: vec!["FOO=bar".to_string(), "BAZ".to_string()],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
             let loaded = load("env-test").unwrap();
             assert_eq!(loaded.env, vec!["FOO=bar", "BAZ"]);
-
-            let dir = sessions_dir().unwrap().join("env-test");
-            let raw = fs::read_to_string(dir.join("env")).unwrap();
-            assert_eq!(raw, "FOO=bar\0BAZ");
         });
     }
 
@@ -597,17 +1209,251 @@ mod tests {
                 project_dir: "/tmp/project".to_string(),
                 image: "alpine:latest".to_string(),
                 mount_path: "/workspace".to_string(),
-                command: vec![],
-                env: vec![],
-                ssh: false,
+                ..Default::default()
             };
             save(&sess).unwrap();
 
-            let dir = sessions_dir().unwrap().join("no-env");
-            assert!(!dir.join("env").exists());
-
             let loaded = load("no-env").unwrap();
             assert!(loaded.env.is_empty());
         });
     }
+
+    #[test]
+    fn test_save_and_load_worktree_flag() {
+        with_temp_home(|_| {
+            let mut sess = Session {
+                name: "wt".to_string(),
+                project_dir: "/tmp/project".to_string(),
+                image: "alpine:latest".to_string(),
+                mount_path: "/workspace".to_string(),
+                worktree: true,
+                ..Default::default()
+            };
+            save(&sess).unwrap();
+            assert!(load("wt").unwrap().worktree);
+
+            sess.worktree = false;
+            save(&sess).unwrap();
+            assert!(!load("wt").unwrap().worktree);
+        });
+    }
+
+    #[test]
+    fn test_save_and_load_limits() {
+        with_temp_home(|_| {
+            let mut sess = Session {
+                name: "limited".to_string(),
+                project_dir: "/tmp/project".to_string(),
+                image: "alpine:latest".to_string(),
+                mount_path: "/workspace".to_string(),
+                cpus: Some("1.5".to_string()),
+                memory: Some("2g".to_string()),
+                ..Default::default()
+            };
+            save(&sess).unwrap();
+            let loaded = load("limited").unwrap();
+            assert_eq!(loaded.cpus.as_deref(), Some("1.5"));
+            assert_eq!(loaded.memory.as_deref(), Some("2g"));
+
+            sess.cpus = None;
+            save_limits(&sess).unwrap();
+            let loaded = load("limited").unwrap();
+            assert_eq!(loaded.cpus, None);
+            assert_eq!(loaded.memory.as_deref(), Some("2g"));
+        });
+    }
+
+    #[test]
+    fn test_save_and_load_note() {
+        with_temp_home(|_| {
+            let mut sess = Session {
+                name: "noted".to_string(),
+                project_dir: "/tmp/project".to_string(),
+                image: "alpine:latest".to_string(),
+                mount_path: "/workspace".to_string(),
+                note: Some("try the new parser".to_string()),
+                ..Default::default()
+            };
+            save(&sess).unwrap();
+            let loaded = load("noted").unwrap();
+            assert_eq!(loaded.note.as_deref(), Some("try the new parser"));
+
+            sess.note = None;
+            save_note(&sess).unwrap();
+            assert_eq!(load("noted").unwrap().note, None);
+        });
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        with_temp_home(|_| {
+            let mut sess = Session {
+                command: vec!["bash".to_string(), "-c".to_string(), "echo hi".to_string()],
+                cpus: Some("2".to_string()),
+                ..Session::from(config::BoxConfig {
+                    name: "edited".to_string(),
+                    project_dir: "/tmp/project".to_string(),
+                    image: "alpine:latest".to_string(),
+                    mount_path: "/workspace".to_string(),
+                    command: vec![],
+                    env: vec![],
+                    docker_args: String::new(),
+                    ssh: false,
+                    worktree: false,
+                })
+            };
+            save(&sess).unwrap();
+
+            let mut settings = sess.settings();
+            assert_eq!(settings.command, "bash -c 'echo hi'");
+            let text = toml::to_string(&settings).unwrap();
+            assert_eq!(toml::from_str::<Settings>(&text).unwrap(), settings);
+
+            settings.image = "python:3.12".to_string();
+            settings.command = "python -m http.server".to_string();
+            settings.cpus = None;
+            sess.apply(settings).unwrap();
+            save_settings(&sess).unwrap();
+
+            let loaded = load("edited").unwrap();
+            assert_eq!(loaded.image, "python:3.12");
+            assert_eq!(loaded.command, ["python", "-m", "http.server"]);
+            assert_eq!(loaded.cpus, None);
+            assert_eq!(loaded.project_dir, "/tmp/project");
+
+            let mut bad = loaded.settings();
+            bad.command = "echo 'unterminated".to_string();
+            assert!(sess.apply(bad).is_err());
+            assert!(toml::from_str::<Settings>("project_dir = \"/x\"").is_err());
+        });
+    }
+
+    #[test]
+    fn test_save_and_load_ports_and_docker_args() {
+        with_temp_home(|_| {
+            let mut sess = Session {
+                name: "web".to_string(),
+                project_dir: "/tmp/project".to_string(),
+                image: "alpine:latest".to_string(),
+                mount_path: "/workspace".to_string(),
+                docker_args: Some("--network host".to_string()),
+                ports: vec!["8080:80".to_string(), "127.0.0.1:5432:5432".to_string()],
+                ..Default::default()
+            };
+            save(&sess).unwrap();
+            let loaded = load("web").unwrap();
+            assert_eq!(loaded.docker_args.as_deref(), Some("--network host"));
+            assert_eq!(loaded.ports, vec!["8080:80", "127.0.0.1:5432:5432"]);
+
+            sess.ports.clear();
+            save_ports(&sess).unwrap();
+            assert!(load("web").unwrap().ports.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_load_legacy_session_without_docker_args() {
+        with_temp_home(|_| {
+            let dir = storage::sessions_dir().unwrap().join("legacy");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("project_dir"), "/tmp/project").unwrap();
+            assert_eq!(load("legacy").unwrap().docker_args, None);
+        });
+    }
+
+    #[test]
+    fn test_recreate_marker() {
+        with_temp_home(|_| {
+            let dir = storage::sessions_dir().unwrap().join("marked");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("project_dir"), "/tmp/project").unwrap();
+            assert!(!needs_recreate("marked").unwrap());
+            mark_recreate("marked").unwrap();
+            assert!(needs_recreate("marked").unwrap());
+            clear_recreate("marked").unwrap();
+            assert!(!needs_recreate("marked").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_legacy_layout_is_migrated() {
+        with_temp_home(|_| {
+            let dir = storage::sessions_dir().unwrap().join("old");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("project_dir"), "/tmp/project\n").unwrap();
+            fs::write(dir.join("image"), "ubuntu:latest").unwrap();
+            fs::write(dir.join("created_at"), "2024-01-02 03:04:05 UTC").unwrap();
+            fs::write(dir.join("command"), "bash\0-l").unwrap();
+            fs::write(dir.join("ports"), "8080:80\n").unwrap();
+            fs::write(dir.join("ssh"), "true").unwrap();
+            fs::write(dir.join("recreate"), "true").unwrap();
+
+            let loaded = load("old").unwrap();
+            assert_eq!(loaded.project_dir, "/tmp/project");
+            assert_eq!(loaded.command, vec!["bash", "-l"]);
+            assert_eq!(loaded.ports, vec!["8080:80"]);
+            assert!(loaded.ssh);
+            assert!(needs_recreate("old").unwrap());
+
+            let names: Vec<_> = fs::read_dir(&dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            assert_eq!(names, ["session.json"]);
+            assert_eq!(list().unwrap()[0].created_at, "2024-01-02 03:04:05 UTC");
+        });
+    }
+
+    #[test]
+    fn test_load_rejects_newer_schema() {
+        with_temp_home(|_| {
+            let dir = storage::sessions_dir().unwrap().join("future");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("session.json"),
+                r#"{"version": 99, "project_dir": "/p", "image": "i", "mount_path": "/m"}"#,
+            )
+            .unwrap();
+            let err = load("future").unwrap_err();
+            assert!(err.to_string().contains("newer version of box"));
+        });
+    }
+
+    #[test]
+    fn test_lock_is_exclusive_and_reentrant() {
+        with_temp_home(|tmp| {
+            let outer = lock("locked").unwrap();
+            let inner = lock("locked").unwrap();
+            drop(inner);
+
+            // Another open file description can't take the lock while held.
+            let path = tmp.join(".local/share/box/locks/locked.lock");
+            let other = fs::File::open(&path).unwrap();
+            let try_lock =
+                |f: &fs::File| unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+            assert_ne!(try_lock(&other), 0);
+
+            drop(outer);
+            assert_eq!(try_lock(&other), 0);
+        });
+    }
+
+    #[test]
+    fn test_lock_blocks_other_threads() {
+        with_temp_home(|_| {
+            let held = lock("shared").unwrap();
+            let (tx, rx) = std::sync::mpsc::channel();
+            let waiter = std::thread::spawn(move || {
+                let _lock = lock("shared").unwrap();
+                tx.send(()).unwrap();
+            });
+
+            let wait = std::time::Duration::from_millis(200);
+            assert!(rx.recv_timeout(wait).is_err());
+
+            drop(held);
+            assert!(rx.recv_timeout(std::time::Duration::from_secs(5)).is_ok());
+            waiter.join().unwrap();
+        });
+    }
 }
@@ -0,0 +1,221 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// A saved copy of a session's workspace.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub name: String,
+    pub created: SystemTime,
+    /// A copy-on-write clone of the workspace directory rather than a tarball.
+    pub reflink: bool,
+    pub path: PathBuf,
+}
+
+/// Snapshot names follow session names, plus dots for timestamps and versions.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!(
+            "Invalid snapshot name '{}'. Use only letters, digits, dots, hyphens, and underscores.",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Default snapshot name: the local time, e.g. `20260102-150405`.
+pub fn default_name() -> String {
+    chrono::Local::now().format("%Y%m%d-%H%M%S").to_string()
+}
+
+/// Snapshots kept in `dir` (a session's snapshots directory), oldest first.
+pub fn list(dir: &Path) -> Result<Vec<Snapshot>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let (name, reflink) = match file_name.strip_suffix(".tar") {
+            Some(name) => (name.to_string(), false),
+            None if entry.path().is_dir() => (file_name, true),
+            None => continue,
+        };
+        let created = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        snapshots.push(Snapshot {
+            name,
+            created,
+            reflink,
+            path: entry.path(),
+        });
+    }
+    snapshots.sort_by(|a, b| a.created.cmp(&b.created).then(a.name.cmp(&b.name)));
+    Ok(snapshots)
+}
+
+fn find(dir: &Path, name: &str) -> Result<Snapshot> {
+    list(dir)?
+        .into_iter()
+        .find(|s| s.name == name)
+        .with_context(|| format!("Snapshot '{}' not found.", name))
+}
+
+/// Copy `workspace` into a new snapshot `name` in `dir`. A copy-on-write
+/// clone (`cp --reflink`, or `cp -c` on macOS) is tried first, which is
+/// instant on btrfs, XFS and APFS; other filesystems get a tarball.
+pub fn create(dir: &Path, workspace: &Path, name: &str) -> Result<Snapshot> {
+    validate_name(name)?;
+    if list(dir)?.iter().any(|s| s.name == name) {
+        bail!("Snapshot '{}' already exists.", name);
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let clone = dir.join(name);
+    if reflink_copy(workspace, &clone) {
+        return find(dir, name);
+    }
+    let _ = std::fs::remove_dir_all(&clone);
+
+    let tarball = dir.join(format!("{}.tar", name));
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&tarball)
+        .arg("-C")
+        .arg(workspace)
+        .arg(".")
+        .status()?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&tarball);
+        bail!("Failed to archive {}", workspace.display());
+    }
+    find(dir, name)
+}
+
+/// Copy the contents of `from` into a new directory `to` as copy-on-write
+/// clones, failing rather than falling back to a full copy.
+fn reflink_copy(from: &Path, to: &Path) -> bool {
+    if std::fs::create_dir(to).is_err() {
+        return false;
+    }
+    let flag = if cfg!(target_os = "macos") {
+        "-c"
+    } else {
+        "--reflink=always"
+    };
+    Command::new("cp")
+        .args(["-a", flag])
+        .arg(from.join("."))
+        .arg(to)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Replace `workspace` with the contents of snapshot `name`. The snapshot
+/// is unpacked next to the workspace first, so a failure leaves it intact.
+pub fn restore(dir: &Path, workspace: &Path, name: &str) -> Result<()> {
+    let snapshot = find(dir, name)?;
+    let staging = workspace.with_extension("restoring");
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging)?;
+
+    let status = if snapshot.reflink {
+        let flag = if cfg!(target_os = "macos") {
+            "-c"
+        } else {
+            "--reflink=auto"
+        };
+        Command::new("cp")
+            .args(["-a", flag])
+            .arg(snapshot.path.join("."))
+            .arg(&staging)
+            .status()?
+    } else {
+        Command::new("tar")
+            .arg("-xf")
+            .arg(&snapshot.path)
+            .arg("-C")
+            .arg(&staging)
+            .status()?
+    };
+    if !status.success() {
+        let _ = std::fs::remove_dir_all(&staging);
+        bail!("Failed to unpack snapshot '{}'", name);
+    }
+
+    if workspace.exists() {
+        std::fs::remove_dir_all(workspace)
+            .with_context(|| format!("Failed to remove {}", workspace.display()))?;
+    }
+    std::fs::rename(&staging, workspace)
+        .with_context(|| format!("Failed to move the snapshot into {}", workspace.display()))?;
+    Ok(())
+}
+
+/// Delete snapshot `name` from `dir`.
+pub fn remove(dir: &Path, name: &str) -> Result<()> {
+    let snapshot = find(dir, name)?;
+    if snapshot.reflink {
+        std::fs::remove_dir_all(&snapshot.path)?;
+    } else {
+        std::fs::remove_file(&snapshot.path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        for ok in ["before-refactor", "20260102-150405", "v1.2", "a_b"] {
+            assert!(validate_name(ok).is_ok(), "{}", ok);
+        }
+        for bad in ["", ".hidden", "a/b", "..", "with space"] {
+            assert!(validate_name(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_create_restore_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("snapshots");
+        let ws = tmp.path().join("ws");
+        std::fs::create_dir_all(ws.join("src")).unwrap();
+        std::fs::write(ws.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(ws.join("keep.txt"), "keep").unwrap();
+
+        let snapshot = create(&dir, &ws, "before").unwrap();
+        assert_eq!(snapshot.name, "before");
+        assert!(create(&dir, &ws, "before").is_err());
+
+        std::fs::write(ws.join("src/main.rs"), "broken").unwrap();
+        std::fs::remove_file(ws.join("keep.txt")).unwrap();
+        std::fs::write(ws.join("junk.txt"), "junk").unwrap();
+
+        restore(&dir, &ws, "before").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(ws.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+        assert!(ws.join("keep.txt").is_file());
+        assert!(!ws.join("junk.txt").exists());
+        assert!(!ws.with_extension("restoring").exists());
+        assert!(restore(&dir, &ws, "missing").is_err());
+
+        let names: Vec<String> = list(&dir).unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["before"]);
+        remove(&dir, "before").unwrap();
+        assert!(list(&dir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use crate::config;
+
+/// Data directories used by older versions, relative to `$HOME`.
+const LEGACY_DIRS: &[&str] = &[".box", ".realm"];
+
+/// Directory for sessions, workspaces and templates: `$XDG_DATA_HOME/box`
+/// (`~/.local/share/box` when unset).
+///
+/// When only a legacy directory exists it is moved here on first use. A
+/// symlink is left in its place so paths recorded by existing containers and
+/// worktrees keep resolving. Legacy data that can't be moved automatically
+/// is left for `box migrate`.
+pub fn data_dir() -> Result<PathBuf> {
+    let home = PathBuf::from(config::home_dir()?);
+    let dir = match std::env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home.join(".local").join("share"),
+    }
+    .join("box");
+
+    let legacy = legacy_dirs(&home);
+    if dir.exists() {
+        if !legacy.is_empty() {
+            static HINT: Once = Once::new();
+            HINT.call_once(|| {
+                eprintln!(
+                    "\x1b[2mhint:\x1b[0m found data from an older version in {}; run `box migrate` to move it to {}",
+                    legacy[0].display(),
+                    dir.display()
+                );
+            });
+        }
+        return Ok(dir);
+    }
+
+    match legacy.first() {
+        Some(legacy) => match move_dir(legacy, &dir) {
+            Ok(()) => {
+                eprintln!(
+                    "\x1b[2mmoved\x1b[0m {} -> {}",
+                    legacy.display(),
+                    dir.display()
+                );
+                Ok(dir)
+            }
+            Err(e) => {
+                eprintln!(
+                    "\x1b[2mwarning:\x1b[0m could not move {} to {}: {}",
+                    legacy.display(),
+                    dir.display(),
+                    e
+                );
+                Ok(legacy.clone())
+            }
+        },
+        None => Ok(dir),
+    }
+}
+
+pub fn sessions_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("sessions"))
+}
+
+pub fn locks_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("locks"))
+}
+
+pub fn templates_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("templates"))
+}
+
+/// Cached clones and downloads of shared template registries.
+pub fn registries_dir() -> Result<PathBuf> {
+    Ok(templates_dir()?.join("registries"))
+}
+
+/// Workspace snapshots of session `name`.
+pub fn snapshots_dir(name: &str) -> Result<PathBuf> {
+    Ok(data_dir()?.join("snapshots").join(name))
+}
+
+/// Where dotfiles cloned from a git URL are cached.
+pub fn dotfiles_cache_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("dotfiles"))
+}
+
+/// Directory holding all session workspaces: `$BOX_WORKSPACE_DIR`, then
+/// `workspace_dir` in the global config, or `workspaces` in the data directory.
+pub fn workspaces_dir() -> Result<PathBuf> {
+    let dir = match std::env::var("BOX_WORKSPACE_DIR") {
+        Ok(dir) if !dir.is_empty() => Some(dir),
+        _ => config::GlobalConfig::load()?.workspace_dir,
+    };
+    match dir {
+        Some(dir) => Ok(PathBuf::from(config::expand_tilde(&dir)?)),
+        None => Ok(data_dir()?.join("workspaces")),
+    }
+}
+
+/// Host path of the workspace for session `name`.
+pub fn workspace_dir(name: &str) -> Result<PathBuf> {
+    Ok(workspaces_dir()?.join(name))
+}
+
+/// Legacy data directories that still hold data (symlinks left behind by a
+/// previous migration are skipped).
+fn legacy_dirs(home: &Path) -> Vec<PathBuf> {
+    LEGACY_DIRS
+        .iter()
+        .map(|name| home.join(name))
+        .filter(|path| {
+            fs::symlink_metadata(path)
+                .map(|meta| meta.is_dir())
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Rename `from` to `to` and leave a symlink to `to` at `from`.
+fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)?;
+    std::os::unix::fs::symlink(to, from)?;
+    Ok(())
+}
+
+/// Outcome of `migrate`.
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// Entries moved into the data directory, as (from, to).
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// Legacy entries left in place because the data directory already has one
+    /// with the same name.
+    pub conflicts: Vec<PathBuf>,
+    /// Legacy directories that were emptied and replaced with a symlink.
+    pub linked: Vec<PathBuf>,
+}
+
+impl Migration {
+    /// Sessions whose metadata moved while their legacy directory is still
+    /// a real directory, so old bind-mount paths no longer resolve.
+    pub fn relocated_sessions(&self) -> Vec<String> {
+        self.moved
+            .iter()
+            .filter(|(from, _)| !self.linked.iter().any(|dir| from.starts_with(dir)))
+            .filter(|(from, _)| {
+                from.parent()
+                    .and_then(|p| p.file_name())
+                    .is_some_and(|n| n == "sessions")
+            })
+            .filter_map(|(from, _)| from.file_name()?.to_str().map(String::from))
+            .collect()
+    }
+}
+
+/// Move everything in the legacy `~/.box` and `~/.realm` directories into the
+/// data directory, merging with what's already there.
+pub fn migrate() -> Result<Migration> {
+    let home = PathBuf::from(config::home_dir()?);
+    let dir = data_dir()?;
+    let mut migration = Migration::default();
+
+    for legacy in legacy_dirs(&home) {
+        if !dir.exists() {
+            move_dir(&legacy, &dir)?;
+            migration.moved.push((legacy.clone(), dir.clone()));
+            migration.linked.push(legacy);
+            continue;
+        }
+
+        for entry in read_dir_sorted(&legacy)? {
+            let name = entry.file_name().unwrap_or_default().to_owned();
+            let target = dir.join(&name);
+            if name == "locks" {
+                // Lock files carry no data; the data directory has its own.
+                let _ = fs::remove_dir_all(&entry);
+            } else if entry.is_dir() && (target.is_dir() || !target.exists()) {
+                fs::create_dir_all(&target)?;
+                for child in read_dir_sorted(&entry)? {
+                    let child_target = target.join(child.file_name().unwrap_or_default());
+                    if child_target.exists() {
+                        migration.conflicts.push(child);
+                    } else {
+                        rename(&child, &child_target, &mut migration)?;
+                    }
+                }
+                let _ = fs::remove_dir(&entry);
+            } else if !target.exists() {
+                rename(&entry, &target, &mut migration)?;
+            } else {
+                migration.conflicts.push(entry);
+            }
+        }
+
+        if fs::remove_dir(&legacy).is_ok() {
+            std::os::unix::fs::symlink(&dir, &legacy)
+                .with_context(|| format!("Failed to link {}", legacy.display()))?;
+            migration.linked.push(legacy);
+        }
+    }
+    Ok(migration)
+}
+
+fn rename(from: &Path, to: &Path, migration: &mut Migration) -> Result<()> {
+    fs::rename(from, to)
+        .with_context(|| format!("Failed to move {} to {}", from.display(), to.display()))?;
+    migration.moved.push((from.to_path_buf(), to.to_path_buf()));
+    Ok(())
+}
+
+fn read_dir_sorted(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Serialize tests that mutate HOME env var
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn with_temp_home<F: FnOnce(&Path)>(f: F) {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let old_home = std::env::var("HOME").ok();
+        std::env::set_var("HOME", tmp.path());
+        std::env::remove_var("XDG_DATA_HOME");
+        f(tmp.path());
+        match old_home {
+            Some(h) => std::env::set_var("HOME", h),
+            None => std::env::remove_var("HOME"),
+        }
+    }
+
+    #[test]
+    fn test_data_dir_xdg_and_legacy_migration() {
+        with_temp_home(|home| {
+            std::env::set_var("XDG_DATA_HOME", home.join("data"));
+            assert_eq!(data_dir().unwrap(), home.join("data/box"));
+            std::env::remove_var("XDG_DATA_HOME");
+
+            let legacy = home.join(".box");
+            fs::create_dir_all(legacy.join("sessions/dev")).unwrap();
+            let dir = data_dir().unwrap();
+            assert_eq!(dir, home.join(".local/share/box"));
+            assert!(dir.join("sessions/dev").is_dir());
+            assert!(fs::symlink_metadata(&legacy).unwrap().is_symlink());
+            assert!(legacy.join("sessions/dev").is_dir());
+            assert_eq!(data_dir().unwrap(), dir);
+            assert_eq!(sessions_dir().unwrap(), dir.join("sessions"));
+        });
+    }
+
+    #[test]
+    fn test_migrate_merges_legacy_dirs() {
+        with_temp_home(|home| {
+            let dir = home.join(".local/share/box");
+            fs::create_dir_all(dir.join("sessions/both")).unwrap();
+            fs::create_dir_all(home.join(".box/sessions/old")).unwrap();
+            fs::create_dir_all(home.join(".box/locks")).unwrap();
+            fs::write(home.join(".box/locks/old.lock"), "").unwrap();
+            fs::create_dir_all(home.join(".realm/sessions/both")).unwrap();
+            fs::create_dir_all(home.join(".realm/workspaces/both")).unwrap();
+
+            let migration = migrate().unwrap();
+            assert!(dir.join("sessions/old").is_dir());
+            assert!(dir.join("workspaces/both").is_dir());
+            assert_eq!(migration.conflicts, vec![home.join(".realm/sessions/both")]);
+            assert_eq!(migration.linked, vec![home.join(".box")]);
+            assert!(fs::symlink_metadata(home.join(".box"))
+                .unwrap()
+                .is_symlink());
+            assert!(home.join(".realm/sessions/both").is_dir());
+            assert!(migration.relocated_sessions().is_empty());
+
+            // Nothing left to do the second time, apart from the conflict
+            let again = migrate().unwrap();
+            assert!(again.moved.is_empty());
+            assert_eq!(again.conflicts, vec![home.join(".realm/sessions/both")]);
+        });
+    }
+
+    #[test]
+    fn test_migrate_reports_relocated_sessions() {
+        with_temp_home(|home| {
+            fs::create_dir_all(home.join(".local/share/box/templates")).unwrap();
+            fs::create_dir_all(home.join(".box/sessions/dev")).unwrap();
+            fs::create_dir_all(home.join(".box/templates")).unwrap();
+            fs::write(home.join(".box/templates/rust.toml"), "").unwrap();
+            fs::write(home.join(".local/share/box/templates/rust.toml"), "").unwrap();
+
+            let migration = migrate().unwrap();
+            assert!(migration.linked.is_empty());
+            assert_eq!(migration.relocated_sessions(), vec!["dev".to_string()]);
+        });
+    }
+}
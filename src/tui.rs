@@ -2,13 +2,21 @@ use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use crossterm::{cursor, execute, terminal};
 use ratatui::prelude::*;
-use ratatui::widgets::{Row, Table, TableState};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
 use ratatui::{TerminalOptions, Viewport};
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 use crate::config;
 use crate::docker;
-use crate::session::{self, SessionSummary};
+use crate::events;
+use crate::git::{self, SyncState};
+use crate::session::{self, Session, SessionSummary};
+use crate::storage;
 
 pub enum TuiAction {
     Resume(String),
@@ -16,6 +24,11 @@ pub enum TuiAction {
         name: String,
         image: Option<String>,
         command: Option<Vec<String>>,
+        template: Option<String>,
+        /// `None` when left at the default.
+        docker_args: Option<String>,
+        env: Vec<String>,
+        ssh: Option<bool>,
     },
     Cd(String),
     Quit,
@@ -26,8 +39,318 @@ enum Mode {
     Normal,
     DeleteConfirm,
     InputName,
+    SelectTemplate,
     InputImage,
     InputCommand,
+    InputDockerArgs,
+    InputEnv,
+    SelectSsh,
+    Logs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Name,
+    Created,
+    LastUsed,
+    RunningFirst,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 4] = [
+        SortKey::Name,
+        SortKey::Created,
+        SortKey::LastUsed,
+        SortKey::RunningFirst,
+    ];
+
+    fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|k| *k == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Map number keys `1`..`4` to a sort order.
+    fn from_digit(c: char) -> Option<Self> {
+        let i = c.to_digit(10)? as usize;
+        Self::ALL.get(i.checked_sub(1)?).copied()
+    }
+}
+
+/// Sort sessions in place. Timestamps are newest-first; ties fall back to name.
+fn sort_sessions(items: &mut [SessionSummary], key: SortKey) {
+    match key {
+        SortKey::Name => items.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortKey::Created => items.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+        SortKey::LastUsed => items.sort_by(|a, b| {
+            b.last_used()
+                .cmp(a.last_used())
+                .then_with(|| a.name.cmp(&b.name))
+        }),
+        SortKey::RunningFirst => {
+            items.sort_by(|a, b| b.running.cmp(&a.running).then_with(|| a.name.cmp(&b.name)))
+        }
+    }
+}
+
+/// Re-sort `items`, keeping the cursor on the same session.
+fn sort_keeping_selection(items: &mut [SessionSummary], key: SortKey, state: &mut TableState) {
+    // Row 0 is "New box...", so sessions start at row 1
+    let selected = state
+        .selected()
+        .and_then(|i| items.get(i.checked_sub(1)?))
+        .map(|s| s.name.clone());
+    sort_sessions(items, key);
+    if let Some(name) = selected {
+        let idx = items.iter().position(|s| s.name == name);
+        state.select(idx.map(|i| i + 1));
+    }
+}
+
+/// Copy container status from `refreshed` into the matching sessions of `items`.
+fn apply_status(items: &mut [SessionSummary], refreshed: &[SessionSummary]) {
+    for item in items {
+        if let Some(r) = refreshed.iter().find(|r| r.name == item.name) {
+            item.running = r.running;
+            item.paused = r.paused;
+            item.started_at = r.started_at.clone();
+            item.finished_at = r.finished_at.clone();
+            item.exit_code = r.exit_code;
+        }
+    }
+}
+
+/// Sessions a delete applies to: every marked session in display order,
+/// or the highlighted one when nothing is marked.
+fn delete_targets(
+    items: &[SessionSummary],
+    marked: &HashSet<String>,
+    selected: Option<usize>,
+) -> Vec<String> {
+    if marked.is_empty() {
+        selected
+            .and_then(|i| items.get(i.checked_sub(1)?))
+            .map(|s| vec![s.name.clone()])
+            .unwrap_or_default()
+    } else {
+        items
+            .iter()
+            .filter(|s| marked.contains(&s.name))
+            .map(|s| s.name.clone())
+            .collect()
+    }
+}
+
+/// Rows reserved for the detail pane, including its top border.
+const DETAIL_HEIGHT: u16 = 12;
+
+/// Key/value rows shown in the detail pane for a session.
+fn detail_rows(
+    sess: &Session,
+    summary: &SessionSummary,
+    disk_usage: Option<u64>,
+) -> Vec<(&'static str, String)> {
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    let or_dash = |s: &str| {
+        if s.is_empty() {
+            "-".to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    vec![
+        ("note", or_dash(sess.note.as_deref().unwrap_or_default())),
+        ("project", or_dash(&sess.project_dir)),
+        ("mount", or_dash(&sess.mount_path)),
+        ("command", or_dash(&shell_words::join(&sess.command))),
+        ("env", or_dash(&sess.env.join(" "))),
+        (
+            "ssh",
+            format!("{}  worktree: {}", yes_no(sess.ssh), yes_no(sess.worktree)),
+        ),
+        (
+            "docker args",
+            or_dash(sess.docker_args.as_deref().unwrap_or_default()),
+        ),
+        (
+            "limits",
+            format!(
+                "cpus: {}  memory: {}",
+                sess.cpus.as_deref().unwrap_or("unlimited"),
+                sess.memory.as_deref().unwrap_or("unlimited")
+            ),
+        ),
+        (
+            "disk usage",
+            disk_usage
+                .map(crate::format_bytes)
+                .unwrap_or_else(|| "workspace missing".to_string()),
+        ),
+        ("created", or_dash(&summary.created_at)),
+        ("resumed", or_dash(&summary.resumed_at)),
+    ]
+}
+
+fn load_details(summary: &SessionSummary) -> Vec<Line<'static>> {
+    let sess = match session::load(&summary.name) {
+        Ok(sess) => sess,
+        Err(e) => return vec![Line::from(e.to_string())],
+    };
+    let disk_usage = storage::workspace_dir(&summary.name)
+        .ok()
+        .filter(|dir| dir.is_dir())
+        .map(|dir| docker::workspace_size(&dir));
+    detail_rows(&sess, summary, disk_usage)
+        .into_iter()
+        .map(|(key, value)| {
+            Line::from(vec![
+                Span::styled(format!("{:<12} ", key), Style::default().dim()),
+                Span::raw(value),
+            ])
+        })
+        .collect()
+}
+
+/// Local images matching what has been typed so far: prefix matches first,
+/// then other substring matches.
+fn image_suggestions<'a>(images: &'a [String], query: &str) -> Vec<&'a str> {
+    let query = query.trim();
+    let (mut prefix, mut other): (Vec<&str>, Vec<&str>) = images
+        .iter()
+        .map(String::as_str)
+        .filter(|image| image.contains(query))
+        .partition(|image| image.starts_with(query));
+    prefix.append(&mut other);
+    prefix
+}
+
+/// Rows added to the viewport while the logs view is open.
+const LOGS_HEIGHT: u16 = 15;
+
+/// Number of trailing log lines fetched for the logs view.
+const LOGS_TAIL: &str = "200";
+
+/// Split the env prompt into `KEY=VALUE` / `KEY` entries, shell-style so
+/// values can be quoted.
+fn parse_env_entries(text: &str) -> Result<Vec<String>, String> {
+    let entries = shell_words::split(text).map_err(|e| format!("Invalid env: {}", e))?;
+    entries.iter().map(|e| crate::parse_env(e)).collect()
+}
+
+/// Make a log line safe to render: drop escape sequences and other control
+/// characters, and expand tabs.
+fn sanitize_log_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // CSI sequences: ESC [ params final-byte
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => out.push_str("    "),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reload sessions and their container status, keeping the current sort order.
+fn refresh_items(items: &mut Vec<SessionSummary>, sort_key: SortKey) {
+    if let Ok(mut refreshed) = session::list() {
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            docker::refresh_status(&mut refreshed)
+        }));
+        sort_sessions(&mut refreshed, sort_key);
+        *items = refreshed;
+    }
+}
+
+/// Start a stopped session's existing container in the background. Sessions
+/// without a reusable container go through `box resume` instead.
+fn start_detached(name: &str) -> Result<()> {
+    if !docker::container_exists(name) || session::needs_recreate(name)? {
+        anyhow::bail!(
+            "Session '{}' needs a new container; run `box resume {} -d`.",
+            name,
+            name
+        );
+    }
+    docker::start_session(name)?;
+    if let Ok(sess) = session::load(name) {
+        events::emit(events::Event::Started, &sess);
+    }
+    session::touch_resumed_at(name)
+}
+
+/// What the background scan found in a session's workspace.
+struct WorkspaceScan {
+    /// `None` means the workspace is missing.
+    size: Option<u64>,
+    /// `None` when the workspace or the host repo isn't a git repository.
+    git: Option<SyncState>,
+}
+
+/// Ask docker for the sessions' container status on a background thread, so
+/// the table can be drawn before docker answers.
+fn spawn_status_refresh(
+    mut sessions: Vec<SessionSummary>,
+) -> mpsc::Receiver<Result<Vec<SessionSummary>, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let result = docker::check()
+            .map(|()| {
+                docker::refresh_status(&mut sessions);
+                sessions
+            })
+            .map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+    rx
+}
+
+/// Measure each session's workspace and compare it with its host repo on a
+/// background thread, sending `(name, scan)` as results come in.
+fn spawn_workspace_scan(
+    sessions: Vec<(String, String)>,
+) -> mpsc::Receiver<(String, WorkspaceScan)> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for (name, project_dir) in sessions {
+            let dir = storage::workspace_dir(&name)
+                .ok()
+                .filter(|dir| dir.is_dir());
+            let scan = WorkspaceScan {
+                size: dir.as_deref().map(docker::workspace_size),
+                git: dir.and_then(|dir| git::sync_state(&dir, Path::new(&project_dir))),
+            };
+            if tx.send((name, scan)).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn inline_terminal(height: u16) -> Result<Terminal<CrosstermBackend<io::Stderr>>> {
+    let options = TerminalOptions {
+        viewport: Viewport::Inline(height),
+    };
+    Ok(Terminal::with_options(
+        CrosstermBackend::new(io::stderr()),
+        options,
+    )?)
 }
 
 struct TextInput {
@@ -54,44 +377,36 @@ impl TextInput {
                 self.text.insert(self.cursor, c);
                 self.cursor += c.len_utf8();
             }
-            KeyCode::Backspace => {
-                if self.cursor > 0 {
-                    let prev = self.text[..self.cursor]
-                        .char_indices()
-                        .next_back()
-                        .map(|(i, _)| i)
-                        .unwrap_or(0);
-                    self.text.drain(prev..self.cursor);
-                    self.cursor = prev;
-                }
+            KeyCode::Backspace if self.cursor > 0 => {
+                let prev = self.text[..self.cursor]
+                    .char_indices()
+                    .next_back()
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
+                self.text.drain(prev..self.cursor);
+                self.cursor = prev;
             }
-            KeyCode::Delete => {
-                if self.cursor < self.text.len() {
-                    let next = self.text[self.cursor..]
-                        .char_indices()
-                        .nth(1)
-                        .map(|(i, _)| self.cursor + i)
-                        .unwrap_or(self.text.len());
-                    self.text.drain(self.cursor..next);
-                }
+            KeyCode::Delete if self.cursor < self.text.len() => {
+                let next = self.text[self.cursor..]
+                    .char_indices()
+                    .nth(1)
+                    .map(|(i, _)| self.cursor + i)
+                    .unwrap_or(self.text.len());
+                self.text.drain(self.cursor..next);
             }
-            KeyCode::Left => {
-                if self.cursor > 0 {
-                    self.cursor = self.text[..self.cursor]
-                        .char_indices()
-                        .next_back()
-                        .map(|(i, _)| i)
-                        .unwrap_or(0);
-                }
+            KeyCode::Left if self.cursor > 0 => {
+                self.cursor = self.text[..self.cursor]
+                    .char_indices()
+                    .next_back()
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
             }
-            KeyCode::Right => {
-                if self.cursor < self.text.len() {
-                    self.cursor = self.text[self.cursor..]
-                        .char_indices()
-                        .nth(1)
-                        .map(|(i, _)| self.cursor + i)
-                        .unwrap_or(self.text.len());
-                }
+            KeyCode::Right if self.cursor < self.text.len() => {
+                self.cursor = self.text[self.cursor..]
+                    .char_indices()
+                    .nth(1)
+                    .map(|(i, _)| self.cursor + i)
+                    .unwrap_or(self.text.len());
             }
             _ => {}
         }
@@ -142,40 +457,150 @@ fn clear_viewport(
 }
 
 pub fn session_manager<F>(sessions: &[SessionSummary], delete_fn: F) -> Result<TuiAction>
+where
+    F: Fn(&str) -> Result<()>,
+{
+    run(sessions, delete_fn, None)
+}
+
+/// Only the "New box..." prompts, on a single line, starting with `name`
+/// filled in. Leaving them returns `Quit`; an invalid answer is an error.
+pub fn create_wizard(name: &str) -> Result<TuiAction> {
+    run(&[], |_| Ok(()), Some(name))
+}
+
+fn run<F>(sessions: &[SessionSummary], delete_fn: F, wizard: Option<&str>) -> Result<TuiAction>
 where
     F: Fn(&str) -> Result<()>,
 {
     let mut items: Vec<SessionSummary> = sessions.to_vec();
-    // +1 for "new session" row, +1 for header, +1 for footer
-    let viewport_height = (items.len() as u16) + 3;
+    // +1 for "new session" row, +1 for header, +1 for footer. The wizard
+    // only has the footer.
+    let base_height = if wizard.is_some() {
+        1
+    } else {
+        (items.len() as u16) + 3
+    };
+    let mut viewport_height = base_height;
 
     terminal::enable_raw_mode()?;
     let _guard = TermGuard;
 
-    let options = TerminalOptions {
-        viewport: Viewport::Inline(viewport_height),
-    };
-    let mut terminal = Terminal::with_options(CrosstermBackend::new(io::stderr()), options)?;
+    let mut terminal = inline_terminal(viewport_height)?;
     let mut state = TableState::default();
     state.select(Some(0));
     // Row 0 = "new session", rows 1.. = actual sessions
     let new_row_idx = 0;
 
-    let mut mode = Mode::Normal;
-    let mut input = TextInput::new();
+    let mut mode = if wizard.is_some() {
+        Mode::InputName
+    } else {
+        Mode::Normal
+    };
+    let mut input = TextInput::with_text(wizard.unwrap_or_default().to_string());
     let mut footer_msg = String::new();
     let mut new_name = String::new();
     let mut new_image: Option<String> = None;
+    // Index 0 = no template, 1.. = entries of `templates`
+    let mut templates: Vec<String> = Vec::new();
+    let mut template_idx = 0;
+    let mut new_command_default = String::new();
+    let mut new_command: Option<Vec<String>> = None;
+    let mut new_docker_args_default = String::new();
+    let mut new_docker_args: Option<String> = None;
+    let mut new_env: Vec<String> = Vec::new();
+    let mut new_ssh = true;
+    // Loaded on first use of the image prompt
+    let mut local_images: Option<Vec<String>> = None;
+    // Repository new sessions are created in, for the default image
+    let project_dir = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| git::find_root(&cwd).map(|root| root.to_string_lossy().to_string()));
+    // While cycling suggestions: the text typed before cycling and the chosen index
+    let mut image_cycle: Option<(String, usize)> = None;
+    let mut sort_key = SortKey::LastUsed;
+    sort_sessions(&mut items, sort_key);
+    let mut marked: HashSet<String> = HashSet::new();
+    let mut show_details = false;
+    // Container status is unknown until the refresh reports back
+    let mut status_rx =
+        (wizard.is_none() && !items.is_empty()).then(|| spawn_status_refresh(items.clone()));
+    let scan_rx = spawn_workspace_scan(
+        items
+            .iter()
+            .map(|s| (s.name.clone(), s.project_dir.clone()))
+            .collect(),
+    );
+    let mut scans: HashMap<String, WorkspaceScan> = HashMap::new();
+    // Details of the last inspected session, keyed by name
+    let mut details: Option<(String, Vec<Line>)> = None;
+    let mut log_name = String::new();
+    let mut log_lines: Vec<String> = Vec::new();
+    // Top line of the logs view; clamped to the last page while drawing
+    let mut log_scroll = 0usize;
 
     loop {
+        // The wizard ends where the session manager would go back to the list
+        if wizard.is_some() && mode == Mode::Normal {
+            clear_viewport(&mut terminal, viewport_height)?;
+            if !footer_msg.is_empty() {
+                anyhow::bail!("{}", footer_msg);
+            }
+            return Ok(TuiAction::Quit);
+        }
+
+        if show_details {
+            let selected = state.selected().and_then(|i| items.get(i.checked_sub(1)?));
+            match selected {
+                Some(s) if details.as_ref().map(|(n, _)| n) != Some(&s.name) => {
+                    details = Some((s.name.clone(), load_details(s)));
+                }
+                None => details = None,
+                _ => {}
+            }
+        }
+
+        if mode == Mode::InputImage && local_images.is_none() {
+            local_images = Some(docker::local_images());
+        }
+
+        while let Ok((name, scan)) = scan_rx.try_recv() {
+            scans.insert(name, scan);
+        }
+        if let Some(Ok(result)) = status_rx.as_ref().map(|rx| rx.try_recv()) {
+            status_rx = None;
+            match result {
+                Ok(refreshed) => {
+                    apply_status(&mut items, &refreshed);
+                    sort_keeping_selection(&mut items, sort_key, &mut state);
+                }
+                Err(e) => footer_msg = e,
+            }
+        }
+
+        let wanted_height = if mode == Mode::Logs {
+            base_height + LOGS_HEIGHT
+        } else if show_details {
+            base_height + DETAIL_HEIGHT
+        } else {
+            base_height
+        };
+        if wanted_height != viewport_height {
+            // Recreate the inline viewport at the new height, in place
+            terminal.clear()?;
+            viewport_height = wanted_height;
+            terminal = inline_terminal(viewport_height)?;
+        }
+
         terminal.draw(|f| {
             let area = f.area();
-            // Reserve last row for footer
+            // Reserve last row for footer, and rows above it for the detail pane
+            let detail_height = if show_details { DETAIL_HEIGHT } else { 0 };
             let table_area = Rect {
                 x: area.x,
                 y: area.y,
                 width: area.width,
-                height: area.height.saturating_sub(1),
+                height: area.height.saturating_sub(1 + detail_height),
             };
             let footer_area = Rect {
                 x: area.x,
@@ -184,31 +609,116 @@ where
                 height: 1,
             };
 
+            // Logs view
+            if mode == Mode::Logs {
+                let log_area = Rect {
+                    height: area.height.saturating_sub(1),
+                    ..area
+                };
+                let max_scroll = log_lines.len().saturating_sub(log_area.height as usize);
+                log_scroll = log_scroll.min(max_scroll);
+                let lines: Vec<Line> = if log_lines.is_empty() {
+                    vec![Line::from("No log output.").dim()]
+                } else {
+                    log_lines
+                        .iter()
+                        .skip(log_scroll)
+                        .map(|l| Line::from(l.as_str()))
+                        .collect()
+                };
+                f.render_widget(Paragraph::new(lines), log_area);
+            }
+
             // Table
-            {
-                let header = Row::new(["NAME", "STATUS", "PROJECT", "IMAGE", "CMD", "CREATED"])
-                    .style(Style::default().dim());
+            if mode != Mode::Logs {
+                // The last column shows creation times only while sorting by them
+                let (time_label, time_key) = if sort_key != SortKey::Created {
+                    ("LAST USED", SortKey::LastUsed)
+                } else {
+                    ("CREATED", SortKey::Created)
+                };
+                let mark = |label: &str, key: SortKey| {
+                    if sort_key == key {
+                        format!("{} \u{25be}", label)
+                    } else {
+                        label.to_string()
+                    }
+                };
+                let header = Row::new([
+                    mark("NAME", SortKey::Name),
+                    mark("STATUS", SortKey::RunningFirst),
+                    "PROJECT".to_string(),
+                    "IMAGE".to_string(),
+                    "CMD".to_string(),
+                    "SIZE".to_string(),
+                    "GIT".to_string(),
+                    mark(time_label, time_key),
+                ])
+                .style(Style::default().dim());
 
                 let total_rows = 1 + items.len(); // "new session" + actual sessions
                 let mut rows: Vec<Row> = Vec::with_capacity(total_rows);
 
                 // First row: "+ new session"
-                rows.push(Row::new(["New box...", "", "", "", "", ""]));
+                rows.push(Row::new(["New box...", "", "", "", "", "", "", ""]));
+
+                // Workspace sizes and git state arrive from the background scan
+                let size_labels: Vec<String> = items
+                    .iter()
+                    .map(|s| match scans.get(&s.name) {
+                        Some(WorkspaceScan {
+                            size: Some(bytes), ..
+                        }) => crate::format_bytes(*bytes),
+                        Some(_) => "-".to_string(),
+                        None => "...".to_string(),
+                    })
+                    .collect();
+                let git_labels: Vec<String> = items
+                    .iter()
+                    .map(|s| match scans.get(&s.name) {
+                        Some(WorkspaceScan { git: Some(git), .. }) => git.label(),
+                        Some(_) => "-".to_string(),
+                        None => "...".to_string(),
+                    })
+                    .collect();
+
+                let status_labels: Vec<String> = items
+                    .iter()
+                    .map(|s| {
+                        if status_rx.is_some() {
+                            "loading".to_string()
+                        } else {
+                            crate::container_status(s).unwrap_or_default()
+                        }
+                    })
+                    .collect();
 
                 // Session rows
                 for (i, s) in items.iter().enumerate() {
-                    let status = if s.running { "running" } else { "" };
                     let row = Row::new([
                         s.name.as_str(),
-                        status,
+                        status_labels[i].as_str(),
                         s.project_dir.as_str(),
                         s.image.as_str(),
                         s.command.as_str(),
-                        s.created_at.as_str(),
+                        size_labels[i].as_str(),
+                        git_labels[i].as_str(),
+                        if sort_key == SortKey::Created {
+                            s.created_at.as_str()
+                        } else {
+                            s.last_used()
+                        },
                     ]);
                     let row_idx = i + 1; // offset by "new session" row
-                    if mode == Mode::DeleteConfirm && state.selected() == Some(row_idx) {
+                    let is_target = if marked.is_empty() {
+                        state.selected() == Some(row_idx)
+                    } else {
+                        marked.contains(&s.name)
+                    };
+                    if mode == Mode::DeleteConfirm && is_target {
                         rows.push(row.style(Style::default().fg(Color::Red)));
+                    } else if marked.contains(&s.name) {
+                        rows.push(row.style(Style::default().fg(Color::Yellow)));
                     } else {
                         rows.push(row);
                     }
@@ -220,6 +730,8 @@ where
                     Constraint::Min(30),
                     Constraint::Min(20),
                     Constraint::Min(15),
+                    Constraint::Min(9),
+                    Constraint::Min(7),
                     Constraint::Min(22),
                 ];
 
@@ -231,6 +743,30 @@ where
                 f.render_stateful_widget(table, table_area, &mut state);
             }
 
+            // Detail pane
+            if show_details && mode != Mode::Logs {
+                let detail_area = Rect {
+                    x: area.x,
+                    y: table_area.y + table_area.height,
+                    width: area.width,
+                    height: detail_height.min(area.height.saturating_sub(1)),
+                };
+                let (title, lines) = match &details {
+                    Some((name, lines)) => (format!(" {} ", name), lines.clone()),
+                    None => (
+                        String::new(),
+                        vec![Line::from("Select a session to see its details.").dim()],
+                    ),
+                };
+                let pane = Paragraph::new(lines).block(
+                    Block::default()
+                        .borders(Borders::TOP)
+                        .border_style(Style::default().dim())
+                        .title(title),
+                );
+                f.render_widget(pane, detail_area);
+            }
+
             // Footer
             let on_new_row = state.selected() == Some(new_row_idx);
             let footer_line: Line = match &mode {
@@ -240,28 +776,151 @@ where
                             footer_msg.as_str(),
                             Style::default().fg(Color::Red),
                         ))
+                    } else if !marked.is_empty() {
+                        Line::from(format!(
+                            "{} marked  [Space] Mark  [d] Delete marked  [Esc] Clear",
+                            marked.len()
+                        ))
+                        .style(Style::default().dim())
                     } else if on_new_row || items.is_empty() {
                         Line::from("[Enter] New  [q] Quit").style(Style::default().dim())
                     } else {
-                        Line::from("[Enter] Resume  [c] Cd  [d] Delete  [q] Quit")
-                            .style(Style::default().dim())
+                        let running = state
+                            .selected()
+                            .and_then(|i| items.get(i.checked_sub(1)?))
+                            .is_some_and(|s| s.running);
+                        let toggle = if running { "[s] Stop" } else { "[S] Start" };
+                        Line::from(format!(
+                            "[Enter] Resume  [c] Cd  [Space] Mark  [d] Delete  {}  [o] Sort  [i] Info  [l] Logs  [q] Quit",
+                            toggle
+                        ))
+                        .style(Style::default().dim())
                     }
                 }
                 Mode::DeleteConfirm => {
-                    let name = state
-                        .selected()
-                        .and_then(|i| items.get(i.saturating_sub(1)))
-                        .map(|s| s.name.as_str())
-                        .unwrap_or("");
-                    Line::from(format!("Delete '{}'? [y/n]", name)).style(Style::default().dim())
+                    let targets = delete_targets(&items, &marked, state.selected());
+                    let unsynced = targets
+                        .iter()
+                        .filter(|name| {
+                            scans
+                                .get(*name)
+                                .and_then(|scan| scan.git)
+                                .is_some_and(|git| git.unsynced())
+                        })
+                        .count();
+                    let warning = match (targets.len(), unsynced) {
+                        (_, 0) => String::new(),
+                        (1, _) => " It has unsynced work.".to_string(),
+                        (_, n) => format!(" {} have unsynced work.", n),
+                    };
+                    let prompt = match targets.as_slice() {
+                        [name] => format!("Delete '{}'?{} [y/n]", name, warning),
+                        _ => format!("Delete {} sessions?{} [y/n]", targets.len(), warning),
+                    };
+                    Line::from(prompt).style(Style::default().dim())
                 }
                 Mode::InputName => Line::from(input.to_spans("Session name: ")),
-                Mode::InputImage => Line::from(input.to_spans("Image: ")),
+                Mode::SelectTemplate => {
+                    let mut spans = vec![Span::styled("Template: ", Style::default().bold())];
+                    let choices =
+                        std::iter::once("none").chain(templates.iter().map(|t| t.as_str()));
+                    for (i, choice) in choices.enumerate() {
+                        if i > 0 {
+                            spans.push(Span::raw(" "));
+                        }
+                        if i == template_idx {
+                            spans.push(Span::styled(
+                                choice.to_string(),
+                                Style::default().reversed(),
+                            ));
+                        } else {
+                            spans.push(Span::raw(choice.to_string()));
+                        }
+                    }
+                    spans.push(Span::styled(
+                        "  [←/→] Choose  [Enter] OK",
+                        Style::default().dim(),
+                    ));
+                    Line::from(spans)
+                }
+                Mode::InputImage => {
+                    let mut spans = input.to_spans("Image: ");
+                    let (query, chosen) = match &image_cycle {
+                        Some((query, idx)) => (query.as_str(), Some(*idx)),
+                        None => (input.text.as_str(), None),
+                    };
+                    let suggestions =
+                        image_suggestions(local_images.as_deref().unwrap_or_default(), query);
+                    if !suggestions.is_empty() {
+                        spans.push(Span::raw("  "));
+                        // Show a window of suggestions that keeps the chosen one visible
+                        let start = chosen.unwrap_or(0).saturating_sub(4);
+                        for (i, image) in suggestions.iter().enumerate().skip(start).take(5) {
+                            let style = if chosen == Some(i) {
+                                Style::default().reversed()
+                            } else {
+                                Style::default().dim()
+                            };
+                            spans.push(Span::styled(image.to_string(), style));
+                            spans.push(Span::raw(" "));
+                        }
+                        spans.push(Span::styled(
+                            " [Tab/↑↓] Choose",
+                            Style::default().dim(),
+                        ));
+                    }
+                    Line::from(spans)
+                }
                 Mode::InputCommand => Line::from(input.to_spans("Command (optional): ")),
+                Mode::InputDockerArgs => {
+                    Line::from(input.to_spans("Docker args (optional): "))
+                }
+                Mode::InputEnv => {
+                    Line::from(input.to_spans("Env, e.g. FOO=bar TOKEN (optional): "))
+                }
+                Mode::SelectSsh => {
+                    let mut spans = vec![Span::styled(
+                        "SSH agent forwarding: ",
+                        Style::default().bold(),
+                    )];
+                    for (i, (label, on)) in [("on", true), ("off", false)].into_iter().enumerate() {
+                        if i > 0 {
+                            spans.push(Span::raw(" "));
+                        }
+                        if on == new_ssh {
+                            spans.push(Span::styled(label, Style::default().reversed()));
+                        } else {
+                            spans.push(Span::raw(label));
+                        }
+                    }
+                    spans.push(Span::styled(
+                        "  [←/→] Choose  [Enter] Create",
+                        Style::default().dim(),
+                    ));
+                    Line::from(spans)
+                }
+                Mode::Logs => {
+                    if !footer_msg.is_empty() {
+                        Line::from(Span::styled(
+                            footer_msg.as_str(),
+                            Style::default().fg(Color::Red),
+                        ))
+                    } else {
+                        Line::from(format!(
+                            "Logs: {}  [j/k] Scroll  [g/G] Top/Bottom  [r] Reload  [q] Back",
+                            log_name
+                        ))
+                        .style(Style::default().dim())
+                    }
+                }
             };
             f.render_widget(footer_line, footer_area);
         })?;
 
+        // Poll so background results (workspace scans) get drawn as they arrive
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
             if key.kind != KeyEventKind::Press {
                 continue;
@@ -309,13 +968,84 @@ where
                                 }
                             }
                         }
-                        KeyCode::Char('d') => {
+                        KeyCode::Char(' ') => {
                             if let Some(i) = state.selected() {
                                 if i != new_row_idx {
-                                    mode = Mode::DeleteConfirm;
+                                    let name = &items[i - 1].name;
+                                    if !marked.remove(name) {
+                                        marked.insert(name.clone());
+                                    }
+                                    let next = if i >= total_rows - 1 { i } else { i + 1 };
+                                    state.select(Some(next));
+                                }
+                            }
+                        }
+                        KeyCode::Char('d')
+                            if !delete_targets(&items, &marked, state.selected()).is_empty() =>
+                        {
+                            mode = Mode::DeleteConfirm;
+                        }
+                        KeyCode::Char('i') => {
+                            show_details = !show_details;
+                        }
+                        KeyCode::Char('l') => {
+                            if let Some(i) = state.selected() {
+                                if i != new_row_idx {
+                                    log_name = items[i - 1].name.clone();
+                                    match docker::recent_logs(&log_name, LOGS_TAIL) {
+                                        Ok(output) => {
+                                            log_lines =
+                                                output.lines().map(sanitize_log_line).collect();
+                                            log_scroll = usize::MAX;
+                                            mode = Mode::Logs;
+                                        }
+                                        Err(e) => footer_msg = format!("Logs failed: {}", e),
+                                    }
+                                }
+                            }
+                        }
+                        KeyCode::Esc if !marked.is_empty() => {
+                            marked.clear();
+                        }
+                        KeyCode::Char('s' | 'S' | 'r') if status_rx.is_some() => {
+                            footer_msg = "Session status is still loading.".to_string();
+                        }
+                        KeyCode::Char('s') => {
+                            if let Some(s) =
+                                state.selected().and_then(|i| items.get(i.checked_sub(1)?))
+                            {
+                                let name = s.name.clone();
+                                if !s.running {
+                                    footer_msg = format!("Session '{}' is not running.", name);
+                                } else if let Err(e) = docker::stop_session(&name, None) {
+                                    footer_msg = e.to_string();
+                                } else if let Ok(sess) = session::load(&name) {
+                                    events::emit(events::Event::Stopped, &sess);
                                 }
+                                refresh_items(&mut items, sort_key);
                             }
                         }
+                        KeyCode::Char('S') | KeyCode::Char('r') => {
+                            if let Some(s) =
+                                state.selected().and_then(|i| items.get(i.checked_sub(1)?))
+                            {
+                                let name = s.name.clone();
+                                if s.running {
+                                    footer_msg = format!("Session '{}' is already running.", name);
+                                } else if let Err(e) = start_detached(&name) {
+                                    footer_msg = e.to_string();
+                                }
+                                refresh_items(&mut items, sort_key);
+                            }
+                        }
+                        KeyCode::Char(c @ ('o' | '1'..='4')) => {
+                            sort_key = if c == 'o' {
+                                sort_key.next()
+                            } else {
+                                SortKey::from_digit(c).unwrap_or(sort_key)
+                            };
+                            sort_keeping_selection(&mut items, sort_key, &mut state);
+                        }
                         KeyCode::Esc | KeyCode::Char('q') => {
                             clear_viewport(&mut terminal, viewport_height)?;
                             return Ok(TuiAction::Quit);
@@ -326,22 +1056,18 @@ where
                 Mode::DeleteConfirm => match key.code {
                     KeyCode::Char('y') | KeyCode::Char('Y') => {
                         if let Some(i) = state.selected() {
-                            let item_idx = i - 1; // offset for "new session" row
-                            let name = items[item_idx].name.clone();
-                            if let Err(e) = delete_fn(&name) {
-                                footer_msg = format!("Delete failed: {}", e);
-                            }
-                            // Refresh list
-                            if let Ok(mut refreshed) = session::list() {
-                                if let Ok(running) =
-                                    std::panic::catch_unwind(docker::running_sessions)
-                                {
-                                    for s in &mut refreshed {
-                                        s.running = running.contains(&s.name);
-                                    }
+                            let mut failed = Vec::new();
+                            for name in delete_targets(&items, &marked, Some(i)) {
+                                if let Err(e) = delete_fn(&name) {
+                                    failed.push(format!("{}: {}", name, e));
                                 }
-                                items = refreshed;
                             }
+                            marked.clear();
+                            if !failed.is_empty() {
+                                footer_msg = format!("Delete failed: {}", failed.join(", "));
+                            }
+                            // Refresh list
+                            refresh_items(&mut items, sort_key);
                             let total_rows = 1 + items.len();
                             if i >= total_rows {
                                 state.select(Some(total_rows - 1));
@@ -367,10 +1093,18 @@ where
                             input = TextInput::new();
                         } else {
                             new_name = name;
-                            let default_image = std::env::var("BOX_DEFAULT_IMAGE")
-                                .unwrap_or_else(|_| config::DEFAULT_IMAGE.to_string());
-                            input = TextInput::with_text(default_image);
-                            mode = Mode::InputImage;
+                            templates = config::Template::list().unwrap_or_default();
+                            template_idx = 0;
+                            if templates.is_empty() {
+                                input = TextInput::with_text(config::default_image(
+                                    project_dir.as_deref(),
+                                ));
+                                new_command_default = config::default_command();
+                                new_docker_args_default = config::default_docker_args();
+                                mode = Mode::InputImage;
+                            } else {
+                                mode = Mode::SelectTemplate;
+                            }
                         }
                     }
                     KeyCode::Esc => {
@@ -380,25 +1114,115 @@ where
                         input.handle_key(key.code);
                     }
                 },
+                Mode::SelectTemplate => match key.code {
+                    KeyCode::Left | KeyCode::Char('h') | KeyCode::BackTab => {
+                        template_idx = if template_idx == 0 {
+                            templates.len()
+                        } else {
+                            template_idx - 1
+                        };
+                    }
+                    KeyCode::Right | KeyCode::Char('l') | KeyCode::Tab => {
+                        template_idx = (template_idx + 1) % (templates.len() + 1);
+                    }
+                    KeyCode::Enter => {
+                        // Prefill the following prompts with the template's values
+                        let template = match template_idx {
+                            0 => config::Template::default(),
+                            i => match config::Template::load(&templates[i - 1]) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    footer_msg = e.to_string();
+                                    mode = Mode::Normal;
+                                    continue;
+                                }
+                            },
+                        };
+                        input = TextInput::with_text(
+                            template
+                                .image
+                                .unwrap_or_else(|| config::default_image(project_dir.as_deref())),
+                        );
+                        new_command_default =
+                            template.command.unwrap_or_else(config::default_command);
+                        new_docker_args_default = template
+                            .docker_args
+                            .unwrap_or_else(config::default_docker_args);
+                        mode = Mode::InputImage;
+                    }
+                    KeyCode::Esc => {
+                        mode = Mode::Normal;
+                    }
+                    _ => {}
+                },
                 Mode::InputImage => match key.code {
+                    KeyCode::Tab | KeyCode::Down | KeyCode::BackTab | KeyCode::Up => {
+                        let forward = matches!(key.code, KeyCode::Tab | KeyCode::Down);
+                        let query = image_cycle
+                            .as_ref()
+                            .map(|(q, _)| q.clone())
+                            .unwrap_or_else(|| input.text.clone());
+                        let suggestions =
+                            image_suggestions(local_images.as_deref().unwrap_or_default(), &query);
+                        if !suggestions.is_empty() {
+                            let n = suggestions.len();
+                            let idx = match image_cycle {
+                                Some((_, i)) if forward => (i + 1) % n,
+                                Some((_, i)) => (i + n - 1) % n,
+                                None if forward => 0,
+                                None => n - 1,
+                            };
+                            input = TextInput::with_text(suggestions[idx].to_string());
+                            image_cycle = Some((query, idx));
+                        }
+                    }
                     KeyCode::Enter => {
+                        image_cycle = None;
                         let image_text = input.text.trim().to_string();
                         new_image = if image_text.is_empty() {
                             None
                         } else {
                             Some(image_text)
                         };
-                        let default_cmd = std::env::var("BOX_DEFAULT_CMD").unwrap_or_default();
-                        input = TextInput::with_text(default_cmd);
+                        input = TextInput::with_text(std::mem::take(&mut new_command_default));
                         mode = Mode::InputCommand;
                     }
                     KeyCode::Esc => {
+                        image_cycle = None;
                         mode = Mode::Normal;
                     }
                     _ => {
+                        image_cycle = None;
                         input.handle_key(key.code);
                     }
                 },
+                Mode::Logs => {
+                    footer_msg.clear();
+                    let page = LOGS_HEIGHT as usize;
+                    match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => {
+                            log_scroll = log_scroll.saturating_sub(1);
+                        }
+                        KeyCode::Down | KeyCode::Char('j') => {
+                            log_scroll = log_scroll.saturating_add(1);
+                        }
+                        KeyCode::PageUp => log_scroll = log_scroll.saturating_sub(page),
+                        KeyCode::PageDown => log_scroll = log_scroll.saturating_add(page),
+                        KeyCode::Home | KeyCode::Char('g') => log_scroll = 0,
+                        KeyCode::End | KeyCode::Char('G') => log_scroll = usize::MAX,
+                        KeyCode::Char('r') => match docker::recent_logs(&log_name, LOGS_TAIL) {
+                            Ok(output) => {
+                                log_lines = output.lines().map(sanitize_log_line).collect();
+                                log_scroll = usize::MAX;
+                            }
+                            Err(e) => footer_msg = format!("Logs failed: {}", e),
+                        },
+                        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('l') => {
+                            mode = Mode::Normal;
+                        }
+                        _ => {}
+                    }
+                }
                 Mode::InputCommand => match key.code {
                     KeyCode::Enter => {
                         let cmd_text = input.text.trim().to_string();
@@ -415,19 +1239,84 @@ where
                                 }
                             }
                         };
+                        new_command = command;
+                        input = TextInput::with_text(new_docker_args_default.clone());
+                        mode = Mode::InputDockerArgs;
+                    }
+                    KeyCode::Esc => {
+                        mode = Mode::Normal;
+                    }
+                    _ => {
+                        input.handle_key(key.code);
+                    }
+                },
+                Mode::InputDockerArgs => match key.code {
+                    KeyCode::Enter => {
+                        let args = input.text.trim().to_string();
+                        new_docker_args = (args != new_docker_args_default.trim()).then_some(args);
+                        input = TextInput::new();
+                        mode = Mode::InputEnv;
+                    }
+                    KeyCode::Esc => {
+                        mode = Mode::Normal;
+                    }
+                    _ => {
+                        input.handle_key(key.code);
+                    }
+                },
+                Mode::InputEnv => match key.code {
+                    KeyCode::Enter => {
+                        match parse_env_entries(&input.text) {
+                            Ok(env) => new_env = env,
+                            Err(e) => {
+                                footer_msg = e;
+                                mode = Mode::Normal;
+                                input = TextInput::new();
+                                continue;
+                            }
+                        }
+                        new_ssh = config::GlobalConfig::load()
+                            .ok()
+                            .and_then(|g| g.ssh)
+                            .unwrap_or(true);
+                        mode = Mode::SelectSsh;
+                    }
+                    KeyCode::Esc => {
+                        mode = Mode::Normal;
+                    }
+                    _ => {
+                        input.handle_key(key.code);
+                    }
+                },
+                Mode::SelectSsh => match key.code {
+                    KeyCode::Left
+                    | KeyCode::Right
+                    | KeyCode::Char('h')
+                    | KeyCode::Char('l')
+                    | KeyCode::Char(' ')
+                    | KeyCode::Tab
+                    | KeyCode::BackTab => {
+                        new_ssh = !new_ssh;
+                    }
+                    KeyCode::Enter => {
                         clear_viewport(&mut terminal, viewport_height)?;
                         return Ok(TuiAction::New {
                             name: new_name,
                             image: new_image,
-                            command,
+                            command: new_command,
+                            template: match template_idx {
+                                0 => None,
+                                i => templates.get(i - 1).cloned(),
+                            },
+                            docker_args: new_docker_args,
+                            env: new_env,
+                            ssh: Some(new_ssh),
                         });
                     }
                     KeyCode::Esc => {
                         mode = Mode::Normal;
                     }
-                    _ => {
-                        input.handle_key(key.code);
-                    }
+                    _ => {}
                 },
             }
         }
@@ -438,6 +1327,174 @@ where
 mod tests {
     use super::*;
 
+    fn summary(name: &str, created_at: &str, resumed_at: &str, running: bool) -> SessionSummary {
+        SessionSummary {
+            name: name.to_string(),
+            project_dir: String::new(),
+            image: String::new(),
+            command: String::new(),
+            created_at: created_at.to_string(),
+            resumed_at: resumed_at.to_string(),
+            running,
+            paused: false,
+            started_at: String::new(),
+            finished_at: String::new(),
+            exit_code: None,
+        }
+    }
+
+    fn names(items: &[SessionSummary]) -> Vec<&str> {
+        items.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_env_entries() {
+        assert_eq!(
+            parse_env_entries("FOO=bar TOKEN 'MSG=hello world'").unwrap(),
+            ["FOO=bar", "TOKEN", "MSG=hello world"]
+        );
+        assert!(parse_env_entries("").unwrap().is_empty());
+        assert!(parse_env_entries("=bar").is_err());
+        assert!(parse_env_entries("'FOO=bar").is_err());
+    }
+
+    #[test]
+    fn test_delete_targets_uses_selection_without_marks() {
+        let items = vec![summary("a", "", "", false), summary("b", "", "", false)];
+        let marked = HashSet::new();
+        assert_eq!(delete_targets(&items, &marked, Some(2)), ["b"]);
+        // Row 0 is "New box..."
+        assert!(delete_targets(&items, &marked, Some(0)).is_empty());
+        assert!(delete_targets(&items, &marked, None).is_empty());
+    }
+
+    #[test]
+    fn test_delete_targets_prefers_marked_in_display_order() {
+        let items = vec![
+            summary("a", "", "", false),
+            summary("b", "", "", false),
+            summary("c", "", "", false),
+        ];
+        let marked: HashSet<String> = ["c".to_string(), "a".to_string()].into();
+        assert_eq!(delete_targets(&items, &marked, Some(2)), ["a", "c"]);
+    }
+
+    #[test]
+    fn test_apply_status_keeps_selection() {
+        let mut items = vec![summary("a", "", "", false), summary("b", "", "", false)];
+        let mut refreshed = items.clone();
+        refreshed[1].running = true;
+        refreshed[1].started_at = "2025-01-01T00:00:00Z".to_string();
+
+        let mut state = TableState::default();
+        state.select(Some(1));
+        apply_status(&mut items, &refreshed);
+        sort_keeping_selection(&mut items, SortKey::RunningFirst, &mut state);
+        assert_eq!(names(&items), ["b", "a"]);
+        assert_eq!(items[0].started_at, "2025-01-01T00:00:00Z");
+        // The cursor follows "a" to its new row
+        assert_eq!(state.selected(), Some(2));
+    }
+
+    #[test]
+    fn test_detail_rows() {
+        let sess = Session {
+            name: "dev".to_string(),
+            project_dir: "/tmp/project".to_string(),
+            image: "alpine:latest".to_string(),
+            mount_path: "/workspace".to_string(),
+            command: vec!["bash".to_string(), "-c".to_string(), "echo hi".to_string()],
+            env: vec!["FOO=bar".to_string()],
+            ssh: true,
+            cpus: Some("2".to_string()),
+            ..Default::default()
+        };
+        let rows = detail_rows(&sess, &summary("dev", "2025-01-01", "", false), Some(2048));
+        let get = |key: &str| rows.iter().find(|(k, _)| *k == key).unwrap().1.clone();
+        assert_eq!(get("note"), "-");
+        assert_eq!(get("command"), "bash -c 'echo hi'");
+        assert_eq!(get("env"), "FOO=bar");
+        assert_eq!(get("ssh"), "yes  worktree: no");
+        assert_eq!(get("docker args"), "-");
+        assert_eq!(get("limits"), "cpus: 2  memory: unlimited");
+        assert_eq!(get("disk usage"), "2.0 KB");
+        assert_eq!(get("resumed"), "-");
+
+        let rows = detail_rows(&sess, &summary("dev", "", "", false), None);
+        assert!(rows.contains(&("disk usage", "workspace missing".to_string())));
+    }
+
+    #[test]
+    fn test_image_suggestions() {
+        let images = vec![
+            "alpine:latest".to_string(),
+            "ghcr.io/me/ubuntu-dev:1".to_string(),
+            "ubuntu:24.04".to_string(),
+        ];
+        assert_eq!(
+            image_suggestions(&images, "ubu"),
+            ["ubuntu:24.04", "ghcr.io/me/ubuntu-dev:1"]
+        );
+        assert_eq!(image_suggestions(&images, "").len(), 3);
+        assert!(image_suggestions(&images, "debian").is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_log_line() {
+        assert_eq!(sanitize_log_line("plain"), "plain");
+        assert_eq!(sanitize_log_line("\x1b[1;31merror\x1b[0m: x"), "error: x");
+        assert_eq!(sanitize_log_line("a\tb\r"), "a    b");
+    }
+
+    #[test]
+    fn test_sort_key_cycle() {
+        assert_eq!(SortKey::Name.next(), SortKey::Created);
+        assert_eq!(SortKey::Created.next(), SortKey::LastUsed);
+        assert_eq!(SortKey::LastUsed.next(), SortKey::RunningFirst);
+        assert_eq!(SortKey::RunningFirst.next(), SortKey::Name);
+    }
+
+    #[test]
+    fn test_sort_key_from_digit() {
+        assert_eq!(SortKey::from_digit('1'), Some(SortKey::Name));
+        assert_eq!(SortKey::from_digit('3'), Some(SortKey::LastUsed));
+        assert_eq!(SortKey::from_digit('4'), Some(SortKey::RunningFirst));
+        assert_eq!(SortKey::from_digit('0'), None);
+        assert_eq!(SortKey::from_digit('5'), None);
+    }
+
+    #[test]
+    fn test_sort_sessions() {
+        let mut items = vec![
+            summary("b", "2025-01-04 12:00:00 UTC", "", false),
+            summary(
+                "a",
+                "2025-01-01 00:00:00 UTC",
+                "2025-01-05 00:00:00 UTC",
+                true,
+            ),
+            summary(
+                "c",
+                "2025-01-03 00:00:00 UTC",
+                "2025-01-04 00:00:00 UTC",
+                false,
+            ),
+        ];
+
+        sort_sessions(&mut items, SortKey::Name);
+        assert_eq!(names(&items), ["a", "b", "c"]);
+
+        sort_sessions(&mut items, SortKey::Created);
+        assert_eq!(names(&items), ["b", "c", "a"]);
+
+        // Never-resumed sessions count as used when they were created
+        sort_sessions(&mut items, SortKey::LastUsed);
+        assert_eq!(names(&items), ["a", "b", "c"]);
+
+        sort_sessions(&mut items, SortKey::RunningFirst);
+        assert_eq!(names(&items), ["a", "b", "c"]);
+    }
+
     #[test]
     fn test_text_input_insert() {
         let mut input = TextInput::new();